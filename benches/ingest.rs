@@ -0,0 +1,203 @@
+use criterion::{
+	criterion_group, criterion_main, BatchSize, Criterion, Throughput,
+};
+use sdd::dae::{decode_descriptor, decode_entry, Daemon, Protocol};
+use sdd::producer::{Producer, Type, Value};
+use sdd::raw::{Capture, Index};
+use sdd::spsc::ReadAhead;
+use sdd::wire::Messages;
+use std::io::Cursor;
+
+//---------------------------------------------------------------------------
+const TABLES: u32 = 32;
+const ENTRIES: u64 = 1000;
+
+fn columns() -> Vec<(String, Type)> {
+	let types = [Type::Int, Type::Float, Type::Bool, Type::Str];
+	(0..8)
+		.map(|i| (format!("f{}", i), types[i % types.len()]))
+		.collect()
+}
+
+/// Stream declaring `tables` tables followed by `entries` entries of the
+/// first one.
+fn stream(tables: u32, entries: u64) -> Vec<u8> {
+	let columns = columns();
+	let columns: Vec<(&str, Type)> =
+		columns.iter().map(|(n, t)| (n.as_str(), *t)).collect();
+
+	let mut producer = Producer::new(vec![]);
+	for i in 0..tables {
+		producer.table(&format!("table_{}", i), &columns).unwrap();
+	}
+
+	let label = producer.string("label").unwrap();
+	for i in 0..entries {
+		let values: Vec<Value> = columns
+			.iter()
+			.map(|(_, t)| match t {
+				Type::Int => Value::Int(i as u32),
+				Type::Float => Value::Float(i as f32 * 0.5),
+				Type::Bool => Value::Bool(i % 2 == 0),
+				Type::Str => Value::Str(label),
+			})
+			.collect();
+		producer.entry(0, &values).unwrap();
+	}
+
+	producer.flush().unwrap();
+	producer.into_inner()
+}
+
+fn dry_run() -> Daemon {
+	let mut daemon = Daemon::new(Protocol::without_output());
+	daemon.verbose = false;
+	daemon.dry_run = true;
+	daemon
+}
+
+//---------------------------------------------------------------------------
+fn decode(c: &mut Criterion) {
+	let mut group = c.benchmark_group("decode");
+
+	let descriptors = stream(TABLES, 0);
+	group.throughput(Throughput::Elements(TABLES as u64));
+	group.bench_function("descriptors", |b| {
+		b.iter_batched(
+			dry_run,
+			|mut daemon| daemon.ingest(&descriptors[..]).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+
+	// A descriptor body with 8 fields and a matching entry body.
+	let mut body = vec![0, 0, 0, 0, 0, 0, 0, 0, 8];
+	for (i, (_, t)) in columns().iter().enumerate() {
+		body.push(*t as u8);
+		body.extend_from_slice(&(i as u32).to_le_bytes());
+	}
+	let (desc, _, _) = decode_descriptor(&body).unwrap();
+	let entry = vec![1; desc.entry_size()];
+	group.throughput(Throughput::Elements(1));
+	group.bench_function("entry_slice", |b| {
+		b.iter(|| decode_entry(&desc, &entry).is_ok())
+	});
+
+	let entries = stream(1, ENTRIES);
+	group.throughput(Throughput::Elements(ENTRIES));
+	group.bench_function("entries", |b| {
+		b.iter_batched(
+			dry_run,
+			|mut daemon| daemon.ingest(&entries[..]).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+
+	group.finish();
+}
+
+fn insert(c: &mut Criterion) {
+	let mut group = c.benchmark_group("insert");
+	group.sample_size(20);
+
+	let entries = stream(1, ENTRIES);
+	group.throughput(Throughput::Elements(ENTRIES));
+	group.bench_function("entries", |b| {
+		b.iter_batched(
+			|| {
+				let proto = Protocol::new(String::from(":memory:")).unwrap();
+				let mut daemon = Daemon::new(proto);
+				daemon.verbose = false;
+				daemon.max_entries = Some(ENTRIES);
+				daemon
+			},
+			|mut daemon| daemon.ingest(&entries[..]).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+
+	group.finish();
+}
+
+/// Decoding a stream read inline against one read ahead by another thread,
+/// in chunks the size of a small read buffer.
+fn read_ahead(c: &mut Criterion) {
+	let mut group = c.benchmark_group("read_ahead");
+
+	let entries = stream(1, ENTRIES * 10);
+	group.throughput(Throughput::Elements(ENTRIES * 10));
+	group.bench_function("inline", |b| {
+		b.iter_batched(
+			dry_run,
+			|mut daemon| daemon.ingest(&entries[..]).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+	group.bench_function("spsc", |b| {
+		b.iter_batched(
+			|| {
+				let reader = Cursor::new(entries.clone());
+				(dry_run(), ReadAhead::spawn(reader, 8, 4096))
+			},
+			|(mut daemon, reader)| daemon.ingest(reader).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+
+	group.finish();
+}
+
+/// Decoding the last checkpoint of a raw capture read into memory against
+/// one mapped, which only reads the pages decoded.
+fn raw_capture(c: &mut Criterion) {
+	let mut group = c.benchmark_group("raw_capture");
+
+	let path = std::env::temp_dir().join("sdd-bench.sddcap");
+	let bytes = stream(1, ENTRIES * 100);
+	std::fs::write(&path, &bytes).unwrap();
+	let index = Index::build(&bytes);
+	let start = index.checkpoints.last().unwrap().offset;
+	let tail = |bytes: &[u8]| {
+		let mut messages = Messages::new(bytes);
+		messages.seek(start);
+		messages.count()
+	};
+
+	group.bench_function("read", |b| {
+		b.iter(|| tail(&std::fs::read(&path).unwrap()))
+	});
+	group.bench_function("mmap", |b| {
+		b.iter(|| tail(&Capture::open(&path).unwrap()))
+	});
+
+	group.finish();
+	let _ = std::fs::remove_file(&path);
+}
+
+/// Decoding a capture as a stream against decoding it on 4 threads.
+fn import(c: &mut Criterion) {
+	let mut group = c.benchmark_group("import");
+
+	let entries = stream(1, ENTRIES * 100);
+	let index = Index::build(&entries);
+	group.throughput(Throughput::Elements(ENTRIES * 100));
+	group.bench_function("stream", |b| {
+		b.iter_batched(
+			dry_run,
+			|mut daemon| daemon.ingest(&entries[..]).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+	group.bench_function("parallel", |b| {
+		b.iter_batched(
+			dry_run,
+			|mut daemon| daemon.import(&entries, &index, 4).is_ok(),
+			BatchSize::SmallInput,
+		)
+	});
+
+	group.finish();
+}
+
+criterion_group!(benches, decode, insert, read_ahead, raw_capture, import);
+criterion_main!(benches);
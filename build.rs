@@ -0,0 +1,26 @@
+fn main() {
+	#[cfg(feature = "grpc")]
+	grpc();
+}
+
+/// Generates the gRPC ingest service, its messages are the prost structs of
+/// src/grpc.rs mirroring proto/sdd.proto.
+#[cfg(feature = "grpc")]
+fn grpc() {
+	let stream = tonic_build::manual::Method::builder()
+		.name("stream")
+		.route_name("Stream")
+		.input_type("crate::grpc::Frame")
+		.output_type("crate::grpc::Summary")
+		.codec_path("tonic::codec::ProstCodec")
+		.client_streaming()
+		.build();
+
+	let service = tonic_build::manual::Service::builder()
+		.name("Ingest")
+		.package("sdd")
+		.method(stream)
+		.build();
+
+	tonic_build::manual::Builder::new().compile(&[service]);
+}
@@ -0,0 +1,512 @@
+//! Frame layout of the sdd protocol, see proto.md. Needs neither std nor an
+//! allocator, so firmware can encode messages into a fixed buffer and send
+//! them over any link.
+#![no_std]
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+//---------------------------------------------------------------------------
+pub const PROTOCOL: u32 = 0xFEEDBEEF;
+
+pub const MSG_STR: u8 = 1;
+pub const MSG_ENTRY: u8 = 2;
+pub const MSG_DESC: u8 = 3;
+pub const MSG_MARKER: u8 = 4;
+pub const MSG_ENTITY: u8 = 5;
+pub const MSG_SCOPE_BEGIN: u8 = 6;
+pub const MSG_SCOPE_END: u8 = 7;
+pub const MSG_COUNTER: u8 = 8;
+pub const MSG_PAUSE: u8 = 9;
+pub const MSG_ATTACHMENT: u8 = 10;
+pub const MSG_THREAD: u8 = 11;
+/// Sent by the daemon back to the producer, see `Encoder::control`.
+pub const MSG_CONTROL: u8 = 12;
+pub const MSG_SOURCE: u8 = 13;
+
+/// Label id of a marker or a pause without a label.
+pub const NO_LABEL: u32 = 0xFFFF_FFFF;
+/// Parent id of a root scope.
+pub const NO_SCOPE: u32 = 0xFFFF_FFFF;
+
+/// "SDDC", opens a stream whose entries are compact, see `compact_entry`.
+pub const COMPACT_MAGIC: [u8; 4] = *b"SDDC";
+/// "SDDA", opens a stream with a pre-shared token, a u32 length followed by
+/// its bytes, ahead of any other preamble.
+pub const AUTH_MAGIC: [u8; 4] = *b"SDDA";
+/// Longest token of `AUTH_MAGIC`.
+pub const MAX_TOKEN: usize = 1024;
+
+/// Columns of a table.
+pub const MAX_FIELDS: usize = 32;
+/// Flag of the column count of a table whose entries are tagged with the
+/// producer thread, see `Encoder::threaded_table`.
+pub const THREADED: u8 = 0x80;
+
+/// Protocol and message type preceding each message.
+pub const HEADER_SIZE: usize = 5;
+/// Largest table and entry messages, strings are as long as their text.
+pub const MAX_TABLE_SIZE: usize = HEADER_SIZE + 9 + MAX_FIELDS * 5;
+pub const MAX_ENTRY_SIZE: usize = HEADER_SIZE + 4 + MAX_FIELDS * 4;
+pub const ENTITY_SIZE: usize = HEADER_SIZE + 12;
+pub const PAUSE_SIZE: usize = HEADER_SIZE + 5;
+pub const THREAD_SIZE: usize = HEADER_SIZE + 4;
+pub const CONTROL_SIZE: usize = HEADER_SIZE + 8;
+pub const SOURCE_SIZE: usize = HEADER_SIZE + 4;
+pub const SCOPE_BEGIN_SIZE: usize = HEADER_SIZE + 20;
+pub const SCOPE_END_SIZE: usize = HEADER_SIZE + 12;
+/// Largest counter message, its delta taking 1 to 5 bytes.
+pub const MAX_COUNTER_SIZE: usize = HEADER_SIZE + 4 + 5;
+/// Largest compact entry message, varints taking up to 5 bytes.
+pub const MAX_COMPACT_ENTRY_SIZE: usize = HEADER_SIZE + 5 + MAX_FIELDS * 5;
+
+/// Size of the string message carrying `len` bytes of text.
+pub const fn string_size(len: usize) -> usize {
+	HEADER_SIZE + 8 + len
+}
+
+/// Size of the attachment message carrying `len` bytes of data.
+pub const fn attachment_size(len: usize) -> usize {
+	HEADER_SIZE + 8 + len
+}
+
+//---------------------------------------------------------------------------
+/// Column types of a table, see proto.md.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Type {
+	Int = 1,
+	Float = 2,
+	Bool = 3,
+	Str = 4,
+}
+
+impl Type {
+	pub fn from_u8(t: u8) -> Option<Type> {
+		match t {
+			1 => Some(Type::Int),
+			2 => Some(Type::Float),
+			3 => Some(Type::Bool),
+			4 => Some(Type::Str),
+			_ => None,
+		}
+	}
+
+	/// Size of a value on the wire.
+	pub const fn size(self) -> usize {
+		match self {
+			Type::Bool => 1,
+			_ => 4,
+		}
+	}
+}
+
+/// Value of an entry field, strings are ids of string messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+	Int(u32),
+	Float(f32),
+	Bool(bool),
+	Str(u32),
+}
+
+impl Value {
+	pub fn data_type(&self) -> Type {
+		match self {
+			Value::Int(..) => Type::Int,
+			Value::Float(..) => Type::Float,
+			Value::Bool(..) => Type::Bool,
+			Value::Str(..) => Type::Str,
+		}
+	}
+}
+
+/// Little endian u32 at the start of `bytes`.
+pub fn word(bytes: &[u8]) -> u32 {
+	u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Unsigned LEB128 value at the start of `bytes` along with its size, `None`
+/// when it does not end within the 5 bytes of a u32.
+pub fn varint(bytes: &[u8]) -> Option<(u32, usize)> {
+	let mut value = 0u32;
+	for (i, b) in bytes.iter().take(5).enumerate() {
+		if i == 4 && *b > 0x0F {
+			return None;
+		}
+		value |= ((b & 0x7F) as u32) << (7 * i);
+		if b & 0x80 == 0 {
+			return Some((value, i + 1));
+		}
+	}
+	None
+}
+
+/// Size of `v` as an unsigned LEB128 varint.
+pub const fn varint_size(v: u32) -> usize {
+	match v {
+		0..=0x7F => 1,
+		0x80..=0x3FFF => 2,
+		0x4000..=0x1F_FFFF => 3,
+		0x20_0000..=0xFFF_FFFF => 4,
+		_ => 5,
+	}
+}
+
+/// Maps a signed delta to an unsigned varint value, small magnitudes of
+/// either sign staying small.
+pub const fn zigzag(v: i32) -> u32 {
+	((v << 1) ^ (v >> 31)) as u32
+}
+
+pub const fn unzigzag(v: u32) -> i32 {
+	((v >> 1) as i32) ^ -((v & 1) as i32)
+}
+
+/// Little endian u64 at the start of `bytes`, e.g. a scope timestamp.
+pub fn dword(bytes: &[u8]) -> u64 {
+	let mut b = [0; 8];
+	b.copy_from_slice(&bytes[..8]);
+	u64::from_le_bytes(b)
+}
+
+//---------------------------------------------------------------------------
+/// Errors of the `Encoder`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+	/// The message does not fit the rest of the buffer, which is left as it
+	/// was.
+	Full,
+	/// Tables have 1 to 32 columns.
+	Columns,
+}
+
+/// Encodes messages back to back into a buffer, a message that does not fit
+/// is not written at all. Unlike `sdd::producer::Producer` it leaves the
+/// string and table ids to the caller.
+pub struct Encoder<'a> {
+	buf: &'a mut [u8],
+	len: usize,
+}
+
+impl<'a> Encoder<'a> {
+	pub fn new(buf: &'a mut [u8]) -> Self {
+		Encoder { buf, len: 0 }
+	}
+
+	/// Encoded messages, to be sent as they are.
+	pub fn bytes(&self) -> &[u8] {
+		&self.buf[..self.len]
+	}
+
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+
+	/// Forgets the messages, e.g. once they have been sent.
+	pub fn clear(&mut self) {
+		self.len = 0;
+	}
+
+	fn put(&mut self, bytes: &[u8]) {
+		self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+		self.len += bytes.len();
+	}
+
+	fn put_varint(&mut self, mut v: u32) {
+		while v >= 0x80 {
+			self.put(&[(v as u8) | 0x80]);
+			v >>= 7;
+		}
+		self.put(&[v as u8]);
+	}
+
+	fn header(&mut self, msg: u8, size: usize) -> Result<(), Error> {
+		if self.buf.len() - self.len < size {
+			return Err(Error::Full);
+		}
+		self.put(&PROTOCOL.to_le_bytes());
+		self.put(&[msg]);
+		Ok(())
+	}
+
+	/// Declares string `id`, used for names, labels and string values.
+	pub fn string(&mut self, id: u32, s: &str) -> Result<(), Error> {
+		self.header(MSG_STR, string_size(s.len()))?;
+		self.put(&id.to_le_bytes());
+		self.put(&(s.len() as u32).to_le_bytes());
+		self.put(s.as_bytes());
+		Ok(())
+	}
+
+	/// Declares table `uid` of (name, type) columns, names being string ids.
+	pub fn table(
+		&mut self,
+		uid: u32,
+		name: u32,
+		columns: &[(u32, Type)],
+	) -> Result<(), Error> {
+		self.table_flags(uid, name, columns, 0)
+	}
+
+	/// Declares table `uid` like `table`, its entries being tagged with the
+	/// thread of the last `thread` message.
+	pub fn threaded_table(
+		&mut self,
+		uid: u32,
+		name: u32,
+		columns: &[(u32, Type)],
+	) -> Result<(), Error> {
+		self.table_flags(uid, name, columns, THREADED)
+	}
+
+	fn table_flags(
+		&mut self,
+		uid: u32,
+		name: u32,
+		columns: &[(u32, Type)],
+		flags: u8,
+	) -> Result<(), Error> {
+		if columns.is_empty() || columns.len() > MAX_FIELDS {
+			return Err(Error::Columns);
+		}
+
+		self.header(MSG_DESC, HEADER_SIZE + 9 + columns.len() * 5)?;
+		self.put(&uid.to_le_bytes());
+		self.put(&name.to_le_bytes());
+		self.put(&[columns.len() as u8 | flags]);
+		for (column, data_type) in columns {
+			self.put(&[*data_type as u8]);
+			self.put(&column.to_le_bytes());
+		}
+		Ok(())
+	}
+
+	/// Entry of table `uid`, `values` in column order.
+	pub fn entry(&mut self, uid: u32, values: &[Value]) -> Result<(), Error> {
+		let size = values.iter().map(|v| v.data_type().size()).sum::<usize>();
+		self.header(MSG_ENTRY, HEADER_SIZE + 4 + size)?;
+		self.put(&uid.to_le_bytes());
+
+		for v in values {
+			match v {
+				Value::Int(v) | Value::Str(v) => self.put(&v.to_le_bytes()),
+				Value::Float(v) => self.put(&v.to_le_bytes()),
+				Value::Bool(v) => self.put(&[*v as u8]),
+			}
+		}
+		Ok(())
+	}
+
+	/// Entry of table `uid` in a stream opened with `COMPACT_MAGIC`. The uid,
+	/// ints and strings are varints, floats the zigzag varint of the
+	/// difference of their bits to the float of `previous`, the values of
+	/// the last entry of the table. Missing previous floats count as zero.
+	pub fn compact_entry(
+		&mut self,
+		uid: u32,
+		values: &[Value],
+		previous: &[Value],
+	) -> Result<(), Error> {
+		let delta = |i: usize, v: f32| {
+			let prev = match previous.get(i) {
+				Some(Value::Float(p)) => p.to_bits(),
+				_ => 0,
+			};
+			zigzag(v.to_bits().wrapping_sub(prev) as i32)
+		};
+
+		let size = values
+			.iter()
+			.enumerate()
+			.map(|(i, v)| match v {
+				Value::Int(v) | Value::Str(v) => varint_size(*v),
+				Value::Float(v) => varint_size(delta(i, *v)),
+				Value::Bool(..) => 1,
+			})
+			.sum::<usize>();
+		self.header(MSG_ENTRY, HEADER_SIZE + varint_size(uid) + size)?;
+		self.put_varint(uid);
+
+		for (i, v) in values.iter().enumerate() {
+			match v {
+				Value::Int(v) | Value::Str(v) => self.put_varint(*v),
+				Value::Float(v) => self.put_varint(delta(i, *v)),
+				Value::Bool(v) => self.put(&[*v as u8]),
+			}
+		}
+		Ok(())
+	}
+
+	/// Starts or stops persisting entries on the daemon, `label` being a
+	/// string id.
+	pub fn marker(
+		&mut self,
+		start: bool,
+		label: Option<u32>,
+	) -> Result<(), Error> {
+		self.header(MSG_MARKER, HEADER_SIZE + 5)?;
+		self.put(&[start as u8]);
+		self.put(&label.unwrap_or(NO_LABEL).to_le_bytes());
+		Ok(())
+	}
+
+	/// Pauses or resumes persisting entries on the daemon without ending
+	/// the session, `reason` being a string id.
+	pub fn pause(
+		&mut self,
+		paused: bool,
+		reason: Option<u32>,
+	) -> Result<(), Error> {
+		self.header(MSG_PAUSE, PAUSE_SIZE)?;
+		self.put(&[paused as u8]);
+		self.put(&reason.unwrap_or(NO_LABEL).to_le_bytes());
+		Ok(())
+	}
+
+	/// Registers entity `id` of this run under a name and a type, both
+	/// string ids.
+	pub fn entity(
+		&mut self,
+		id: u32,
+		name: u32,
+		kind: u32,
+	) -> Result<(), Error> {
+		self.header(MSG_ENTITY, ENTITY_SIZE)?;
+		self.put(&id.to_le_bytes());
+		self.put(&name.to_le_bytes());
+		self.put(&kind.to_le_bytes());
+		Ok(())
+	}
+
+	/// Tags the entries that follow with the thread named by string
+	/// `name`, until the next thread message.
+	pub fn thread(&mut self, name: u32) -> Result<(), Error> {
+		self.header(MSG_THREAD, THREAD_SIZE)?;
+		self.put(&name.to_le_bytes());
+		Ok(())
+	}
+
+	/// Attributes the messages that follow to the producer named by string
+	/// `name`, e.g. for a relay forwarding several producers.
+	pub fn source(&mut self, name: u32) -> Result<(), Error> {
+		self.header(MSG_SOURCE, SOURCE_SIZE)?;
+		self.put(&name.to_le_bytes());
+		Ok(())
+	}
+
+	/// Asks the producer to send one in `every` entries of table `uid`, none
+	/// when 0, all when 1. The daemon sends it back over the connection.
+	pub fn control(&mut self, uid: u32, every: u32) -> Result<(), Error> {
+		self.header(MSG_CONTROL, CONTROL_SIZE)?;
+		self.put(&uid.to_le_bytes());
+		self.put(&every.to_le_bytes());
+		Ok(())
+	}
+
+	/// Attaches a one-off binary payload such as a minidump or a
+	/// screenshot, `name` being a string id.
+	pub fn attachment(&mut self, name: u32, data: &[u8]) -> Result<(), Error> {
+		self.header(MSG_ATTACHMENT, attachment_size(data.len()))?;
+		self.put(&name.to_le_bytes());
+		self.put(&(data.len() as u32).to_le_bytes());
+		self.put(data);
+		Ok(())
+	}
+
+	/// Adds `delta` to the last column of table `uid`, an int, the other
+	/// columns repeating the previous entry of the table.
+	pub fn counter(&mut self, uid: u32, delta: u32) -> Result<(), Error> {
+		self.header(MSG_COUNTER, HEADER_SIZE + 4 + varint_size(delta))?;
+		self.put(&uid.to_le_bytes());
+		self.put_varint(delta);
+		Ok(())
+	}
+
+	/// Opens scope `id` within `parent` at `ts`, the name being a string id.
+	/// Scope ids are free again once the scope ended.
+	pub fn scope_begin(
+		&mut self,
+		id: u32,
+		parent: Option<u32>,
+		name: u32,
+		ts: u64,
+	) -> Result<(), Error> {
+		self.header(MSG_SCOPE_BEGIN, SCOPE_BEGIN_SIZE)?;
+		self.put(&id.to_le_bytes());
+		self.put(&parent.unwrap_or(NO_SCOPE).to_le_bytes());
+		self.put(&name.to_le_bytes());
+		self.put(&ts.to_le_bytes());
+		Ok(())
+	}
+
+	/// Closes scope `id` at `ts`, in the clock of its begin.
+	pub fn scope_end(&mut self, id: u32, ts: u64) -> Result<(), Error> {
+		self.header(MSG_SCOPE_END, SCOPE_END_SIZE)?;
+		self.put(&id.to_le_bytes());
+		self.put(&ts.to_le_bytes());
+		Ok(())
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fixed_buffer() {
+		let mut buf = [0; 32];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.string(0, "ts").unwrap();
+		encoder
+			.entry(1, &[Value::Int(7), Value::Bool(true)])
+			.unwrap();
+		assert_eq!(encoder.len(), string_size(2) + HEADER_SIZE + 9);
+
+		let len = encoder.len();
+		assert_eq!(encoder.table(0, 0, &[(0, Type::Int)]), Err(Error::Full));
+		assert_eq!(encoder.table(0, 0, &[]), Err(Error::Columns));
+		assert_eq!(encoder.len(), len);
+
+		assert_eq!(
+			&encoder.bytes()[string_size(2)..],
+			&[0xEF, 0xBE, 0xED, 0xFE, 2, 1, 0, 0, 0, 7, 0, 0, 0, 1]
+		);
+	}
+
+	#[test]
+	fn counter_deltas() {
+		for delta in [0, 127, 128, 300, u32::MAX] {
+			let mut buf = [0; MAX_COUNTER_SIZE];
+			let mut encoder = Encoder::new(&mut buf);
+			encoder.counter(3, delta).unwrap();
+			let bytes = &encoder.bytes()[HEADER_SIZE + 4..];
+			assert_eq!(varint(bytes), Some((delta, bytes.len())));
+		}
+
+		assert_eq!(varint(&[0xAC, 0x02]), Some((300, 2)));
+		for v in [0, 1, -1, i32::MAX, i32::MIN] {
+			assert_eq!(unzigzag(zigzag(v)), v);
+		}
+		assert_eq!(zigzag(-1), 1);
+		assert_eq!(varint(&[0x80, 0x80]), None);
+		assert_eq!(varint(&[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]), None);
+	}
+
+	#[test]
+	fn compact_entry() {
+		let mut buf = [0; MAX_COMPACT_ENTRY_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		let values = [Value::Int(300), Value::Float(1.5), Value::Bool(true)];
+		encoder
+			.compact_entry(3, &values, &[Value::Int(7), Value::Float(1.5)])
+			.unwrap();
+		assert_eq!(&encoder.bytes()[HEADER_SIZE..], &[3, 0xAC, 0x02, 0, 1]);
+	}
+}
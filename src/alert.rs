@@ -0,0 +1,317 @@
+use crate::config::parse_duration;
+use crate::http;
+use serde::Deserialize;
+use serde_json::json;
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+	/// `[table.]column <op> <value> [for <duration>]`, e.g.
+	/// `frame.frame_ms > 33 for 5s`.
+	pub rule: String,
+	/// Url receiving a json POST when the alert fires.
+	pub webhook: Option<String>,
+	/// Shell command run when the alert fires, with `SDD_ALERT` and
+	/// `SDD_VALUE` in its environment.
+	pub command: Option<String>,
+}
+
+//---------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Op {
+	Gt,
+	Ge,
+	Lt,
+	Le,
+	Eq,
+	Ne,
+}
+
+impl Op {
+	fn eval(self, a: f64, b: f64) -> bool {
+		match self {
+			Op::Gt => a > b,
+			Op::Ge => a >= b,
+			Op::Lt => a < b,
+			Op::Le => a <= b,
+			Op::Eq => a == b,
+			Op::Ne => a != b,
+		}
+	}
+}
+
+impl FromStr for Op {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			">" => Ok(Op::Gt),
+			">=" => Ok(Op::Ge),
+			"<" => Ok(Op::Lt),
+			"<=" => Ok(Op::Le),
+			"==" => Ok(Op::Eq),
+			"!=" => Ok(Op::Ne),
+			_ => Err(format!("Unknown operator '{}'", s)),
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+pub struct Rule {
+	pub table: Option<String>,
+	pub column: String,
+	pub op: Op,
+	pub threshold: f64,
+	/// How long the condition has to hold before the alert fires.
+	pub hold: Duration,
+}
+
+impl FromStr for Rule {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parts: Vec<&str> = s.split_whitespace().collect();
+		let (target, op, threshold, hold) = match parts.as_slice() {
+			[t, o, v] => (t, o, v, None),
+			[t, o, v, "for", d] => (t, o, v, Some(d)),
+			_ => return Err(format!("Malformed alert rule '{}'", s)),
+		};
+
+		let (table, column) = match target.rfind('.') {
+			Some(i) => (Some(target[..i].to_string()), &target[i + 1..]),
+			None => (None, &target[..]),
+		};
+
+		Ok(Rule {
+			table,
+			column: column.to_string(),
+			op: op.parse()?,
+			threshold: threshold
+				.parse()
+				.map_err(|_| format!("Bad threshold in '{}'", s))?,
+			hold: match hold {
+				Some(d) => parse_duration(d)?,
+				None => Duration::from_secs(0),
+			},
+		})
+	}
+}
+
+//---------------------------------------------------------------------------
+struct Alert {
+	config: AlertConfig,
+	rule: Rule,
+}
+
+/// A rule watching a column of a table, a rule without a table holding
+/// for each of the tables it watches on its own.
+#[derive(Clone)]
+struct Binding {
+	alert: usize,
+	field: usize,
+	since: Option<Instant>,
+	fired: bool,
+}
+
+impl Alert {
+	fn fire(&self, value: f64) {
+		let rule = self.config.rule.clone();
+
+		if let Some(url) = self.config.webhook.clone() {
+			let body = json!({ "alert": rule, "value": value }).to_string();
+			thread::spawn(move || {
+				if let Err(e) =
+					http::post(&url, "application/json", body.as_bytes())
+				{
+					println!("Alert webhook {} failed: {}", url, e);
+				}
+			});
+		}
+
+		if let Some(cmd) = self.config.command.clone() {
+			thread::spawn(move || {
+				let status = Command::new("sh")
+					.arg("-c")
+					.arg(&cmd)
+					.env("SDD_ALERT", &rule)
+					.env("SDD_VALUE", value.to_string())
+					.status();
+
+				if let Err(e) = status {
+					println!("Alert command '{}' failed: {}", cmd, e);
+				}
+			});
+		}
+	}
+}
+
+/// Alert rules evaluated against every inserted entry.
+#[derive(Default)]
+pub struct Alerts {
+	alerts: Vec<Alert>,
+	/// Per descriptor uid, the rules to evaluate.
+	bindings: Vec<Vec<Binding>>,
+}
+
+impl Alerts {
+	pub fn new(configs: &[AlertConfig]) -> Result<Alerts, String> {
+		let mut alerts = Alerts::default();
+		for config in configs {
+			alerts.alerts.push(Alert {
+				rule: config.rule.parse()?,
+				config: config.clone(),
+			});
+		}
+
+		Ok(alerts)
+	}
+
+	/// Resolves the rules watching columns of a newly registered table.
+	pub fn bind(&mut self, uid: u32, table: &str, columns: &[String]) {
+		let uid = uid as usize;
+		if self.bindings.len() <= uid {
+			self.bindings.resize(uid + 1, vec![]);
+		}
+		// A reconnected producer may reuse the uid for another table.
+		self.bindings[uid].clear();
+
+		for (i, alert) in self.alerts.iter().enumerate() {
+			if alert.rule.table.as_ref().is_some_and(|t| t != table) {
+				continue;
+			}
+
+			if let Some(field) =
+				columns.iter().position(|c| *c == alert.rule.column)
+			{
+				self.bindings[uid].push(Binding {
+					alert: i,
+					field,
+					since: None,
+					fired: false,
+				});
+			}
+		}
+	}
+
+	/// Evaluates the rules bound to `uid`, returning log lines for alerts
+	/// that fired or resolved.
+	pub fn check<F>(&mut self, uid: u32, value: F, now: Instant) -> Vec<String>
+	where
+		F: Fn(usize) -> Option<f64>,
+	{
+		let mut log = vec![];
+		let bindings = match self.bindings.get_mut(uid as usize) {
+			Some(b) => b,
+			None => return log,
+		};
+
+		for binding in bindings {
+			let alert = &self.alerts[binding.alert];
+			let v = match value(binding.field) {
+				Some(v) => v,
+				None => continue,
+			};
+
+			if alert.rule.op.eval(v, alert.rule.threshold) {
+				let since = *binding.since.get_or_insert(now);
+				if !binding.fired && now - since >= alert.rule.hold {
+					binding.fired = true;
+					alert.fire(v);
+					log.push(format!(
+						"Alert: {} (value {})",
+						alert.config.rule, v
+					));
+				}
+			} else {
+				if binding.fired {
+					log.push(format!("Resolved: {}", alert.config.rule));
+				}
+
+				binding.since = None;
+				binding.fired = false;
+			}
+		}
+
+		log
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_rule() {
+		let rule: Rule = "frame.frame_ms > 33 for 5s".parse().unwrap();
+		assert_eq!(rule.table.as_deref(), Some("frame"));
+		assert_eq!(rule.column, "frame_ms");
+		assert_eq!(rule.op, Op::Gt);
+		assert_eq!(rule.threshold, 33.0);
+		assert_eq!(rule.hold, Duration::from_secs(5));
+
+		assert!("frame_ms >> 33".parse::<Rule>().is_err());
+		let forever = format!("frame_ms > 33 for {}h", "9".repeat(400));
+		assert!(forever.parse::<Rule>().is_err());
+	}
+
+	#[test]
+	fn hold_duration() {
+		let config = AlertConfig {
+			rule: String::from("frame_ms >= 33 for 1s"),
+			webhook: None,
+			command: None,
+		};
+
+		let mut alerts = Alerts::new(&[config]).unwrap();
+		alerts.bind(
+			0,
+			"frame",
+			&[String::from("ts"), String::from("frame_ms")],
+		);
+
+		let t0 = Instant::now();
+		let slow = |i: usize| Some(if i == 1 { 40.0 } else { 0.0 });
+
+		assert!(alerts.check(0, slow, t0).is_empty());
+		assert!(alerts
+			.check(0, slow, t0 + Duration::from_millis(500))
+			.is_empty());
+		assert_eq!(alerts.check(0, slow, t0 + Duration::from_secs(1)).len(), 1);
+		assert!(alerts
+			.check(0, slow, t0 + Duration::from_secs(2))
+			.is_empty());
+		assert_eq!(
+			alerts
+				.check(0, |_| Some(0.0), t0 + Duration::from_secs(3))
+				.len(),
+			1
+		);
+	}
+
+	#[test]
+	fn tables_hold_apart() {
+		let config = AlertConfig {
+			rule: String::from("ms > 10 for 1s"),
+			webhook: None,
+			command: None,
+		};
+
+		let mut alerts = Alerts::new(&[config]).unwrap();
+		alerts.bind(0, "frame", &[String::from("ms")]);
+		alerts.bind(1, "tick", &[String::from("ms")]);
+
+		let t0 = Instant::now();
+		let t1 = t0 + Duration::from_secs(1);
+		assert!(alerts.check(0, |_| Some(20.0), t0).is_empty());
+		assert!(alerts.check(1, |_| Some(20.0), t1).is_empty());
+		assert_eq!(alerts.check(0, |_| Some(20.0), t1).len(), 1);
+		assert!(alerts.check(1, |_| Some(0.0), t1).is_empty());
+	}
+}
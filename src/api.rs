@@ -0,0 +1,188 @@
+use crate::cipher;
+use crate::export;
+use crate::http::{self, Request, Response};
+use crate::value;
+use rusqlite;
+use serde_json::{json, Value};
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Rows returned by a request unless `limit` asks for fewer.
+const DEFAULT_ROWS: u32 = 1000;
+const MAX_ROWS: u32 = 10_000;
+/// Time a request waits for the writer to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+
+//---------------------------------------------------------------------------
+pub struct Options {
+	pub db: PathBuf,
+}
+
+/// Serves the read-only query api over the live capture database on `addr`:
+/// `GET /tables` and `GET /tables/<name>/rows?since=<rowid>&limit=<n>`. The
+/// daemon writes the database in WAL mode meanwhile, so polling readers do
+/// not block it.
+pub fn serve(addr: &str, opts: Options) -> io::Result<thread::JoinHandle<()>> {
+	println!("Query api listening on http://{}", addr);
+	crate::http::serve(addr, move |req| handle(req, &opts))
+}
+
+fn handle(req: &Request, opts: &Options) -> Response {
+	if req.method != "GET" {
+		return Response::text(405, "Method not allowed");
+	}
+
+	let path: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+	let result = match path[..] {
+		["tables"] => tables(opts),
+		["tables", name, "rows"] => {
+			let name = http::percent_decode(name);
+			match rows(req, &name, opts).transpose() {
+				Some(result) => result,
+				None => return Response::text(404, "Unknown table"),
+			}
+		}
+		_ => return Response::text(404, "Not found"),
+	};
+
+	match result {
+		Ok(body) => Response::json(body.to_string()),
+		Err(export::Error::Format(e)) => Response::text(400, e),
+		Err(e) => Response::text(500, &e.to_string()),
+	}
+}
+
+fn open(opts: &Options) -> Result<rusqlite::Connection, export::Error> {
+	let con =
+		cipher::open(&opts.db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+	con.busy_timeout(BUSY_TIMEOUT)?;
+
+	Ok(con)
+}
+
+/// Rowid of the last row of `table`, the `since` of a poll for new rows.
+fn last_rowid(
+	con: &rusqlite::Connection,
+	table: &str,
+) -> Result<i64, export::Error> {
+	let sql = format!("SELECT coalesce(max(rowid), 0) FROM \"{}\"", table);
+	Ok(con.query_row(&sql, rusqlite::NO_PARAMS, |r| r.get(0))?)
+}
+
+fn tables(opts: &Options) -> Result<Value, export::Error> {
+	let con = open(opts)?;
+
+	let mut tables = vec![];
+	for table in export::tables(&con)? {
+		let columns: Vec<Value> = table
+			.columns
+			.iter()
+			.map(|c| json!({ "name": c.name, "type": c.decl_type }))
+			.collect();
+		tables.push(json!({
+			"name": table.name,
+			"columns": columns,
+			"last": last_rowid(&con, &table.name)?,
+		}));
+	}
+
+	Ok(json!({ "tables": tables }))
+}
+
+/// Rows of table `name` after rowid `since`, in insertion order, along with
+/// the rowid of the last one to poll from next, `None` if there is no such
+/// table.
+fn rows(
+	req: &Request,
+	name: &str,
+	opts: &Options,
+) -> Result<Option<Value>, export::Error> {
+	let since = req.param("since").and_then(|s| s.parse().ok());
+	let since: i64 = since.unwrap_or(0);
+	let limit = req.param("limit").and_then(|l| l.parse().ok());
+	let limit = limit.unwrap_or(DEFAULT_ROWS).min(MAX_ROWS);
+
+	let con = open(opts)?;
+	let schema = export::tables(&con)?;
+	let table = match schema.iter().find(|t| t.name == name) {
+		Some(table) => table,
+		None => return Ok(None),
+	};
+
+	let mut stmt = con.prepare(&format!(
+		"SELECT rowid, * FROM \"{}\" WHERE rowid > ?1 ORDER BY rowid LIMIT ?2",
+		table.name
+	))?;
+
+	let mut last = since;
+	let mut rows = vec![];
+	let mut query = stmt.query(rusqlite::params![since, limit])?;
+	while let Some(row) = query.next()? {
+		last = row.get(0)?;
+		let values: Vec<Value> = (1..=table.columns.len())
+			.map(|i| value::Value::from(row.get_raw(i)).to_json())
+			.collect();
+		rows.push(Value::from(values));
+	}
+
+	let columns: Vec<&str> =
+		table.columns.iter().map(|c| c.name.as_str()).collect();
+	Ok(Some(
+		json!({ "columns": columns, "rows": rows, "last": last }),
+	))
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn poll_rows() {
+		let db = std::env::temp_dir()
+			.join(format!("sdd-api-test-{}.db", std::process::id()));
+		let _ = std::fs::remove_file(&db);
+		let con = rusqlite::Connection::open(&db).unwrap();
+		con.execute_batch(
+			"CREATE TABLE frame (ts INTEGER, scene TEXT); \
+			 INSERT INTO frame VALUES (1, 'menu'), (2, 'menu'), (3, 'hud'); \
+			 CREATE TABLE \"hit log\" (n INTEGER); \
+			 INSERT INTO \"hit log\" VALUES (7);",
+		)
+		.unwrap();
+
+		let opts = Options { db: db.clone() };
+		let get = |path: &str, query: &str| {
+			let req = Request {
+				method: String::from("GET"),
+				path: path.to_string(),
+				query: query.to_string(),
+				headers: vec![],
+				body: vec![],
+			};
+			let response = handle(&req, &opts);
+			(response.status, String::from_utf8(response.body).unwrap())
+		};
+
+		let (status, body) = get("/tables", "");
+		assert_eq!(status, 200);
+		assert!(body.contains(r#""name":"frame""#));
+		assert!(body.contains(r#""last":3"#));
+
+		let (_, body) = get("/tables/frame/rows", "since=1&limit=1");
+		let body: Value = serde_json::from_str(&body).unwrap();
+		assert_eq!(body["rows"], json!([[2, "menu"]]));
+		assert_eq!(body["last"], 2);
+
+		let (_, body) = get("/tables/hit%20log/rows", "");
+		let body: Value = serde_json::from_str(&body).unwrap();
+		assert_eq!(body["rows"], json!([[7]]));
+
+		assert_eq!(get("/tables/nope/rows", "").0, 404);
+		assert_eq!(get("/nope", "").0, 404);
+		let _ = std::fs::remove_file(&db);
+	}
+}
@@ -0,0 +1,160 @@
+use crate::config::AuthConfig;
+use sdd_core::{AUTH_MAGIC, MAX_TOKEN};
+use std::io::{self, Read};
+use std::net::IpAddr;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Time a producer has to send its token.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Network of an allowlist, an address and the length of its prefix.
+struct Network {
+	addr: IpAddr,
+	prefix: u32,
+}
+
+impl Network {
+	fn parse(s: &str) -> Result<Network, String> {
+		let invalid = || format!("Invalid allowed address {}", s);
+		let (addr, prefix) = match s.split_once('/') {
+			Some((addr, prefix)) => (addr, Some(prefix)),
+			None => (s, None),
+		};
+
+		let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+		let bits = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(p) => p.parse().map_err(|_| invalid())?,
+			None => bits,
+		};
+		if prefix > bits {
+			return Err(invalid());
+		}
+
+		Ok(Network { addr, prefix })
+	}
+
+	fn contains(&self, ip: IpAddr) -> bool {
+		let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+			(IpAddr::V4(n), IpAddr::V4(i)) => {
+				(u32::from(n) as u128, u32::from(i) as u128, 32)
+			}
+			(IpAddr::V6(n), IpAddr::V6(i)) => {
+				(u128::from(n), u128::from(i), 128)
+			}
+			_ => return false,
+		};
+		let shift = bits - self.prefix;
+		shift == bits || net >> shift == ip >> shift
+	}
+}
+
+/// Compares without leaking the position of the first difference.
+fn same(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |d, (x, y)| d | (x ^ y)) == 0
+}
+
+//---------------------------------------------------------------------------
+/// Rejects the producers outside the allowlist, then the ones not opening
+/// their stream with the token, before any of their messages are decoded.
+pub struct Auth {
+	token: Option<Vec<u8>>,
+	allow: Vec<Network>,
+}
+
+impl Auth {
+	pub fn new(config: &AuthConfig) -> Result<Auth, String> {
+		let allow = config
+			.allow
+			.iter()
+			.map(|s| Network::parse(s))
+			.collect::<Result<_, _>>()?;
+		let token = config.token.as_ref().map(|t| t.as_bytes().to_vec());
+		if token.as_ref().is_some_and(|t| t.len() > MAX_TOKEN) {
+			return Err(String::from("The auth token is too long"));
+		}
+
+		Ok(Auth { token, allow })
+	}
+
+	/// Checks the producer at `peer`, reading the token from `reader`,
+	/// whose reads should time out.
+	pub fn check<R: Read>(
+		&self,
+		peer: IpAddr,
+		reader: R,
+	) -> Result<(), String> {
+		if !self.allow.is_empty()
+			&& !self.allow.iter().any(|n| n.contains(peer))
+		{
+			return Err(String::from("Address not allowed"));
+		}
+
+		let token = match &self.token {
+			Some(token) => token,
+			None => return Ok(()),
+		};
+		match read_token(reader) {
+			Ok(Some(sent)) if same(&sent, token) => Ok(()),
+			Ok(_) => Err(String::from("Invalid token")),
+			Err(e) => Err(format!("No token: {}", e)),
+		}
+	}
+}
+
+/// Token of the `AUTH_MAGIC` preamble, `None` when the stream lacks it.
+fn read_token<R: Read>(mut reader: R) -> io::Result<Option<Vec<u8>>> {
+	let mut header = [0; 8];
+	reader.read_exact(&mut header)?;
+	if header[..4] != AUTH_MAGIC {
+		return Ok(None);
+	}
+
+	let len = sdd_core::word(&header[4..]) as usize;
+	if len > MAX_TOKEN {
+		return Ok(None);
+	}
+	let mut token = vec![0; len];
+	reader.read_exact(&mut token)?;
+	Ok(Some(token))
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::Producer;
+
+	#[test]
+	fn check_peers() {
+		let config = AuthConfig {
+			token: Some(String::from("secret")),
+			allow: vec![String::from("10.0.0.0/8"), String::from("::1")],
+		};
+		let auth = Auth::new(&config).unwrap();
+
+		let stream = |token: &str| {
+			let mut producer = Producer::new(vec![]);
+			producer.authenticate(token).unwrap();
+			producer.string("scene").unwrap();
+			producer.into_inner()
+		};
+		let ip = |s: &str| s.parse().unwrap();
+
+		assert!(auth.check(ip("10.1.2.3"), &stream("secret")[..]).is_ok());
+		assert!(auth.check(ip("::1"), &stream("secret")[..]).is_ok());
+		assert!(auth.check(ip("11.1.2.3"), &stream("secret")[..]).is_err());
+		assert!(auth.check(ip("10.1.2.3"), &stream("secreT")[..]).is_err());
+		let mut plain = Producer::new(vec![]);
+		plain.string("scene").unwrap();
+		let plain = plain.into_inner();
+		assert!(auth.check(ip("10.1.2.3"), &plain[..]).is_err());
+
+		let config = AuthConfig {
+			allow: vec![String::from("10.0.0.0/33")],
+			..AuthConfig::default()
+		};
+		assert!(Auth::new(&config).is_err());
+	}
+}
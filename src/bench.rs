@@ -0,0 +1,213 @@
+use crate::dae::{Daemon, Protocol};
+use crate::producer::{Producer, Type, Value};
+use crate::stats::Summary;
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::io::BufWriter;
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+/// Producer pacing interval.
+const TICK: Duration = Duration::from_millis(1);
+/// How often the in-process daemon is sampled for latency.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+/// Extra time the daemon gets to drain the stream.
+const GRACE: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct Options {
+	pub tables: u32,
+	/// Columns per table, the first one carries the send time.
+	pub fields: u32,
+	/// Entries per second over all tables.
+	pub rate: u64,
+	pub duration: Duration,
+	/// Wait for an external daemon on this address instead.
+	pub listen: Option<String>,
+	/// Buffers the in-process daemon reads ahead into.
+	pub read_ahead: Option<usize>,
+}
+
+pub struct Report {
+	pub sent: u64,
+	pub send_time: Duration,
+	/// Entries stored by the in-process daemon.
+	pub ingested: Option<u64>,
+	pub ingest_time: Duration,
+	/// Send to insert latencies in milliseconds.
+	pub latency: Option<Summary>,
+}
+
+impl Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"sent     {} entries in {:.2}s, {:.0} entries/s",
+			self.sent,
+			self.send_time.as_secs_f64(),
+			self.sent as f64 / self.send_time.as_secs_f64()
+		)?;
+
+		if let Some(ingested) = self.ingested {
+			writeln!(
+				f,
+				"ingested {} entries in {:.2}s, {:.0} entries/s",
+				ingested,
+				self.ingest_time.as_secs_f64(),
+				ingested as f64 / self.ingest_time.as_secs_f64()
+			)?;
+		}
+
+		if let Some(latency) = &self.latency {
+			write!(f, "latency (ms) {}", latency)?;
+		}
+
+		Ok(())
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Sends `opts.rate` entries per second for `opts.duration`, round robin over
+/// the tables.
+fn produce(
+	listener: TcpListener,
+	opts: &Options,
+	epoch: Instant,
+) -> io::Result<u64> {
+	let (stream, _) = listener.accept()?;
+	let mut producer = Producer::new(BufWriter::new(stream));
+
+	let types = [Type::Float, Type::Int, Type::Bool];
+	let mut columns = vec![(String::from("sent_us"), Type::Int)];
+	for i in 1..opts.fields {
+		columns.push((format!("f{}", i), types[i as usize % types.len()]));
+	}
+	let columns: Vec<(&str, Type)> =
+		columns.iter().map(|(n, t)| (n.as_str(), *t)).collect();
+
+	for i in 0..opts.tables {
+		producer.table(&format!("bench_{}", i), &columns)?;
+	}
+
+	let total = opts.rate * opts.duration.as_secs_f64() as u64;
+	let start = Instant::now();
+	let mut values = vec![Value::Int(0); columns.len()];
+	let mut sent = 0;
+
+	while sent < total {
+		let due = (opts.rate as f64 * start.elapsed().as_secs_f64()) as u64;
+		while sent < due.min(total) {
+			values[0] = Value::Int(epoch.elapsed().as_micros() as u32);
+			for (i, (_, t)) in columns.iter().enumerate().skip(1) {
+				values[i] = match t {
+					Type::Float => Value::Float(sent as f32),
+					Type::Bool => Value::Bool(sent % 2 == 0),
+					_ => Value::Int(sent as u32),
+				};
+			}
+
+			producer.entry((sent % opts.tables as u64) as u32, &values)?;
+			sent += 1;
+		}
+
+		producer.flush()?;
+		thread::sleep(TICK);
+	}
+
+	producer.flush()?;
+	Ok(sent)
+}
+
+/// Runs the load generator, against an in-process daemon over loopback
+/// unless `opts.listen` is set.
+pub fn run(opts: Options) -> io::Result<Report> {
+	if opts.tables == 0 || opts.fields == 0 || opts.fields > 32 {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"Benchmarks need tables and 1 to 32 fields",
+		));
+	}
+
+	let epoch = Instant::now();
+
+	if let Some(addr) = &opts.listen {
+		let listener = TcpListener::bind(addr)?;
+		let sent = produce(listener, &opts, epoch)?;
+		return Ok(Report {
+			sent,
+			send_time: epoch.elapsed(),
+			ingested: None,
+			ingest_time: Duration::default(),
+			latency: None,
+		});
+	}
+
+	let listener = TcpListener::bind("127.0.0.1:0")?;
+	let addr = listener.local_addr()?.to_string();
+
+	// A database of its own rather than the capture output, which would be
+	// recreated.
+	let output = std::env::temp_dir()
+		.join(format!("sdd-bench-{}.db", std::process::id()))
+		.to_string_lossy()
+		.into_owned();
+	let proto = Protocol::new(output.clone()).map_err(io::Error::other)?;
+	let mut daemon = Daemon::new(proto);
+	daemon.verbose = false;
+	daemon.max_entries = Some(opts.rate * opts.duration.as_secs_f64() as u64);
+	daemon.duration = Some(opts.duration + GRACE);
+	daemon.read_ahead = opts.read_ahead;
+	let stats = daemon.stats.clone();
+
+	let producer = {
+		let opts = opts.clone();
+		thread::spawn(move || {
+			let sent = produce(listener, &opts, epoch);
+			(sent, epoch.elapsed())
+		})
+	};
+	let capture = thread::spawn(move || daemon.start(&addr).is_ok());
+
+	// The first column of the last stored entry of each table is its send
+	// time, sampling it gives the end to end latency.
+	let mut last_seen = vec![None; opts.tables as usize];
+	let mut latencies = vec![];
+	while !capture.is_finished() {
+		thread::sleep(SAMPLE_INTERVAL);
+		let now = epoch.elapsed().as_micros() as u64;
+
+		let stats = stats.lock().unwrap();
+		for (t, seen) in stats.tables.iter().zip(last_seen.iter_mut()) {
+			let sent = t.last_values().first().and_then(|v| v.parse().ok());
+			if let Some(sent) = sent.filter(|s| Some(*s) != *seen) {
+				latencies.push(now.saturating_sub(sent) as f64 / 1e3);
+				*seen = Some(sent);
+			}
+		}
+	}
+
+	let ingest_time = epoch.elapsed();
+	let captured = capture.join().unwrap_or(false);
+	for suffix in ["", "-wal", "-shm"] {
+		let _ = fs::remove_file(format!("{}{}", output, suffix));
+	}
+	if !captured {
+		return Err(io::Error::other("The daemon stopped with an error"));
+	}
+
+	let (sent, send_time) = producer.join().unwrap();
+	let sent = sent?;
+
+	let ingested = stats.lock().unwrap().entries;
+	Ok(Report {
+		sent,
+		send_time,
+		ingested: Some(ingested),
+		ingest_time,
+		latency: Summary::new(&mut latencies),
+	})
+}
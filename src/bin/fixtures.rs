@@ -0,0 +1,44 @@
+//! Writes the conformance streams of `sdd::conformance` along with the
+//! captures the daemon makes of them, see `tests/conformance`.
+use sdd::conformance;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "sdd-fixtures")]
+struct Cli {
+	/// Directory to write `<case>.sddcap` and `<case>.expected` into.
+	#[structopt(parse(from_os_str), default_value = "tests/conformance")]
+	dir: PathBuf,
+}
+
+fn main() {
+	let cli = Cli::from_args();
+	if let Err(e) = fs::create_dir_all(&cli.dir) {
+		println!("{}: {}", cli.dir.display(), e);
+		return;
+	}
+
+	for case in conformance::cases() {
+		let db = std::env::temp_dir().join(format!("sdd-{}.db", case.name));
+		let dump = match conformance::capture(&case.stream, &db) {
+			Ok(dump) => dump,
+			Err(e) => {
+				println!("{}: {}", case.name, e);
+				continue;
+			}
+		};
+		let _ = fs::remove_file(&db);
+
+		let stream = cli.dir.join(format!("{}.sddcap", case.name));
+		let expected = cli.dir.join(format!("{}.expected", case.name));
+		let written = fs::write(&stream, &case.stream).and_then(|_| {
+			fs::write(&expected, format!("# {}\n{}", case.about, dump))
+		});
+		match written {
+			Ok(()) => println!("Wrote {}", expected.display()),
+			Err(e) => println!("{}: {}", case.name, e),
+		}
+	}
+}
@@ -0,0 +1,545 @@
+use crate::producer::{Producer, Type, Value};
+use sdd_core::{Encoder, MSG_COUNTER, MSG_ENTRY};
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, Write};
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+/// Name of the table of the drop counter, the first table of the stream.
+pub const STATS_TABLE: &str = "sdd_producer";
+/// Drops are reported at most this often.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a full ring does with another entry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+	/// Wait for the link to catch up.
+	Block,
+	/// Drop the oldest queued entry.
+	DropOldest,
+	/// Drop the entry.
+	DropNewest,
+}
+
+/// How the stream starts, as set up by the `Producer` methods of the same
+/// name, which a `Buffered` has to call before declaring its first table.
+#[derive(Clone, Default)]
+pub struct Options {
+	/// Token of a daemon listening with an `[auth]` token.
+	pub authenticate: Option<String>,
+	/// Encodes the entries compactly, see `Producer::compact`.
+	pub compact: bool,
+}
+
+/// Whether a message can be dropped, the others declare what later
+/// messages refer to and are always queued.
+fn droppable(msg: &[u8]) -> bool {
+	matches!(msg.get(4), Some(&MSG_ENTRY) | Some(&MSG_COUNTER))
+}
+
+//---------------------------------------------------------------------------
+struct Ring {
+	messages: VecDeque<Vec<u8>>,
+	/// Queued entries, which count against the capacity.
+	entries: usize,
+	dropped: u64,
+	closed: bool,
+	/// The writer failed and stopped.
+	failed: Option<io::ErrorKind>,
+}
+
+struct Shared {
+	ring: Mutex<Ring>,
+	/// Signals the writer thread of queued messages.
+	queued: Condvar,
+	/// Signals blocked producers of space in the ring.
+	space: Condvar,
+	capacity: usize,
+	overflow: Overflow,
+}
+
+impl Shared {
+	fn new(capacity: usize, overflow: Overflow) -> Shared {
+		Shared {
+			ring: Mutex::new(Ring {
+				messages: VecDeque::new(),
+				entries: 0,
+				dropped: 0,
+				closed: false,
+				failed: None,
+			}),
+			queued: Condvar::new(),
+			space: Condvar::new(),
+			capacity: capacity.max(1),
+			overflow,
+		}
+	}
+
+	fn lock(&self) -> MutexGuard<'_, Ring> {
+		self.ring.lock().unwrap()
+	}
+
+	fn push(&self, msg: &[u8]) -> io::Result<()> {
+		let mut ring = self.lock();
+		let entry = droppable(msg);
+
+		while entry && ring.entries >= self.capacity && ring.failed.is_none() {
+			match self.overflow {
+				Overflow::Block => ring = self.space.wait(ring).unwrap(),
+				Overflow::DropNewest => {
+					ring.dropped += 1;
+					return Ok(());
+				}
+				Overflow::DropOldest => {
+					let oldest =
+						ring.messages.iter().position(|m| droppable(m));
+					ring.messages.remove(oldest.unwrap());
+					ring.entries -= 1;
+					ring.dropped += 1;
+				}
+			}
+		}
+
+		if let Some(kind) = ring.failed {
+			return Err(io::Error::new(kind, "The buffered writer failed"));
+		}
+
+		ring.entries += entry as usize;
+		ring.messages.push_back(msg.to_vec());
+		self.queued.notify_one();
+		Ok(())
+	}
+}
+
+/// Writer of a `Producer` queueing each message into the ring.
+pub struct Queue {
+	shared: Arc<Shared>,
+}
+
+impl Write for Queue {
+	/// Producers write each message at once.
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.shared.push(buf)?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Producer along with the threads of its entries.
+struct Tagged {
+	producer: Producer<Queue>,
+	/// Tables declared with `Buffered::threaded_table`.
+	threaded: HashSet<u32>,
+	/// Thread of the last entry of a threaded table.
+	thread: Option<thread::ThreadId>,
+}
+
+//---------------------------------------------------------------------------
+/// Thread-safe producer queueing the messages into a ring, which a thread of
+/// its own sends over a possibly slow link, so instrumented code does not
+/// stall on it unless the overflow policy blocks. Only entries and counters
+/// count against the capacity and get dropped, other messages are always
+/// queued. Drops are counted in the `dropped` column of the `STATS_TABLE`
+/// entries. Newest entries are dropped before they are encoded, while the
+/// oldest ones cannot be dropped from a compact stream, whose float deltas
+/// build on them, see `Producer::compact`. The entries of threaded tables
+/// are tagged with the thread sending them.
+pub struct Buffered {
+	producer: Mutex<Tagged>,
+	shared: Arc<Shared>,
+	writer: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl Buffered {
+	/// Sends the messages to `writer` from a new thread, `capacity` being
+	/// the number of entries the ring holds.
+	pub fn new<W: Write + Send + 'static>(
+		mut writer: W,
+		capacity: usize,
+		overflow: Overflow,
+		opts: Options,
+	) -> io::Result<Buffered> {
+		if opts.compact && overflow == Overflow::DropOldest {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"A compact stream cannot drop the oldest entries",
+			));
+		}
+		let shared = Arc::new(Shared::new(capacity, overflow));
+
+		// The token goes out before the ring, whose messages are told apart
+		// by their header.
+		if let Some(token) = &opts.authenticate {
+			Producer::new(&mut writer).authenticate(token)?;
+		}
+		let mut producer = Producer::new(Queue {
+			shared: shared.clone(),
+		});
+		if opts.compact {
+			producer.compact()?;
+		}
+		let stats = producer.table(STATS_TABLE, &[("dropped", Type::Int)])?;
+
+		let drained = shared.clone();
+		let compact = opts.compact;
+		let writer =
+			thread::spawn(move || drain(&drained, writer, stats, compact));
+
+		Ok(Buffered {
+			producer: Mutex::new(Tagged {
+				producer,
+				threaded: HashSet::new(),
+				thread: None,
+			}),
+			shared,
+			writer: Some(writer),
+		})
+	}
+
+	/// Encodes messages with the producer, e.g. markers or scopes.
+	pub fn with<T>(
+		&self,
+		f: impl FnOnce(&mut Producer<Queue>) -> io::Result<T>,
+	) -> io::Result<T> {
+		f(&mut self.producer.lock().unwrap().producer)
+	}
+
+	/// Encodes an entry of table `uid` with the producer, preceded by a
+	/// thread message when a threaded table gets it from another thread.
+	/// Drops it unencoded when the ring is full and drops the newest.
+	fn tagged(
+		&self,
+		uid: u32,
+		f: impl FnOnce(&mut Producer<Queue>) -> io::Result<()>,
+	) -> io::Result<()> {
+		let mut tagged = self.producer.lock().unwrap();
+		let current = thread::current();
+
+		// Only the producer queues entries, so the ring stays full.
+		if self.shared.overflow == Overflow::DropNewest {
+			let mut ring = self.shared.lock();
+			if ring.entries >= self.shared.capacity && ring.failed.is_none() {
+				ring.dropped += 1;
+				return Ok(());
+			}
+		}
+
+		if tagged.threaded.contains(&uid) && tagged.thread != Some(current.id())
+		{
+			let name = match current.name() {
+				Some(name) => name.to_string(),
+				None => format!("{:?}", current.id()),
+			};
+			tagged.producer.thread(&name)?;
+			tagged.thread = Some(current.id());
+		}
+		f(&mut tagged.producer)
+	}
+
+	/// Declares a table of (name, type) columns, returning its uid.
+	pub fn table(
+		&self,
+		name: &str,
+		columns: &[(&str, Type)],
+	) -> io::Result<u32> {
+		self.with(|p| p.table(name, columns))
+	}
+
+	/// Declares a table whose entries are tagged with the thread sending
+	/// them, named after it or its id, see `Producer::threaded_table`.
+	pub fn threaded_table(
+		&self,
+		name: &str,
+		columns: &[(&str, Type)],
+	) -> io::Result<u32> {
+		let mut tagged = self.producer.lock().unwrap();
+		let uid = tagged.producer.threaded_table(name, columns)?;
+		tagged.threaded.insert(uid);
+		Ok(uid)
+	}
+
+	/// Queues an entry of table `uid` as the overflow policy allows.
+	pub fn entry(&self, uid: u32, values: &[Value]) -> io::Result<()> {
+		self.tagged(uid, |p| p.entry(uid, values))
+	}
+
+	pub fn counter(&self, uid: u32, delta: u32) -> io::Result<()> {
+		self.tagged(uid, |p| p.counter(uid, delta))
+	}
+
+	/// Entries dropped so far.
+	pub fn dropped(&self) -> u64 {
+		self.shared.lock().dropped
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		self.shared.lock().closed = true;
+		self.shared.queued.notify_one();
+
+		match self.writer.take().map(|w| w.join()) {
+			Some(Ok(result)) => result,
+			Some(Err(_)) => {
+				Err(io::Error::other("The buffered writer panicked"))
+			}
+			None => Ok(()),
+		}
+	}
+
+	/// Sends the queued messages and waits for the writer thread.
+	pub fn close(mut self) -> io::Result<()> {
+		self.finish()
+	}
+}
+
+impl Drop for Buffered {
+	fn drop(&mut self) {
+		let _ = self.finish();
+	}
+}
+
+/// Sends the queued messages to `writer` until the ring is closed, adding
+/// an entry of table `stats` when the drop count changed, encoded compactly
+/// in a `compact` stream.
+fn drain<W: Write>(
+	shared: &Shared,
+	mut writer: W,
+	stats: u32,
+	compact: bool,
+) -> io::Result<()> {
+	let mut reported = 0;
+	let mut last_report = Instant::now();
+
+	loop {
+		let mut ring = shared.lock();
+		while ring.messages.is_empty() && !ring.closed {
+			if ring.dropped == reported {
+				ring = shared.queued.wait(ring).unwrap();
+				continue;
+			}
+
+			// Report pending drops once the interval is over.
+			let wait = STATS_INTERVAL.saturating_sub(last_report.elapsed());
+			let (r, timeout) = shared.queued.wait_timeout(ring, wait).unwrap();
+			ring = r;
+			if timeout.timed_out() {
+				break;
+			}
+		}
+
+		let batch = mem::take(&mut ring.messages);
+		ring.entries = 0;
+		let (dropped, closed) = (ring.dropped, ring.closed);
+		drop(ring);
+		shared.space.notify_all();
+
+		let mut result = batch.iter().try_for_each(|m| writer.write_all(m));
+		if dropped != reported
+			&& (closed || last_report.elapsed() >= STATS_INTERVAL)
+		{
+			let mut buf = [0; sdd_core::HEADER_SIZE + 10];
+			let mut encoder = Encoder::new(&mut buf);
+			let values = [Value::Int(dropped as u32)];
+			let _ = match compact {
+				true => encoder.compact_entry(stats, &values, &[]),
+				false => encoder.entry(stats, &values),
+			};
+			result = result.and_then(|_| writer.write_all(encoder.bytes()));
+			reported = dropped;
+			last_report = Instant::now();
+		}
+
+		if let Err(e) = result.and_then(|_| writer.flush()) {
+			shared.lock().failed = Some(e.kind());
+			shared.space.notify_all();
+			return Err(e);
+		}
+		if closed {
+			return Ok(());
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(uid: u32, v: u32) -> Vec<u8> {
+		let mut buf = [0; sdd_core::HEADER_SIZE + 8];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.entry(uid, &[Value::Int(v)]).unwrap();
+		encoder.bytes().to_vec()
+	}
+
+	/// Writer whose bytes outlive it.
+	#[derive(Clone, Default)]
+	struct Output(Arc<Mutex<Vec<u8>>>);
+
+	impl Write for Output {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn stream_options() {
+		let out = Output::default();
+		let opts = Options {
+			authenticate: Some(String::from("secret")),
+			compact: true,
+		};
+		let buffered =
+			Buffered::new(out.clone(), 4, Overflow::Block, opts).unwrap();
+		buffered.close().unwrap();
+
+		let mut expected = sdd_core::AUTH_MAGIC.to_vec();
+		expected.extend_from_slice(&6u32.to_le_bytes());
+		expected.extend_from_slice(b"secret");
+		expected.extend_from_slice(&sdd_core::COMPACT_MAGIC);
+		assert!(out.0.lock().unwrap().starts_with(&expected));
+	}
+
+	#[test]
+	fn blocking_overflow() {
+		let shared = Arc::new(Shared::new(1, Overflow::Block));
+		shared.push(&entry(0, 0)).unwrap();
+
+		let pushing = shared.clone();
+		let blocked = thread::spawn(move || pushing.push(&entry(0, 1)));
+		thread::sleep(Duration::from_millis(50));
+		assert!(!blocked.is_finished());
+
+		let mut ring = shared.lock();
+		ring.messages.clear();
+		ring.entries = 0;
+		drop(ring);
+		shared.space.notify_all();
+
+		blocked.join().unwrap().unwrap();
+		let ring = shared.lock();
+		assert_eq!(ring.messages, [entry(0, 1)]);
+		assert_eq!(ring.dropped, 0);
+	}
+
+	#[test]
+	fn overflow_policies() {
+		for (overflow, kept) in [
+			(Overflow::DropOldest, [3, 4]),
+			(Overflow::DropNewest, [0, 1]),
+		] {
+			let shared = Shared::new(2, overflow);
+			let mut table = vec![];
+			Producer::new(&mut table)
+				.table("frame", &[("ts", Type::Int)])
+				.unwrap();
+			shared.push(&table).unwrap();
+			for v in 0..5 {
+				shared.push(&entry(1, v)).unwrap();
+			}
+			assert_eq!(shared.lock().dropped, 3);
+			assert_eq!(shared.lock().messages.len(), 3);
+
+			shared.lock().closed = true;
+			let mut out = vec![];
+			drain(&shared, &mut out, 0, false).unwrap();
+
+			let mut expected = table;
+			for v in kept {
+				expected.extend_from_slice(&entry(1, v));
+			}
+			expected.extend_from_slice(&entry(0, 3));
+			assert_eq!(out, expected);
+		}
+	}
+
+	/// Writer holding the writes until it is opened.
+	#[derive(Clone, Default)]
+	struct Gate {
+		out: Output,
+		open: Arc<(Mutex<bool>, Condvar)>,
+	}
+
+	impl Gate {
+		fn open(&self) {
+			*self.open.0.lock().unwrap() = true;
+			self.open.1.notify_all();
+		}
+	}
+
+	impl Write for Gate {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			let open = self.open.0.lock().unwrap();
+			drop(self.open.1.wait_while(open, |open| !*open).unwrap());
+			self.out.write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn compact_overflow() {
+		let opts = Options {
+			authenticate: None,
+			compact: true,
+		};
+		let oldest = opts.clone();
+		assert!(Buffered::new(vec![], 1, Overflow::DropOldest, oldest).is_err());
+
+		let gate = Gate::default();
+		let buffered =
+			Buffered::new(gate.clone(), 1, Overflow::DropNewest, opts).unwrap();
+		let settled = |f: &dyn Fn(&Ring) -> bool| {
+			while !f(&buffered.shared.lock()) {
+				thread::sleep(Duration::from_millis(1));
+			}
+		};
+		// The writer holds the start of the stream, the rest queues up.
+		settled(&|ring| ring.messages.is_empty());
+		let uid = buffered.table("frame", &[("ms", Type::Float)]).unwrap();
+		for v in &[1.5, 2.5, 3.5] {
+			buffered.entry(uid, &[Value::Float(*v)]).unwrap();
+		}
+		assert_eq!(buffered.dropped(), 2);
+
+		gate.open();
+		settled(&|ring| ring.entries == 0);
+		buffered.entry(uid, &[Value::Float(4.5)]).unwrap();
+		buffered.close().unwrap();
+
+		let out = gate.out.0.lock().unwrap();
+		assert!(out.starts_with(&sdd_core::COMPACT_MAGIC));
+		let mut expander = crate::compact::Expander::default();
+		let mut input = &out[4..];
+		let mut expanded = vec![];
+		while !input.is_empty() {
+			let size = expander.expand(input, &mut expanded).unwrap().unwrap();
+			input = &input[size..];
+		}
+
+		let mut plain = Producer::new(vec![]);
+		plain.table(STATS_TABLE, &[("dropped", Type::Int)]).unwrap();
+		plain.table("frame", &[("ms", Type::Float)]).unwrap();
+		plain.entry(uid, &[Value::Float(1.5)]).unwrap();
+		let start = plain.into_inner();
+		let mut buf = [0; sdd_core::HEADER_SIZE + 8];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.entry(uid, &[Value::Float(4.5)]).unwrap();
+		let last = encoder.bytes().to_vec();
+		// The drops are reported once the interval is over or on closing.
+		let closing = [&start[..], &last, &entry(0, 2)].concat();
+		let timed = [&start[..], &entry(0, 2), &last].concat();
+		assert!(expanded == closing || expanded == timed);
+	}
+}
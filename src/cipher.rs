@@ -0,0 +1,57 @@
+use rusqlite::{Connection, OpenFlags};
+use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
+
+//---------------------------------------------------------------------------
+/// Variable holding the key when the config sets none.
+const KEY_VAR: &str = "SDD_KEY";
+
+/// Key set by the config, taking precedence over `SDD_KEY`.
+static KEY: OnceLock<String> = OnceLock::new();
+
+/// Keys the databases opened from now on, see `open`.
+pub fn set_key(key: &str) {
+	let _ = KEY.set(key.to_string());
+}
+
+fn key() -> Option<String> {
+	KEY.get()
+		.cloned()
+		.or_else(|| env::var(KEY_VAR).ok())
+		.filter(|k| !k.is_empty())
+}
+
+/// Fails when a key is set but sdd was built without SQLCipher, which would
+/// leave the captures in plain text.
+pub fn check() -> Result<(), String> {
+	match key() {
+		Some(_) if cfg!(not(feature = "sqlcipher")) => {
+			Err(String::from("sdd was built without the sqlcipher feature."))
+		}
+		_ => Ok(()),
+	}
+}
+
+/// Opens a capture database, encrypted with SQLCipher when a key is set in
+/// the `[encryption]` config section or `SDD_KEY`. The whole file is
+/// encrypted, the `_sdd_` tables included.
+pub fn open<P: AsRef<Path>>(
+	path: P,
+	flags: OpenFlags,
+) -> rusqlite::Result<Connection> {
+	let con = Connection::open_with_flags(path, flags)?;
+
+	if let Some(key) = key() {
+		// Has to come before any other statement.
+		con.pragma_update(None, "key", &key)?;
+		// Fails right away on a wrong key or a plain database.
+		con.query_row(
+			"SELECT count(*) FROM sqlite_master",
+			rusqlite::NO_PARAMS,
+			|_| Ok(()),
+		)?;
+	}
+
+	Ok(con)
+}
@@ -0,0 +1,237 @@
+use crate::config::ClickHouseConfig;
+use crate::dae::{Entry, FieldType, Strings};
+use crate::http;
+use crate::sink::{self, Delivery, Sink};
+use serde_json::{Map, Value};
+use std::mem;
+
+//---------------------------------------------------------------------------
+/// Rows of a table buffered before an insert.
+const BATCH: usize = 1000;
+
+struct Table {
+	name: String,
+	columns: Vec<String>,
+	/// Whether the ClickHouse table was created, on the first entry.
+	created: bool,
+	/// JSONEachRow rows not yet inserted.
+	rows: Vec<u8>,
+	count: usize,
+}
+
+//---------------------------------------------------------------------------
+/// Inserts the entries into ClickHouse over its HTTP interface. Each table
+/// is created after its descriptor with a `source` column added, its rows
+/// inserted in batches.
+pub struct ClickHouse {
+	/// Url of the HTTP interface with the database.
+	url: String,
+	tables: Vec<Option<Table>>,
+	delivery: Delivery,
+}
+
+impl ClickHouse {
+	pub fn new(config: &ClickHouseConfig) -> Result<ClickHouse, String> {
+		if !config.url.starts_with("http://") {
+			return Err(format!(
+				"ClickHouse: {} is not an http:// url",
+				config.url
+			));
+		}
+
+		let database = config.database.as_deref().unwrap_or("default");
+		let url = format!(
+			"{}/?database={}",
+			config.url.trim_end_matches('/'),
+			http::percent_encode(database)
+		);
+
+		// Kept out of the url, which ends up in the spool and the logs.
+		let mut headers = vec![];
+		if let Some(user) = &config.user {
+			headers.push((String::from("X-ClickHouse-User"), user.clone()));
+		}
+		if let Some(password) = &config.password {
+			headers.push((String::from("X-ClickHouse-Key"), password.clone()));
+		}
+
+		Ok(ClickHouse {
+			url,
+			tables: vec![],
+			delivery: Delivery::new(
+				"ClickHouse",
+				config.spool.as_deref(),
+				config.spool_size.as_deref(),
+				headers,
+			)?,
+		})
+	}
+
+	/// Queues an insert of the buffered rows of table `uid`.
+	fn insert(&mut self, uid: usize) -> Result<(), String> {
+		let table = match self.tables.get_mut(uid) {
+			Some(Some(t)) if t.count > 0 => t,
+			_ => return Ok(()),
+		};
+
+		let query = format!(
+			"INSERT INTO {} FORMAT JSONEachRow",
+			identifier(&table.name)
+		);
+		let url =
+			format!("{}&query={}", self.url, http::percent_encode(&query));
+		table.count = 0;
+		let rows = mem::take(&mut table.rows);
+		self.delivery.post(url, "application/json", rows)
+	}
+}
+
+/// Backquoted identifier.
+fn identifier(name: &str) -> String {
+	format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+/// `CREATE TABLE` statement of a table, the column types taken from an
+/// entry.
+fn create_table(table: &str, columns: &[String], entry: &Entry) -> String {
+	let mut definitions: Vec<String> = columns
+		.iter()
+		.zip(entry.values())
+		.map(|(c, v)| {
+			let t = match v {
+				FieldType::Int(_) => "UInt32",
+				FieldType::Float(_) => "Float64",
+				FieldType::Bool(_) => "Bool",
+				FieldType::Str(_) => "String",
+			};
+			format!("{} {}", identifier(c), t)
+		})
+		.collect();
+	definitions.push(String::from("`source` LowCardinality(String)"));
+
+	format!(
+		"CREATE TABLE IF NOT EXISTS {} ({}) ENGINE = MergeTree ORDER BY tuple()",
+		identifier(table),
+		definitions.join(", ")
+	)
+}
+
+impl Sink for ClickHouse {
+	fn bind(&mut self, uid: u32, table: &str, columns: &[String]) {
+		let uid = uid as usize;
+		if self.tables.len() <= uid {
+			self.tables.resize_with(uid + 1, || None);
+		}
+
+		// Rows of a table the uid was bound to before.
+		if let Err(e) = self.insert(uid) {
+			println!("{}", e);
+		}
+
+		self.tables[uid] = Some(Table {
+			name: table.to_string(),
+			columns: columns.to_vec(),
+			created: false,
+			rows: vec![],
+			count: 0,
+		});
+	}
+
+	fn send(
+		&mut self,
+		uid: u32,
+		source: &str,
+		entry: &Entry,
+		strings: &Strings,
+	) -> Result<(), String> {
+		let table = match self.tables.get_mut(uid as usize) {
+			Some(Some(t)) => t,
+			_ => return Ok(()),
+		};
+
+		if !table.created {
+			table.created = true;
+			let create = create_table(&table.name, &table.columns, entry);
+			let url = self.url.clone();
+			self.delivery.post(url, "text/plain", create.into_bytes())?;
+		}
+
+		let mut row: Map<String, Value> = table
+			.columns
+			.iter()
+			.zip(entry.values())
+			.map(|(c, v)| (c.clone(), sink::json_value(v, strings)))
+			.collect();
+		row.insert(String::from("source"), Value::from(source));
+
+		let table = self.tables[uid as usize].as_mut().unwrap();
+		table
+			.rows
+			.extend_from_slice(Value::Object(row).to_string().as_bytes());
+		table.rows.push(b'\n');
+		table.count += 1;
+
+		match table.count < BATCH {
+			true => Ok(()),
+			false => self.insert(uid as usize),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), String> {
+		let inserted: Result<Vec<()>, String> =
+			(0..self.tables.len()).map(|uid| self.insert(uid)).collect();
+		self.delivery.flush();
+		inserted.map(|_| ())
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{BufReader, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	#[test]
+	fn table_schema() {
+		let (_, entry) = sink::test_entry();
+
+		let columns = [String::from("n"), String::from("o`k")];
+		assert_eq!(
+			create_table("log", &columns, &entry),
+			"CREATE TABLE IF NOT EXISTS `log` (`n` UInt32, `o\\`k` Bool, \
+			 `source` LowCardinality(String)) ENGINE = MergeTree ORDER BY \
+			 tuple()"
+		);
+	}
+
+	#[test]
+	fn credential_headers() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let config = ClickHouseConfig {
+			url: format!("http://{}", listener.local_addr().unwrap()),
+			database: None,
+			user: Some(String::from("sdd")),
+			password: Some(String::from("secret")),
+			spool: None,
+			spool_size: None,
+		};
+		let mut clickhouse = ClickHouse::new(&config).unwrap();
+		let received = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let request = http::Request::read(&mut reader).unwrap();
+			let mut stream = stream;
+			stream.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+			request
+		});
+
+		let url = clickhouse.url.clone();
+		clickhouse.delivery.post(url, "text/plain", vec![]).unwrap();
+		let request = received.join().unwrap();
+		assert_eq!(request.header("X-ClickHouse-User"), Some("sdd"));
+		assert_eq!(request.header("X-ClickHouse-Key"), Some("secret"));
+		assert_eq!(request.param("password"), None);
+	}
+}
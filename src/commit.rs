@@ -0,0 +1,224 @@
+use crate::config::parse_duration;
+use rusqlite;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+/// Weight of the latest batch in the moving averages of an adaptive policy.
+const SMOOTHING: f64 = 0.25;
+
+/// How the rows stored in a database are grouped into transactions.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+	/// Every row in its own transaction, on disk as soon as it is stored.
+	#[default]
+	Each,
+	/// Batches of `rows` rows.
+	Fixed,
+	/// Batches sized so that committing takes `overhead` of the time, up to
+	/// `rows` rows.
+	Adaptive,
+}
+
+/// Batching of the rows into transactions, see `Batch`.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommitConfig {
+	pub policy: Policy,
+	/// Rows of a fixed batch, the most rows of an adaptive one.
+	pub rows: u32,
+	/// Longest a row waits for its commit while the producer keeps sending,
+	/// e.g. `250ms`.
+	pub delay: String,
+	/// Share of the time an adaptive policy spends committing.
+	pub overhead: f64,
+}
+
+impl Default for CommitConfig {
+	fn default() -> CommitConfig {
+		CommitConfig {
+			policy: Policy::Each,
+			rows: 1000,
+			delay: String::from("1s"),
+			overhead: 0.05,
+		}
+	}
+}
+
+impl CommitConfig {
+	pub fn pacing(&self) -> Result<Pacing, String> {
+		if self.rows == 0 {
+			return Err(String::from("A commit batch needs at least one row"));
+		}
+		if !(self.overhead > 0.0 && self.overhead < 1.0) {
+			return Err(String::from("The commit overhead is between 0 and 1"));
+		}
+
+		Ok(Pacing {
+			policy: self.policy,
+			rows: self.rows,
+			delay: parse_duration(&self.delay)?,
+			overhead: self.overhead,
+		})
+	}
+}
+
+/// Validated `CommitConfig`, committing each row by default.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Pacing {
+	pub policy: Policy,
+	pub rows: u32,
+	pub delay: Duration,
+	pub overhead: f64,
+}
+
+//---------------------------------------------------------------------------
+/// Transaction the rows of a database are written behind. It is committed
+/// once it holds enough rows, once its oldest row waited long enough, or as
+/// soon as the producer has nothing more at hand, so that readers see the
+/// rows of a quiet producer right away while a busy one is written in large
+/// batches.
+pub struct Batch {
+	pacing: Pacing,
+	/// Rows stored since the transaction began, 0 with none open.
+	pending: u32,
+	began: Instant,
+	/// Rows a batch is committed at.
+	target: u32,
+	/// Moving averages of the rows stored per second and of the seconds a
+	/// commit takes.
+	rate: f64,
+	latency: f64,
+}
+
+impl Batch {
+	pub fn new(pacing: Pacing) -> Batch {
+		Batch {
+			pacing,
+			pending: 0,
+			began: Instant::now(),
+			target: match pacing.policy {
+				Policy::Adaptive => 1,
+				_ => pacing.rows,
+			},
+			rate: 0.0,
+			latency: 0.0,
+		}
+	}
+
+	/// Rows a batch is currently committed at.
+	pub fn target(&self) -> u32 {
+		self.target
+	}
+
+	/// Opens the transaction of the row about to be stored if need be.
+	pub fn add(&mut self, con: &rusqlite::Connection) -> rusqlite::Result<()> {
+		if self.pacing.policy == Policy::Each {
+			return Ok(());
+		}
+
+		if self.pending == 0 {
+			con.execute_batch("BEGIN")?;
+			self.began = Instant::now();
+		}
+		self.pending += 1;
+		Ok(())
+	}
+
+	/// Commits the transaction once due, `idle` when the producer has
+	/// nothing more at hand.
+	pub fn poll(
+		&mut self,
+		con: &rusqlite::Connection,
+		idle: bool,
+	) -> rusqlite::Result<()> {
+		let due = idle
+			|| self.pending >= self.target
+			|| self.began.elapsed() >= self.pacing.delay;
+		match due {
+			true => self.commit(con),
+			false => Ok(()),
+		}
+	}
+
+	/// Commits the open transaction.
+	pub fn commit(
+		&mut self,
+		con: &rusqlite::Connection,
+	) -> rusqlite::Result<()> {
+		if self.pending == 0 {
+			return Ok(());
+		}
+
+		let start = Instant::now();
+		con.execute_batch("COMMIT")?;
+		let took = start.elapsed().as_secs_f64();
+		// Coarse clocks may see no time pass while the batch filled up.
+		let filling = self.began.elapsed().as_secs_f64().max(1e-6);
+		let rate = self.pending as f64 / filling;
+		self.pending = 0;
+
+		if self.pacing.policy == Policy::Adaptive {
+			self.adapt(rate, took);
+		}
+		Ok(())
+	}
+
+	/// Sizes the batches so that a batch filling up at `rate` takes long
+	/// enough for its commit, taking `latency`, to be `overhead` of the time.
+	fn adapt(&mut self, rate: f64, latency: f64) {
+		self.rate += SMOOTHING * (rate - self.rate);
+		self.latency += SMOOTHING * (latency - self.latency);
+
+		let overhead = self.pacing.overhead;
+		let fill = self.latency * (1.0 - overhead) / overhead;
+		let target = (self.rate * fill).ceil();
+		self.target = target.clamp(1.0, self.pacing.rows as f64) as u32;
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn adaptive_batches() {
+		let config = CommitConfig {
+			policy: Policy::Adaptive,
+			rows: 500,
+			..CommitConfig::default()
+		};
+		let mut batch = Batch::new(config.pacing().unwrap());
+		assert_eq!(batch.target(), 1);
+
+		// Rows arriving faster than they are committed grow the batches.
+		for _ in 0..20 {
+			batch.adapt(100_000.0, 0.005);
+		}
+		assert_eq!(batch.target(), 500);
+		// A quiet producer gets its rows committed one by one.
+		for _ in 0..60 {
+			batch.adapt(10.0, 0.005);
+		}
+		assert_eq!(batch.target(), 1);
+
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch("CREATE TABLE frame (ts INTEGER)")
+			.unwrap();
+		for ts in 0..3 {
+			batch.add(&con).unwrap();
+			con.execute("INSERT INTO frame VALUES (?1)", [ts]).unwrap();
+		}
+		assert!(!con.is_autocommit());
+		batch.poll(&con, true).unwrap();
+		assert!(con.is_autocommit());
+
+		let config = CommitConfig {
+			overhead: 1.0,
+			..CommitConfig::default()
+		};
+		assert!(config.pacing().is_err());
+	}
+}
@@ -0,0 +1,211 @@
+use crate::wire;
+use sdd_core::MSG_SOURCE;
+use sdd_core::{Type, HEADER_SIZE, MSG_COUNTER, MSG_DESC, MSG_ENTITY};
+use sdd_core::{MSG_ATTACHMENT, MSG_SCOPE_END, MSG_STR, MSG_THREAD};
+use sdd_core::{MSG_ENTRY, MSG_MARKER, MSG_PAUSE, MSG_SCOPE_BEGIN};
+use std::collections::HashMap;
+
+//---------------------------------------------------------------------------
+struct Table {
+	columns: Vec<Type>,
+	/// Float bits of the last entry, the base of the next float deltas.
+	previous: Vec<u32>,
+}
+
+/// Expands the compact entries of a stream opened with
+/// `sdd_core::COMPACT_MAGIC` into plain entries, passing the other messages
+/// through. Tracks the columns and last floats of each table.
+#[derive(Default)]
+pub(crate) struct Expander {
+	tables: HashMap<u32, Table>,
+}
+
+impl Expander {
+	/// Expands the message at the start of `input` into `out`, returning
+	/// its size or `None` while it is incomplete. Errors leave the stream
+	/// in an unknown state.
+	pub fn expand(
+		&mut self,
+		input: &[u8],
+		out: &mut Vec<u8>,
+	) -> Result<Option<usize>, &'static str> {
+		if input.len() < HEADER_SIZE {
+			return Ok(None);
+		}
+		if sdd_core::word(input) == sdd_core::PROTOCOL && input[4] == MSG_ENTRY
+		{
+			return self.entry(input, out);
+		}
+		let size = match message_size(input, |_| None)? {
+			Some(size) => size,
+			None => return Ok(None),
+		};
+
+		if input[4] == MSG_DESC {
+			let (desc, uid, _) = wire::decode_descriptor(&input[HEADER_SIZE..])
+				.map_err(|_| "Invalid table")?;
+			let columns: Vec<Type> = desc.columns().map(|(_, t)| t).collect();
+			let previous = vec![0; columns.len()];
+			self.tables.insert(uid, Table { columns, previous });
+		}
+
+		out.extend_from_slice(&input[..size]);
+		Ok(Some(size))
+	}
+
+	fn entry(
+		&mut self,
+		input: &[u8],
+		out: &mut Vec<u8>,
+	) -> Result<Option<usize>, &'static str> {
+		let mut pos = HEADER_SIZE;
+		let varint = |pos: &mut usize| match sdd_core::varint(&input[*pos..]) {
+			Some((v, len)) => {
+				*pos += len;
+				Ok(Some(v))
+			}
+			None if input.len() - *pos < 5 => Ok(None),
+			None => Err("Malformed varint"),
+		};
+
+		let uid = match varint(&mut pos)? {
+			Some(uid) => uid,
+			None => return Ok(None),
+		};
+		let table = self
+			.tables
+			.get(&uid)
+			.ok_or("Compact entry of an undeclared table")?;
+
+		let mut values = vec![];
+		for t in &table.columns {
+			let value = match t {
+				Type::Bool if pos < input.len() => {
+					pos += 1;
+					input[pos - 1] as u32
+				}
+				Type::Bool => return Ok(None),
+				_ => match varint(&mut pos)? {
+					Some(v) => v,
+					None => return Ok(None),
+				},
+			};
+			values.push(value);
+		}
+
+		out.extend_from_slice(&input[..HEADER_SIZE]);
+		out.extend_from_slice(&uid.to_le_bytes());
+
+		let table = self.tables.get_mut(&uid).unwrap();
+		for (i, (t, v)) in table.columns.iter().zip(values).enumerate() {
+			match t {
+				Type::Bool => out.push(v as u8),
+				Type::Float => {
+					let bits = table.previous[i]
+						.wrapping_add(sdd_core::unzigzag(v) as u32);
+					table.previous[i] = bits;
+					out.extend_from_slice(&bits.to_le_bytes());
+				}
+				_ => out.extend_from_slice(&v.to_le_bytes()),
+			}
+		}
+
+		Ok(Some(pos))
+	}
+}
+
+/// Size of the plain message at the start of `input`, `None` while it is
+/// incomplete, `entry_size` giving the size of the values of a table.
+pub(crate) fn message_size(
+	input: &[u8],
+	entry_size: impl Fn(u32) -> Option<usize>,
+) -> Result<Option<usize>, &'static str> {
+	if input.len() < HEADER_SIZE {
+		return Ok(None);
+	}
+	if sdd_core::word(input) != sdd_core::PROTOCOL {
+		return Err("Expected a message header");
+	}
+
+	let body = &input[HEADER_SIZE..];
+	let size = match input[4] {
+		MSG_STR if body.len() < 8 => return Ok(None),
+		MSG_STR => sdd_core::string_size(sdd_core::word(&body[4..]) as usize),
+		MSG_ATTACHMENT if body.len() < 8 => return Ok(None),
+		MSG_ATTACHMENT => {
+			sdd_core::attachment_size(sdd_core::word(&body[4..]) as usize)
+		}
+		MSG_DESC if body.len() < 9 => return Ok(None),
+		MSG_DESC => {
+			let columns = body[8] & !sdd_core::THREADED;
+			HEADER_SIZE + 9 + columns as usize * 5
+		}
+		MSG_THREAD => sdd_core::THREAD_SIZE,
+		MSG_SOURCE => sdd_core::SOURCE_SIZE,
+		MSG_MARKER => HEADER_SIZE + 5,
+		MSG_PAUSE => sdd_core::PAUSE_SIZE,
+		MSG_ENTITY => sdd_core::ENTITY_SIZE,
+		MSG_SCOPE_BEGIN => sdd_core::SCOPE_BEGIN_SIZE,
+		MSG_SCOPE_END => sdd_core::SCOPE_END_SIZE,
+		MSG_COUNTER => match sdd_core::varint(body.get(4..).unwrap_or(&[])) {
+			Some((_, len)) => HEADER_SIZE + 4 + len,
+			None if body.len() < 9 => return Ok(None),
+			None => return Err("Malformed counter delta"),
+		},
+		MSG_ENTRY if body.len() < 4 => return Ok(None),
+		MSG_ENTRY => match entry_size(sdd_core::word(body)) {
+			Some(size) => HEADER_SIZE + 4 + size,
+			None => return Err("Entry of an undeclared table"),
+		},
+		_ => return Err("Unknown message type"),
+	};
+	match input.len() < size {
+		true => Ok(None),
+		false => Ok(Some(size)),
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::{Producer, Value};
+
+	#[test]
+	fn expand_entries() {
+		let values = [
+			[Value::Int(300), Value::Float(16.6), Value::Bool(true)],
+			[Value::Int(301), Value::Float(16.7), Value::Bool(false)],
+		];
+
+		let mut plain = Producer::new(vec![]);
+		let mut compact = Producer::new(vec![]);
+		compact.compact().unwrap();
+		for p in [&mut plain, &mut compact] {
+			let columns = [
+				("ts", Type::Int),
+				("frame_ms", Type::Float),
+				("ok", Type::Bool),
+			];
+			let uid = p.table("frame", &columns).unwrap();
+			for v in &values {
+				p.entry(uid, v).unwrap();
+			}
+			p.marker(false, Some("done")).unwrap();
+		}
+		let plain = plain.into_inner();
+		let compact = compact.into_inner();
+		assert!(compact.len() - 4 < plain.len());
+
+		let mut expander = Expander::default();
+		let mut input = &compact[4..];
+		let mut out = vec![];
+		// Incomplete messages wait for more input.
+		assert_eq!(expander.expand(&input[..3], &mut out), Ok(None));
+		while !input.is_empty() {
+			let size = expander.expand(input, &mut out).unwrap().unwrap();
+			input = &input[size..];
+		}
+		assert_eq!(out, plain);
+	}
+}
@@ -0,0 +1,504 @@
+use crate::alert;
+use crate::commit;
+use crate::dedupe;
+use crate::finalize;
+use crate::limit;
+use crate::notify;
+use crate::rollup;
+use crate::search;
+use crate::validate;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Daemon configuration, read from a TOML file passed with `--config`.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	/// Producers connected to along with the `--address` ones.
+	pub address: Vec<String>,
+	pub alert: Vec<alert::AlertConfig>,
+	/// Webhooks of the lifecycle events of the capture.
+	pub notify: Vec<notify::NotifyConfig>,
+	pub rollup: Vec<rollup::RollupConfig>,
+	/// Full-text search indices, disabled when the section is missing.
+	pub search: Option<search::SearchConfig>,
+	/// Finalization of the finished databases, disabled when missing.
+	pub finalize: Option<finalize::FinalizeConfig>,
+	/// Grouping of the stored rows into transactions.
+	pub commit: commit::CommitConfig,
+	pub source: SourceConfig,
+	pub naming: NamingConfig,
+	/// Columns decoded but not stored, see `DropConfig`.
+	pub drop: Vec<DropConfig>,
+	pub route: Vec<RouteConfig>,
+	pub limit: Vec<limit::LimitConfig>,
+	/// Caps on what each connection creates, see `limit::Quota`.
+	pub quota: limit::QuotaConfig,
+	pub dedupe: Vec<dedupe::DedupeConfig>,
+	/// Validity ranges of columns, see `validate::Validations`.
+	pub validate: Vec<validate::ValidateConfig>,
+	pub memory: MemoryConfig,
+	/// Authentication of the producers accepted by `--listen`.
+	pub auth: Option<AuthConfig>,
+	/// Sampling sent back to the producers, see `dae::Daemon::sampling`.
+	pub sample: Vec<SampleConfig>,
+	/// Broker subscribed to by `--mqtt`.
+	pub mqtt: Option<MqttConfig>,
+	/// Server subscribed to by `--nats`.
+	pub nats: Option<NatsConfig>,
+	/// Topic the entries are published to, disabled when missing.
+	pub kafka: Option<KafkaConfig>,
+	/// Collector the entries are exported to, disabled when missing.
+	pub otlp: Option<OtlpConfig>,
+	/// Server the entries are inserted into, disabled when missing.
+	pub clickhouse: Option<ClickHouseConfig>,
+	/// Object storage the rotated captures are uploaded to.
+	pub archive: Option<ArchiveConfig>,
+	/// Encryption of the capture databases, see `cipher::open`.
+	pub encryption: Option<EncryptionConfig>,
+	/// Certificates of `--quic` and `--quic-listen`.
+	pub quic: Option<QuicConfig>,
+}
+
+/// Tagging of the captured rows with the producer they came from.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceConfig {
+	/// Name of the producer, its address when not set.
+	pub name: Option<String>,
+	/// Add a `source` column holding the name to every table.
+	pub column: bool,
+	/// Prefix table names with the name.
+	pub prefix: bool,
+}
+
+/// Naming of the tables created for the producers, so that producers
+/// styled differently end up with the same schema.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamingConfig {
+	/// Prefix of every producer table, e.g. `cap1_`.
+	pub prefix: String,
+	/// Turn table and column names into snake_case, e.g. `FrameTime` and
+	/// `gpu ms` into `frame_time` and `gpu_ms`.
+	pub snake_case: bool,
+	/// Local names of producer tables and `table.column` columns, taking
+	/// precedence over `snake_case`.
+	pub rename: HashMap<String, String>,
+}
+
+impl NamingConfig {
+	pub fn check(&self) -> Result<(), String> {
+		let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+		if !self.prefix.chars().all(valid)
+			|| self.prefix.starts_with(|c: char| c.is_ascii_digit())
+		{
+			return Err(format!("Invalid table prefix '{}'", self.prefix));
+		}
+		for name in self.rename.values() {
+			if name.is_empty()
+				|| !name.chars().all(valid)
+				|| name.starts_with(|c: char| c.is_ascii_digit())
+			{
+				return Err(format!("Invalid name '{}'", name));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Local name of producer table `table`, without the prefixes.
+	pub fn table(&self, table: &str) -> String {
+		self.local(table, table)
+	}
+
+	/// Local name of column `column` of producer table `table`.
+	pub fn column(&self, table: &str, column: &str) -> String {
+		self.local(&format!("{}.{}", table, column), column)
+	}
+
+	fn local(&self, key: &str, name: &str) -> String {
+		match self.rename.get(key) {
+			Some(local) => local.clone(),
+			None if self.snake_case => snake_case(name),
+			None => name.to_string(),
+		}
+	}
+}
+
+/// Turns a name like `FrameTime` or `gpu ms` into a snake_case identifier,
+/// characters other than ASCII letters and digits separating words.
+pub fn snake_case(name: &str) -> String {
+	let chars: Vec<char> = name.chars().collect();
+	let mut snake = String::new();
+
+	for (i, &c) in chars.iter().enumerate() {
+		if !c.is_ascii_alphanumeric() {
+			if !snake.is_empty() && !snake.ends_with('_') {
+				snake.push('_');
+			}
+			continue;
+		}
+
+		// A word starts at an upper case letter after a lower case one or a
+		// digit, or at the last letter of an acronym like HTTPCode.
+		let prev = i.checked_sub(1).map(|p| chars[p]);
+		let next = chars.get(i + 1);
+		let boundary = c.is_ascii_uppercase()
+			&& prev.is_some_and(|p| {
+				p.is_ascii_lowercase()
+					|| p.is_ascii_digit()
+					|| p.is_ascii_uppercase()
+						&& next.is_some_and(|n| n.is_ascii_lowercase())
+			});
+		if boundary && !snake.ends_with('_') {
+			snake.push('_');
+		}
+		snake.push(c.to_ascii_lowercase());
+	}
+
+	if snake.ends_with('_') {
+		snake.pop();
+	}
+	if snake.is_empty() || snake.starts_with(|c: char| c.is_ascii_digit()) {
+		snake.insert(0, '_');
+	}
+	snake
+}
+
+/// Columns of the tables matching a glob that are left out of the capture,
+/// e.g. verbose debug strings. Their values still reach the alerts, the
+/// validation and the sinks.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DropConfig {
+	/// Table name glob, e.g. `render_*`.
+	pub tables: String,
+	/// Column name globs, e.g. `debug_*`.
+	pub columns: Vec<String>,
+}
+
+/// Caps on the memory held by the daemon, sizes like `64M`. Table and
+/// column names have to fit, descriptors naming a dropped string are fatal.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+	/// Text of the interned strings, further new strings are stored as
+	/// `<dropped>`.
+	pub strings: Option<String>,
+	/// Longest string message, longer ones are skipped and stored as
+	/// `<dropped>`.
+	pub string_length: Option<String>,
+	/// Bodies received from the network transports waiting to be read.
+	/// Streams wait for room, posted bodies are refused with status 503 and
+	/// broker messages dropped.
+	pub queue: Option<String>,
+}
+
+/// Producers `--listen` accepts, see `auth::Auth`.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+	/// Pre-shared token the producers open their stream with, see
+	/// `Producer::authenticate`.
+	pub token: Option<String>,
+	/// Peer addresses or networks like `10.0.0.0/8` allowed to connect, any
+	/// when empty.
+	pub allow: Vec<String>,
+}
+
+/// Share of the entries of a table the producers send, one in `every`,
+/// none when 0.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SampleConfig {
+	pub table: String,
+	pub every: u32,
+}
+
+impl MemoryConfig {
+	pub fn strings(&self) -> Result<Option<usize>, String> {
+		parse_size(&self.strings)
+	}
+
+	pub fn string_length(&self) -> Result<Option<usize>, String> {
+		parse_size(&self.string_length)
+	}
+
+	pub fn queue(&self) -> Result<Option<usize>, String> {
+		parse_size(&self.queue)
+	}
+}
+
+fn parse_size(size: &Option<String>) -> Result<Option<usize>, String> {
+	match size {
+		Some(s) => Ok(Some(parse_count(s)? as usize)),
+		None => Ok(None),
+	}
+}
+
+/// Topics whose payloads hold protocol messages, each topic captured into
+/// its own shard.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+	/// Broker address, port 1883 when omitted.
+	pub broker: String,
+	/// Topic filters, e.g. `fleet/+/sdd`.
+	pub topics: Vec<String>,
+	#[serde(default = "default_client_id")]
+	pub client_id: String,
+	pub username: Option<String>,
+	pub password: Option<String>,
+	/// Largest accepted payload, 1M when not set.
+	pub max_payload: Option<String>,
+}
+
+fn default_client_id() -> String {
+	String::from("sdd")
+}
+
+impl MqttConfig {
+	pub fn max_payload(&self) -> Result<usize, String> {
+		Ok(parse_size(&self.max_payload)?.unwrap_or(1024 * 1024))
+	}
+}
+
+/// Subjects whose messages hold protocol messages, each subject captured
+/// into its own shard.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NatsConfig {
+	/// Server url, e.g. `nats://bus.local:4222`.
+	pub server: String,
+	/// Subjects, e.g. `fleet.*.sdd`.
+	pub subjects: Vec<String>,
+	pub token: Option<String>,
+	pub username: Option<String>,
+	pub password: Option<String>,
+}
+
+/// Publishes each entry as a message keyed by its table name.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaConfig {
+	/// Bootstrap servers, e.g. `kafka-1:9092,kafka-2:9092`.
+	pub brokers: String,
+	pub topic: String,
+	/// Message format (json, avro), json when not set.
+	pub format: Option<String>,
+}
+
+/// Exports each entry to an OpenTelemetry collector over OTLP/HTTP.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+	/// Base url of the collector, e.g. `http://collector:4318`.
+	pub endpoint: String,
+	/// Signal the entries are exported as (metrics, logs), metrics when not
+	/// set.
+	pub signal: Option<String>,
+	/// `service.name` of the exported resource, `sdd` when not set.
+	pub service: Option<String>,
+	/// Directory keeping the exports the collector could not take, see
+	/// `sink::Delivery`.
+	pub spool: Option<String>,
+	/// Size the spool is kept under, like `1G`, see `sink::SPOOL_SIZE`.
+	pub spool_size: Option<String>,
+}
+
+/// Inserts the entries into ClickHouse tables over its HTTP interface.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClickHouseConfig {
+	/// Url of the HTTP interface, e.g. `http://clickhouse:8123`.
+	pub url: String,
+	/// Database of the tables, `default` when not set.
+	pub database: Option<String>,
+	pub user: Option<String>,
+	pub password: Option<String>,
+	/// Directory keeping the inserts ClickHouse could not take, see
+	/// `sink::Delivery`.
+	pub spool: Option<String>,
+	/// Size the spool is kept under, like `1G`, see `sink::SPOOL_SIZE`.
+	pub spool_size: Option<String>,
+}
+
+/// Uploads the rotated captures to S3-compatible object storage.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveConfig {
+	/// Url of the storage, e.g. `https://s3.eu-central-1.amazonaws.com`,
+	/// buckets are addressed path style.
+	pub endpoint: String,
+	pub bucket: String,
+	/// Prefix of the object keys, e.g. `captures/box-7/`.
+	pub prefix: Option<String>,
+	/// Signing region, `us-east-1` when not set.
+	pub region: Option<String>,
+	/// `AWS_ACCESS_KEY_ID` when not set.
+	pub access_key: Option<String>,
+	/// `AWS_SECRET_ACCESS_KEY` when not set.
+	pub secret_key: Option<String>,
+	/// Gzip the captures before uploading, on when not set.
+	pub compress: Option<bool>,
+	/// Keep the local copies of uploaded captures.
+	#[serde(default)]
+	pub keep: bool,
+}
+
+/// Encrypts the capture databases at rest with SQLCipher.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+	/// Passphrase, `SDD_KEY` is used when the section is missing.
+	pub key: String,
+}
+
+/// PEM files securing the QUIC transport.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct QuicConfig {
+	/// Certificate chain presented when listening.
+	pub cert: Option<String>,
+	/// Private key of the certificate.
+	pub key: Option<String>,
+	/// Certificates trusted when connecting to a producer.
+	pub ca: Option<String>,
+	/// Name checked against the producer certificate, its address when not
+	/// set.
+	pub server_name: Option<String>,
+}
+
+impl Config {
+	pub fn load(path: &Path) -> Result<Config, String> {
+		let text = fs::read_to_string(path)
+			.map_err(|e| format!("{}: {}", path.display(), e))?;
+
+		toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+	}
+}
+
+/// Sends the tables matching a glob to a separate output database.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+	/// Table name glob, e.g. `render_*`.
+	pub tables: String,
+	pub output: String,
+}
+
+/// Matches `name` against a glob where `*` matches any run of characters
+/// and `?` any single character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+	let p: Vec<char> = pattern.chars().collect();
+	let n: Vec<char> = name.chars().collect();
+
+	// Position of the last `*` and of the name when it was reached.
+	let mut star: Option<(usize, usize)> = None;
+	let (mut i, mut j) = (0, 0);
+
+	while j < n.len() {
+		if i < p.len() && (p[i] == '?' || p[i] == n[j]) {
+			i += 1;
+			j += 1;
+		} else if i < p.len() && p[i] == '*' {
+			star = Some((i, j));
+			i += 1;
+		} else if let Some((si, sj)) = star {
+			i = si + 1;
+			j = sj + 1;
+			star = Some((si, sj + 1));
+		} else {
+			return false;
+		}
+	}
+
+	p[i..].iter().all(|c| *c == '*')
+}
+
+/// Parses counts like `500`, `10k`, `1M` or `2G`.
+pub fn parse_count(s: &str) -> Result<u64, String> {
+	let (value, scale) = match s.char_indices().last() {
+		Some((i, 'k')) => (&s[..i], 1e3),
+		Some((i, 'M')) => (&s[..i], 1e6),
+		Some((i, 'G')) => (&s[..i], 1e9),
+		_ => (s, 1.0),
+	};
+
+	let value: f64 = value
+		.parse()
+		.map_err(|_| format!("Malformed count '{}'", s))?;
+
+	Ok((value * scale) as u64)
+}
+
+/// Parses durations like `250ms`, `5s`, `2m` or `1h`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+	let split = s.find(|c: char| !c.is_ascii_digit() && c != '.');
+	let (value, unit) = s.split_at(split.unwrap_or(s.len()));
+
+	let value: f64 = value
+		.parse()
+		.map_err(|_| format!("Malformed duration '{}'", s))?;
+
+	let secs = match unit {
+		"ms" => value / 1000.0,
+		"s" | "" => value,
+		"m" => value * 60.0,
+		"h" => value * 3600.0,
+		_ => return Err(format!("Unknown duration unit in '{}'", s)),
+	};
+
+	Duration::try_from_secs_f64(secs)
+		.map_err(|_| format!("Duration '{}' out of range", s))
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn globs() {
+		assert!(glob_match("render_*", "render_stats"));
+		assert!(glob_match("*", ""));
+		assert!(glob_match("a*b?d", "axxbcd"));
+		assert!(!glob_match("render_*", "gameplay"));
+		assert!(!glob_match("a?", "a"));
+	}
+
+	#[test]
+	fn naming() {
+		assert_eq!(snake_case("FrameTime"), "frame_time");
+		assert_eq!(snake_case("gpu ms"), "gpu_ms");
+		assert_eq!(snake_case("HTTPCode-2xx"), "http_code_2xx");
+		assert_eq!(snake_case(" 3d pass "), "_3d_pass");
+		assert_eq!(snake_case("ÿ"), "_");
+
+		let naming: NamingConfig = toml::from_str(
+			"snake_case = true\n\
+			 [rename]\n\
+			 FrameTime = \"frames\"\n\
+			 \"FrameTime.gpu\" = \"gpu_ms\"",
+		)
+		.unwrap();
+		assert_eq!(naming.table("FrameTime"), "frames");
+		assert_eq!(naming.column("FrameTime", "gpu"), "gpu_ms");
+		assert_eq!(naming.column("Hud", "gpu"), "gpu");
+		assert_eq!(naming.column("Hud", "DrawCalls"), "draw_calls");
+		assert!(naming.check().is_ok());
+	}
+
+	#[test]
+	fn counts() {
+		assert_eq!(parse_count("500"), Ok(500));
+		assert_eq!(parse_count("1M"), Ok(1_000_000));
+		assert_eq!(parse_count("2.5k"), Ok(2500));
+		assert!(parse_count("M").is_err());
+	}
+}
@@ -0,0 +1,494 @@
+//! Canonical streams covering every message type and edge case of the
+//! protocol, along with what the daemon stores for them. `sdd-fixtures`
+//! writes them to `tests/conformance` as `<case>.sddcap` and
+//! `<case>.expected` files, which the conformance tests check the daemon
+//! against. Producers in other languages can compare their bytes to the
+//! streams.
+use crate::dae::{Daemon, Protocol};
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use sdd_core::{Encoder, Type, Value};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+//---------------------------------------------------------------------------
+pub struct Case {
+	pub name: &'static str,
+	/// What the case covers, the first line of its expected file.
+	pub about: &'static str,
+	pub stream: Vec<u8>,
+}
+
+/// Stream built message by message, ids being given explicitly.
+#[derive(Default)]
+struct Stream {
+	bytes: Vec<u8>,
+}
+
+impl Stream {
+	fn encode(
+		&mut self,
+		f: impl FnOnce(&mut Encoder) -> Result<(), sdd_core::Error>,
+	) -> &mut Self {
+		let mut buf = [0; 4096];
+		let mut encoder = Encoder::new(&mut buf);
+		f(&mut encoder).expect("conformance message");
+		self.bytes.extend_from_slice(encoder.bytes());
+		self
+	}
+
+	fn string(&mut self, id: u32, s: &str) -> &mut Self {
+		self.encode(|e| e.string(id, s))
+	}
+
+	fn table(
+		&mut self,
+		uid: u32,
+		name: u32,
+		columns: &[(u32, Type)],
+	) -> &mut Self {
+		self.encode(|e| e.table(uid, name, columns))
+	}
+
+	fn entry(&mut self, uid: u32, values: &[Value]) -> &mut Self {
+		self.encode(|e| e.entry(uid, values))
+	}
+
+	fn compact_entry(
+		&mut self,
+		uid: u32,
+		values: &[Value],
+		previous: &[Value],
+	) -> &mut Self {
+		self.encode(|e| e.compact_entry(uid, values, previous))
+	}
+
+	fn marker(&mut self, start: bool, label: Option<u32>) -> &mut Self {
+		self.encode(|e| e.marker(start, label))
+	}
+
+	fn threaded_table(
+		&mut self,
+		uid: u32,
+		name: u32,
+		columns: &[(u32, Type)],
+	) -> &mut Self {
+		self.encode(|e| e.threaded_table(uid, name, columns))
+	}
+
+	fn thread(&mut self, name: u32) -> &mut Self {
+		self.encode(|e| e.thread(name))
+	}
+
+	fn source(&mut self, name: u32) -> &mut Self {
+		self.encode(|e| e.source(name))
+	}
+
+	fn attachment(&mut self, name: u32, data: &[u8]) -> &mut Self {
+		self.encode(|e| e.attachment(name, data))
+	}
+
+	fn pause(&mut self, paused: bool, reason: Option<u32>) -> &mut Self {
+		self.encode(|e| e.pause(paused, reason))
+	}
+
+	fn entity(&mut self, id: u32, name: u32, kind: u32) -> &mut Self {
+		self.encode(|e| e.entity(id, name, kind))
+	}
+
+	fn counter(&mut self, uid: u32, delta: u32) -> &mut Self {
+		self.encode(|e| e.counter(uid, delta))
+	}
+
+	fn scope_begin(
+		&mut self,
+		id: u32,
+		parent: Option<u32>,
+		name: u32,
+		ts: u64,
+	) -> &mut Self {
+		self.encode(|e| e.scope_begin(id, parent, name, ts))
+	}
+
+	fn scope_end(&mut self, id: u32, ts: u64) -> &mut Self {
+		self.encode(|e| e.scope_end(id, ts))
+	}
+
+	fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+		self.bytes.extend_from_slice(bytes);
+		self
+	}
+
+	fn case(&mut self, name: &'static str, about: &'static str) -> Case {
+		Case {
+			name,
+			about,
+			stream: std::mem::take(&mut self.bytes),
+		}
+	}
+}
+
+/// The cases in a fixed order.
+pub fn cases() -> Vec<Case> {
+	let mut cases = vec![];
+	let mut s = Stream::default();
+
+	cases.push(
+		s.string(0, "sample")
+			.string(1, "i")
+			.string(2, "f")
+			.string(3, "b")
+			.string(4, "s")
+			.string(5, "text")
+			.table(
+				0,
+				0,
+				&[
+					(1, Type::Int),
+					(2, Type::Float),
+					(3, Type::Bool),
+					(4, Type::Str),
+				],
+			)
+			.entry(
+				0,
+				&[
+					Value::Int(0),
+					Value::Float(0.0),
+					Value::Bool(false),
+					Value::Str(5),
+				],
+			)
+			.entry(
+				0,
+				&[
+					Value::Int(u32::MAX),
+					Value::Float(-1.5),
+					Value::Bool(true),
+					Value::Str(5),
+				],
+			)
+			.case("all_types", "Every field type with its extreme values."),
+	);
+
+	s.string(0, "wide");
+	let columns: Vec<(u32, Type)> = (0..sdd_core::MAX_FIELDS as u32)
+		.map(|i| (i + 1, Type::Int))
+		.collect();
+	for (id, _) in &columns {
+		s.string(*id, &format!("c{}", id - 1));
+	}
+	let values: Vec<Value> =
+		(0..sdd_core::MAX_FIELDS as u32).map(Value::Int).collect();
+	cases.push(
+		s.table(0, 0, &columns)
+			.entry(0, &values)
+			.case("max_fields", "A table of the maximum of 32 columns."),
+	);
+
+	cases.push(
+		s.string(0, "log")
+			.string(1, "msg")
+			.string(2, "")
+			.string(3, "héllo ✓")
+			.table(0, 0, &[(1, Type::Str)])
+			.entry(0, &[Value::Str(2)])
+			.entry(0, &[Value::Str(3)])
+			.entry(0, &[Value::Str(9)])
+			.case("strings", "Empty, UTF-8 and undeclared strings as values."),
+	);
+
+	cases.push(
+		s.string(0, "gauge")
+			.string(1, "v")
+			.table(0, 0, &[(1, Type::Float)])
+			.entry(0, &[Value::Float(f32::NAN)])
+			.entry(0, &[Value::Float(f32::INFINITY)])
+			.entry(0, &[Value::Float(-0.0)])
+			.entry(0, &[Value::Float(f32::MIN_POSITIVE)])
+			.case("floats", "NaN, infinite, negative zero and tiny floats."),
+	);
+
+	cases.push(
+		s.string(0, "a")
+			.string(1, "b")
+			.string(2, "n")
+			.table(0, 0, &[(2, Type::Int)])
+			.table(1, 1, &[(2, Type::Bool)])
+			.entry(1, &[Value::Bool(true)])
+			.entry(0, &[Value::Int(1)])
+			.entry(1, &[Value::Bool(false)])
+			.case("tables", "Entries of two tables interleaved."),
+	);
+
+	cases.push(
+		s.string(0, "frame")
+			.string(1, "n")
+			.string(2, "warmup")
+			.table(0, 0, &[(1, Type::Int)])
+			.entry(0, &[Value::Int(1)])
+			.marker(false, Some(2))
+			.entry(0, &[Value::Int(2)])
+			.marker(true, None)
+			.entry(0, &[Value::Int(3)])
+			.case("markers", "Entries between a stop and a start marker."),
+	);
+
+	cases.push(
+		s.string(0, "frame")
+			.string(1, "n")
+			.table(0, 0, &[(1, Type::Int)])
+			.raw(b"junk")
+			.entry(0, &[Value::Int(1)])
+			.raw(&[0xef, 0xbe, 0xed, 0xfe, 0xff])
+			.entry(0, &[Value::Int(2)])
+			.case("malformed", "Junk bytes and an unknown message type."),
+	);
+
+	let mut truncated = s
+		.string(0, "frame")
+		.string(1, "n")
+		.table(0, 0, &[(1, Type::Int)])
+		.entry(0, &[Value::Int(1)])
+		.entry(0, &[Value::Int(2)])
+		.case("truncated", "A stream ending within an entry.");
+	truncated.stream.truncate(truncated.stream.len() - 2);
+	cases.push(truncated);
+
+	cases.push(
+		s.string(0, "frame")
+			.string(1, "n")
+			.table(0, 0, &[(1, Type::Int)])
+			.entry(0, &[Value::Int(1)])
+			.entry(7, &[Value::Int(2)])
+			.case("unknown_table", "An entry of a table never declared."),
+	);
+
+	cases.push(
+		s.string(0, "unit")
+			.string(1, "entity_id")
+			.string(2, "hp")
+			.string(3, "player")
+			.string(4, "hero")
+			.table(0, 0, &[(1, Type::Int), (2, Type::Int)])
+			.entity(1000, 3, 4)
+			.entry(0, &[Value::Int(1000), Value::Int(90)])
+			.entity(1001, 3, 9)
+			.entity(1000, 4, 4)
+			.entry(0, &[Value::Int(1001), Value::Int(50)])
+			.case(
+				"entities",
+				"Entities registered, replaced and with undeclared strings.",
+			),
+	);
+
+	cases.push(
+		s.string(0, "frame")
+			.string(1, "update")
+			.string(2, "render")
+			.scope_begin(0, None, 0, 1000)
+			.scope_begin(1, Some(0), 1, 1000)
+			.scope_end(1, 1400)
+			.scope_begin(1, Some(0), 2, 1400)
+			.scope_begin(2, Some(1), 2, 1500)
+			.scope_end(2, 1600)
+			.scope_end(1, 1900)
+			.scope_end(0, 5_000_000_000)
+			.scope_end(3, 2000)
+			.case("scopes", "Nested scopes, a reused id and a stray end."),
+	);
+
+	cases.push(
+		s.string(0, "alloc")
+			.string(1, "scene")
+			.string(2, "bytes")
+			.string(3, "gauge")
+			.string(4, "menu")
+			.table(0, 0, &[(1, Type::Str), (2, Type::Int)])
+			.table(1, 3, &[(2, Type::Float)])
+			.counter(0, 5)
+			.entry(0, &[Value::Str(4), Value::Int(1000)])
+			.counter(0, 300)
+			.counter(0, 0)
+			.entry(0, &[Value::Str(4), Value::Int(u32::MAX)])
+			.counter(0, 2)
+			.counter(1, 1)
+			.counter(2, 1)
+			.raw(&[0xef, 0xbe, 0xed, 0xfe, 8, 0, 0, 0, 0])
+			.raw(&[0xff; 5])
+			.entry(0, &[Value::Str(4), Value::Int(7)])
+			.case(
+				"counters",
+				"Counter deltas, wrapping, on other tables and malformed.",
+			),
+	);
+
+	let first = [Value::Int(300), Value::Float(16.6), Value::Bool(true)];
+	let second = [Value::Int(301), Value::Float(-0.5), Value::Bool(false)];
+	cases.push(
+		s.raw(&sdd_core::COMPACT_MAGIC)
+			.string(0, "frame")
+			.string(1, "ts")
+			.string(2, "ms")
+			.string(3, "ok")
+			.table(0, 0, &[(1, Type::Int), (2, Type::Float), (3, Type::Bool)])
+			.compact_entry(0, &first, &[])
+			.compact_entry(0, &second, &first)
+			.compact_entry(0, &second, &second)
+			.marker(false, None)
+			.compact_entry(0, &first, &second)
+			.marker(true, None)
+			.compact_entry(1, &first, &[])
+			.entry(0, &first)
+			.case(
+				"compact",
+				"Compact entries with float deltas, then an unknown table.",
+			),
+	);
+
+	cases.push(
+		s.string(0, "frame")
+			.string(1, "ts")
+			.string(2, "loading")
+			.table(0, 0, &[(1, Type::Int)])
+			.entry(0, &[Value::Int(1)])
+			.pause(true, Some(2))
+			.entry(0, &[Value::Int(2)])
+			.entry(0, &[Value::Int(3)])
+			.pause(true, None)
+			.entry(0, &[Value::Int(5)])
+			.pause(false, None)
+			.pause(false, None)
+			.entry(0, &[Value::Int(4)])
+			.pause(true, None)
+			.case("pauses", "Pauses with and without a reason, repeated."),
+	);
+
+	cases.push(
+		s.string(0, "config.json")
+			.string(1, "crash.dmp")
+			.attachment(0, b"{\"vsync\": true}")
+			.attachment(1, &[])
+			.marker(false, None)
+			.attachment(1, &[0xde, 0xad])
+			.marker(true, None)
+			.attachment(1, &[0xbe, 0xef])
+			.attachment(2, b"unnamed")
+			.case("attachments", "Attachments, empty, stopped and unnamed."),
+	);
+
+	cases.push(
+		s.string(0, "job")
+			.string(1, "n")
+			.string(2, "main")
+			.string(3, "worker 1")
+			.string(4, "frame")
+			.threaded_table(0, 0, &[(1, Type::Int)])
+			.table(1, 4, &[(1, Type::Int)])
+			.entry(0, &[Value::Int(1)])
+			.thread(2)
+			.entry(0, &[Value::Int(2)])
+			.entry(1, &[Value::Int(3)])
+			.thread(3)
+			.entry(0, &[Value::Int(4)])
+			.counter(0, 1)
+			.thread(9)
+			.entry(0, &[Value::Int(6)])
+			.case("threads", "Entries of a threaded table tagged by thread."),
+	);
+
+	cases.push(
+		s.string(0, "dump")
+			.string(1, "console 2")
+			.attachment(0, b"a")
+			.source(1)
+			.attachment(0, b"b")
+			.source(7)
+			.attachment(0, b"c")
+			.case("sources", "Attachments attributed by source messages."),
+	);
+
+	cases
+}
+
+//---------------------------------------------------------------------------
+/// Captures `stream` into a new database at `db`, returning its dump.
+pub fn capture(stream: &[u8], db: &Path) -> Result<String, String> {
+	let _ = fs::remove_file(db);
+	let proto = Protocol::new(db.to_string_lossy().into_owned())?;
+	let mut daemon = Daemon::new(proto);
+	daemon.verbose = false;
+	daemon.exit_on_eof = true;
+	let result = daemon.capture_reader(stream, "conformance");
+	drop(daemon);
+
+	let con = Connection::open(db).map_err(|e| e.to_string())?;
+	let mut out = dump(&con).map_err(|e| e.to_string())?;
+	if let Err(e) = result {
+		let _ = writeln!(out, "# result\n{}", e);
+	}
+	Ok(out)
+}
+
+fn cell(value: ValueRef) -> String {
+	match value {
+		ValueRef::Null => String::from("NULL"),
+		ValueRef::Integer(v) => v.to_string(),
+		ValueRef::Real(v) => format!("{:?}", v),
+		ValueRef::Text(s) => format!("{:?}", String::from_utf8_lossy(s)),
+		ValueRef::Blob(b) => b.iter().map(|b| format!("{:02x}", b)).collect(),
+	}
+}
+
+/// Text dump of every table of a capture, leaving out the wall clock
+/// `time` columns of the `_sdd_` tables and the random capture id.
+pub fn dump(con: &Connection) -> rusqlite::Result<String> {
+	let mut stmt = con.prepare(
+		"SELECT name FROM sqlite_master WHERE type = 'table' \
+		 AND name NOT LIKE 'sqlite_%' AND name <> '_sdd_capture' \
+		 ORDER BY name",
+	)?;
+	let tables = stmt
+		.query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))?
+		.collect::<rusqlite::Result<Vec<String>>>()?;
+
+	let mut out = String::new();
+	for table in tables {
+		let mut stmt =
+			con.prepare(&format!("PRAGMA table_info(\"{}\")", table))?;
+		let columns = stmt
+			.query_map(rusqlite::NO_PARAMS, |row| {
+				Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+			})?
+			.collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+		let columns: Vec<(String, String)> = columns
+			.into_iter()
+			.filter(|(c, _)| {
+				!(table.starts_with("_sdd_")
+					&& (c == "time" || c.ends_with("_time")))
+			})
+			.collect();
+
+		let declared: Vec<String> = columns
+			.iter()
+			.map(|(c, t)| format!("{} {}", c, t))
+			.collect();
+		let _ = writeln!(out, "# {} ({})", table, declared.join(", "));
+
+		let selected: Vec<String> =
+			columns.iter().map(|(c, _)| format!("\"{}\"", c)).collect();
+		let mut stmt = con.prepare(&format!(
+			"SELECT {} FROM \"{}\" ORDER BY rowid",
+			selected.join(", "),
+			table
+		))?;
+		let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+		while let Some(row) = rows.next()? {
+			let cells: Vec<String> =
+				(0..columns.len()).map(|i| cell(row.get_raw(i))).collect();
+			let _ = writeln!(out, "{}", cells.join("|"));
+		}
+	}
+	Ok(out)
+}
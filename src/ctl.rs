@@ -0,0 +1,232 @@
+use crate::dae::{SharedStats, TableInfo};
+use crate::wire::type_name;
+use serde_json::json;
+use std::io;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use std::{fs, thread};
+
+//---------------------------------------------------------------------------
+/// How long a client waits for the daemon to pick up a command. The daemon
+/// polls between messages, so an idle producer delays the reply.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+//---------------------------------------------------------------------------
+/// Commands executed by the capturing daemon itself.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+	Flush,
+	Rotate,
+	Pause,
+	Resume,
+	/// Whether to echo log messages to stdout.
+	SetLogLevel(bool),
+	/// Has the producers send one in n entries of a table, none when 0.
+	Sample(String, u32),
+	/// Lists the declared tables with their columns and row counts.
+	Tables,
+}
+
+impl FromStr for Command {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parts: Vec<&str> = s.split_whitespace().collect();
+		match parts.as_slice() {
+			["flush"] => Ok(Command::Flush),
+			["rotate"] => Ok(Command::Rotate),
+			["pause"] => Ok(Command::Pause),
+			["resume"] => Ok(Command::Resume),
+			["tables"] => Ok(Command::Tables),
+			["set-log-level", "off"] => Ok(Command::SetLogLevel(false)),
+			["set-log-level", "info"] => Ok(Command::SetLogLevel(true)),
+			["set-log-level", ..] => {
+				Err(String::from("Log level must be off or info"))
+			}
+			["disable", table] => Ok(Command::Sample(table.to_string(), 0)),
+			["enable", table] => Ok(Command::Sample(table.to_string(), 1)),
+			["sample", table, every] => match every.parse() {
+				Ok(every) => Ok(Command::Sample(table.to_string(), every)),
+				Err(_) => Err(String::from("Sampling must be a count")),
+			},
+			_ => Err(format!("Unknown command '{}'", s.trim())),
+		}
+	}
+}
+
+pub struct Request {
+	pub command: Command,
+	pub reply: mpsc::Sender<String>,
+}
+
+//---------------------------------------------------------------------------
+fn status(stats: &SharedStats) -> String {
+	let stats = stats.lock().unwrap();
+	let tables: Vec<_> = stats
+		.tables
+		.iter()
+		.map(
+			|t| json!({ "name": t.name, "rows": t.rows, "dropped": t.dropped }),
+		)
+		.collect();
+
+	json!({
+		"peer": stats.peer,
+		"recording": stats.recording,
+		"stale": stats.stale,
+		"entries": stats.entries,
+		"reads": stats.reads,
+		"bytes": stats.bytes,
+		"tables": tables,
+	})
+	.to_string()
+}
+
+/// Response of the `tables` command.
+pub fn tables(tables: &[TableInfo]) -> String {
+	let tables: Vec<_> = tables
+		.iter()
+		.map(|t| {
+			let columns: Vec<_> = t
+				.columns
+				.iter()
+				.map(
+					|c| json!({ "name": c.name, "type": type_name(c.data_type) }),
+				)
+				.collect();
+			json!({ "name": t.name, "columns": columns, "rows": t.rows })
+		})
+		.collect();
+
+	json!({ "tables": tables }).to_string()
+}
+
+fn handle(
+	line: &str,
+	stats: &SharedStats,
+	requests: &mpsc::Sender<Request>,
+) -> String {
+	if line.trim() == "status" {
+		return status(stats);
+	}
+
+	let command = match line.parse() {
+		Ok(c) => c,
+		Err(e) => return e,
+	};
+
+	let (reply, response) = mpsc::channel();
+	if requests.send(Request { command, reply }).is_err() {
+		return String::from("The daemon is not running");
+	}
+
+	response
+		.recv_timeout(REPLY_TIMEOUT)
+		.unwrap_or_else(|_| String::from("Queued until the next message"))
+}
+
+//---------------------------------------------------------------------------
+/// Listens for control commands on a unix socket at `path`, one command line
+/// per connection. The returned receiver is polled by the daemon.
+#[cfg(unix)]
+pub fn serve(
+	path: &Path,
+	stats: SharedStats,
+) -> io::Result<mpsc::Receiver<Request>> {
+	let _ = fs::remove_file(path);
+	let listener = UnixListener::bind(path)?;
+	let (requests, receiver) = mpsc::channel();
+
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let mut stream = match stream {
+				Ok(s) => s,
+				Err(e) => {
+					println!("Control accept failed: {}", e);
+					continue;
+				}
+			};
+
+			let mut line = String::new();
+			let read = match stream.try_clone() {
+				Ok(s) => BufReader::new(s).read_line(&mut line),
+				Err(e) => Err(e),
+			};
+
+			let response = match read {
+				Ok(_) => handle(&line, &stats, &requests),
+				Err(e) => e.to_string(),
+			};
+
+			let _ = writeln!(stream, "{}", response);
+		}
+	});
+
+	Ok(receiver)
+}
+
+#[cfg(not(unix))]
+pub fn serve(
+	_path: &std::path::Path,
+	_stats: SharedStats,
+) -> io::Result<mpsc::Receiver<Request>> {
+	Err(io::Error::new(
+		io::ErrorKind::Other,
+		"Control sockets need unix domain sockets",
+	))
+}
+
+/// Sends a command line to the daemon listening at `path`, returning its
+/// response.
+#[cfg(unix)]
+pub fn send(path: &Path, line: &str) -> io::Result<String> {
+	let mut stream = UnixStream::connect(path)?;
+	writeln!(stream, "{}", line)?;
+
+	let mut response = String::new();
+	stream.read_to_string(&mut response)?;
+	Ok(response.trim_end().to_string())
+}
+
+#[cfg(not(unix))]
+pub fn send(_path: &std::path::Path, _line: &str) -> io::Result<String> {
+	Err(io::Error::new(
+		io::ErrorKind::Other,
+		"Control sockets need unix domain sockets",
+	))
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_commands() {
+		assert_eq!("rotate".parse(), Ok(Command::Rotate));
+		assert_eq!(
+			" set-log-level off\n".parse(),
+			Ok(Command::SetLogLevel(false))
+		);
+		assert!("set-log-level loud".parse::<Command>().is_err());
+		assert_eq!(
+			"sample particle 10".parse(),
+			Ok(Command::Sample(String::from("particle"), 10))
+		);
+		assert_eq!(
+			"disable particle".parse(),
+			Ok(Command::Sample(String::from("particle"), 0))
+		);
+		assert_eq!("tables".parse(), Ok(Command::Tables));
+		assert!("explode".parse::<Command>().is_err());
+	}
+}
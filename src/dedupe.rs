@@ -0,0 +1,142 @@
+use crate::config::glob_match;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+//---------------------------------------------------------------------------
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DedupeConfig {
+	/// Table name glob, e.g. `*_state`.
+	pub tables: String,
+	/// Columns identifying an entity, entries are compared with the previous
+	/// entry of the same entity. The whole table is one entity when empty.
+	#[serde(default)]
+	pub key: Vec<String>,
+}
+
+//---------------------------------------------------------------------------
+struct Binding {
+	/// Field indices of the key columns.
+	key: Vec<usize>,
+	/// Last values per key.
+	last: HashMap<Vec<u64>, Vec<u64>>,
+}
+
+/// Suppresses entries repeating the previous entry of their table.
+#[derive(Default)]
+pub struct Dedupe {
+	configs: Vec<DedupeConfig>,
+	/// Indexed by descriptor uid.
+	bindings: Vec<Option<Binding>>,
+}
+
+impl Dedupe {
+	pub fn new(configs: &[DedupeConfig]) -> Dedupe {
+		Dedupe {
+			configs: configs.to_vec(),
+			bindings: vec![],
+		}
+	}
+
+	/// Picks the first rule matching a newly registered table.
+	pub fn bind(
+		&mut self,
+		uid: u32,
+		table: &str,
+		columns: &[String],
+	) -> Result<(), String> {
+		let uid = uid as usize;
+		if self.bindings.len() <= uid {
+			self.bindings.resize_with(uid + 1, || None);
+		}
+
+		let config =
+			match self.configs.iter().find(|c| glob_match(&c.tables, table)) {
+				Some(c) => c,
+				None => return Ok(()),
+			};
+
+		let mut key = vec![];
+		for k in &config.key {
+			match columns.iter().position(|c| c == k) {
+				Some(f) => key.push(f),
+				None => {
+					return Err(format!(
+						"Dedupe key {}.{} does not exist",
+						table, k
+					))
+				}
+			}
+		}
+
+		self.bindings[uid] = Some(Binding {
+			key,
+			last: HashMap::new(),
+		});
+
+		Ok(())
+	}
+
+	/// Whether an entry of `uid` with `num_fields` fields whose raw values
+	/// are given by `value` repeats the previous one remembered.
+	pub fn is_repeat<F>(&self, uid: u32, num_fields: usize, value: F) -> bool
+	where
+		F: Fn(usize) -> u64,
+	{
+		let binding = match self.bindings.get(uid as usize) {
+			Some(Some(b)) => b,
+			_ => return false,
+		};
+
+		let key: Vec<u64> = binding.key.iter().map(|f| value(*f)).collect();
+		let values: Vec<u64> = (0..num_fields).map(value).collect();
+		binding.last.get(&key) == Some(&values)
+	}
+
+	/// Remembers a stored entry, entries dropped later on must not suppress
+	/// the next one.
+	pub fn remember<F>(&mut self, uid: u32, num_fields: usize, value: F)
+	where
+		F: Fn(usize) -> u64,
+	{
+		if let Some(Some(binding)) = self.bindings.get_mut(uid as usize) {
+			let key = binding.key.iter().map(|f| value(*f)).collect();
+			let values = (0..num_fields).map(value).collect();
+			binding.last.insert(key, values);
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keyed_repeats() {
+		let config = DedupeConfig {
+			tables: String::from("*_state"),
+			key: vec![String::from("player")],
+		};
+
+		let mut dedupe = Dedupe::new(&[config]);
+		let columns = [String::from("player"), String::from("hp")];
+		dedupe.bind(0, "player_state", &columns).unwrap();
+
+		let entries = [[1, 100], [2, 100], [1, 100], [2, 90], [2, 90]];
+		let mut repeats = vec![];
+		for e in &entries {
+			let repeat = dedupe.is_repeat(0, 2, |i| e[i]);
+			if !repeat {
+				dedupe.remember(0, 2, |i| e[i]);
+			}
+			repeats.push(repeat);
+		}
+		assert_eq!(repeats, vec![false, false, true, false, true]);
+
+		// Entries dropped after the check are not remembered.
+		assert!(!dedupe.is_repeat(0, 2, |i| [1, 50][i]));
+		assert!(!dedupe.is_repeat(0, 2, |i| [1, 50][i]));
+		assert!(dedupe.bind(1, "npc_state", &columns[1..]).is_err());
+	}
+}
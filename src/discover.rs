@@ -0,0 +1,131 @@
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+
+//---------------------------------------------------------------------------
+/// mDNS service type producers advertise themselves with.
+pub const SERVICE: &str = "_sdd._tcp.local.";
+
+fn other(e: mdns_sd::Error) -> io::Error {
+	io::Error::other(e.to_string())
+}
+
+/// Instance name of the service `fullname`, `None` for other services.
+fn instance(fullname: &str) -> Option<&str> {
+	fullname
+		.strip_suffix(SERVICE)
+		.and_then(|name| name.strip_suffix('.'))
+		.filter(|name| !name.is_empty())
+}
+
+/// A producer announcing itself on the LAN until dropped.
+pub struct Advertisement {
+	daemon: ServiceDaemon,
+	fullname: String,
+}
+
+/// Advertises a producer named `name` accepting the daemon on `port` of
+/// every interface, see `Producer::listen`. The name tags the captured rows
+/// and should be unique on the LAN, e.g. the devkit name.
+pub fn advertise(name: &str, port: u16) -> io::Result<Advertisement> {
+	let daemon = ServiceDaemon::new().map_err(other)?;
+	let host = format!("{}.local.", name);
+	let info = ServiceInfo::new(SERVICE, name, &host, "", port, None)
+		.map_err(other)?
+		.enable_addr_auto();
+	let fullname = info.get_fullname().to_string();
+	daemon.register(info).map_err(other)?;
+
+	Ok(Advertisement { daemon, fullname })
+}
+
+impl Drop for Advertisement {
+	fn drop(&mut self) {
+		let _ = self.daemon.unregister(&self.fullname);
+		let _ = self.daemon.shutdown();
+	}
+}
+
+//---------------------------------------------------------------------------
+/// A producer that appeared on the LAN.
+pub struct Found {
+	pub name: String,
+	pub addr: SocketAddr,
+}
+
+/// Iterates over the producers as they appear, a producer appearing again
+/// once it was removed.
+pub struct Browser {
+	daemon: ServiceDaemon,
+	events: Receiver<ServiceEvent>,
+	/// Producers found and not removed since, by full name.
+	found: HashSet<String>,
+}
+
+/// Browses the LAN for advertised producers.
+pub fn browse() -> io::Result<Browser> {
+	let daemon = ServiceDaemon::new().map_err(other)?;
+	let events = daemon.browse(SERVICE).map_err(other)?;
+
+	Ok(Browser {
+		daemon,
+		events,
+		found: HashSet::new(),
+	})
+}
+
+impl Iterator for Browser {
+	type Item = Found;
+
+	fn next(&mut self) -> Option<Found> {
+		loop {
+			let info = match self.events.recv().ok()? {
+				ServiceEvent::ServiceResolved(info) => info,
+				ServiceEvent::ServiceRemoved(_, fullname) => {
+					self.found.remove(&fullname);
+					continue;
+				}
+				_ => continue,
+			};
+
+			let fullname = info.get_fullname();
+			let name = match instance(fullname) {
+				Some(name) => name.to_string(),
+				None => continue,
+			};
+			// IPv4 addresses first, link-local IPv6 ones need a scope.
+			let ip = info
+				.get_addresses()
+				.iter()
+				.min_by_key(|ip| (ip.is_ipv6(), **ip))
+				.copied();
+			let ip = match ip {
+				Some(ip) if self.found.insert(fullname.to_string()) => ip,
+				_ => continue,
+			};
+
+			let addr = SocketAddr::new(ip, info.get_port());
+			return Some(Found { name, addr });
+		}
+	}
+}
+
+impl Drop for Browser {
+	fn drop(&mut self) {
+		let _ = self.daemon.shutdown();
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn instance_names() {
+		assert_eq!(instance("devkit-3._sdd._tcp.local."), Some("devkit-3"));
+		assert_eq!(instance("._sdd._tcp.local."), None);
+		assert_eq!(instance("printer._ipp._tcp.local."), None);
+	}
+}
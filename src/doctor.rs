@@ -0,0 +1,277 @@
+use crate::alert::Alerts;
+use crate::config::Config;
+use crate::notify::Notify;
+use crate::rollup::Rollups;
+use crate::validate::Validations;
+use rusqlite;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+/// Free space below which a capture would fail soon after it started.
+const MIN_FREE: u64 = 100 << 20;
+/// Free space below which a long capture may run out.
+const LOW_FREE: u64 = 1 << 30;
+/// Bytes written to measure the write speed.
+const PROBE_SIZE: usize = 16 << 20;
+/// Write speed below which the stored rows may fall behind the producers,
+/// in bytes per second.
+const SLOW_WRITE: f64 = 20e6;
+/// Time a producer has to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Status {
+	Ok,
+	Warning,
+	Failed,
+}
+
+/// Outcome of a check of `sdd doctor`.
+pub struct Check {
+	pub name: &'static str,
+	pub status: Status,
+	/// What was found, along with what to do about a problem.
+	pub detail: String,
+}
+
+impl Check {
+	fn new(name: &'static str, status: Status, detail: String) -> Check {
+		Check {
+			name,
+			status,
+			detail,
+		}
+	}
+
+	fn result(name: &'static str, result: Result<String, String>) -> Check {
+		match result {
+			Ok(detail) => Check::new(name, Status::Ok, detail),
+			Err(detail) => Check::new(name, Status::Failed, detail),
+		}
+	}
+}
+
+fn mib(bytes: f64) -> String {
+	format!("{:.0} MiB", bytes / (1 << 20) as f64)
+}
+
+//---------------------------------------------------------------------------
+/// Checks the sections of a loaded config that are only validated once a
+/// capture starts.
+pub fn config(config: &Config, path: Option<&Path>) -> Check {
+	let result = Alerts::new(&config.alert)
+		.and(Rollups::new(&config.rollup).map(drop))
+		.and(Validations::new(&config.validate).map(drop))
+		.and(Notify::new(&config.notify, String::new()).map(drop))
+		.and(config.commit.pacing().map(drop))
+		.and(config.naming.check())
+		.and(config.memory.strings().map(drop))
+		.and(config.memory.string_length().map(drop))
+		.and(config.memory.queue().map(drop));
+
+	let name =
+		path.map_or(String::from("defaults"), |p| p.display().to_string());
+	Check::result("config", result.map(|_| name))
+}
+
+/// Checks the free space of the file system of `dir`.
+#[cfg(unix)]
+pub fn disk_space(dir: &Path) -> Check {
+	use std::ffi::CString;
+	use std::os::unix::ffi::OsStrExt;
+
+	let path = CString::new(dir.as_os_str().as_bytes()).unwrap_or_default();
+	let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+	if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+		let e = std::io::Error::last_os_error();
+		let detail = format!("{}: {}, pick another -o", dir.display(), e);
+		return Check::new("disk space", Status::Failed, detail);
+	}
+
+	free_space(stat.f_bavail as u64 * stat.f_frsize as u64, dir)
+}
+
+#[cfg(not(unix))]
+pub fn disk_space(dir: &Path) -> Check {
+	let detail = format!("Not checked for {} on this platform", dir.display());
+	Check::new("disk space", Status::Warning, detail)
+}
+
+/// Rates `free` bytes in `dir`.
+fn free_space(free: u64, dir: &Path) -> Check {
+	let detail = format!("{} free in {}", mib(free as f64), dir.display());
+	match free {
+		f if f < MIN_FREE => Check::new(
+			"disk space",
+			Status::Failed,
+			format!("Only {}, free some space or pick another -o", detail),
+		),
+		f if f < LOW_FREE => Check::new(
+			"disk space",
+			Status::Warning,
+			format!("Only {}, enough for a short capture", detail),
+		),
+		_ => Check::new("disk space", Status::Ok, detail),
+	}
+}
+
+/// Writes and syncs a file in `dir` to check that captures can be written
+/// there and how fast.
+pub fn write_speed(dir: &Path) -> Check {
+	let path = dir.join(".sdd-doctor");
+	let start = Instant::now();
+	let written = File::create(&path).and_then(|mut file| {
+		let chunk = vec![0x5a; 1 << 20];
+		for _ in 0..PROBE_SIZE / chunk.len() {
+			file.write_all(&chunk)?;
+		}
+		file.sync_all()
+	});
+	let took = start.elapsed().as_secs_f64();
+	let _ = fs::remove_file(&path);
+
+	if let Err(e) = written {
+		let detail = format!(
+			"Cannot write to {}: {}, pick another -o",
+			dir.display(),
+			e
+		);
+		return Check::new("write speed", Status::Failed, detail);
+	}
+	speed(PROBE_SIZE as f64 / took.max(1e-6), dir)
+}
+
+/// Rates a write speed to `dir` of `speed` bytes per second.
+fn speed(speed: f64, dir: &Path) -> Check {
+	let detail = format!("{}/s to {}", mib(speed), dir.display());
+	match speed < SLOW_WRITE {
+		true => Check::new(
+			"write speed",
+			Status::Warning,
+			format!(
+				"Only {}, a fast producer may get ahead of the \
+				 capture, see [commit] to write rows in batches",
+				detail
+			),
+		),
+		false => Check::new("write speed", Status::Ok, detail),
+	}
+}
+
+/// Checks that the linked SQLite has what the capture is configured to
+/// use.
+pub fn sqlite(config: &Config) -> Check {
+	let result = rusqlite::Connection::open_in_memory()
+		.and_then(|con| {
+			let mut stmt = con.prepare("PRAGMA compile_options")?;
+			let options = stmt
+				.query_map(rusqlite::NO_PARAMS, |row| row.get(0))?
+				.collect::<rusqlite::Result<Vec<String>>>()?;
+			Ok(options)
+		})
+		.map_err(|e| format!("Cannot open a database: {}", e))
+		.and_then(|options| {
+			let version = rusqlite::version();
+			if options.iter().any(|o| o == "THREADSAFE=0") {
+				return Err(format!(
+					"SQLite {} is built without thread safety",
+					version
+				));
+			}
+			if config.search.is_some()
+				&& !options.iter().any(|o| o == "ENABLE_FTS5")
+			{
+				return Err(format!(
+					"SQLite {} lacks FTS5, which [search] needs",
+					version
+				));
+			}
+			Ok(format!("SQLite {}", version))
+		});
+
+	Check::result("sqlite", result)
+}
+
+/// Checks that a producer accepts connections on `addr`.
+pub fn connect(addr: &str) -> Check {
+	let result = addr
+		.to_socket_addrs()
+		.and_then(|mut addrs| {
+			addrs.next().ok_or_else(|| {
+				std::io::Error::other("The address resolves to nothing")
+			})
+		})
+		.and_then(|a| TcpStream::connect_timeout(&a, CONNECT_TIMEOUT))
+		.map(|_| format!("{} accepts connections", addr))
+		.map_err(|e| {
+			format!(
+				"{}: {}, start the producer first or accept it with --listen",
+				addr, e
+			)
+		});
+
+	Check::result("producer", result)
+}
+
+/// Checks that producers can be accepted on `addr`.
+pub fn listen(addr: &str) -> Check {
+	let result = TcpListener::bind(addr)
+		.map(|_| format!("{} is free to listen on", addr))
+		.map_err(|e| {
+			format!("{}: {}, is another capture listening there?", addr, e)
+		});
+
+	Check::result("listen", result)
+}
+
+/// Prints the checks. Returns whether none failed.
+pub fn report(checks: &[Check]) -> bool {
+	for check in checks {
+		let status = match check.status {
+			Status::Ok => "ok",
+			Status::Warning => "warning",
+			Status::Failed => "FAILED",
+		};
+		println!("{:<8} {:<12} {}", status, check.name, check.detail);
+	}
+
+	checks.iter().all(|c| c.status != Status::Failed)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn health_checks() {
+		let dir = std::env::temp_dir();
+		assert_eq!(free_space(MIN_FREE - 1, &dir).status, Status::Failed);
+		assert_eq!(free_space(MIN_FREE, &dir).status, Status::Warning);
+		assert_eq!(free_space(LOW_FREE, &dir).status, Status::Ok);
+		assert_eq!(speed(SLOW_WRITE / 2.0, &dir).status, Status::Warning);
+		assert_eq!(speed(SLOW_WRITE, &dir).status, Status::Ok);
+		let missing = dir.join("sdd-doctor-missing");
+		assert_eq!(write_speed(&missing).status, Status::Failed);
+
+		let mut settings = Config::default();
+		assert_eq!(config(&settings, None).status, Status::Ok);
+		assert_eq!(sqlite(&settings).status, Status::Ok);
+		settings.commit.overhead = 2.0;
+		let check = config(&settings, None);
+		assert_eq!(check.status, Status::Failed);
+		assert!(check.detail.contains("overhead"));
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		assert_eq!(connect(&addr).status, Status::Ok);
+		assert_eq!(listen(&addr).status, Status::Failed);
+		drop(listener);
+		assert_eq!(connect(&addr).status, Status::Failed);
+		assert!(!report(&[connect("127.0.0.1:0"), listen(&addr)]));
+	}
+}
@@ -0,0 +1,289 @@
+use rusqlite;
+use rusqlite::types::ValueRef;
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+pub mod folded;
+pub mod perfetto;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+
+//---------------------------------------------------------------------------
+// Column names recognized as carrying timing information.
+pub use crate::wire::TS_COLUMNS;
+pub const DUR_COLUMNS: [&str; 2] = ["dur", "duration"];
+pub const TRACK_COLUMNS: [&str; 3] = ["track", "thread", "tid"];
+
+//---------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum Error {
+	Sql(rusqlite::Error),
+	Io(std::io::Error),
+	Format(&'static str),
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Sql(e) => write!(f, "SqlError: {}", e),
+			Error::Io(e) => write!(f, "IoError: {}", e),
+			Error::Format(m) => write!(f, "FormatError: {}", m),
+		}
+	}
+}
+
+impl From<rusqlite::Error> for Error {
+	fn from(e: rusqlite::Error) -> Self {
+		Error::Sql(e)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(e: std::io::Error) -> Self {
+		Error::Io(e)
+	}
+}
+
+//---------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum TimeUnit {
+	Ns,
+	Us,
+	#[default]
+	Ms,
+	S,
+}
+
+impl TimeUnit {
+	fn ns_per_unit(self) -> f64 {
+		match self {
+			TimeUnit::Ns => 1.0,
+			TimeUnit::Us => 1e3,
+			TimeUnit::Ms => 1e6,
+			TimeUnit::S => 1e9,
+		}
+	}
+
+	pub fn to_ns(self, value: f64) -> u64 {
+		(value * self.ns_per_unit()).max(0.0) as u64
+	}
+
+	pub fn from_ns(self, ns: f64) -> f64 {
+		ns / self.ns_per_unit()
+	}
+}
+
+impl FromStr for TimeUnit {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"ns" => Ok(TimeUnit::Ns),
+			"us" => Ok(TimeUnit::Us),
+			"ms" => Ok(TimeUnit::Ms),
+			"s" => Ok(TimeUnit::S),
+			_ => Err("Expected one of ns, us, ms, s"),
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+pub struct Column {
+	pub name: String,
+	pub decl_type: String,
+}
+
+impl Column {
+	pub fn is_numeric(&self) -> bool {
+		self.decl_type == "INTEGER" || self.decl_type == "REAL"
+	}
+}
+
+pub struct Table {
+	pub name: String,
+	pub columns: Vec<Column>,
+}
+
+impl Table {
+	/// Index of the first column whose name matches one of `names`.
+	pub fn find_column(&self, names: &[&str]) -> Option<usize> {
+		self.columns
+			.iter()
+			.position(|c| names.iter().any(|n| c.name.eq_ignore_ascii_case(n)))
+	}
+}
+
+//---------------------------------------------------------------------------
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Agg {
+	Avg,
+	Min,
+	Max,
+	Sum,
+	Count,
+}
+
+impl Agg {
+	fn sql(self) -> &'static str {
+		match self {
+			Agg::Avg => "AVG",
+			Agg::Min => "MIN",
+			Agg::Max => "MAX",
+			Agg::Sum => "SUM",
+			Agg::Count => "COUNT",
+		}
+	}
+}
+
+impl FromStr for Agg {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"avg" => Ok(Agg::Avg),
+			"min" => Ok(Agg::Min),
+			"max" => Ok(Agg::Max),
+			"sum" => Ok(Agg::Sum),
+			"count" => Ok(Agg::Count),
+			_ => Err("Expected one of avg, min, max, sum, count"),
+		}
+	}
+}
+
+/// Buckets rows by their timestamp column, aggregating every numeric column
+/// within a bucket.
+#[derive(Debug, Copy, Clone)]
+pub struct Downsample {
+	/// Bucket size in the unit of the timestamp column.
+	pub bucket: f64,
+	pub agg: Agg,
+}
+
+/// Inclusive bounds on the timestamp column, in its unit.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Range {
+	pub from: Option<f64>,
+	pub to: Option<f64>,
+}
+
+impl Range {
+	pub fn contains(&self, ts: f64) -> bool {
+		self.from.is_none_or(|from| ts >= from)
+			&& self.to.is_none_or(|to| ts <= to)
+	}
+
+	/// `WHERE` clause keeping the rows within the range, empty when it is
+	/// not bounded.
+	pub fn where_sql(&self, column: &str) -> String {
+		let bounds: Vec<String> = [(">=", self.from), ("<=", self.to)]
+			.iter()
+			.filter_map(|(op, b)| {
+				b.map(|b| format!("\"{}\" {} {}", column, op, b))
+			})
+			.collect();
+		if bounds.is_empty() {
+			String::new()
+		} else {
+			format!(" WHERE {}", bounds.join(" AND "))
+		}
+	}
+}
+
+/// Query reading all rows of `table` in column order, downsampled when
+/// requested and the table has a timestamp column. Rows are limited to
+/// `range` by that column, tables without one being read whole.
+pub fn select_sql(
+	table: &Table,
+	downsample: Option<&Downsample>,
+	range: &Range,
+) -> String {
+	let ts = table.find_column(&TS_COLUMNS);
+	let filter =
+		ts.map_or(String::new(), |ts| range.where_sql(&table.columns[ts].name));
+	let (ts, downsample) = match (ts, downsample) {
+		(Some(ts), Some(d)) => (ts, d),
+		_ => {
+			return format!(
+				"SELECT * FROM \"{}\"{} ORDER BY rowid",
+				table.name, filter
+			)
+		}
+	};
+
+	let columns: Vec<String> = table
+		.columns
+		.iter()
+		.enumerate()
+		.map(|(i, c)| {
+			if i == ts {
+				format!(
+					"CAST(\"{c}\" / {b} AS INTEGER) * {b}",
+					c = c.name,
+					b = downsample.bucket
+				)
+			} else if c.is_numeric() {
+				format!("{}(\"{}\")", downsample.agg.sql(), c.name)
+			} else {
+				format!("\"{}\"", c.name)
+			}
+		})
+		.collect();
+
+	format!(
+		"SELECT {} FROM \"{}\"{} GROUP BY 1 ORDER BY 1",
+		columns.join(", "),
+		table.name,
+		filter
+	)
+}
+
+/// Lists the user tables of a capture along with their columns. Internal
+/// `_sdd_` tables, virtual tables such as the search indices and their shadow
+/// tables are skipped.
+pub fn tables(con: &rusqlite::Connection) -> Result<Vec<Table>, Error> {
+	let mut stmt = con.prepare(
+		"SELECT name FROM sqlite_master t WHERE type = 'table' \
+		 AND name NOT LIKE 'sqlite_%' \
+		 AND name NOT LIKE '\\_sdd\\_%' ESCAPE '\\' \
+		 AND sql NOT LIKE 'CREATE VIRTUAL TABLE%' \
+		 AND NOT EXISTS (SELECT 1 FROM sqlite_master v \
+		 WHERE v.sql LIKE 'CREATE VIRTUAL TABLE%' \
+		 AND t.name LIKE v.name || '\\_%' ESCAPE '\\') \
+		 ORDER BY name",
+	)?;
+
+	let names = stmt
+		.query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))?
+		.collect::<Result<Vec<String>, _>>()?;
+
+	let mut tables = Vec::with_capacity(names.len());
+	for name in names {
+		let mut stmt =
+			con.prepare(&format!("PRAGMA table_info(\"{}\")", name))?;
+
+		let columns = stmt
+			.query_map(rusqlite::NO_PARAMS, |row| {
+				Ok(Column {
+					name: row.get(1)?,
+					decl_type: row.get(2)?,
+				})
+			})?
+			.collect::<Result<Vec<Column>, _>>()?;
+
+		tables.push(Table { name, columns });
+	}
+
+	Ok(tables)
+}
+
+pub fn as_f64(value: ValueRef) -> Option<f64> {
+	match value {
+		ValueRef::Integer(v) => Some(v as f64),
+		ValueRef::Real(v) => Some(v),
+		_ => None,
+	}
+}
@@ -0,0 +1,232 @@
+use super::{Downsample, Error, Range, Table};
+use crate::cipher;
+use arrow_array::builder::{
+	ArrayBuilder, BinaryBuilder, Float64Builder, Int64Builder, StringBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+use rusqlite;
+use rusqlite::types::ValueRef;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+//---------------------------------------------------------------------------
+/// Rows of a record batch, large captures are split into several.
+const BATCH: usize = 64 * 1024;
+
+pub struct Options {
+	/// Tables to export, all of them when empty.
+	pub tables: Vec<String>,
+	pub downsample: Option<Downsample>,
+	pub range: Range,
+}
+
+impl From<ArrowError> for Error {
+	fn from(e: ArrowError) -> Self {
+		match e {
+			ArrowError::IoError(_, e) => Error::Io(e),
+			_ => Error::Format("Arrow encoding failed"),
+		}
+	}
+}
+
+/// Writes each table of the capture at `db` into `<out_dir>/<table>.arrow`
+/// in the Arrow IPC file format. Returns the number of rows written.
+pub fn export(
+	db: &Path,
+	out_dir: &Path,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let con = cipher::open(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+	fs::create_dir_all(out_dir)?;
+
+	let mut rows = 0;
+	for table in super::tables(&con)? {
+		if !opts.tables.is_empty() && !opts.tables.contains(&table.name) {
+			continue;
+		}
+
+		let path = out_dir.join(format!("{}.arrow", table.name));
+		let mut writer = BufWriter::new(File::create(path)?);
+		rows += write_table(&con, &table, &mut writer, opts)?;
+		writer.flush()?;
+	}
+
+	Ok(rows)
+}
+
+pub fn write_table<W: Write>(
+	con: &rusqlite::Connection,
+	table: &Table,
+	out: &mut W,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let schema = Arc::new(schema(table, opts.downsample.is_some()));
+	let mut writer = FileWriter::try_new(out, &schema)?;
+
+	let sql = super::select_sql(table, opts.downsample.as_ref(), &opts.range);
+	let mut stmt = con.prepare(&sql)?;
+	let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+
+	let mut builders = builders(&schema);
+	let mut written = 0;
+	while let Some(row) = rows.next()? {
+		for (i, builder) in builders.iter_mut().enumerate() {
+			builder.append(row.get_raw(i));
+		}
+
+		written += 1;
+		if written % BATCH == 0 {
+			writer.write(&batch(&schema, &mut builders)?)?;
+		}
+	}
+
+	if written == 0 || written % BATCH != 0 {
+		writer.write(&batch(&schema, &mut builders)?)?;
+	}
+	writer.finish()?;
+
+	Ok(written)
+}
+
+/// Columns keep their declared types, numeric columns become doubles when
+/// downsampled as the aggregates are.
+fn schema(table: &Table, downsampled: bool) -> Schema {
+	let fields: Vec<Field> = table
+		.columns
+		.iter()
+		.map(|c| {
+			let data_type = match c.decl_type.as_str() {
+				"INTEGER" if downsampled => DataType::Float64,
+				"INTEGER" => DataType::Int64,
+				"REAL" => DataType::Float64,
+				"BLOB" => DataType::Binary,
+				_ => DataType::Utf8,
+			};
+			Field::new(&c.name, data_type, true)
+		})
+		.collect();
+
+	Schema::new(fields)
+}
+
+//---------------------------------------------------------------------------
+enum Builder {
+	Int(Int64Builder),
+	Float(Float64Builder),
+	Str(StringBuilder),
+	Bin(BinaryBuilder),
+}
+
+impl Builder {
+	/// Appends a value, converting it to the type of the column.
+	fn append(&mut self, value: ValueRef) {
+		match (self, value) {
+			(Builder::Int(b), ValueRef::Integer(v)) => b.append_value(v),
+			(Builder::Int(b), ValueRef::Real(v)) => b.append_value(v as i64),
+			(Builder::Float(b), ValueRef::Integer(v)) => {
+				b.append_value(v as f64)
+			}
+			(Builder::Float(b), ValueRef::Real(v)) => b.append_value(v),
+			(Builder::Str(b), ValueRef::Text(t)) => {
+				b.append_value(String::from_utf8_lossy(t))
+			}
+			(Builder::Str(b), ValueRef::Integer(v)) => {
+				b.append_value(v.to_string())
+			}
+			(Builder::Str(b), ValueRef::Real(v)) => {
+				b.append_value(v.to_string())
+			}
+			(Builder::Bin(b), ValueRef::Blob(v)) => b.append_value(v),
+			(Builder::Bin(b), ValueRef::Text(v)) => b.append_value(v),
+			(builder, _) => builder.append_null(),
+		}
+	}
+
+	fn append_null(&mut self) {
+		match self {
+			Builder::Int(b) => b.append_null(),
+			Builder::Float(b) => b.append_null(),
+			Builder::Str(b) => b.append_null(),
+			Builder::Bin(b) => b.append_null(),
+		}
+	}
+
+	fn finish(&mut self) -> ArrayRef {
+		match self {
+			Builder::Int(b) => ArrayBuilder::finish(b),
+			Builder::Float(b) => ArrayBuilder::finish(b),
+			Builder::Str(b) => ArrayBuilder::finish(b),
+			Builder::Bin(b) => ArrayBuilder::finish(b),
+		}
+	}
+}
+
+fn builders(schema: &Schema) -> Vec<Builder> {
+	schema
+		.fields()
+		.iter()
+		.map(|f| match f.data_type() {
+			DataType::Int64 => Builder::Int(Int64Builder::new()),
+			DataType::Float64 => Builder::Float(Float64Builder::new()),
+			DataType::Binary => Builder::Bin(BinaryBuilder::new()),
+			_ => Builder::Str(StringBuilder::new()),
+		})
+		.collect()
+}
+
+fn batch(
+	schema: &Arc<Schema>,
+	builders: &mut [Builder],
+) -> Result<RecordBatch, ArrowError> {
+	let columns = builders.iter_mut().map(Builder::finish).collect();
+	RecordBatch::try_new(schema.clone(), columns)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use arrow_array::cast::AsArray;
+	use arrow_array::types::{Float64Type, Int64Type};
+	use arrow_array::Array;
+	use arrow_ipc::reader::FileReader;
+	use std::io::Cursor;
+
+	#[test]
+	fn table_roundtrip() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch(
+			"CREATE TABLE frame (ts INTEGER, frame_ms REAL, tag TEXT); \
+			 INSERT INTO frame VALUES (0, 10, 'a'), (400, NULL, 'b');",
+		)
+		.unwrap();
+
+		let table = super::super::tables(&con).unwrap().remove(0);
+		let opts = Options {
+			tables: vec![],
+			downsample: None,
+			range: Range::default(),
+		};
+
+		let mut out = vec![];
+		let rows = write_table(&con, &table, &mut out, &opts).unwrap();
+		assert_eq!(rows, 2);
+
+		let mut reader = FileReader::try_new(Cursor::new(out), None).unwrap();
+		let batch = reader.next().unwrap().unwrap();
+		assert_eq!(batch.num_rows(), 2);
+
+		let ts = batch.column(0).as_primitive::<Int64Type>();
+		assert_eq!(ts.values().to_vec(), vec![0, 400]);
+		let frame = batch.column(1).as_primitive::<Float64Type>();
+		assert_eq!(frame.value(0), 10.0);
+		assert!(frame.is_null(1));
+		assert_eq!(batch.column(2).as_string::<i32>().value(1), "b");
+	}
+}
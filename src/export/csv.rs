@@ -0,0 +1,151 @@
+use super::{Downsample, Error, Range, Table};
+use crate::cipher;
+use rusqlite;
+use rusqlite::types::ValueRef;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+//---------------------------------------------------------------------------
+pub struct Options {
+	/// Tables to export, all of them when empty.
+	pub tables: Vec<String>,
+	pub downsample: Option<Downsample>,
+	pub range: Range,
+}
+
+/// Writes each table of the capture at `db` into `<out_dir>/<table>.csv`.
+/// Returns the number of rows written.
+pub fn export(
+	db: &Path,
+	out_dir: &Path,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let con = cipher::open(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+	fs::create_dir_all(out_dir)?;
+
+	let mut rows = 0;
+	for table in super::tables(&con)? {
+		if !opts.tables.is_empty() && !opts.tables.contains(&table.name) {
+			continue;
+		}
+
+		let path = out_dir.join(format!("{}.csv", table.name));
+		let mut writer = BufWriter::new(File::create(path)?);
+		rows += write_table(&con, &table, &mut writer, opts)?;
+		writer.flush()?;
+	}
+
+	Ok(rows)
+}
+
+pub fn write_table<W: Write>(
+	con: &rusqlite::Connection,
+	table: &Table,
+	out: &mut W,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let header: Vec<String> =
+		table.columns.iter().map(|c| escape(&c.name)).collect();
+	writeln!(out, "{}", header.join(","))?;
+
+	let sql = super::select_sql(table, opts.downsample.as_ref(), &opts.range);
+	let mut stmt = con.prepare(&sql)?;
+	let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+
+	let mut written = 0;
+	while let Some(row) = rows.next()? {
+		for i in 0..table.columns.len() {
+			if i > 0 {
+				out.write_all(b",")?;
+			}
+
+			match row.get_raw(i) {
+				ValueRef::Null => {}
+				ValueRef::Integer(v) => write!(out, "{}", v)?,
+				ValueRef::Real(v) => write!(out, "{}", v)?,
+				ValueRef::Text(t) => out.write_all(
+					escape(&String::from_utf8_lossy(t)).as_bytes(),
+				)?,
+				ValueRef::Blob(b) => write!(out, "<{} bytes>", b.len())?,
+			}
+		}
+
+		out.write_all(b"\n")?;
+		written += 1;
+	}
+
+	Ok(written)
+}
+
+fn escape(s: &str) -> String {
+	if s.contains([',', '"', '\n', '\r']) {
+		format!("\"{}\"", s.replace('"', "\"\""))
+	} else {
+		s.to_string()
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::super::Agg;
+	use super::*;
+
+	#[test]
+	fn downsampled_table() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch(
+			"CREATE TABLE frame (ts INTEGER, frame_ms REAL, tag TEXT); \
+			 INSERT INTO frame VALUES (0, 10, 'a'), (400, 20, 'a'), \
+			 (1000, 30, 'b,c');",
+		)
+		.unwrap();
+
+		let table = super::super::tables(&con).unwrap().remove(0);
+		let opts = Options {
+			tables: vec![],
+			downsample: Some(Downsample {
+				bucket: 1000.0,
+				agg: Agg::Avg,
+			}),
+			range: Range::default(),
+		};
+
+		let mut out = vec![];
+		let rows = write_table(&con, &table, &mut out, &opts).unwrap();
+
+		assert_eq!(rows, 2);
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"ts,frame_ms,tag\n0,15,a\n1000,30,\"b,c\"\n"
+		);
+	}
+	#[test]
+	fn time_range() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch(
+			"CREATE TABLE frame (ts INTEGER, frame_ms REAL); \
+			 INSERT INTO frame VALUES (0, 10), (400, 20), (1000, 30);",
+		)
+		.unwrap();
+
+		let table = super::super::tables(&con).unwrap().remove(0);
+		let opts = Options {
+			tables: vec![],
+			downsample: None,
+			range: Range {
+				from: Some(400.0),
+				to: Some(999.0),
+			},
+		};
+
+		let mut out = vec![];
+		let rows = write_table(&con, &table, &mut out, &opts).unwrap();
+
+		assert_eq!(rows, 1);
+		assert_eq!(String::from_utf8(out).unwrap(), "ts,frame_ms\n400,20\n");
+	}
+}
@@ -0,0 +1,101 @@
+use super::{Error, Range};
+use crate::cipher;
+use rusqlite;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+//---------------------------------------------------------------------------
+pub struct Options {
+	pub range: Range,
+}
+
+/// Writes the spans of the capture at `db` as folded stacks, a line per call
+/// path with its self time, the input of flame graph tools such as
+/// flamegraph.pl or inferno. Returns the number of lines written.
+pub fn export(db: &Path, out: &Path, opts: &Options) -> Result<usize, Error> {
+	let con = cipher::open(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+	let mut writer = BufWriter::new(File::create(out)?);
+	let lines = write_folded(&con, &mut writer, opts)?;
+	writer.flush()?;
+
+	Ok(lines)
+}
+
+pub fn write_folded<W: Write>(
+	con: &rusqlite::Connection,
+	out: &mut W,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let sql = format!(
+		"SELECT path, SUM(dur) FROM _sdd_spans{} GROUP BY path",
+		opts.range.where_sql("ts")
+	);
+	let mut stmt = con.prepare(&sql)?;
+	let totals = stmt
+		.query_map(rusqlite::NO_PARAMS, |row| {
+			Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+		})?
+		.collect::<rusqlite::Result<BTreeMap<String, i64>>>()?;
+
+	// The self time of a path is its time not spent in the paths below it.
+	let mut self_time = totals.clone();
+	for (path, total) in &totals {
+		if let Some((parent, _)) = path.rsplit_once(';') {
+			if let Some(t) = self_time.get_mut(parent) {
+				*t -= total;
+			}
+		}
+	}
+
+	let mut lines = 0;
+	for (path, time) in self_time.iter().filter(|(_, t)| **t > 0) {
+		writeln!(out, "{} {}", path, time)?;
+		lines += 1;
+	}
+
+	Ok(lines)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn self_times() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch(
+			"CREATE TABLE _sdd_spans (path TEXT, ts INTEGER, dur INTEGER); \
+			 INSERT INTO _sdd_spans VALUES ('frame;physics', 0, 4), \
+			 ('frame;render', 4, 6), ('frame', 0, 10), \
+			 ('frame;render', 10, 5), ('frame', 10, 8), ('load', 20, 3);",
+		)
+		.unwrap();
+
+		let mut out = vec![];
+		let opts = Options {
+			range: Range::default(),
+		};
+		assert_eq!(write_folded(&con, &mut out, &opts).unwrap(), 4);
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"frame 3\nframe;physics 4\nframe;render 11\nload 3\n"
+		);
+
+		let mut out = vec![];
+		let opts = Options {
+			range: Range {
+				from: Some(10.0),
+				to: None,
+			},
+		};
+		write_folded(&con, &mut out, &opts).unwrap();
+		assert_eq!(
+			String::from_utf8(out).unwrap(),
+			"frame 3\nframe;render 5\nload 3\n"
+		);
+	}
+}
@@ -0,0 +1,295 @@
+use super::{Downsample, Error, Range, Table};
+use crate::cipher;
+use rusqlite;
+use rusqlite::types::ValueRef;
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use std::path::Path;
+
+//---------------------------------------------------------------------------
+/// Limits of the worksheet names.
+const NAME_LEN: usize = 31;
+const NAME_INVALID: [char; 7] = ['[', ']', ':', '*', '?', '/', '\\'];
+/// Rows of a worksheet below its header.
+const MAX_ROWS: usize = 1_048_575;
+
+pub struct Options {
+	/// Tables to export, all of them when empty.
+	pub tables: Vec<String>,
+	pub downsample: Option<Downsample>,
+	pub range: Range,
+	/// Rows written per worksheet, the rest of the table is left out.
+	pub max_rows: usize,
+}
+
+impl Options {
+	/// Rows written per worksheet, at most what a worksheet holds.
+	fn max_rows(&self) -> usize {
+		self.max_rows.min(MAX_ROWS)
+	}
+}
+
+/// Rows of a table written to its worksheet.
+struct Written {
+	table: String,
+	sheet: String,
+	rows: usize,
+	truncated: bool,
+}
+
+impl From<XlsxError> for Error {
+	fn from(e: XlsxError) -> Self {
+		match e {
+			XlsxError::IoError(e) => Error::Io(e),
+			_ => Error::Format("Excel encoding failed"),
+		}
+	}
+}
+
+/// Writes the capture at `db` into the workbook `out`, a summary sheet
+/// followed by a worksheet per table. Returns the number of rows written.
+pub fn export(db: &Path, out: &Path, opts: &Options) -> Result<usize, Error> {
+	let con = cipher::open(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+	let mut workbook = Workbook::new();
+	let rows =
+		write_workbook(&con, &db.display().to_string(), &mut workbook, opts)?;
+	workbook.save(out)?;
+
+	Ok(rows)
+}
+
+fn write_workbook(
+	con: &rusqlite::Connection,
+	capture: &str,
+	workbook: &mut Workbook,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let bold = Format::new().set_bold();
+	let mut names = vec![String::from("Summary")];
+
+	let mut written = vec![];
+	for table in super::tables(con)? {
+		if !opts.tables.is_empty() && !opts.tables.contains(&table.name) {
+			continue;
+		}
+
+		let sheet = sheet_name(&table.name, &names);
+		names.push(sheet.clone());
+
+		let worksheet = workbook.add_worksheet();
+		worksheet.set_name(&sheet)?;
+		let (rows, truncated) =
+			write_table(con, &table, worksheet, &bold, opts)?;
+		written.push(Written {
+			table: table.name,
+			sheet,
+			rows,
+			truncated,
+		});
+	}
+
+	let summary = workbook.add_worksheet();
+	summary.set_name("Summary")?;
+	write_summary(con, capture, summary, &bold, &written, opts)?;
+
+	// The summary is added last to know the rows, but shown first.
+	workbook.worksheets_mut().rotate_right(1);
+
+	Ok(written.iter().map(|w| w.rows).sum())
+}
+
+/// Writes the header and up to `max_rows` rows of a table, returning their
+/// count and whether rows were left out.
+fn write_table(
+	con: &rusqlite::Connection,
+	table: &Table,
+	sheet: &mut Worksheet,
+	bold: &Format,
+	opts: &Options,
+) -> Result<(usize, bool), Error> {
+	for (i, column) in table.columns.iter().enumerate() {
+		sheet.write_string_with_format(0, i as u16, &column.name, bold)?;
+	}
+	sheet.set_freeze_panes(1, 0)?;
+
+	let sql = format!(
+		"{} LIMIT {}",
+		super::select_sql(table, opts.downsample.as_ref(), &opts.range),
+		opts.max_rows() + 1
+	);
+	let mut stmt = con.prepare(&sql)?;
+	let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+
+	let mut written = 0;
+	while let Some(row) = rows.next()? {
+		if written == opts.max_rows() {
+			return Ok((written, true));
+		}
+
+		let r = written as u32 + 1;
+		for i in 0..table.columns.len() {
+			let c = i as u16;
+			match row.get_raw(i) {
+				ValueRef::Null => {}
+				ValueRef::Integer(v) => {
+					sheet.write_number(r, c, v as f64)?;
+				}
+				ValueRef::Real(v) => {
+					sheet.write_number(r, c, v)?;
+				}
+				ValueRef::Text(t) => {
+					sheet.write_string(r, c, String::from_utf8_lossy(t))?;
+				}
+				ValueRef::Blob(b) => {
+					sheet.write_string(r, c, format!("<{} bytes>", b.len()))?;
+				}
+			}
+		}
+
+		written += 1;
+	}
+
+	Ok((written, false))
+}
+
+/// Capture, exported tables and the recording markers of the session.
+fn write_summary(
+	con: &rusqlite::Connection,
+	capture: &str,
+	sheet: &mut Worksheet,
+	bold: &Format,
+	written: &[Written],
+	opts: &Options,
+) -> Result<(), Error> {
+	let errors: i64 = con
+		.query_row(
+			"SELECT COUNT(*) FROM _sdd_errors",
+			rusqlite::NO_PARAMS,
+			|row| row.get(0),
+		)
+		.unwrap_or(0);
+	let quarantined: i64 = con
+		.query_row(
+			"SELECT COUNT(*) FROM _sdd_quarantine",
+			rusqlite::NO_PARAMS,
+			|row| row.get(0),
+		)
+		.unwrap_or(0);
+
+	let properties = [
+		("Capture", capture.to_string()),
+		("Row cap", opts.max_rows().to_string()),
+		("Errors", errors.to_string()),
+		("Quarantined", quarantined.to_string()),
+	];
+	for (r, (name, value)) in properties.iter().enumerate() {
+		sheet.write_string_with_format(r as u32, 0, *name, bold)?;
+		sheet.write_string(r as u32, 1, value)?;
+	}
+
+	let mut r = properties.len() as u32 + 1;
+	let header = ["Table", "Sheet", "Rows", "Truncated"];
+	for (c, name) in header.iter().enumerate() {
+		sheet.write_string_with_format(r, c as u16, *name, bold)?;
+	}
+	for w in written {
+		r += 1;
+		sheet.write_string(r, 0, &w.table)?;
+		sheet.write_string(r, 1, &w.sheet)?;
+		sheet.write_number(r, 2, w.rows as f64)?;
+		sheet.write_boolean(r, 3, w.truncated)?;
+	}
+
+	let mut stmt = match con
+		.prepare("SELECT time, action, label FROM _sdd_markers ORDER BY rowid")
+	{
+		Ok(stmt) => stmt,
+		Err(_) => return Ok(()),
+	};
+	let mut markers = stmt.query(rusqlite::NO_PARAMS)?;
+
+	r += 2;
+	let header = ["Marker time", "Action", "Label"];
+	for (c, name) in header.iter().enumerate() {
+		sheet.write_string_with_format(r, c as u16, *name, bold)?;
+	}
+	while let Some(row) = markers.next()? {
+		r += 1;
+		sheet.write_number(r, 0, row.get::<_, i64>(0)? as f64)?;
+		sheet.write_string(r, 1, row.get::<_, String>(1)?)?;
+		if let Some(label) = row.get::<_, Option<String>>(2)? {
+			sheet.write_string(r, 2, label)?;
+		}
+	}
+	sheet.autofit();
+
+	Ok(())
+}
+
+/// A valid worksheet name for `table` not among the `taken` ones, which
+/// Excel compares case-insensitively.
+fn sheet_name(table: &str, taken: &[String]) -> String {
+	let base: String = table
+		.chars()
+		.map(|c| if NAME_INVALID.contains(&c) { '_' } else { c })
+		.collect();
+	let base = base.trim_matches('\'');
+	let base = if base.is_empty() { "table" } else { base };
+
+	let mut n = 1;
+	let mut name = base.chars().take(NAME_LEN).collect::<String>();
+	while taken.iter().any(|t| t.eq_ignore_ascii_case(&name)) {
+		n += 1;
+		let suffix = format!("~{}", n);
+		name = base
+			.chars()
+			.take(NAME_LEN - suffix.len())
+			.collect::<String>();
+		name.push_str(&suffix);
+	}
+	name
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn capped_sheets() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch(
+			"CREATE TABLE _sdd_markers (time INTEGER, action TEXT, label TEXT); \
+			 CREATE TABLE _sdd_errors (time INTEGER, offset INTEGER, \
+			 error TEXT, bytes BLOB); \
+			 CREATE TABLE summary (ts INTEGER, tag TEXT); \
+			 INSERT INTO summary VALUES (0, 'a'), (1, 'b'), (2, 'c'); \
+			 INSERT INTO _sdd_markers VALUES (5, 'start', 'warmup');",
+		)
+		.unwrap();
+
+		let opts = Options {
+			tables: vec![],
+			downsample: None,
+			range: Range::default(),
+			max_rows: 2,
+		};
+		let mut workbook = Workbook::new();
+		let rows = write_workbook(&con, "c.db", &mut workbook, &opts).unwrap();
+		assert_eq!(rows, 2);
+		let unlimited = Options {
+			max_rows: usize::MAX,
+			..opts
+		};
+		assert_eq!(unlimited.max_rows(), MAX_ROWS);
+
+		let names: Vec<String> =
+			workbook.worksheets().iter().map(|s| s.name()).collect();
+		assert_eq!(names, vec!["Summary", "summary~2"]);
+		assert!(workbook.save_to_buffer().unwrap().starts_with(b"PK"));
+
+		let long = "a".repeat(40);
+		assert_eq!(sheet_name(&long, &[]).len(), NAME_LEN);
+		assert_eq!(sheet_name("x[1]", &[]), "x_1_");
+	}
+}
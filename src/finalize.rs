@@ -0,0 +1,107 @@
+use rusqlite;
+use serde::Deserialize;
+
+//---------------------------------------------------------------------------
+/// Work done on a database once its capture is complete, on rotation and on
+/// a clean shutdown, producing a compact file quick to query for archival.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FinalizeConfig {
+	/// Indexes created, e.g. on the columns queried by ranges.
+	pub index: Vec<IndexConfig>,
+	/// Gather the statistics of the query planner.
+	pub analyze: bool,
+	/// Rebuild the file without its free pages.
+	pub vacuum: bool,
+	/// Replace the database by a `.db.zst` compressed at this level, 1 to
+	/// 22, see `zst::compress`. Needs the `zstd` feature.
+	pub zstd: Option<i32>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IndexConfig {
+	pub table: String,
+	pub columns: Vec<String>,
+}
+
+/// Finalizes the capture database `con`, skipping the indexes of tables it
+/// does not have.
+pub fn finalize(
+	con: &rusqlite::Connection,
+	config: &FinalizeConfig,
+) -> rusqlite::Result<()> {
+	// Cached statements would keep VACUUM from running.
+	con.flush_prepared_statement_cache();
+
+	for index in &config.index {
+		let tables: i64 = con.query_row(
+			"SELECT count(*) FROM sqlite_master \
+			 WHERE type = 'table' AND name = ?1",
+			rusqlite::params![index.table],
+			|r| r.get(0),
+		)?;
+		if tables == 0 {
+			continue;
+		}
+
+		let columns: Vec<String> =
+			index.columns.iter().map(|c| format!("\"{}\"", c)).collect();
+		con.execute_batch(&format!(
+			"CREATE INDEX IF NOT EXISTS \"{t}_{}\" ON \"{t}\" ({})",
+			index.columns.join("_"),
+			columns.join(", "),
+			t = index.table
+		))?;
+	}
+
+	if config.analyze {
+		con.execute_batch("ANALYZE")?;
+	}
+	if config.vacuum {
+		con.execute_batch("VACUUM")?;
+	}
+	Ok(())
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn finalize_capture() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch(
+			"CREATE TABLE frame (ts INTEGER, ms REAL); \
+			 INSERT INTO frame VALUES (1, 16.6), (2, 17.1);",
+		)
+		.unwrap();
+
+		let config = FinalizeConfig {
+			index: vec![
+				IndexConfig {
+					table: String::from("frame"),
+					columns: vec![String::from("ts")],
+				},
+				IndexConfig {
+					table: String::from("hud"),
+					columns: vec![String::from("ts")],
+				},
+			],
+			analyze: true,
+			vacuum: true,
+			zstd: None,
+		};
+		finalize(&con, &config).unwrap();
+
+		let names = |kind: &str| -> Vec<String> {
+			let sql = "SELECT name FROM sqlite_master WHERE type = ?1";
+			let mut stmt = con.prepare(sql).unwrap();
+			let rows = stmt.query_map(&[kind], |r| r.get(0)).unwrap();
+			rows.collect::<rusqlite::Result<_>>().unwrap()
+		};
+		assert_eq!(names("index"), ["frame_ts"]);
+		assert!(names("table").contains(&String::from("sqlite_stat1")));
+	}
+}
@@ -0,0 +1,200 @@
+use crate::cipher;
+use crate::export;
+use crate::export::TimeUnit;
+use crate::http::{Request, Response};
+use rusqlite;
+use serde_json::{json, Value};
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+
+//---------------------------------------------------------------------------
+#[derive(Clone)]
+pub struct Options {
+	pub db: PathBuf,
+	/// Unit of the timestamp columns, used to map rows onto epoch time.
+	pub time_unit: TimeUnit,
+}
+
+/// Serves the Grafana JSON datasource contract (`/`, `/search`, `/query`,
+/// `/annotations`) over the capture database on `addr`.
+pub fn serve(addr: &str, opts: Options) -> io::Result<thread::JoinHandle<()>> {
+	println!("Grafana datasource listening on {}", addr);
+	crate::http::serve(addr, move |req| handle(req, &opts))
+}
+
+fn handle(req: &Request, opts: &Options) -> Response {
+	let result = match (req.method.as_str(), req.path.as_str()) {
+		("GET", "/") => return Response::text(200, "OK"),
+		("POST", "/search") => search(opts),
+		("POST", "/query") => query(req, opts),
+		("POST", "/annotations") => Ok(json!([])),
+		_ => return Response::text(404, "Not found"),
+	};
+
+	match result {
+		Ok(body) => Response::json(body.to_string()),
+		Err(e) => Response::text(500, &e.to_string()),
+	}
+}
+
+fn open(opts: &Options) -> Result<rusqlite::Connection, export::Error> {
+	let con =
+		cipher::open(&opts.db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+	Ok(con)
+}
+
+/// Numeric columns of tables that have a timestamp, as `table.column`.
+fn metrics(con: &rusqlite::Connection) -> Result<Vec<Metric>, export::Error> {
+	let mut metrics = vec![];
+	for table in export::tables(con)? {
+		let ts = match table.find_column(&export::TS_COLUMNS) {
+			Some(i) => i,
+			None => continue,
+		};
+
+		for (i, column) in table.columns.iter().enumerate() {
+			if i != ts && column.is_numeric() {
+				metrics.push(Metric {
+					table: table.name.clone(),
+					ts: table.columns[ts].name.clone(),
+					column: column.name.clone(),
+				});
+			}
+		}
+	}
+
+	Ok(metrics)
+}
+
+struct Metric {
+	table: String,
+	ts: String,
+	column: String,
+}
+
+impl Metric {
+	fn target(&self) -> String {
+		format!("{}.{}", self.table, self.column)
+	}
+}
+
+fn search(opts: &Options) -> Result<Value, export::Error> {
+	let con = open(opts)?;
+	let targets: Vec<String> =
+		metrics(&con)?.iter().map(|m| m.target()).collect();
+
+	Ok(json!(targets))
+}
+
+fn query(req: &Request, opts: &Options) -> Result<Value, export::Error> {
+	let body: Value = serde_json::from_slice(&req.body)
+		.map_err(|_| export::Error::Format("Malformed query body"))?;
+
+	let from = body["range"]["from"]
+		.as_str()
+		.and_then(parse_time_ms)
+		.unwrap_or(0.0);
+	let to = body["range"]["to"]
+		.as_str()
+		.and_then(parse_time_ms)
+		.unwrap_or(f64::MAX);
+	let max_points = body["maxDataPoints"].as_u64().unwrap_or(1000) as usize;
+	let max_points = max_points.max(1);
+
+	let unit = opts.time_unit;
+	let from = unit.from_ns(from * 1e6);
+	let to = unit.from_ns(to * 1e6);
+
+	let con = open(opts)?;
+	let metrics = metrics(&con)?;
+
+	let mut series = vec![];
+	let targets = body["targets"].as_array().cloned().unwrap_or_default();
+	for target in targets {
+		let name = match target["target"].as_str() {
+			Some(n) => n,
+			None => continue,
+		};
+
+		let metric = match metrics.iter().find(|m| m.target() == name) {
+			Some(m) => m,
+			None => continue,
+		};
+
+		let mut stmt = con.prepare(&format!(
+			"SELECT \"{ts}\", \"{col}\" FROM \"{table}\" \
+			 WHERE \"{ts}\" BETWEEN ?1 AND ?2 ORDER BY \"{ts}\"",
+			ts = metric.ts,
+			col = metric.column,
+			table = metric.table,
+		))?;
+
+		let mut points = vec![];
+		let mut rows = stmt.query([from, to])?;
+		while let Some(row) = rows.next()? {
+			let ts = export::as_f64(row.get_raw(0));
+			let value = export::as_f64(row.get_raw(1));
+			if let (Some(ts), Some(value)) = (ts, value) {
+				let ms = unit.to_ns(ts) as f64 / 1e6;
+				points.push(json!([value, ms]));
+			}
+		}
+
+		let stride = points.len().div_ceil(max_points);
+		let points: Vec<Value> =
+			points.into_iter().step_by(stride.max(1)).collect();
+
+		series.push(json!({ "target": name, "datapoints": points }));
+	}
+
+	Ok(json!(series))
+}
+
+/// Parses an RFC 3339 UTC timestamp (`2016-10-31T06:33:44.866Z`) into
+/// milliseconds since the unix epoch.
+fn parse_time_ms(s: &str) -> Option<f64> {
+	let s = s.trim_end_matches('Z');
+	let (date, time) = {
+		let mut parts = s.splitn(2, 'T');
+		(parts.next()?, parts.next().unwrap_or("00:00:00"))
+	};
+
+	let mut date = date.splitn(3, '-').map(|p| p.parse::<i64>());
+	let (y, m, d) =
+		(date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+	let mut time = time.splitn(3, ':');
+	let hours: f64 = time.next()?.parse().ok()?;
+	let minutes: f64 = time.next()?.parse().ok()?;
+	let seconds: f64 = time.next().unwrap_or("0").parse().ok()?;
+
+	// Days from civil, proleptic Gregorian calendar.
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = if y >= 0 { y } else { y - 399 } / 400;
+	let yoe = y - era * 400;
+	let mp = (m + 9) % 12;
+	let doy = (153 * mp + 2) / 5 + d - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = era * 146_097 + doe - 719_468;
+
+	let secs =
+		days as f64 * 86400.0 + hours * 3600.0 + minutes * 60.0 + seconds;
+	Some(secs * 1000.0)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn time_parsing() {
+		assert_eq!(parse_time_ms("1970-01-01T00:00:00Z"), Some(0.0));
+		assert_eq!(
+			parse_time_ms("2016-10-31T06:33:44.866Z"),
+			Some(1_477_895_624_866.0)
+		);
+	}
+}
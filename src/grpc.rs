@@ -0,0 +1,414 @@
+use crate::ingest::{self, Bodies};
+use sdd_core::{
+	MSG_ATTACHMENT, MSG_COUNTER, MSG_DESC, MSG_ENTITY, MSG_ENTRY, MSG_MARKER,
+	MSG_PAUSE, MSG_SCOPE_BEGIN, MSG_SCOPE_END, MSG_SOURCE, MSG_STR, MSG_THREAD,
+	NO_LABEL, NO_SCOPE, PROTOCOL, THREADED,
+};
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+include!(concat!(env!("OUT_DIR"), "/sdd.Ingest.rs"));
+
+//---------------------------------------------------------------------------
+// Messages of proto/sdd.proto.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Frame {
+	#[prost(
+		oneof = "frame::Message",
+		tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13"
+	)]
+	pub message: Option<frame::Message>,
+}
+
+pub mod frame {
+	#[derive(Clone, PartialEq, prost::Oneof)]
+	pub enum Message {
+		#[prost(message, tag = "1")]
+		String(super::Str),
+		#[prost(message, tag = "2")]
+		Table(super::Table),
+		#[prost(message, tag = "3")]
+		Entry(super::Entry),
+		#[prost(message, tag = "4")]
+		Marker(super::Marker),
+		#[prost(message, tag = "5")]
+		Entity(super::Entity),
+		#[prost(message, tag = "6")]
+		ScopeBegin(super::ScopeBegin),
+		#[prost(message, tag = "7")]
+		ScopeEnd(super::ScopeEnd),
+		#[prost(message, tag = "8")]
+		Counter(super::Counter),
+		#[prost(message, tag = "9")]
+		Pause(super::Pause),
+		#[prost(message, tag = "10")]
+		Attachment(super::Attachment),
+		#[prost(message, tag = "11")]
+		Thread(super::Thread),
+		#[prost(message, tag = "13")]
+		Source(super::Source),
+	}
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Str {
+	#[prost(uint32, tag = "1")]
+	pub id: u32,
+	#[prost(string, tag = "2")]
+	pub text: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Column {
+	#[prost(uint32, tag = "1")]
+	pub r#type: u32,
+	#[prost(uint32, tag = "2")]
+	pub name: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Table {
+	#[prost(uint32, tag = "1")]
+	pub uid: u32,
+	#[prost(uint32, tag = "2")]
+	pub name: u32,
+	#[prost(message, repeated, tag = "3")]
+	pub columns: Vec<Column>,
+	#[prost(bool, tag = "4")]
+	pub threaded: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Value {
+	#[prost(oneof = "value::Value", tags = "1, 2, 3, 4")]
+	pub value: Option<value::Value>,
+}
+
+pub mod value {
+	#[derive(Clone, PartialEq, prost::Oneof)]
+	pub enum Value {
+		#[prost(uint32, tag = "1")]
+		Int(u32),
+		#[prost(float, tag = "2")]
+		Float(f32),
+		#[prost(bool, tag = "3")]
+		Bool(bool),
+		#[prost(uint32, tag = "4")]
+		Str(u32),
+	}
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Entry {
+	#[prost(uint32, tag = "1")]
+	pub uid: u32,
+	#[prost(message, repeated, tag = "2")]
+	pub values: Vec<Value>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Marker {
+	#[prost(bool, tag = "1")]
+	pub start: bool,
+	#[prost(uint32, optional, tag = "2")]
+	pub label: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Entity {
+	#[prost(uint32, tag = "1")]
+	pub id: u32,
+	#[prost(uint32, tag = "2")]
+	pub name: u32,
+	#[prost(uint32, tag = "3")]
+	pub kind: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeBegin {
+	#[prost(uint32, tag = "1")]
+	pub id: u32,
+	#[prost(uint32, optional, tag = "2")]
+	pub parent: Option<u32>,
+	#[prost(uint32, tag = "3")]
+	pub name: u32,
+	#[prost(uint64, tag = "4")]
+	pub ts: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ScopeEnd {
+	#[prost(uint32, tag = "1")]
+	pub id: u32,
+	#[prost(uint64, tag = "2")]
+	pub ts: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Counter {
+	#[prost(uint32, tag = "1")]
+	pub uid: u32,
+	#[prost(uint32, tag = "2")]
+	pub delta: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Pause {
+	#[prost(bool, tag = "1")]
+	pub paused: bool,
+	#[prost(uint32, optional, tag = "2")]
+	pub reason: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Attachment {
+	#[prost(uint32, tag = "1")]
+	pub name: u32,
+	#[prost(bytes = "vec", tag = "2")]
+	pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Thread {
+	#[prost(uint32, tag = "1")]
+	pub name: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Source {
+	#[prost(uint32, tag = "1")]
+	pub name: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Summary {
+	#[prost(uint64, tag = "1")]
+	pub frames: u64,
+}
+
+//---------------------------------------------------------------------------
+fn header(out: &mut Vec<u8>, msg: u8) {
+	out.extend_from_slice(&PROTOCOL.to_le_bytes());
+	out.push(msg);
+}
+
+/// Appends the wire encoding of `frame`, see proto.md.
+pub fn encode(frame: &Frame, out: &mut Vec<u8>) -> Result<(), &'static str> {
+	match &frame.message {
+		Some(frame::Message::String(s)) => {
+			header(out, MSG_STR);
+			out.extend_from_slice(&s.id.to_le_bytes());
+			out.extend_from_slice(&(s.text.len() as u32).to_le_bytes());
+			out.extend_from_slice(s.text.as_bytes());
+		}
+		Some(frame::Message::Table(t)) => {
+			if t.columns.is_empty() || t.columns.len() > 32 {
+				return Err("Tables have 1 to 32 columns");
+			}
+
+			header(out, MSG_DESC);
+			out.extend_from_slice(&t.uid.to_le_bytes());
+			out.extend_from_slice(&t.name.to_le_bytes());
+			let threaded = if t.threaded { THREADED } else { 0 };
+			out.push(t.columns.len() as u8 | threaded);
+			for column in &t.columns {
+				out.push(column.r#type as u8);
+				out.extend_from_slice(&column.name.to_le_bytes());
+			}
+		}
+		Some(frame::Message::Entry(e)) => {
+			header(out, MSG_ENTRY);
+			out.extend_from_slice(&e.uid.to_le_bytes());
+			for v in &e.values {
+				match v.value {
+					Some(value::Value::Int(v)) | Some(value::Value::Str(v)) => {
+						out.extend_from_slice(&v.to_le_bytes())
+					}
+					Some(value::Value::Float(v)) => {
+						out.extend_from_slice(&v.to_le_bytes())
+					}
+					Some(value::Value::Bool(v)) => out.push(v as u8),
+					None => return Err("Entry value without a type"),
+				}
+			}
+		}
+		Some(frame::Message::Marker(m)) => {
+			header(out, MSG_MARKER);
+			out.push(m.start as u8);
+			out.extend_from_slice(&m.label.unwrap_or(NO_LABEL).to_le_bytes());
+		}
+		Some(frame::Message::Entity(e)) => {
+			header(out, MSG_ENTITY);
+			out.extend_from_slice(&e.id.to_le_bytes());
+			out.extend_from_slice(&e.name.to_le_bytes());
+			out.extend_from_slice(&e.kind.to_le_bytes());
+		}
+		Some(frame::Message::ScopeBegin(s)) => {
+			header(out, MSG_SCOPE_BEGIN);
+			out.extend_from_slice(&s.id.to_le_bytes());
+			out.extend_from_slice(&s.parent.unwrap_or(NO_SCOPE).to_le_bytes());
+			out.extend_from_slice(&s.name.to_le_bytes());
+			out.extend_from_slice(&s.ts.to_le_bytes());
+		}
+		Some(frame::Message::ScopeEnd(s)) => {
+			header(out, MSG_SCOPE_END);
+			out.extend_from_slice(&s.id.to_le_bytes());
+			out.extend_from_slice(&s.ts.to_le_bytes());
+		}
+		Some(frame::Message::Counter(c)) => {
+			header(out, MSG_COUNTER);
+			out.extend_from_slice(&c.uid.to_le_bytes());
+			let mut delta = c.delta;
+			while delta >= 0x80 {
+				out.push((delta as u8) | 0x80);
+				delta >>= 7;
+			}
+			out.push(delta as u8);
+		}
+		Some(frame::Message::Pause(p)) => {
+			header(out, MSG_PAUSE);
+			out.push(p.paused as u8);
+			out.extend_from_slice(&p.reason.unwrap_or(NO_LABEL).to_le_bytes());
+		}
+		Some(frame::Message::Attachment(a)) => {
+			header(out, MSG_ATTACHMENT);
+			out.extend_from_slice(&a.name.to_le_bytes());
+			out.extend_from_slice(&(a.data.len() as u32).to_le_bytes());
+			out.extend_from_slice(&a.data);
+		}
+		Some(frame::Message::Thread(t)) => {
+			header(out, MSG_THREAD);
+			out.extend_from_slice(&t.name.to_le_bytes());
+		}
+		Some(frame::Message::Source(s)) => {
+			header(out, MSG_SOURCE);
+			out.extend_from_slice(&s.name.to_le_bytes());
+		}
+		None => return Err("Empty frame"),
+	}
+
+	Ok(())
+}
+
+//---------------------------------------------------------------------------
+struct Service {
+	sender: ingest::Sender,
+	/// Streams are captured one at a time.
+	session: tokio::sync::Mutex<()>,
+}
+
+impl Service {
+	/// Queues `bytes` for the daemon, false once it has finished.
+	fn send(&self, bytes: Vec<u8>) -> bool {
+		tokio::task::block_in_place(|| self.sender.send(bytes)).is_ok()
+	}
+
+	async fn forward(
+		&self,
+		stream: &mut Streaming<Frame>,
+	) -> Result<u64, Status> {
+		let mut frames = 0;
+		while let Some(frame) = stream.message().await? {
+			let mut bytes = vec![];
+			encode(&frame, &mut bytes).map_err(Status::invalid_argument)?;
+			if !self.send(bytes) {
+				return Err(Status::unavailable("The capture has finished"));
+			}
+			frames += 1;
+		}
+		Ok(frames)
+	}
+}
+
+#[tonic::async_trait]
+impl ingest_server::Ingest for Service {
+	async fn stream(
+		&self,
+		request: Request<Streaming<Frame>>,
+	) -> Result<Response<Summary>, Status> {
+		let _session = self.session.lock().await;
+
+		let result = self.forward(&mut request.into_inner()).await;
+		self.send(vec![]);
+
+		result.map(|frames| Response::new(Summary { frames }))
+	}
+}
+
+/// Serves the `sdd.Ingest` service on `addr`, each stream read as one
+/// session.
+pub fn serve(addr: &str) -> io::Result<Bodies> {
+	let addr: SocketAddr = addr.parse().map_err(|_| {
+		io::Error::new(io::ErrorKind::InvalidInput, "Bad address")
+	})?;
+
+	let runtime = tokio::runtime::Runtime::new()?;
+	let incoming = {
+		let _guard = runtime.enter();
+		TcpIncoming::new(addr, true, None).map_err(io::Error::other)?
+	};
+
+	let (sender, bodies) = ingest::channel();
+	let service = Service {
+		sender,
+		session: tokio::sync::Mutex::new(()),
+	};
+
+	thread::spawn(move || {
+		let server = Server::builder()
+			.add_service(ingest_server::IngestServer::new(service))
+			.serve_with_incoming(incoming);
+
+		if let Err(e) = runtime.block_on(server) {
+			println!("gRPC server failed: {}", e);
+		}
+	});
+
+	Ok(bodies)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encode_frames() {
+		let frames = [
+			frame::Message::Table(Table {
+				uid: 0,
+				name: 1,
+				columns: vec![Column { r#type: 3, name: 2 }],
+				threaded: false,
+			}),
+			frame::Message::Entry(Entry {
+				uid: 0,
+				values: vec![Value {
+					value: Some(value::Value::Bool(true)),
+				}],
+			}),
+		];
+
+		let mut bytes = vec![];
+		for message in frames {
+			let frame = Frame {
+				message: Some(message),
+			};
+			encode(&frame, &mut bytes).unwrap();
+		}
+
+		let mut expected = vec![0xEF, 0xBE, 0xED, 0xFE, 3];
+		expected.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0, 1, 3, 2, 0, 0, 0]);
+		expected.extend_from_slice(&[0xEF, 0xBE, 0xED, 0xFE, 2]);
+		expected.extend_from_slice(&[0, 0, 0, 0, 1]);
+		assert_eq!(bytes, expected);
+
+		let empty = Frame { message: None };
+		assert!(encode(&empty, &mut bytes).is_err());
+	}
+}
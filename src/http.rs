@@ -0,0 +1,328 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+//---------------------------------------------------------------------------
+pub struct Request {
+	pub method: String,
+	pub path: String,
+	pub query: String,
+	pub headers: Vec<(String, String)>,
+	pub body: Vec<u8>,
+}
+
+impl Request {
+	pub fn read<R: Read>(reader: &mut BufReader<R>) -> io::Result<Request> {
+		let mut line = String::new();
+		reader.read_line(&mut line)?;
+
+		let mut parts = line.split_whitespace();
+		let method = parts.next().unwrap_or("").to_string();
+		let target = parts.next().unwrap_or("/");
+		let (path, query) = match target.find('?') {
+			Some(i) => (&target[..i], &target[i + 1..]),
+			None => (target, ""),
+		};
+
+		let mut request = Request {
+			method,
+			path: path.to_string(),
+			query: query.to_string(),
+			headers: vec![],
+			body: vec![],
+		};
+
+		loop {
+			line.clear();
+			if reader.read_line(&mut line)? == 0 {
+				break;
+			}
+
+			let header = line.trim_end();
+			if header.is_empty() {
+				break;
+			}
+
+			if let Some(i) = header.find(':') {
+				let name = header[..i].trim().to_ascii_lowercase();
+				let value = header[i + 1..].trim().to_string();
+				request.headers.push((name, value));
+			}
+		}
+
+		let len = request
+			.header("content-length")
+			.and_then(|l| l.parse::<usize>().ok())
+			.unwrap_or(0);
+
+		if len > MAX_BODY {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"Request body too large",
+			));
+		}
+
+		request.body = vec![0; len];
+		reader.read_exact(&mut request.body)?;
+
+		Ok(request)
+	}
+
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.headers
+			.iter()
+			.find(|(n, _)| n.eq_ignore_ascii_case(name))
+			.map(|(_, v)| v.as_str())
+	}
+
+	/// Decoded value of a `key=value` pair in the query string.
+	pub fn param(&self, key: &str) -> Option<String> {
+		self.query.split('&').find_map(|pair| {
+			let mut kv = pair.splitn(2, '=');
+			match (kv.next(), kv.next()) {
+				(Some(k), Some(v)) if k == key => Some(percent_decode(v)),
+				_ => None,
+			}
+		})
+	}
+}
+
+/// `s` with its `%XX` escapes decoded and `+` read as a space.
+pub fn percent_decode(s: &str) -> String {
+	let bytes = s.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+
+	let mut i = 0;
+	while i < bytes.len() {
+		match bytes[i] {
+			b'+' => out.push(b' '),
+			b'%' if i + 2 < bytes.len() => {
+				let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+				match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+					Some(b) => {
+						out.push(b);
+						i += 2;
+					}
+					None => out.push(b'%'),
+				}
+			}
+			b => out.push(b),
+		}
+		i += 1;
+	}
+
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Query string value of `s`, unreserved characters kept as they are.
+pub fn percent_encode(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	for b in s.bytes() {
+		match b {
+			b'A'..=b'Z'
+			| b'a'..=b'z'
+			| b'0'..=b'9'
+			| b'-'
+			| b'_'
+			| b'.'
+			| b'~' => out.push(b as char),
+			_ => out.push_str(&format!("%{:02X}", b)),
+		}
+	}
+	out
+}
+
+//---------------------------------------------------------------------------
+pub struct Response {
+	pub status: u16,
+	pub content_type: &'static str,
+	pub body: Vec<u8>,
+}
+
+impl Response {
+	pub fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+		Response {
+			status,
+			content_type,
+			body,
+		}
+	}
+
+	pub fn json(body: String) -> Self {
+		Response::new(200, "application/json", body.into_bytes())
+	}
+
+	pub fn text(status: u16, body: &str) -> Self {
+		Response::new(status, "text/plain", body.as_bytes().to_vec())
+	}
+
+	pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		let reason = match self.status {
+			200 => "OK",
+			204 => "No Content",
+			400 => "Bad Request",
+			401 => "Unauthorized",
+			404 => "Not Found",
+			_ => "Internal Server Error",
+		};
+
+		write!(
+			out,
+			"HTTP/1.1 {} {}\r\n\
+			 Content-Type: {}\r\n\
+			 Content-Length: {}\r\n\
+			 Access-Control-Allow-Origin: *\r\n\
+			 Access-Control-Allow-Headers: accept, content-type\r\n\
+			 Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+			 Connection: close\r\n\r\n",
+			self.status,
+			reason,
+			self.content_type,
+			self.body.len()
+		)?;
+
+		out.write_all(&self.body)?;
+		out.flush()
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Serves `handler` on `addr`, one thread per connection.
+pub fn serve<F>(addr: &str, handler: F) -> io::Result<thread::JoinHandle<()>>
+where
+	F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+	let listener = TcpListener::bind(addr)?;
+	let handler = Arc::new(handler);
+
+	Ok(thread::spawn(move || {
+		for stream in listener.incoming() {
+			let stream = match stream {
+				Ok(s) => s,
+				Err(e) => {
+					println!("Http accept failed: {}", e);
+					continue;
+				}
+			};
+
+			let handler = handler.clone();
+			thread::spawn(move || {
+				if let Err(e) = handle(stream, &*handler) {
+					println!("Http connection error: {}", e);
+				}
+			});
+		}
+	}))
+}
+
+fn handle<F>(stream: TcpStream, handler: &F) -> io::Result<()>
+where
+	F: Fn(&Request) -> Response,
+{
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let request = Request::read(&mut reader)?;
+
+	let response = if request.method == "OPTIONS" {
+		Response::new(204, "text/plain", vec![])
+	} else {
+		handler(&request)
+	};
+
+	let mut stream = stream;
+	response.write(&mut stream)
+}
+
+//---------------------------------------------------------------------------
+/// Time a request of sdd may take to connect, and each read of its response.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a POST request to a plain `http://` url, returning the status code.
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<u16> {
+	post_with(url, content_type, &[], body)
+}
+
+/// `post` with further `(name, value)` headers.
+pub fn post_with(
+	url: &str,
+	content_type: &str,
+	headers: &[(String, String)],
+	body: &[u8],
+) -> io::Result<u16> {
+	let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Bad url");
+
+	let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+	let (host, path) = match rest.find('/') {
+		Some(i) => (&rest[..i], &rest[i..]),
+		None => (rest, "/"),
+	};
+	let addr = if host.contains(':') {
+		host.to_string()
+	} else {
+		format!("{}:80", host)
+	};
+
+	let addr = addr.to_socket_addrs()?.next().ok_or_else(invalid)?;
+	let mut stream = TcpStream::connect_timeout(&addr, CLIENT_TIMEOUT)?;
+	stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+	stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+	write!(
+		stream,
+		"POST {} HTTP/1.1\r\n\
+		 Host: {}\r\n\
+		 Content-Type: {}\r\n\
+		 Content-Length: {}\r\n\
+		 Connection: close\r\n",
+		path,
+		host,
+		content_type,
+		body.len()
+	)?;
+	for (name, value) in headers {
+		write!(stream, "{}: {}\r\n", name, value)?;
+	}
+	stream.write_all(b"\r\n")?;
+	stream.write_all(body)?;
+
+	let mut status = String::new();
+	BufReader::new(stream).read_line(&mut status)?;
+
+	status
+		.split_whitespace()
+		.nth(1)
+		.and_then(|c| c.parse().ok())
+		.ok_or_else(|| {
+			io::Error::new(io::ErrorKind::InvalidData, "Malformed response")
+		})
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_request() {
+		let raw = b"POST /query?a=1%202&b=two HTTP/1.1\r\n\
+			Host: localhost\r\n\
+			Content-Length: 4\r\n\
+			\r\n\
+			{}{}";
+
+		let mut reader = BufReader::new(&raw[..]);
+		let request = Request::read(&mut reader).unwrap();
+
+		assert_eq!(request.method, "POST");
+		assert_eq!(request.path, "/query");
+		assert_eq!(request.param("a").as_deref(), Some("1 2"));
+		assert_eq!(request.param("b").as_deref(), Some("two"));
+		assert_eq!(request.header("Host"), Some("localhost"));
+		assert_eq!(request.body, b"{}{}");
+	}
+}
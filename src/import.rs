@@ -0,0 +1,155 @@
+use crate::raw::Index;
+use crate::spsc;
+use crate::wire::{Error, Message, Messages};
+use std::ops::Range;
+use std::thread;
+
+//---------------------------------------------------------------------------
+/// Segments a decoding thread gets ahead of the one storing them.
+const AHEAD: usize = 2;
+
+/// Message of a capture along with its bytes, an error standing for bytes
+/// that could not be decoded.
+pub type Decoded<'a> = (Result<Message<'a>, Error>, Range<usize>);
+
+/// Offsets of the segments of a capture, from one checkpoint of its index to
+/// the next. Once the declarations before it are known, the entries of a
+/// segment are decoded without the rest of the capture.
+fn segments(index: &Index) -> Vec<Range<usize>> {
+	let mut starts: Vec<usize> =
+		index.checkpoints.iter().map(|c| c.offset).collect();
+	if starts.first() != Some(&0) {
+		starts.insert(0, 0);
+	}
+
+	let ends = starts.iter().skip(1).cloned().chain([index.size]);
+	starts
+		.iter()
+		.cloned()
+		.zip(ends)
+		.map(|(s, e)| s..e)
+		.collect()
+}
+
+/// Decodes the segments of the capture in `bytes` given to one of `threads`
+/// threads, each segment going to them in turn.
+fn decode_segments<'a>(
+	bytes: &'a [u8],
+	index: &Index,
+	segments: impl Iterator<Item = Range<usize>>,
+	mut decoded: spsc::Sender<Vec<Decoded<'a>>>,
+) {
+	let mut messages = Messages::new(bytes);
+	let mut declarations = index.declarations.iter().peekable();
+
+	for segment in segments {
+		// Only the tables declared before the segment size its entries.
+		while let Some((offset, _)) =
+			declarations.next_if(|(offset, _)| *offset < segment.start)
+		{
+			messages.seek(*offset);
+			messages.next();
+		}
+
+		let mut part = vec![];
+		messages.seek(segment.start);
+		while messages.offset() < segment.end {
+			let start = messages.offset();
+			match messages.next() {
+				Some(message) => part.push((message, start..messages.offset())),
+				// What ends the decoding, e.g. an entry of an unknown table,
+				// is left to the stream decoder.
+				None => {
+					let rest = start..segment.end;
+					part.push((Err(Error::ReadFailure), rest));
+					break;
+				}
+			}
+		}
+
+		if decoded.send(part).is_err() {
+			return;
+		}
+	}
+}
+
+/// Decodes the raw capture in `bytes`, indexed by `index`, on `threads`
+/// threads and passes its messages to `apply` a segment at a time, in the
+/// order of the capture, until it returns false. A thread decodes every
+/// `threads`th segment and keeps up to `AHEAD` of them waiting.
+pub fn decode<'a, F>(
+	bytes: &'a [u8],
+	index: &Index,
+	threads: usize,
+	mut apply: F,
+) where
+	F: FnMut(Vec<Decoded<'a>>) -> bool,
+{
+	let segments = segments(index);
+	let threads = threads.clamp(1, segments.len());
+
+	thread::scope(|scope| {
+		let mut receivers = vec![];
+		for i in 0..threads {
+			let (sender, receiver) = spsc::channel(AHEAD);
+			let mine = segments.iter().skip(i).step_by(threads).cloned();
+			scope.spawn(move || decode_segments(bytes, index, mine, sender));
+			receivers.push(receiver);
+		}
+
+		// The segments come from the threads in turn.
+		for i in (0..threads).cycle() {
+			let part = match receivers[i].recv() {
+				Some(part) => part,
+				None => break,
+			};
+			// Dropping the receivers stops the threads.
+			if !apply(part) {
+				break;
+			}
+		}
+	});
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::{Producer, Type, Value};
+
+	#[test]
+	fn ordered_segments() {
+		let mut producer = Producer::new(vec![]);
+		let frame = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+		for ts in 0..10_000 {
+			if ts == 5000 {
+				producer.table("log", &[("level", Type::Int)]).unwrap();
+			}
+			producer.entry(frame, &[Value::Int(ts)]).unwrap();
+		}
+		let bytes = producer.into_inner();
+		let index = Index::build(&bytes);
+
+		let mut ts = vec![];
+		let mut parts = 0;
+		decode(&bytes, &index, 4, |part| {
+			parts += 1;
+			for (message, range) in part {
+				assert!(range.start < range.end);
+				if let Ok(Message::Entry { entry, .. }) = message {
+					ts.extend(entry.values().filter_map(|v| v.as_f64()));
+				}
+			}
+			true
+		});
+		assert_eq!(parts, 4);
+		assert_eq!(ts, (0..10_000).map(f64::from).collect::<Vec<_>>());
+
+		let mut parts = 0;
+		decode(&bytes, &index, 2, |_| {
+			parts += 1;
+			false
+		});
+		assert_eq!(parts, 1);
+	}
+}
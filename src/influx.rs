@@ -0,0 +1,167 @@
+use crate::json::{self, Translator};
+use serde_json::{Map, Number, Value};
+use std::io::Read;
+
+//---------------------------------------------------------------------------
+/// Translates InfluxDB line protocol into protocol messages. Measurements
+/// become tables, tags string columns and fields columns typed after their
+/// first line. Timestamps are dropped, rows carry no time of their own.
+pub fn translator<R: Read>(reader: R) -> Translator<R> {
+	Translator::with_parser(reader, parse_line, "Influx")
+}
+
+/// `measurement,tag=value field=1i,other="text" 1465839830100400200` as an
+/// `Encoder` entry.
+fn parse_line(line: &[u8]) -> Result<Option<Value>, String> {
+	let line = std::str::from_utf8(line).map_err(|e| e.to_string())?;
+	let line = line.trim();
+	if line.starts_with('#') {
+		return Ok(None);
+	}
+
+	let sections = split(line, ' ');
+	let (key, fields) = match sections.as_slice() {
+		[key, fields] | [key, fields, _] => (key, fields),
+		_ => return Err(String::from("Expected a measurement and fields")),
+	};
+
+	let mut tags = split(key, ',').into_iter();
+	let table = identifier(&unescape(tags.next().unwrap_or_default()));
+
+	let mut columns = Map::new();
+	for tag in tags {
+		let (name, value) = pair(tag)?;
+		columns.insert(name, Value::from(unescape(value)));
+	}
+
+	for field in split(fields, ',') {
+		let (name, value) = pair(field)?;
+		if columns.contains_key(&name) {
+			return Err(format!("{} is both a tag and a field", name));
+		}
+		columns.insert(name, field_value(value)?);
+	}
+
+	Ok(Some(json::entry(&table, columns)))
+}
+
+/// Splits at `sep` outside of escapes and quoted strings.
+fn split(s: &str, sep: char) -> Vec<&str> {
+	let mut parts = vec![];
+	let (mut start, mut escaped, mut quoted) = (0, false, false);
+
+	for (i, c) in s.char_indices() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' => escaped = true,
+			'"' => quoted = !quoted,
+			_ if c == sep && !quoted => {
+				if i > start {
+					parts.push(&s[start..i]);
+				}
+				start = i + 1;
+			}
+			_ => {}
+		}
+	}
+
+	if start < s.len() {
+		parts.push(&s[start..]);
+	}
+	parts
+}
+
+fn unescape(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut chars = s.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => out.extend(chars.next()),
+			_ => out.push(c),
+		}
+	}
+	out
+}
+
+/// Column names are used in SQL as they are.
+fn identifier(s: &str) -> String {
+	let mut out: String = s
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect();
+
+	if !out.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+		out.insert(0, '_');
+	}
+	out
+}
+
+/// Splits `key=value`, the key made an identifier.
+fn pair(s: &str) -> Result<(String, &str), String> {
+	let parts = split(s, '=');
+	match parts.as_slice() {
+		[key, _] => {
+			let value = &s[key.len() + 1..];
+			Ok((identifier(&unescape(key)), value))
+		}
+		_ => Err(format!("Expected key=value, got {}", s)),
+	}
+}
+
+fn field_value(s: &str) -> Result<Value, String> {
+	if let Some(text) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+		return Ok(Value::from(unescape(text)));
+	}
+
+	let bad = || format!("Bad field value {}", s);
+	match s {
+		"t" | "T" | "true" | "True" | "TRUE" => Ok(Value::from(true)),
+		"f" | "F" | "false" | "False" | "FALSE" => Ok(Value::from(false)),
+		_ if s.ends_with('i') => {
+			let v: i64 = s[..s.len() - 1].parse().map_err(|_| bad())?;
+			Ok(Value::from(v))
+		}
+		_ if s.ends_with('u') => {
+			let v: u64 = s[..s.len() - 1].parse().map_err(|_| bad())?;
+			Ok(Value::from(v))
+		}
+		_ => {
+			let v: f64 = s.parse().map_err(|_| bad())?;
+			Number::from_f64(v).map(Value::Number).ok_or_else(bad)
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_lines() {
+		let line = br#"disk.io,host=a\ b,dev=sda reads=5i,util=0.5,ok=t,note="x \"y\"" 1465839830100400200"#;
+		let entry = parse_line(line).unwrap().unwrap();
+		assert_eq!(
+			entry,
+			serde_json::json!({
+				"table": "disk_io",
+				"fields": {
+					"host": "a b",
+					"dev": "sda",
+					"reads": 5,
+					"util": 0.5,
+					"ok": true,
+					"note": "x \"y\"",
+				},
+			})
+		);
+
+		// Floats stay floats even without a fraction.
+		let entry = parse_line(b"cpu load=1").unwrap().unwrap();
+		assert!(entry["fields"]["load"].is_f64());
+
+		assert_eq!(parse_line(b"# comment").unwrap(), None);
+		assert!(parse_line(b"cpu").is_err());
+		assert!(parse_line(b"cpu load=1x").is_err());
+	}
+}
@@ -0,0 +1,363 @@
+use crate::http::{self, Request, Response};
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+#[cfg(feature = "udp")]
+use std::net::UdpSocket;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Request bodies waiting for the daemon, senders block once it is full.
+const QUEUE: usize = 64;
+/// How long a read waits for the next body, the daemon polls its control
+/// socket and bounds in between.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Bytes buffered by a `Writer` before it queues them on its own.
+const CHUNK: usize = 64 * 1024;
+/// Largest UDP payload.
+#[cfg(feature = "udp")]
+const MAX_DATAGRAM: usize = 65536;
+
+//---------------------------------------------------------------------------
+/// Payload received from a message broker.
+pub struct Message {
+	pub topic: String,
+	pub payload: Vec<u8>,
+}
+
+/// Bytes of the bodies waiting in a channel, shared by its ends.
+#[derive(Default)]
+struct Queued {
+	pending: Mutex<Pending>,
+	/// Notified as the bodies are read.
+	drained: Condvar,
+}
+
+#[derive(Default)]
+struct Pending {
+	bytes: usize,
+	/// Cap on `bytes`, see `Bodies::limit`.
+	limit: Option<usize>,
+	/// Whether the reader is gone, nothing waits for room then.
+	closed: bool,
+	/// Whether the last offered body was refused, reported only once.
+	refusing: bool,
+}
+
+impl Pending {
+	/// Whether a body of `len` bytes may be queued, a single one always.
+	fn fits(&self, len: usize) -> bool {
+		self.closed
+			|| self.bytes == 0
+			|| self.limit.is_none_or(|l| self.bytes + len <= l)
+	}
+}
+
+/// Queues bodies for `Bodies`.
+#[derive(Clone)]
+pub struct Sender {
+	sender: mpsc::SyncSender<Vec<u8>>,
+	queued: Arc<Queued>,
+}
+
+impl Sender {
+	/// Queues a body, waiting while the queue is full or over its memory
+	/// cap. Parts of a stream are sent this way, dropping one would desync
+	/// the stream.
+	pub fn send(&self, body: Vec<u8>) -> Result<(), mpsc::SendError<Vec<u8>>> {
+		let mut pending = self.queued.pending.lock().unwrap();
+		while !pending.fits(body.len()) {
+			pending = self.queued.drained.wait(pending).unwrap();
+		}
+		pending.bytes += body.len();
+		drop(pending);
+		self.sender.send(body)
+	}
+
+	/// Queues a body holding whole messages, returning false without
+	/// queueing it when the queue is over its memory cap. Waits while the
+	/// queue is full.
+	pub fn offer(
+		&self,
+		body: Vec<u8>,
+	) -> Result<bool, mpsc::SendError<Vec<u8>>> {
+		let mut pending = self.queued.pending.lock().unwrap();
+		if !pending.fits(body.len()) {
+			if !mem::replace(&mut pending.refusing, true) {
+				println!(
+					"Received bodies over the {} byte memory cap, refusing them",
+					pending.limit.unwrap_or(0)
+				);
+			}
+			return Ok(false);
+		}
+		pending.bytes += body.len();
+		pending.refusing = false;
+		drop(pending);
+		self.sender.send(body).map(|_| true)
+	}
+}
+
+/// Reads the received bodies back to back, as if they arrived on one
+/// connection. An empty body ends the session, the read returns 0.
+pub struct Bodies {
+	receiver: mpsc::Receiver<Vec<u8>>,
+	queued: Arc<Queued>,
+	body: Vec<u8>,
+	pos: usize,
+}
+
+impl Bodies {
+	fn new(receiver: mpsc::Receiver<Vec<u8>>, queued: Arc<Queued>) -> Bodies {
+		Bodies {
+			receiver,
+			queued,
+			body: vec![],
+			pos: 0,
+		}
+	}
+
+	/// Caps the bytes of the bodies waiting to be read, see
+	/// `MemoryConfig::queue`.
+	pub fn limit(&self, bytes: Option<usize>) {
+		self.queued.pending.lock().unwrap().limit = bytes;
+		self.queued.drained.notify_all();
+	}
+
+	fn recv(
+		&self,
+		timeout: Duration,
+	) -> Result<Vec<u8>, mpsc::RecvTimeoutError> {
+		let body = self.receiver.recv_timeout(timeout)?;
+		self.queued.pending.lock().unwrap().bytes -= body.len();
+		self.queued.drained.notify_all();
+		Ok(body)
+	}
+
+	/// Discards the rest of the current session.
+	pub fn skip_session(&mut self) {
+		self.pos = self.body.len();
+		loop {
+			match self.recv(POLL_INTERVAL) {
+				Ok(body) if body.is_empty() => break,
+				Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => {}
+				Err(mpsc::RecvTimeoutError::Disconnected) => break,
+			}
+		}
+	}
+}
+
+impl Drop for Bodies {
+	fn drop(&mut self) {
+		self.queued.pending.lock().unwrap().closed = true;
+		self.queued.drained.notify_all();
+	}
+}
+
+impl Read for Bodies {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.pos == self.body.len() {
+			match self.recv(POLL_INTERVAL) {
+				Ok(body) if body.is_empty() => return Ok(0),
+				Ok(body) => {
+					self.body = body;
+					self.pos = 0;
+				}
+				Err(mpsc::RecvTimeoutError::Timeout) => {
+					return Err(io::ErrorKind::TimedOut.into())
+				}
+				Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+			}
+		}
+
+		let n = buf.len().min(self.body.len() - self.pos);
+		buf[..n].copy_from_slice(&self.body[self.pos..self.pos + n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+/// Bodies sent by other transports.
+pub fn channel() -> (Sender, Bodies) {
+	let (sender, receiver) = mpsc::sync_channel(QUEUE);
+	let queued = Arc::new(Queued::default());
+	let sender = Sender {
+		sender,
+		queued: queued.clone(),
+	};
+	(sender, Bodies::new(receiver, queued))
+}
+
+/// Queues protocol messages for a daemon in the same process, flushes
+/// queue the buffered bytes as one body. Dropping it ends the session.
+pub struct Writer {
+	sender: Sender,
+	buf: Vec<u8>,
+}
+
+impl Write for Writer {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.buf.extend_from_slice(buf);
+		if self.buf.len() >= CHUNK {
+			self.flush()?;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		if self.buf.is_empty() {
+			return Ok(());
+		}
+
+		let chunk = mem::replace(&mut self.buf, Vec::with_capacity(CHUNK));
+		self.sender
+			.send(chunk)
+			.map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+	}
+}
+
+impl Drop for Writer {
+	fn drop(&mut self) {
+		if self.flush().is_ok() {
+			let _ = self.sender.send(vec![]);
+		}
+	}
+}
+
+/// In-process transport, the daemon reads what the writer produces without
+/// a socket in between.
+pub fn pipe() -> (Writer, Bodies) {
+	let (sender, bodies) = channel();
+	let writer = Writer {
+		sender,
+		buf: Vec::with_capacity(CHUNK),
+	};
+	(writer, bodies)
+}
+
+/// Reads the datagrams received on a socket back to back.
+#[cfg(feature = "udp")]
+pub struct Datagrams {
+	socket: UdpSocket,
+	/// End each datagram with a newline unless it has one.
+	lines: bool,
+	datagram: Vec<u8>,
+	pos: usize,
+}
+
+/// Receives datagrams holding whole messages or lines on `addr`.
+#[cfg(feature = "udp")]
+pub fn bind_udp(addr: &str, lines: bool) -> io::Result<Datagrams> {
+	let socket = UdpSocket::bind(addr)?;
+	socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+	Ok(Datagrams {
+		socket,
+		lines,
+		datagram: vec![0; MAX_DATAGRAM],
+		pos: MAX_DATAGRAM,
+	})
+}
+
+#[cfg(feature = "udp")]
+impl Read for Datagrams {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.pos == self.datagram.len() {
+			self.datagram.resize(MAX_DATAGRAM, 0);
+			let n = match self.socket.recv(&mut self.datagram) {
+				Ok(n) => n,
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+					return Err(io::ErrorKind::TimedOut.into())
+				}
+				Err(e) => return Err(e),
+			};
+
+			self.datagram.truncate(n);
+			if self.lines && !self.datagram.ends_with(b"\n") {
+				self.datagram.push(b'\n');
+			}
+			self.pos = 0;
+		}
+
+		let n = buf.len().min(self.datagram.len() - self.pos);
+		buf[..n].copy_from_slice(&self.datagram[self.pos..self.pos + n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Accepts `POST /ingest` requests on `addr` whose bodies hold whole
+/// protocol messages.
+pub fn serve(addr: &str) -> io::Result<Bodies> {
+	let (sender, bodies) = channel();
+
+	http::serve(addr, move |req| handle(req, &sender))?;
+	Ok(bodies)
+}
+
+fn handle(req: &Request, sender: &Sender) -> Response {
+	match (req.method.as_str(), req.path.as_str()) {
+		("POST", "/ingest") => {
+			if req.body.is_empty() {
+				return Response::text(400, "Empty body");
+			}
+
+			match sender.offer(req.body.clone()) {
+				Ok(true) => Response::new(204, "text/plain", vec![]),
+				Ok(false) => {
+					Response::text(503, "The capture is not keeping up")
+				}
+				Err(_) => Response::text(500, "The capture has finished"),
+			}
+		}
+		_ => Response::text(404, "Not found"),
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn read_bodies() {
+		let (sender, mut bodies) = channel();
+
+		sender.send(vec![1, 2, 3]).unwrap();
+		sender.send(vec![4]).unwrap();
+		sender.send(vec![]).unwrap();
+
+		let mut buf = [0; 4];
+		bodies.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3, 4]);
+		assert_eq!(bodies.read(&mut buf).unwrap(), 0);
+
+		let timeout = bodies.read(&mut buf).unwrap_err();
+		assert_eq!(timeout.kind(), io::ErrorKind::TimedOut);
+
+		drop(sender);
+		assert_eq!(bodies.read(&mut buf).unwrap(), 0);
+	}
+
+	#[test]
+	fn queue_memory_cap() {
+		let (sender, mut bodies) = channel();
+		bodies.limit(Some(4));
+
+		assert!(sender.offer(vec![1, 2, 3]).unwrap());
+		assert!(!sender.offer(vec![4, 5]).unwrap());
+
+		// Waits for the first body to be read.
+		let stream = sender.clone();
+		let waiting = std::thread::spawn(move || stream.send(vec![6, 7]));
+		let mut buf = [0; 5];
+		bodies.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3, 6, 7]);
+		waiting.join().unwrap().unwrap();
+
+		// A single body is queued whatever its size.
+		assert!(sender.offer(vec![0; 8]).unwrap());
+	}
+}
@@ -0,0 +1,204 @@
+use crate::wire::{string, type_name, Error, FieldType, Message, Messages};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+//---------------------------------------------------------------------------
+/// Messages and errors found by `inspect`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+	pub messages: usize,
+	pub errors: usize,
+	/// Bytes after the last decoded message, e.g. of a truncated one.
+	pub trailing: usize,
+}
+
+/// Quoted text of string `id`, see `wire::string`.
+fn quoted(strings: &HashMap<u32, String>, id: u32) -> String {
+	match strings.get(&id) {
+		Some(s) => format!("{:?}", s),
+		None => string(strings, id),
+	}
+}
+
+/// Writes a line per message of the stream in `bytes` to `out`: its offset,
+/// type, length and decoded contents, along with the decoding errors. With
+/// `hex` the bytes of every message follow it.
+pub fn inspect<W: Write>(
+	bytes: &[u8],
+	out: &mut W,
+	hex: bool,
+) -> io::Result<Summary> {
+	let mut summary = Summary::default();
+	let mut strings = HashMap::new();
+	// Table names by uid.
+	let mut tables = HashMap::new();
+
+	let mut messages = Messages::new(bytes);
+	loop {
+		let offset = messages.offset();
+		let message = match messages.next() {
+			Some(m) => m,
+			None => break,
+		};
+		let len = messages.offset() - offset;
+
+		let (kind, contents) = match message {
+			Ok(Message::String { id, value }) => {
+				let value = String::from_utf8_lossy(value).into_owned();
+				let contents = format!("#{} = {:?}", id, value);
+				strings.insert(id, value);
+				("string", contents)
+			}
+			Ok(Message::Table { uid, desc }) => {
+				let table = string(&strings, desc.name());
+				let columns: Vec<String> = desc
+					.columns()
+					.map(|(c, t)| {
+						format!("{}: {}", string(&strings, c), type_name(t))
+					})
+					.collect();
+				let threaded = if desc.threaded() { " threaded" } else { "" };
+				let contents = format!(
+					"#{} {} ({}){}",
+					uid,
+					table,
+					columns.join(", "),
+					threaded
+				);
+				tables.insert(uid, table);
+				("table", contents)
+			}
+			Ok(Message::Entry { uid, entry }) => {
+				let values: Vec<String> = entry
+					.values()
+					.map(|v| match *v {
+						FieldType::Str(id) => quoted(&strings, id),
+						FieldType::Float(v) => (v as f32).to_string(),
+						v => v.value_string(),
+					})
+					.collect();
+				let table = tables.get(&uid).cloned().unwrap_or_default();
+				("entry", format!("{} [{}]", table, values.join(", ")))
+			}
+			Ok(Message::Marker { start, label }) => {
+				let action = if start { "start" } else { "stop" };
+				let label = label.map(|l| format!(" {}", quoted(&strings, l)));
+				("marker", format!("{}{}", action, label.unwrap_or_default()))
+			}
+			Ok(Message::Pause { paused, reason }) => {
+				let action = if paused { "pause" } else { "resume" };
+				let reason = reason.map(|r| quoted(&strings, r));
+				(action, reason.unwrap_or_default())
+			}
+			Ok(Message::Thread { name }) => ("thread", quoted(&strings, name)),
+			Ok(Message::Source { name }) => ("source", quoted(&strings, name)),
+			Ok(Message::Attachment { name, data }) => {
+				let name = quoted(&strings, name);
+				("attach", format!("{} of {} bytes", name, data.len()))
+			}
+			Ok(Message::Entity { id, name, kind }) => {
+				let name = quoted(&strings, name);
+				let contents =
+					format!("#{} {} {}", id, name, quoted(&strings, kind));
+				("entity", contents)
+			}
+			Ok(Message::ScopeBegin {
+				id,
+				parent,
+				name,
+				ts,
+			}) => {
+				let parent = parent.map(|p| format!(" in #{}", p));
+				let name = quoted(&strings, name);
+				let parent = parent.unwrap_or_default();
+				("begin", format!("#{} {}{} at {}", id, name, parent, ts))
+			}
+			Ok(Message::Counter { uid, delta }) => {
+				let table = tables.get(&uid).cloned();
+				let table = table.unwrap_or_else(|| format!("#{}", uid));
+				("count", format!("{} +{}", table, delta))
+			}
+			Ok(Message::ScopeEnd { id, ts }) => {
+				("end", format!("#{} at {}", id, ts))
+			}
+			Err(e) => {
+				summary.errors += 1;
+				let error = match e {
+					Error::ReadFailure => String::from("Truncated message"),
+					e => e.to_string(),
+				};
+				("error", error)
+			}
+		};
+		if kind != "error" {
+			summary.messages += 1;
+		}
+
+		writeln!(out, "{:08}  {:<6} {:>5}  {}", offset, kind, len, contents)?;
+		if hex {
+			for line in bytes[offset..offset + len].chunks(16) {
+				let line: Vec<String> =
+					line.iter().map(|b| format!("{:02x}", b)).collect();
+				writeln!(out, "{:16}{}", "", line.join(" "))?;
+			}
+		}
+	}
+
+	summary.trailing = bytes.len() - messages.offset();
+	if summary.trailing > 0 {
+		writeln!(
+			out,
+			"{:08}  {} bytes not decoded",
+			messages.offset(),
+			summary.trailing
+		)?;
+	}
+	Ok(summary)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::{Producer, Type, Value};
+
+	#[test]
+	fn annotated_stream() {
+		let mut producer = Producer::new(vec![]);
+		let uid = producer
+			.table("frame", &[("ts", Type::Int), ("scene", Type::Str)])
+			.unwrap();
+		let scene = producer.string("menu").unwrap();
+		producer.marker(true, Some("warmup")).unwrap();
+		producer
+			.entry(uid, &[Value::Int(7), Value::Str(scene)])
+			.unwrap();
+
+		// Junk before the entry and a truncated entry after it.
+		let mut bytes = producer.into_inner();
+		let entry = bytes.len() - 17;
+		let truncated = bytes[entry..entry + 9].to_vec();
+		bytes.splice(entry..entry, b"junk".iter().cloned());
+		bytes.extend_from_slice(&truncated);
+
+		let mut out = vec![];
+		let summary = inspect(&bytes, &mut out, false).unwrap();
+		let out = String::from_utf8(out).unwrap();
+		let lines: Vec<&str> = out.lines().collect();
+
+		assert_eq!(
+			lines[3],
+			"00000051  table     24  #0 frame (ts: int, scene: str)"
+		);
+		assert_eq!(lines[6], "00000111  marker    10  start \"warmup\"");
+		assert!(
+			lines[7].ends_with("error      4  Fatal: Not a protocol header")
+		);
+		assert_eq!(lines[8], "00000125  entry     17  frame [7, \"menu\"]");
+		assert_eq!(lines[9], "00000142  error      9  Truncated message");
+		assert_eq!(lines.len(), 10);
+		assert_eq!(summary.messages, 8);
+		assert_eq!(summary.errors, 2);
+		assert_eq!(summary.trailing, 0);
+	}
+}
@@ -0,0 +1,273 @@
+use crate::producer::{Producer, Type, Value};
+use serde_json::Map;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::mem;
+
+//---------------------------------------------------------------------------
+/// Bytes read from the producer at once.
+const CHUNK: usize = 4096;
+
+struct Table {
+	uid: u32,
+	columns: Vec<(String, Type)>,
+}
+
+//---------------------------------------------------------------------------
+/// Encodes self-describing entries like
+/// `{"table": "frame", "fields": {"ts": 16, "frame_ms": 16.6}}` as protocol
+/// messages. The first entry of a table declares its columns in name order,
+/// typed after its values: integers fitting 32 bits are ints, other numbers
+/// floats. Later entries of the table need the same fields.
+pub(crate) struct Encoder {
+	producer: Producer<Vec<u8>>,
+	tables: HashMap<String, Table>,
+}
+
+impl Encoder {
+	pub fn new() -> Encoder {
+		Encoder {
+			producer: Producer::new(vec![]),
+			tables: HashMap::new(),
+		}
+	}
+
+	/// Moves the encoded messages to `out`, which has to be empty.
+	pub fn take(&mut self, out: &mut Vec<u8>) {
+		mem::swap(out, self.producer.get_mut());
+	}
+
+	pub fn encode(&mut self, entry: &serde_json::Value) -> Result<(), String> {
+		let table = match entry.get("table") {
+			Some(serde_json::Value::String(t)) => t,
+			_ => return Err(String::from("Expected a \"table\" string")),
+		};
+		let fields = match entry.get("fields") {
+			Some(serde_json::Value::Object(f)) if !f.is_empty() => f,
+			_ => return Err(String::from("Expected a \"fields\" object")),
+		};
+
+		if !self.tables.contains_key(table) {
+			let columns = infer_columns(fields)?;
+			let named: Vec<(&str, Type)> =
+				columns.iter().map(|(c, t)| (c.as_str(), *t)).collect();
+			let uid = self
+				.producer
+				.table(table, &named)
+				.map_err(|e| e.to_string())?;
+			self.tables.insert(table.clone(), Table { uid, columns });
+		}
+		let table = &self.tables[table];
+
+		if fields.len() != table.columns.len() {
+			return Err(format!("Expected {} fields", table.columns.len()));
+		}
+
+		let mut values = vec![];
+		for (column, data_type) in &table.columns {
+			let value = fields
+				.get(column)
+				.ok_or_else(|| format!("Missing field {}", column))?;
+
+			values.push(match (data_type, value) {
+				(Type::Int, serde_json::Value::Number(n)) => n
+					.as_u64()
+					.filter(|&v| v <= u32::MAX as u64)
+					.map(|v| Value::Int(v as u32))
+					.ok_or_else(|| format!("{} is not a 32 bit int", column))?,
+				(Type::Float, serde_json::Value::Number(n)) => {
+					Value::Float(n.as_f64().unwrap_or(f64::NAN) as f32)
+				}
+				(Type::Bool, serde_json::Value::Bool(b)) => Value::Bool(*b),
+				(Type::Str, serde_json::Value::String(s)) => Value::Str(
+					self.producer.string(s).map_err(|e| e.to_string())?,
+				),
+				_ => {
+					return Err(format!("{} is not a {:?}", column, data_type))
+				}
+			});
+		}
+
+		let uid = table.uid;
+		self.producer.entry(uid, &values).map_err(|e| e.to_string())
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Parses a line into an entry for the `Encoder`, `None` for lines without
+/// one.
+pub(crate) type Parser = fn(&[u8]) -> Result<Option<serde_json::Value>, String>;
+
+fn parse_json(line: &[u8]) -> Result<Option<serde_json::Value>, String> {
+	serde_json::from_slice(line)
+		.map(Some)
+		.map_err(|e| e.to_string())
+}
+
+/// Translates newline-delimited JSON entries into protocol messages, see
+/// `Encoder`.
+pub struct Translator<R: Read> {
+	reader: R,
+	parse: Parser,
+	/// Name of the line format in reports.
+	format: &'static str,
+	/// Input not yet translated, up to a partial line.
+	input: Vec<u8>,
+	encoder: Encoder,
+	output: Vec<u8>,
+	pos: usize,
+	line: u64,
+}
+
+impl<R: Read> Translator<R> {
+	pub fn new(reader: R) -> Translator<R> {
+		Translator::with_parser(reader, parse_json, "JSON")
+	}
+
+	/// Translator of another line format.
+	pub(crate) fn with_parser(
+		reader: R,
+		parse: Parser,
+		format: &'static str,
+	) -> Translator<R> {
+		Translator {
+			reader,
+			parse,
+			format,
+			input: vec![],
+			encoder: Encoder::new(),
+			output: vec![],
+			pos: 0,
+			line: 0,
+		}
+	}
+
+	/// Encodes the entry of a line, bad lines are reported and skipped.
+	fn translate(&mut self, line: &[u8]) {
+		self.line += 1;
+		if line.iter().all(u8::is_ascii_whitespace) {
+			return;
+		}
+
+		let encoded = (self.parse)(line).and_then(|entry| match entry {
+			Some(entry) => self.encoder.encode(&entry),
+			None => Ok(()),
+		});
+		if let Err(e) = encoded {
+			println!("{} line {}: {}", self.format, self.line, e);
+		}
+		self.encoder.take(&mut self.output);
+	}
+}
+
+/// `Encoder` entry of a row of `table`.
+pub(crate) fn entry(
+	table: &str,
+	fields: Map<String, serde_json::Value>,
+) -> serde_json::Value {
+	let mut entry = Map::new();
+	entry.insert(String::from("table"), serde_json::Value::from(table));
+	entry.insert(String::from("fields"), serde_json::Value::Object(fields));
+	serde_json::Value::Object(entry)
+}
+
+/// Value of a float column, even when integral. NaN and infinities are
+/// stored as 0.
+#[cfg(any(feature = "udp", feature = "otlp"))]
+pub(crate) fn float(v: f64) -> serde_json::Value {
+	let v = serde_json::Number::from_f64(v)
+		.unwrap_or_else(|| serde_json::Number::from_f64(0.0).unwrap());
+	serde_json::Value::Number(v)
+}
+
+/// Columns of a table in name order, typed after the first entry.
+fn infer_columns(
+	fields: &Map<String, serde_json::Value>,
+) -> Result<Vec<(String, Type)>, String> {
+	fields
+		.iter()
+		.map(|(name, value)| {
+			let data_type = match value {
+				serde_json::Value::Number(n)
+					if n.as_u64().is_some_and(|v| v <= u32::MAX as u64) =>
+				{
+					Type::Int
+				}
+				serde_json::Value::Number(_) => Type::Float,
+				serde_json::Value::Bool(_) => Type::Bool,
+				serde_json::Value::String(_) => Type::Str,
+				_ => return Err(format!("{} has no column type", name)),
+			};
+			Ok((name.clone(), data_type))
+		})
+		.collect()
+}
+
+impl<R: Read> Read for Translator<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			if self.pos < self.output.len() {
+				let n = buf.len().min(self.output.len() - self.pos);
+				buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+				self.pos += n;
+				return Ok(n);
+			}
+
+			self.pos = 0;
+			self.output.clear();
+
+			if let Some(end) = self.input.iter().position(|&b| b == b'\n') {
+				let line: Vec<u8> = self.input.drain(..=end).collect();
+				self.translate(&line);
+				continue;
+			}
+
+			let mut chunk = [0; CHUNK];
+			let n = self.reader.read(&mut chunk)?;
+			if n == 0 {
+				// The last line may lack its newline.
+				if self.input.is_empty() {
+					return Ok(0);
+				}
+				let line = mem::take(&mut self.input);
+				self.translate(&line);
+				continue;
+			}
+
+			self.input.extend_from_slice(&chunk[..n]);
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn translate_lines() {
+		let input = concat!(
+			"{\"table\": \"log\", \"fields\": {\"ts\": 7, \"ok\": true}}\n",
+			"not json\n",
+			"{\"table\": \"log\", \"fields\": {\"ts\": 1.5, \"ok\": false}}\n",
+			"{\"table\": \"log\", \"fields\": {\"ts\": 8, \"ok\": false}}",
+		);
+
+		let mut output = vec![];
+		Translator::new(input.as_bytes())
+			.read_to_end(&mut output)
+			.unwrap();
+
+		let mut expected = Producer::new(vec![]);
+		let uid = expected
+			.table("log", &[("ok", Type::Bool), ("ts", Type::Int)])
+			.unwrap();
+		expected
+			.entry(uid, &[Value::Bool(true), Value::Int(7)])
+			.unwrap();
+		expected
+			.entry(uid, &[Value::Bool(false), Value::Int(8)])
+			.unwrap();
+		assert_eq!(output, expected.into_inner());
+	}
+}
@@ -0,0 +1,259 @@
+use crate::config::KafkaConfig;
+use crate::dae::{Entry, FieldType, Strings};
+use crate::sink::{self, Sink};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{BaseRecord, DefaultProducerContext, ThreadedProducer};
+use rdkafka::ClientConfig;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// How long a finished capture waits for the queued messages.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Seed of the CRC-64-AVRO schema fingerprint.
+const AVRO_EMPTY: u64 = 0xc15d_213a_a4d7_a795;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+	Json,
+	/// Avro single object encoding, the schema travels in the
+	/// `avro.schema` header.
+	Avro,
+}
+
+struct Table {
+	name: String,
+	columns: Vec<String>,
+	/// Canonical schema and its fingerprint, known from the first entry.
+	schema: Option<(String, u64)>,
+}
+
+//---------------------------------------------------------------------------
+/// Publishes the entries to a Kafka topic, keyed by their table name.
+pub struct Kafka {
+	producer: ThreadedProducer<DefaultProducerContext>,
+	topic: String,
+	format: Format,
+	tables: Vec<Option<Table>>,
+	/// Whether the last send failed, failures are only reported once.
+	failing: bool,
+}
+
+impl Kafka {
+	pub fn new(config: &KafkaConfig) -> Result<Kafka, String> {
+		let format = match config.format.as_deref() {
+			None | Some("json") => Format::Json,
+			Some("avro") => Format::Avro,
+			Some(f) => return Err(format!("Unknown kafka format: {}", f)),
+		};
+
+		let producer = ClientConfig::new()
+			.set("bootstrap.servers", &config.brokers)
+			.create()
+			.map_err(|e| format!("Kafka: {}", e))?;
+
+		Ok(Kafka {
+			producer,
+			topic: config.topic.clone(),
+			format,
+			tables: vec![],
+			failing: false,
+		})
+	}
+}
+
+impl Sink for Kafka {
+	fn bind(&mut self, uid: u32, table: &str, columns: &[String]) {
+		let uid = uid as usize;
+		if self.tables.len() <= uid {
+			self.tables.resize_with(uid + 1, || None);
+		}
+
+		self.tables[uid] = Some(Table {
+			name: table.to_string(),
+			columns: columns.to_vec(),
+			schema: None,
+		});
+	}
+
+	fn send(
+		&mut self,
+		uid: u32,
+		source: &str,
+		entry: &Entry,
+		strings: &Strings,
+	) -> Result<(), String> {
+		let table = match self.tables.get_mut(uid as usize) {
+			Some(Some(t)) => t,
+			_ => return Ok(()),
+		};
+
+		let headers = OwnedHeaders::new().insert(Header {
+			key: "source",
+			value: Some(source),
+		});
+
+		let result = match self.format {
+			Format::Json => {
+				let payload = sink::json_entry(
+					&table.name,
+					source,
+					&table.columns,
+					entry,
+					strings,
+				);
+				let record = BaseRecord::to(&self.topic)
+					.key(&table.name)
+					.payload(&payload)
+					.headers(headers);
+				self.producer.send(record).map_err(|(e, _)| e)
+			}
+			Format::Avro => {
+				if table.schema.is_none() {
+					let schema =
+						avro_schema(&table.name, &table.columns, entry);
+					let fingerprint = avro_fingerprint(&schema);
+					table.schema = Some((schema, fingerprint));
+				}
+				let (schema, fingerprint) = table.schema.as_ref().unwrap();
+
+				let payload = avro_entry(*fingerprint, entry, strings);
+				let headers = headers.insert(Header {
+					key: "avro.schema",
+					value: Some(schema.as_str()),
+				});
+				let record = BaseRecord::to(&self.topic)
+					.key(&table.name)
+					.payload(&payload)
+					.headers(headers);
+				self.producer.send(record).map_err(|(e, _)| e)
+			}
+		};
+
+		let failed = result.is_err();
+		let report = failed && !self.failing;
+		self.failing = failed;
+
+		match result {
+			Err(e) if report => Err(format!("Kafka publish failed: {}", e)),
+			_ => Ok(()),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), String> {
+		use rdkafka::producer::Producer;
+
+		self.producer
+			.flush(FLUSH_TIMEOUT)
+			.map_err(|e| format!("Kafka flush failed: {}", e))
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Avro names start with a letter or underscore, followed by alphanumerics
+/// and underscores.
+fn avro_name(name: &str) -> String {
+	let mut out: String = name
+		.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+		.collect();
+
+	if !out.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+		out.insert(0, '_');
+	}
+	out
+}
+
+/// Record schema of a table in parsing canonical form, the field types are
+/// taken from an entry.
+fn avro_schema(table: &str, columns: &[String], entry: &Entry) -> String {
+	let fields: Vec<String> = columns
+		.iter()
+		.zip(entry.values())
+		.map(|(c, v)| {
+			let t = match v {
+				FieldType::Int(_) => "long",
+				FieldType::Float(_) => "double",
+				FieldType::Bool(_) => "boolean",
+				FieldType::Str(_) => "string",
+			};
+			format!(r#"{{"name":"{}","type":"{}"}}"#, avro_name(c), t)
+		})
+		.collect();
+
+	format!(
+		r#"{{"name":"sdd.{}","type":"record","fields":[{}]}}"#,
+		avro_name(table),
+		fields.join(",")
+	)
+}
+
+/// CRC-64-AVRO (Rabin) fingerprint of a canonical schema.
+fn avro_fingerprint(schema: &str) -> u64 {
+	let mut table = [0u64; 256];
+	for (i, entry) in table.iter_mut().enumerate() {
+		let mut fp = i as u64;
+		for _ in 0..8 {
+			fp = (fp >> 1) ^ (AVRO_EMPTY & (fp & 1).wrapping_neg());
+		}
+		*entry = fp;
+	}
+
+	schema.bytes().fold(AVRO_EMPTY, |fp, b| {
+		(fp >> 8) ^ table[((fp ^ b as u64) & 0xff) as usize]
+	})
+}
+
+fn avro_long(out: &mut Vec<u8>, v: i64) {
+	let mut n = ((v << 1) ^ (v >> 63)) as u64;
+	while n >= 0x80 {
+		out.push(n as u8 | 0x80);
+		n >>= 7;
+	}
+	out.push(n as u8);
+}
+
+/// Single object encoding of an entry: marker, fingerprint and the record.
+fn avro_entry(fingerprint: u64, entry: &Entry, strings: &Strings) -> Vec<u8> {
+	let mut out = vec![0xC3, 0x01];
+	out.extend_from_slice(&fingerprint.to_le_bytes());
+
+	for v in entry.values() {
+		match *v {
+			FieldType::Int(v) => avro_long(&mut out, v as i64),
+			FieldType::Float(v) => out.extend_from_slice(&v.to_le_bytes()),
+			FieldType::Bool(v) => out.push(v as u8),
+			FieldType::Str(id) => {
+				let text = match strings.resolve(id) {
+					Some(s) => s.to_string(),
+					None => format!("#{}", id),
+				};
+				avro_long(&mut out, text.len() as i64);
+				out.extend_from_slice(text.as_bytes());
+			}
+		}
+	}
+
+	out
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn avro_encoding() {
+		// Fingerprint from the Avro specification test vectors.
+		assert_eq!(avro_fingerprint(r#""int""#), 0x7275_d51a_3f39_5c8f);
+
+		let mut out = vec![];
+		for v in [0, -1, 1, 64] {
+			avro_long(&mut out, v);
+		}
+		assert_eq!(out, [0x00, 0x01, 0x02, 0x80, 0x01]);
+
+		assert_eq!(avro_name("render.frame-time"), "render_frame_time");
+		assert_eq!(avro_name("3d"), "_3d");
+	}
+}
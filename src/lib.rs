@@ -1,504 +1,3830 @@
+#[cfg(feature = "sqlite")]
+pub mod alert;
+#[cfg(feature = "sqlite")]
+pub mod api;
+#[cfg(feature = "s3")]
+pub mod archive;
+#[cfg(feature = "sqlite")]
+pub mod auth;
+#[cfg(feature = "sqlite")]
+pub mod bench;
+pub mod buffered;
+#[cfg(feature = "sqlite")]
+pub mod cipher;
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "sqlite")]
+pub mod commit;
+pub mod compact;
+#[cfg(feature = "sqlite")]
+pub mod config;
+#[cfg(feature = "sqlite")]
+pub mod conformance;
+#[cfg(feature = "sqlite")]
+pub mod ctl;
+#[cfg(feature = "sqlite")]
+pub mod dedupe;
+#[cfg(feature = "mdns")]
+pub mod discover;
+#[cfg(feature = "sqlite")]
+pub mod doctor;
+#[cfg(feature = "sqlite")]
+pub mod export;
+#[cfg(feature = "sqlite")]
+pub mod finalize;
+#[cfg(feature = "sqlite")]
+pub mod grafana;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod http;
+pub mod import;
+pub mod influx;
+pub mod ingest;
+pub mod inspect;
+pub mod json;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "sqlite")]
+pub mod limit;
+pub mod merge;
+#[cfg(feature = "tui")]
+pub mod monitor;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod msgpack;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "sqlite")]
+pub mod notify;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod producer;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod raw;
+#[cfg(feature = "sqlite")]
+pub mod replay;
+#[cfg(feature = "sqlite")]
+pub mod report;
+#[cfg(feature = "sqlite")]
+pub mod rollup;
+pub mod schema;
+#[cfg(feature = "sqlite")]
+pub mod search;
+#[cfg(feature = "serial")]
+pub mod serial;
+#[cfg(feature = "shm")]
+pub mod shm;
+#[cfg(feature = "sqlite")]
+pub mod sink;
+pub mod spsc;
+#[cfg(feature = "sqlite")]
+pub mod stats;
+#[cfg(feature = "udp")]
+pub mod statsd;
+#[cfg(feature = "sqlite")]
+pub mod subscribe;
+#[cfg(feature = "sqlite")]
+pub mod sync;
+pub mod uring;
+#[cfg(feature = "sqlite")]
+pub mod validate;
+pub mod value;
+#[cfg(feature = "sqlite")]
+pub mod web;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+pub mod wire;
+#[cfg(feature = "zmq")]
+pub mod zmq;
+pub mod zst;
+
+#[cfg(feature = "sqlite")]
 pub mod dae {
+	use crate::alert::Alerts;
+	use crate::commit::{Batch, Pacing};
+	use crate::config::{glob_match, DropConfig, NamingConfig, SourceConfig};
+	use crate::ctl;
+	use crate::dedupe::Dedupe;
+	use crate::finalize::{finalize, FinalizeConfig};
+	use crate::import::{self, Decoded};
+	use crate::influx;
+	use crate::json;
+	use crate::limit::{Limits, Quota};
+	use crate::merge::Merge;
+	use crate::msgpack;
+	use crate::notify::Notify;
+	use crate::producer::Type;
+	use crate::raw;
+	use crate::rollup::Rollups;
+	use crate::schema::{self, Schema};
+	use crate::search::Search;
+	use crate::sink::Sink;
+	use crate::spsc;
+	use crate::subscribe::Subscription;
+	use crate::validate::{Policy, Validations};
+	use crate::value::{Row, Value};
+	pub use crate::wire::FieldType;
+	pub use crate::wire::{decode_descriptor, decode_entry, Entry, Error};
+	use crate::wire::{
+		entity_column, Descriptor, FieldDescriptor, Message, MsgType,
+	};
+	use crate::zst;
 	use rusqlite;
-	use std::convert::TryInto;
+	use rusqlite::OptionalExtension;
+	use sdd_core::PROTOCOL;
+	use serde::de::DeserializeOwned;
+	use std::collections::{HashMap, VecDeque};
 	use std::fmt;
 	use std::fmt::Display;
 	use std::fmt::Write;
 	use std::fs;
 	use std::io::BufReader;
+	use std::io::ErrorKind;
 	use std::io::Read;
-	use std::net::TcpStream;
+	use std::io::Write as _;
+	use std::mem;
+	use std::net::{Shutdown, TcpStream};
+	use std::ops::{Deref, DerefMut};
+	use std::path::Path;
+	use std::sync::atomic::{AtomicBool, Ordering};
+	use std::sync::mpsc;
+	use std::sync::{Arc, Mutex};
+	use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 	use std::{thread, time};
 
 	//---------------------------------------------------------------------------
-	const PROTOCOL: u32 = 0xFEEDBEEF;
-
-	//---------------------------------------------------------------------------
-	enum MsgType {
-		Invalid = 0,
-		Str = 1,
-		Entry = 2,
-		Desc = 3,
-	}
+	/// Cached insert statements per database, one per table.
+	const STATEMENT_CACHE: usize = 256;
 
-	impl From<u8> for MsgType {
-		fn from(t: u8) -> Self {
-			match t {
-				1 => MsgType::Str,
-				2 => MsgType::Entry,
-				3 => MsgType::Desc,
-				_ => MsgType::Invalid,
-			}
-		}
+	fn unix_now() -> u64 {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0)
 	}
 
 	//---------------------------------------------------------------------------
-	#[derive(Debug, Copy, Clone)]
-	enum FieldType {
-		Int(u32),
-		Float(f64),
-		Bool(bool),
-		Str(u32),
-	}
-
-	impl PartialEq for FieldType {
-		fn eq(&self, other: &Self) -> bool {
-			match (self, other) {
-				(FieldType::Int(..), FieldType::Int(..)) => true,
-				(FieldType::Float(..), FieldType::Float(..)) => true,
-				(FieldType::Bool(..), FieldType::Bool(..)) => true,
-				(FieldType::Str(..), FieldType::Str(..)) => true,
-				_ => false,
+	impl Display for FieldType {
+		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+			match self {
+				FieldType::Int(..) => write!(f, "INTEGER"),
+				FieldType::Float(..) => write!(f, "REAL"),
+				FieldType::Bool(..) => write!(f, "INTEGER"),
+				FieldType::Str(..) => write!(f, "TEXT"),
 			}
 		}
 	}
 
-	impl From<u8> for FieldType {
-		fn from(t: u8) -> Self {
-			match t {
-				1 => FieldType::Int(0),
-				2 => FieldType::Float(0.0),
-				3 => FieldType::Bool(false),
-				4 => FieldType::Str(0),
-				v => {
-					println!("{}", v);
-					panic!();
+	impl FieldType {
+		/// Binds the value to parameter `index` of an insert statement,
+		/// string ids are replaced by their text when `strings` is given.
+		fn bind(
+			&self,
+			stmt: &mut rusqlite::Statement,
+			index: usize,
+			strings: Option<&Strings>,
+		) -> rusqlite::Result<()> {
+			match (self, strings) {
+				(FieldType::Int(v), _) => stmt.raw_bind_parameter(index, v),
+				(FieldType::Float(v), _) => stmt.raw_bind_parameter(index, v),
+				(FieldType::Bool(v), _) => stmt.raw_bind_parameter(index, v),
+				(FieldType::Str(v), Some(strings)) => {
+					match strings.values.get(*v as usize) {
+						Some(s) => stmt.raw_bind_parameter(index, s),
+						None => stmt
+							.raw_bind_parameter(index, rusqlite::types::Null),
+					}
 				}
-			}
-		}
-	}
-
-	impl ToString for FieldType {
-		fn to_string(&self) -> String {
-			match self {
-				FieldType::Int(..) => String::from("INTEGER"),
-				FieldType::Float(..) => String::from("REAL"),
-				FieldType::Bool(..) => String::from("INTEGER"),
-				FieldType::Str(..) => String::from("TEXT"),
+				(FieldType::Str(v), None) => stmt.raw_bind_parameter(index, v),
 			}
 		}
 	}
 
 	//---------------------------------------------------------------------------
-	#[derive(Copy, Clone)]
-	struct FieldDescriptor {
-		data_type: FieldType,
-		name: u32,
+	/// Descriptor of a registered table, its entries being inserted into
+	/// `table`.
+	#[derive(Clone)]
+	pub struct EntryDescriptor {
+		sql_cmd: String,
+		/// Table name, the descriptor name with the source prefix if any.
+		table: String,
+		/// Column names of the fields, see `NamingConfig::column`.
+		columns: Vec<String>,
+		/// Fields decoded but not stored, a bit per field, see `DropConfig`.
+		dropped: u32,
+		/// Whether the table has a trailing `source` column.
+		source: bool,
+		/// Whether the table has a trailing `valid` column, see
+		/// `validate::Policy::Flag`.
+		valid: bool,
+		/// Whether the table has `recv_time` and `recv_mono` columns, see
+		/// `Daemon::receive_time`.
+		received: bool,
+		/// Whether an entry was decoded, the base of the counters.
+		loaded: bool,
+		/// Entries stored since the descriptor was declared.
+		rows: u64,
+		/// Index of the output database.
+		db: usize,
+		/// Trailing columns of the schema the producer does not send, along
+		/// with their defaults.
+		backfill: Vec<(String, FieldType)>,
+		wire: Descriptor,
 	}
 
-	impl FieldDescriptor {
-		fn sql_from_raw<R: Read>(
-			&mut self,
-			reader: &mut BufReader<R>,
-		) -> Result<&dyn rusqlite::ToSql, std::io::Error> {
-			match &mut self.data_type {
-				FieldType::Int(data) => {
-					let mut bytes = [0; 4];
-					reader.read_exact(&mut bytes)?;
-
-					*data = u32::from_le_bytes(bytes);
-					Ok(data)
-				}
-				FieldType::Float(data) => {
-					let mut bytes = [0; 4];
-					reader.read_exact(&mut bytes)?;
-
-					*data = f32::from_le_bytes(bytes).into();
-					Ok(data)
-				}
-				FieldType::Bool(data) => {
-					let mut bytes = [0; 1];
-					reader.read_exact(&mut bytes)?;
-
-					*data = bytes[0] > 0;
-					Ok(data)
-				}
-				FieldType::Str(data) => {
-					let mut bytes = [0; 4];
-					reader.read_exact(&mut bytes)?;
+	impl Deref for EntryDescriptor {
+		type Target = Descriptor;
 
-					*data = u32::from_le_bytes(bytes);
-					Ok(data)
-				}
-			}
+		fn deref(&self) -> &Descriptor {
+			&self.wire
 		}
 	}
 
-	//---------------------------------------------------------------------------
-	#[derive(Clone)]
-	struct EntryDescriptor {
-		sql_cmd: String,
-		name: u32,
-		num_fields: u8,
-		fields: [Option<FieldDescriptor>; 32],
+	impl DerefMut for EntryDescriptor {
+		fn deref_mut(&mut self) -> &mut Descriptor {
+			&mut self.wire
+		}
 	}
 
 	impl EntryDescriptor {
 		pub fn make() -> EntryDescriptor {
+			EntryDescriptor::from(Descriptor::default())
+		}
+
+		fn from(wire: Descriptor) -> EntryDescriptor {
 			EntryDescriptor {
 				sql_cmd: String::from("INSERT INTO "),
-				name: 0,
-				num_fields: 0,
-				fields: [Option::None; 32],
+				table: String::new(),
+				columns: vec![],
+				dropped: 0,
+				source: false,
+				valid: false,
+				received: false,
+				loaded: false,
+				rows: 0,
+				db: 0,
+				backfill: vec![],
+				wire,
 			}
 		}
 
-		pub fn compile(&mut self, strings: &Vec<String>) {
-			let name = &strings.get(self.name as usize).unwrap();
-			self.sql_cmd.push_str(name);
-			self.sql_cmd.push_str(" (");
+		/// Inserts the decoded entry through the cached insert statement,
+		/// binding the fields in place.
+		fn insert(
+			&self,
+			con: &rusqlite::Connection,
+			strings: Option<&Strings>,
+			source: &str,
+			thread: Option<&str>,
+			received: Option<(i64, i64)>,
+			valid: bool,
+		) -> rusqlite::Result<()> {
+			let mut stmt = con.prepare_cached(&self.sql_cmd)?;
+
+			let mut index = 0;
+			for (f, _) in self.stored() {
+				index += 1;
+				f.data_type.bind(&mut stmt, index, strings)?;
+			}
+			for (_, value) in &self.backfill {
+				index += 1;
+				value.bind(&mut stmt, index, strings)?;
+			}
+			if self.threaded {
+				index += 1;
+				stmt.raw_bind_parameter(index, thread)?;
+			}
+			if self.received {
+				let (time, mono) = received.unwrap_or_default();
+				stmt.raw_bind_parameter(index + 1, time)?;
+				stmt.raw_bind_parameter(index + 2, mono)?;
+				index += 2;
+			}
+			if self.source {
+				index += 1;
+				stmt.raw_bind_parameter(index, source)?;
+			}
+			if self.valid {
+				stmt.raw_bind_parameter(index + 1, valid)?;
+			}
+
+			stmt.raw_execute()?;
+			Ok(())
+		}
 
-			for i in 0..(self.num_fields as usize) {
-				let field = &self.fields[i].unwrap();
+		/// Fields stored in the table along with their column names.
+		fn stored(&self) -> impl Iterator<Item = (&FieldDescriptor, &String)> {
+			self.fields
+				.iter()
+				.flatten()
+				.zip(&self.columns)
+				.enumerate()
+				.filter(move |(i, _)| self.dropped & 1 << i == 0)
+				.map(|(_, field)| field)
+		}
 
-				let name = &strings.get(field.name as usize).unwrap();
-				self.sql_cmd.push_str(name);
+		/// Names and types of the columns of the stored fields.
+		fn column_types(&self) -> Vec<(String, Type)> {
+			self.stored()
+				.map(|(f, name)| (name.clone(), f.data_type.into()))
+				.collect()
+		}
+
+		/// Checks the descriptor refers to known strings and has fields.
+		pub fn validate(&self, strings: &Strings) -> Result<(), String> {
+			if self.num_fields == 0 {
+				return Err(String::from("no fields"));
+			}
 
-				if i < self.num_fields as usize - 1 {
-					self.sql_cmd.push_str(", ");
-				} else {
-					self.sql_cmd.push_str(")");
+			let ids = std::iter::once(self.name)
+				.chain(self.fields.iter().flatten().map(|f| f.name));
+			for id in ids {
+				match strings.get(id) {
+					None => return Err(format!("unknown string id {}", id)),
+					Some(DROPPED_STRING) => {
+						return Err(format!(
+							"name {} was dropped by the memory caps",
+							id
+						))
+					}
+					Some(_) => {}
 				}
 			}
 
+			Ok(())
+		}
+
+		pub fn compile(&mut self) {
+			self.sql_cmd.push_str(&self.table);
+			self.sql_cmd.push_str(" (");
+			let names: Vec<String> =
+				self.stored().map(|(_, name)| name.clone()).collect();
+			self.sql_cmd.push_str(&names.join(", "));
+
+			for (name, _) in &self.backfill {
+				self.sql_cmd.push_str(", ");
+				self.sql_cmd.push_str(name);
+			}
+			if self.threaded {
+				self.sql_cmd.push_str(", thread");
+			}
+			if self.received {
+				self.sql_cmd.push_str(", recv_time, recv_mono");
+			}
+			if self.source {
+				self.sql_cmd.push_str(", source");
+			}
+			if self.valid {
+				self.sql_cmd.push_str(", valid");
+			}
+			self.sql_cmd.push(')');
+
+			let num_params = names.len()
+				+ self.backfill.len()
+				+ self.threaded as usize
+				+ self.received as usize * 2
+				+ self.source as usize
+				+ self.valid as usize;
 			self.sql_cmd.push_str(" VALUES (");
-			for i in 1..(self.num_fields as usize) {
+			for i in 1..num_params {
 				write!(&mut self.sql_cmd, "?{}, ", i).unwrap();
 			}
 
-			write!(&mut self.sql_cmd, "?{})", self.num_fields).unwrap();
+			write!(&mut self.sql_cmd, "?{})", num_params).unwrap();
 		}
 
-		pub fn make_create_cmd(&self, strings: &Vec<String>) -> String {
-			let mut cmd = String::from("CREATE TABLE ");
-			cmd.push_str(&strings[self.name as usize]);
+		pub fn make_create_cmd(&self) -> String {
+			let mut cmd = String::from("CREATE TABLE IF NOT EXISTS ");
+			cmd.push_str(&self.table);
 			cmd.push_str(" (");
 
-			fn push_param(
-				cmd: &mut String,
-				field: &FieldDescriptor,
-				strings: &Vec<String>,
-			) {
-				cmd.push_str(&strings[field.name as usize]);
-				cmd.push_str(" ");
-				cmd.push_str(&field.data_type.to_string());
+			let params: Vec<String> = self
+				.stored()
+				.map(|(field, name)| format!("{} {}", name, field.data_type))
+				.collect();
+			cmd.push_str(&params.join(", "));
+			for (name, value) in &self.backfill {
+				write!(&mut cmd, ", {} {}", name, value).unwrap();
+			}
+			if self.threaded {
+				cmd.push_str(", thread TEXT");
+			}
+			if self.received {
+				cmd.push_str(", recv_time INTEGER, recv_mono INTEGER");
+			}
+			if self.source {
+				cmd.push_str(", source TEXT");
+			}
+			if self.valid {
+				cmd.push_str(", valid INTEGER");
+			}
+			cmd.push(')');
+			cmd
+		}
+
+		/// View `<name>_view` showing the string columns resolved through
+		/// `_sdd_strings`, `None` when the entry has no string fields.
+		pub fn make_view_cmd(&self) -> Option<String> {
+			let table = &self.table;
+			let mut columns = vec![];
+			let mut joins = String::new();
+
+			for (i, (field, name)) in self.stored().enumerate() {
+				match field.data_type {
+					FieldType::Str(..) => {
+						columns.push(format!("s{}.value AS {}", i, name));
+						write!(
+							&mut joins,
+							" LEFT JOIN _sdd_strings s{i} ON s{i}.id = t.{}",
+							name,
+							i = i
+						)
+						.unwrap();
+					}
+					_ => columns.push(format!("t.{}", name)),
+				}
+			}
+
+			if joins.is_empty() {
+				return None;
+			}
+
+			for (name, _) in &self.backfill {
+				columns.push(format!("t.{}", name));
+			}
+			if self.threaded {
+				columns.push(String::from("t.thread"));
 			}
+			if self.received {
+				columns.push(String::from("t.recv_time"));
+				columns.push(String::from("t.recv_mono"));
+			}
+			if self.source {
+				columns.push(String::from("t.source"));
+			}
+			if self.valid {
+				columns.push(String::from("t.valid"));
+			}
+
+			Some(format!(
+				"CREATE VIEW IF NOT EXISTS {t}_view AS SELECT {} FROM {t} t{}",
+				columns.join(", "),
+				joins,
+				t = table
+			))
+		}
+
+		/// View `<name>_entities` adding the name and type of the entities
+		/// the entity id columns refer to, `None` when there are none.
+		pub fn make_entity_view_cmd(&self) -> Option<String> {
+			let mut columns = vec![String::from("t.*")];
+			let mut joins = String::new();
 
-			let num_fields = self.num_fields as usize;
-			for i in 0..num_fields - 1 {
-				let field = &self.fields[i].unwrap();
-				push_param(&mut cmd, field, strings);
-				cmd.push_str(", ");
+			for (i, (field, name)) in self.stored().enumerate() {
+				let entity = match entity_column(name) {
+					Some(e) if field.data_type == FieldType::Int(0) => e,
+					_ => continue,
+				};
+
+				columns.push(format!("e{}.name AS {}_name", i, entity));
+				columns.push(format!("e{}.type AS {}_type", i, entity));
+				write!(
+					&mut joins,
+					" LEFT JOIN _sdd_entities e{i} ON e{i}.id = t.{}",
+					name,
+					i = i
+				)
+				.unwrap();
 			}
 
-			let last_field = &self.fields[num_fields - 1].unwrap();
-			push_param(&mut cmd, last_field, strings);
-			cmd.push_str(")");
+			if joins.is_empty() {
+				return None;
+			}
 
-			return cmd;
+			Some(format!(
+				"CREATE VIEW IF NOT EXISTS {t}_entities AS SELECT {} FROM {t} t{}",
+				columns.join(", "),
+				joins,
+				t = self.table
+			))
 		}
 	}
 
 	//---------------------------------------------------------------------------
-	pub struct Protocol {
-		con: rusqlite::Connection,
-		descriptors: Vec<EntryDescriptor>,
-		strings: Vec<String>,
+	/// Text stored in place of strings shed by the memory caps.
+	pub const DROPPED_STRING: &str = "<dropped>";
+
+	/// Intern pool deduplicating the strings of all sessions. Producers number
+	/// their strings per session, those ids are remapped to canonical ids
+	/// which are the ones persisted in `_sdd_strings` and the tables.
+	#[derive(Default)]
+	pub struct Strings {
+		/// Indexed by canonical id.
+		values: Vec<String>,
+		ids: HashMap<String, u32>,
+		/// Canonical ids indexed by the producer ids of the current session.
+		session: Vec<u32>,
+		/// Cap on the text bytes of `values`.
+		limit: Option<usize>,
+		bytes: usize,
+		/// Number of strings replaced by `DROPPED_STRING`.
+		pub dropped: u64,
 	}
 
-	impl Protocol {
-		pub fn new(db_path: String) -> Result<Protocol, &'static str> {
-			match fs::remove_file(&db_path) {
-				_ => {}
-			};
+	impl Strings {
+		/// Forgets the producer ids of the previous session.
+		pub fn begin_session(&mut self) {
+			self.session.clear();
+		}
 
-			let connection = match rusqlite::Connection::open(db_path) {
-				Ok(c) => c,
-				Err(_) => return Result::Err("Connection error"),
+		/// Number of strings received during the current session.
+		pub fn session_len(&self) -> usize {
+			self.session.len()
+		}
+
+		/// Interns the next string of the session, returning its canonical
+		/// id and whether it was not seen before.
+		pub fn intern(&mut self, s: String) -> (u32, bool) {
+			let over = |limit| self.bytes + s.len() > limit;
+			let s = match self.limit {
+				Some(limit)
+					if over(limit)
+						&& s != DROPPED_STRING
+						&& !self.ids.contains_key(&s) =>
+				{
+					self.dropped += 1;
+					String::from(DROPPED_STRING)
+				}
+				_ => s,
 			};
 
-			let proto = Protocol {
-				con: connection,
-				descriptors: vec![],
-				strings: vec![],
+			let (id, new) = match self.ids.get(&s) {
+				Some(id) => (*id, false),
+				None => {
+					let id = self.values.len() as u32;
+					self.bytes += s.len();
+					self.values.push(s.clone());
+					self.ids.insert(s, id);
+					(id, true)
+				}
 			};
 
-			Result::Ok(proto)
+			self.session.push(id);
+			(id, new)
+		}
+
+		/// Stands in for a string which was not kept, see `DROPPED_STRING`.
+		pub fn intern_dropped(&mut self) -> (u32, bool) {
+			self.dropped += 1;
+			self.intern(String::from(DROPPED_STRING))
+		}
+
+		/// Canonical id of a producer id.
+		pub fn canonical(&self, id: u32) -> Option<u32> {
+			self.session.get(id as usize).copied()
+		}
+
+		/// Text of a producer id.
+		pub fn get(&self, id: u32) -> Option<&str> {
+			self.canonical(id).map(|c| self.values[c as usize].as_str())
+		}
+
+		/// Text of a canonical id.
+		pub fn resolve(&self, id: u32) -> Option<&str> {
+			self.values.get(id as usize).map(|s| s.as_str())
+		}
+
+		/// Texts of the name and the field names of a descriptor, none when
+		/// one of them is unknown.
+		fn names(&self, desc: &EntryDescriptor) -> Option<(&str, Vec<&str>)> {
+			let fields = desc.fields.iter().flatten().map(|f| self.get(f.name));
+			Some((self.get(desc.name)?, fields.collect::<Option<_>>()?))
 		}
 	}
 
 	//---------------------------------------------------------------------------
-	pub enum Error {
-		Space,
-		ReadFailure,
-		Fatal(&'static str),
+	/// A column of a declared table, see `Protocol::tables`.
+	#[derive(Clone, Debug, PartialEq)]
+	pub struct ColumnInfo {
+		pub name: String,
+		pub data_type: Type,
 	}
 
-	impl Display for Error {
-		fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-			match self {
-				Error::Space => write!(f, "SpaceError"),
-				Error::ReadFailure => write!(f, "ReadFailure"),
-				Error::Fatal(m) => write!(f, "Fatal: {}", m),
-			}
-		}
+	/// A table declared by the producer, see `Protocol::tables`.
+	#[derive(Clone, Debug, PartialEq)]
+	pub struct TableInfo {
+		pub name: String,
+		/// Columns sent by the producer, without the ones the daemon adds.
+		pub columns: Vec<ColumnInfo>,
+		/// Rows stored since the table was declared.
+		pub rows: u64,
 	}
 
 	//---------------------------------------------------------------------------
-	pub struct Daemon {
-		pub proto: Protocol,
+	pub struct Protocol {
+		/// Output databases, the first one being the default output.
+		dbs: Vec<rusqlite::Connection>,
+		paths: Vec<String>,
+		/// (table glob, index into `dbs`) pairs, first match wins.
+		routes: Vec<(String, usize)>,
+		descriptors: Vec<EntryDescriptor>,
+		strings: Strings,
+		/// (name, type) of the entities registered by the producer.
+		entities: HashMap<u32, (String, String)>,
+		/// Whether existing databases are continued, see `append`.
+		append: bool,
+		/// Whether the outputs are in WAL mode, see `wal`.
+		wal: bool,
+		/// Finalization of the finished databases, see `finalize_with`.
+		finalize: Option<FinalizeConfig>,
+		/// Paths of the captures moved aside by `rotate`.
+		rotated: Vec<String>,
+		/// Open transaction of each database, see `commit_with`.
+		batches: Vec<Batch>,
+		pacing: Pacing,
 	}
 
-	impl Daemon {
-		fn read_descriptor<R: Read>(
-			reader: &mut BufReader<R>,
-		) -> Result<(EntryDescriptor, u32), Error> {
-			let mut msg_id_bytes = [0; 4];
-			let mut msg_name_bytes = [0; 4];
-			let mut msg_num_fields_bytes = [0; 1];
+	impl Protocol {
+		pub fn new(db_path: String) -> Result<Protocol, &'static str> {
+			Protocol::create(db_path, false)
+		}
 
-			if reader.read_exact(&mut msg_id_bytes).is_err()
-				|| reader.read_exact(&mut msg_name_bytes).is_err()
-				|| reader.read_exact(&mut msg_num_fields_bytes).is_err()
-			{
-				return Err(Error::ReadFailure);
+		/// Continues the capture at `db_path` instead of replacing it. Its
+		/// strings keep their ids and descriptors have to match the tables
+		/// already in it, see `Daemon::check_fingerprint`.
+		pub fn append(db_path: String) -> Result<Protocol, &'static str> {
+			let mut proto = Protocol::create(db_path, true)?;
+
+			let mut stmt = proto.dbs[0]
+				.prepare("SELECT id, value FROM _sdd_strings ORDER BY id")
+				.map_err(|_| "Could not read the string table")?;
+			let rows = stmt
+				.query_map(rusqlite::NO_PARAMS, |row| {
+					Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+				})
+				.and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+				.map_err(|_| "Could not read the string table")?;
+			drop(stmt);
+
+			for (id, value) in rows {
+				if proto.strings.intern(value) != (id as u32, true) {
+					return Err("The string table has gaps or duplicates");
+				}
 			}
+			proto.strings.begin_session();
 
-			let msg_id = u32::from_le_bytes(msg_id_bytes);
-			let msg_name = u32::from_le_bytes(msg_name_bytes);
-			let msg_num_fields = msg_num_fields_bytes[0] as usize;
+			Result::Ok(proto)
+		}
 
-			let mut desc = EntryDescriptor::make();
-			desc.num_fields = msg_num_fields_bytes[0];
-			desc.name = msg_name;
+		fn create(
+			db_path: String,
+			append: bool,
+		) -> Result<Protocol, &'static str> {
+			let con = Protocol::open(&db_path, append)?;
+			if con
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_markers (time INTEGER, action TEXT, label TEXT)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the marker table");
+			}
 
-			for i in 0..msg_num_fields {
-				let mut data_type_bytes = [0; 1];
-				let mut name_bytes = [0; 4];
+			if con
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_errors (time INTEGER, offset INTEGER, error TEXT, bytes BLOB)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the error table");
+			}
 
-				if reader.read_exact(&mut data_type_bytes).is_err()
-					|| reader.read_exact(&mut name_bytes).is_err()
-				{
-					return Err(Error::ReadFailure);
-				}
+			if con
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_quarantine (time INTEGER, uid INTEGER, name TEXT, reason TEXT, bytes BLOB)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the quarantine table");
+			}
 
-				let data_type = FieldType::from(data_type_bytes[0]);
-				let name = u32::from_le_bytes(name_bytes);
-				let field = FieldDescriptor { data_type, name };
+			if con
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_spans (id INTEGER, parent INTEGER, name TEXT, path TEXT, ts INTEGER, dur INTEGER, depth INTEGER)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the span table");
+			}
 
-				desc.fields[i as usize] = Option::Some(field);
+			if con
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_pauses (time INTEGER, end_time INTEGER, reason TEXT, skipped INTEGER)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the pause table");
 			}
 
-			Result::Ok((desc, msg_id))
-		}
+			if con
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_attachments (time INTEGER, source TEXT, name TEXT, size INTEGER, data BLOB)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the attachment table");
+			}
 
-		fn find_descriptor<'a, 'b, R: Read>(
-			reader: &'a mut BufReader<R>,
-			register: &'b mut Vec<EntryDescriptor>,
-		) -> Result<&'b mut EntryDescriptor, Error> {
-			let mut uid_bytes = [0; 4];
-			match reader.read_exact(&mut uid_bytes) {
-				Ok(_) => {}
-				Err(_) => return Err(Error::Space),
+			let proto = Protocol {
+				dbs: vec![con],
+				paths: vec![db_path],
+				batches: vec![Batch::new(Pacing::default())],
+				pacing: Pacing::default(),
+				routes: vec![],
+				descriptors: vec![],
+				strings: Strings::default(),
+				entities: HashMap::new(),
+				append,
+				wal: false,
+				finalize: None,
+				rotated: vec![],
 			};
 
-			let uid = u32::from_le_bytes(uid_bytes);
-			if register.len() < uid as usize {
-				return Err(Error::Fatal(
-					"Uid not found among the descriptors",
-				));
-			}
+			Result::Ok(proto)
+		}
 
-			Result::Ok(&mut register[uid as usize])
+		/// Caps the text bytes of the interned strings, see `MemoryConfig`.
+		pub fn limit_strings(&mut self, bytes: Option<usize>) {
+			self.strings.limit = bytes;
 		}
 
-		fn register_descriptor<'a>(
-			desc: EntryDescriptor,
-			uid: u32,
-			register: &'a mut Vec<EntryDescriptor>,
-		) -> Result<(), Error> {
-			if uid as usize != register.len() {
-				return Err(Error::Fatal("Unexpected UID"));
+		/// A protocol without any output database, for dry runs and
+		/// captures only going to sinks.
+		pub fn without_output() -> Protocol {
+			Protocol {
+				dbs: vec![],
+				paths: vec![],
+				batches: vec![],
+				pacing: Pacing::default(),
+				routes: vec![],
+				descriptors: vec![],
+				strings: Strings::default(),
+				entities: HashMap::new(),
+				append: false,
+				wal: false,
+				finalize: None,
+				rotated: vec![],
 			}
+		}
 
-			register.push(desc);
-			Result::Ok(())
+		/// Switches the outputs to WAL mode, so that readers of the live
+		/// databases, see `api`, do not block the writer.
+		pub fn wal(&mut self) -> Result<(), &'static str> {
+			self.commit()
+				.map_err(|_| "Could not commit the pending rows")?;
+			self.wal = true;
+			self.dbs.iter().try_for_each(Protocol::set_wal)
 		}
 
-		pub fn start(&mut self, addr: &String) -> Result<(), Error> {
-			println!("Starting the daemon");
+		/// Finalizes the databases moved aside by `rotate` and the ones
+		/// open when the capture ends, unless it panicked.
+		pub fn finalize_with(&mut self, config: FinalizeConfig) {
+			self.finalize = Some(config);
+		}
 
-			let stream = TcpStream::connect(addr)
-				.expect("Could not connect to the address.");
-			let reader = BufReader::new(stream);
+		/// Groups the rows stored into transactions as `pacing` asks for,
+		/// see `commit::Batch`.
+		pub fn commit_with(&mut self, pacing: Pacing) {
+			self.pacing = pacing;
+			for batch in &mut self.batches {
+				*batch = Batch::new(pacing);
+			}
+		}
 
-			self.run(reader)?;
+		/// Commits the transactions that are due, `idle` when the producer
+		/// has nothing more at hand.
+		pub fn poll_commit(&mut self, idle: bool) -> rusqlite::Result<()> {
+			for (batch, con) in self.batches.iter_mut().zip(&self.dbs) {
+				batch.poll(con, idle)?;
+			}
 			Ok(())
 		}
 
-		fn run<TBuf: Read>(
-			&mut self,
-			mut reader: BufReader<TBuf>,
-		) -> Result<(), Error> {
-			enum State {
-				HeaderParsing,
-				DescParsing,
-				EntryParsing,
-				StringParsing,
+		/// Commits the open transactions.
+		pub fn commit(&mut self) -> rusqlite::Result<()> {
+			for (batch, con) in self.batches.iter_mut().zip(&self.dbs) {
+				batch.commit(con)?;
+			}
+			Ok(())
+		}
+
+		/// Compresses the finished database at `path` if configured,
+		/// returning the path of the capture.
+		fn compress(path: String, config: &FinalizeConfig) -> String {
+			let level = match config.zstd {
+				Some(level) => level,
+				None => return path,
 			};
 
-			let mut state = State::HeaderParsing;
+			match zst::compress(&path, level) {
+				Ok(packed) => packed,
+				Err(e) => {
+					println!("Compressing {} failed: {}", path, e);
+					path
+				}
+			}
+		}
 
-			// Read protocol messages until shutdown.
-			loop {
-				match state {
-					State::HeaderParsing => {
-						let mut proto_bytes: [u8; 4] = [0; 4];
-						let mut type_bytes: [u8; 1] = [0];
+		/// Checkpoints the WAL of the outputs as far as the readers allow,
+		/// without waiting for them.
+		pub fn checkpoint(&self) -> Result<(), String> {
+			if !self.wal {
+				return Ok(());
+			}
 
-						if reader.read_exact(&mut proto_bytes).is_err()
-							|| reader.read_exact(&mut type_bytes).is_err()
-						{
-							thread::sleep(time::Duration::from_millis(50));
-							continue;
-						};
+			for con in &self.dbs {
+				con.query_row(
+					"PRAGMA wal_checkpoint(PASSIVE)",
+					rusqlite::NO_PARAMS,
+					|_| Ok(()),
+				)
+				.map_err(|e| e.to_string())?;
+			}
+			Ok(())
+		}
 
-						if u32::from_le_bytes(proto_bytes) != PROTOCOL {
-							println!("Error: not a protocol header.");
-							continue;
-						}
+		fn set_wal(con: &rusqlite::Connection) -> Result<(), &'static str> {
+			con.query_row("PRAGMA journal_mode=WAL", rusqlite::NO_PARAMS, |r| {
+				r.get::<_, String>(0)
+			})
+			.map(|_| ())
+			.map_err(|_| "Could not switch to WAL mode")
+		}
 
-						state = match type_bytes[0].try_into().unwrap() {
-							MsgType::Desc => State::DescParsing,
-							MsgType::Entry => State::EntryParsing,
-							MsgType::Str => State::StringParsing,
-							MsgType::Invalid => State::HeaderParsing,
-						};
-					}
-					State::DescParsing => {
-						match Daemon::read_descriptor(&mut reader) {
-							Ok((mut desc, uid)) => {
-								desc.compile(&self.proto.strings);
+		fn open(
+			db_path: &str,
+			append: bool,
+		) -> Result<rusqlite::Connection, &'static str> {
+			if !append {
+				// A log left by a crash would be replayed into the new file.
+				for suffix in ["", "-wal", "-shm"] {
+					let _ = fs::remove_file(format!("{}{}", db_path, suffix));
+				}
+			}
 
-								let create_cmd =
-									desc.make_create_cmd(&self.proto.strings);
+			let flags = rusqlite::OpenFlags::default();
+			let connection = match crate::cipher::open(db_path, flags) {
+				Ok(c) => c,
+				Err(_) => return Result::Err("Connection error"),
+			};
+			connection.set_prepared_statement_cache_capacity(STATEMENT_CACHE);
 
-								Daemon::register_descriptor(
-									desc,
-									uid,
-									&mut self.proto.descriptors,
-								)?;
+			if connection
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_strings (id INTEGER PRIMARY KEY, value TEXT)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the string table");
+			}
 
-								self.proto
-									.con
-									.execute(&create_cmd, rusqlite::NO_PARAMS)
-									.expect("SQL creation query failed");
-							}
-							Err(Error::ReadFailure) => {
-								println!("Read failure occured during descriptor parsing.");
-							}
-							Err(e) => return Err(e),
-						};
+			if connection
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_descriptors (name TEXT PRIMARY KEY, columns TEXT, fingerprint TEXT)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the descriptor table");
+			}
 
-						state = State::HeaderParsing
-					}
-					State::EntryParsing => {
-						match Daemon::find_descriptor(
-							&mut reader,
-							&mut self.proto.descriptors,
-						) {
-							Ok(desc) => {
-								let mut params =
-									Vec::<&dyn rusqlite::ToSql>::with_capacity(
-										desc.num_fields as usize,
-									);
+			if connection
+				.execute(
+					"CREATE TABLE IF NOT EXISTS _sdd_entities (id INTEGER PRIMARY KEY, name TEXT, type TEXT)",
+					rusqlite::NO_PARAMS,
+				)
+				.is_err()
+			{
+				return Result::Err("Could not create the entity table");
+			}
 
-								let mut failed = false;
-								for field in &mut desc.fields {
-									match field {
-										Some(val) => {
-											let to_sql = match val
-												.sql_from_raw(&mut reader)
-											{
-												Ok(val) => val,
-												Err(e) => {
-													println!("Error during the sql_from_raw!");
-													println!("{}", e);
-
-													failed = true;
-													break;
-												}
-											};
-
-											params.push(to_sql);
-										}
-										_ => {
-											break;
-										}
-									}
-								}
+			// Tells `sdd sync` a new file under the same name from the one it
+			// pushed before.
+			let id = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map_or(0, |d| d.as_nanos());
+			let id = format!("{:x}-{:x}", id, std::process::id());
+			if connection
+				.execute_batch(
+					"CREATE TABLE IF NOT EXISTS _sdd_capture (id TEXT)",
+				)
+				.and_then(|_| {
+					connection.execute(
+						"INSERT INTO _sdd_capture SELECT ?1 \
+						 WHERE NOT EXISTS (SELECT 1 FROM _sdd_capture)",
+						&[&id],
+					)
+				})
+				.is_err()
+			{
+				return Result::Err("Could not create the capture table");
+			}
 
-								if !failed {
-									let con = &self.proto.con;
-									let cmd = &desc.sql_cmd;
+			Result::Ok(connection)
+		}
 
-									con.execute(cmd, params)
-										.expect("SQL Query failed");
-								}
-							}
-							Err(Error::Space) => {
-								println!("Not enough data in the buffer");
-							}
-							Err(e) => {
-								return Err(e);
-							}
-						};
+		/// Registers entity `id` in every output, replacing the entity of a
+		/// previous run with the same id.
+		fn register_entity(
+			&mut self,
+			id: u32,
+			name: &str,
+			kind: &str,
+		) -> rusqlite::Result<()> {
+			for con in &self.dbs {
+				Protocol::insert_entity(con, id, name, kind)?;
+			}
+			self.entities
+				.insert(id, (name.to_string(), kind.to_string()));
+			Ok(())
+		}
 
-						state = State::HeaderParsing;
+		fn insert_entity(
+			con: &rusqlite::Connection,
+			id: u32,
+			name: &str,
+			kind: &str,
+		) -> rusqlite::Result<()> {
+			con.execute(
+				"INSERT OR REPLACE INTO _sdd_entities VALUES (?1, ?2, ?3)",
+				&[Value::from(id), name.into(), kind.into()],
+			)?;
+			Ok(())
+		}
+
+		/// Sends the tables matching `pattern` into the database at `db_path`.
+		pub fn add_route(
+			&mut self,
+			pattern: &str,
+			db_path: String,
+		) -> Result<(), &'static str> {
+			let db = match self.paths.iter().position(|p| *p == db_path) {
+				Some(db) => db,
+				None => {
+					let con = Protocol::open(&db_path, self.append)?;
+					if self.wal {
+						Protocol::set_wal(&con)?;
+					}
+					// Strings are numbered by the main database.
+					for (id, value) in self.strings.values.iter().enumerate() {
+						con.execute(
+							"INSERT OR IGNORE INTO _sdd_strings VALUES (?1, ?2)",
+							&[Value::from(id as i64), value.as_str().into()],
+						)
+						.map_err(|_| "Could not copy the strings")?;
+					}
+					for (id, (name, kind)) in &self.entities {
+						Protocol::insert_entity(&con, *id, name, kind)
+							.map_err(|_| "Could not copy the entities")?;
 					}
-					State::StringParsing => {
-						let mut uid_bytes = [0; 4];
-						let mut size_bytes = [0; 4];
+
+					self.dbs.push(con);
+					self.paths.push(db_path);
+					self.batches.push(Batch::new(self.pacing));
+					self.dbs.len() - 1
+				}
+			};
+
+			self.routes.push((pattern.to_string(), db));
+			Result::Ok(())
+		}
+
+		/// Moves the outputs aside to `<stem>-<unix time>.<ext>` and continues
+		/// in fresh databases with the same schema. Returns the paths of the
+		/// rotated captures.
+		pub fn rotate(&mut self) -> Result<Vec<String>, String> {
+			self.commit().map_err(|e| e.to_string())?;
+			let stamp = unix_now();
+			let mut rotated = vec![];
+
+			for i in 0..self.dbs.len() {
+				let schema = Protocol::schema(&self.dbs[i])
+					.map_err(|e| e.to_string())?;
+				let descriptors = Protocol::descriptors(&self.dbs[i])
+					.map_err(|e| e.to_string())?;
+
+				let path = &self.paths[i];
+				let target = suffixed_path(path, stamp);
+
+				let memory = rusqlite::Connection::open_in_memory()
+					.map_err(|e| e.to_string())?;
+				drop(mem::replace(&mut self.dbs[i], memory));
+				fs::rename(path, &target)
+					.map_err(|e| format!("{}: {}", path, e))?;
+
+				let con = Protocol::open(path, false)?;
+				if self.wal {
+					Protocol::set_wal(&con)?;
+				}
+				for sql in &schema {
+					con.execute_batch(sql).map_err(|e| e.to_string())?;
+				}
+				for row in &descriptors {
+					con.execute(
+						"INSERT INTO _sdd_descriptors VALUES (?1, ?2, ?3)",
+						row,
+					)
+					.map_err(|e| e.to_string())?;
+				}
+				for (id, value) in self.strings.values.iter().enumerate() {
+					con.execute(
+						"INSERT INTO _sdd_strings VALUES (?1, ?2)",
+						&[Value::from(id as i64), value.as_str().into()],
+					)
+					.map_err(|e| e.to_string())?;
+				}
+				for (id, (name, kind)) in &self.entities {
+					Protocol::insert_entity(&con, *id, name, kind)
+						.map_err(|e| e.to_string())?;
+				}
+
+				self.dbs[i] = con;
+				let target = match &self.finalize {
+					Some(config) => {
+						let flags = rusqlite::OpenFlags::default();
+						let finalized = crate::cipher::open(&target, flags)
+							.and_then(|con| finalize(&con, config));
+						if let Err(e) = finalized {
+							println!("Finalizing {} failed: {}", target, e);
+						}
+						Protocol::compress(target, config)
+					}
+					None => target,
+				};
+				rotated.push(target);
+			}
+			self.rotated.extend(rotated.iter().cloned());
+
+			Ok(rotated)
+		}
+
+		/// Statements recreating the tables and views of `con`, except for the
+		/// tables created by `open` and the shadow tables of virtual tables.
+		fn schema(con: &rusqlite::Connection) -> rusqlite::Result<Vec<String>> {
+			let mut stmt = con.prepare(
+				"SELECT sql FROM sqlite_master t WHERE sql IS NOT NULL \
+				 AND name NOT LIKE 'sqlite_%' \
+				 AND name NOT IN ('_sdd_strings', '_sdd_descriptors', \
+				 '_sdd_entities') \
+				 AND NOT EXISTS (SELECT 1 FROM sqlite_master v \
+				 WHERE v.sql LIKE 'CREATE VIRTUAL TABLE%' \
+				 AND t.name LIKE v.name || '\\_%' ESCAPE '\\') \
+				 ORDER BY rowid",
+			)?;
+
+			let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| row.get(0))?;
+			rows.collect()
+		}
+
+		/// Rows of the descriptor table, see `Daemon::check_fingerprint`.
+		fn descriptors(
+			con: &rusqlite::Connection,
+		) -> rusqlite::Result<Vec<[String; 3]>> {
+			let mut stmt = con.prepare(
+				"SELECT name, columns, fingerprint FROM _sdd_descriptors",
+			)?;
+
+			let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+				Ok([row.get(0)?, row.get(1)?, row.get(2)?])
+			})?;
+			rows.collect()
+		}
+
+		/// Tables declared in the current session, by uid.
+		pub fn tables(&self) -> Vec<TableInfo> {
+			let column = |(f, name): (&FieldDescriptor, &String)| ColumnInfo {
+				name: name.clone(),
+				data_type: f.data_type.into(),
+			};
+
+			self.descriptors
+				.iter()
+				.map(|desc| TableInfo {
+					name: desc.table.clone(),
+					columns: desc
+						.fields
+						.iter()
+						.flatten()
+						.zip(&desc.columns)
+						.map(column)
+						.collect(),
+					rows: desc.rows,
+				})
+				.collect()
+		}
+
+		fn route(&self, table: &str) -> usize {
+			self.routes
+				.iter()
+				.find(|(pattern, _)| glob_match(pattern, table))
+				.map_or(0, |(_, db)| *db)
+		}
+	}
+
+	impl Protocol {
+		/// Commits, finalizes and closes the outputs. Returns the paths of
+		/// the captures written, the rotated ones first.
+		pub fn close(mut self) -> Vec<String> {
+			let mut outputs = mem::take(&mut self.rotated);
+			outputs.extend(self.finish());
+			outputs
+		}
+
+		fn finish(&mut self) -> Vec<String> {
+			if let Err(e) = self.commit() {
+				println!("Committing the pending rows failed: {}", e);
+			}
+
+			let config =
+				self.finalize.as_ref().filter(|_| !thread::panicking());
+			let mut outputs = vec![];
+			for (con, path) in self.dbs.drain(..).zip(&self.paths) {
+				let path = match config {
+					Some(config) => {
+						if let Err(e) = finalize(&con, config) {
+							println!("Finalizing {} failed: {}", path, e);
+						}
+						drop(con);
+						Protocol::compress(path.clone(), config)
+					}
+					None => path.clone(),
+				};
+				outputs.push(path);
+			}
+			outputs
+		}
+	}
+
+	impl Drop for Protocol {
+		fn drop(&mut self) {
+			self.finish();
+		}
+	}
+
+	//---------------------------------------------------------------------------
+	/// Bytes of a message kept for diagnostics, longer messages are cut.
+	const MAX_DUMP_MESSAGE: usize = 256;
+	/// Bytes following a malformed message included in its dump.
+	const DUMP_CONTEXT: usize = 64;
+
+	/// Reader tracking the stream offset and the bytes of the current
+	/// message, see `Daemon::hexdump`.
+	struct Tracked<R> {
+		inner: BufReader<R>,
+		offset: u64,
+		/// Offset of the current message.
+		start: u64,
+		message: Vec<u8>,
+		keep: bool,
+		/// Ends the wait for the rest of a message, see `read_exact`.
+		stop: Arc<AtomicBool>,
+		/// How long the rest of a message is waited for, forever if `None`.
+		patience: Option<Duration>,
+	}
+
+	impl<R: Read> Tracked<R> {
+		fn new(inner: BufReader<R>, keep: bool) -> Tracked<R> {
+			Tracked {
+				inner,
+				offset: 0,
+				start: 0,
+				message: vec![],
+				keep,
+				stop: Arc::new(AtomicBool::new(false)),
+				patience: None,
+			}
+		}
+
+		/// Marks the start of a new message.
+		fn begin(&mut self) {
+			self.start = self.offset;
+			self.message.clear();
+		}
+
+		/// Skips to the next protocol header, `header` being the bytes read
+		/// in place of one. Returns the message type.
+		fn resync(&mut self, mut header: [u8; 5]) -> std::io::Result<u8> {
+			while u32::from_le_bytes([
+				header[0], header[1], header[2], header[3],
+			]) != PROTOCOL
+			{
+				header.rotate_left(1);
+				self.read_exact(&mut header[4..])?;
+			}
+
+			self.begin();
+			self.start -= header.len() as u64;
+			if self.keep {
+				self.message.extend_from_slice(&header);
+			}
+			Ok(header[4])
+		}
+
+		/// Buffered bytes following the current position.
+		fn ahead(&self) -> &[u8] {
+			let ahead = self.inner.buffer();
+			&ahead[..ahead.len().min(DUMP_CONTEXT)]
+		}
+
+		/// Bytes of the current message followed by the buffered ones.
+		fn dump(&self) -> Vec<u8> {
+			let mut bytes = self.message.clone();
+			bytes.extend_from_slice(self.ahead());
+			bytes
+		}
+	}
+
+	impl<R: Read> Tracked<Metered<R>> {
+		/// Whether every byte received so far was read.
+		fn idle(&self) -> bool {
+			self.inner.buffer().is_empty() && self.inner.get_ref().short
+		}
+	}
+
+	impl<R: Read> Read for Tracked<R> {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			let n = self.inner.read(buf)?;
+			self.offset += n as u64;
+
+			if self.keep {
+				let room = MAX_DUMP_MESSAGE.saturating_sub(self.message.len());
+				self.message.extend_from_slice(&buf[..n.min(room)]);
+			}
+
+			Ok(n)
+		}
+
+		/// A read timing out before the first byte of a message is returned
+		/// for the caller to poll in between. Within a message the rest is
+		/// waited for, the bytes read so far would be lost otherwise, until
+		/// `patience` runs out or a stop is requested.
+		fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+			let mut filled = 0;
+			let mut waiting = Instant::now();
+			while filled < buf.len() {
+				let e = match self.read(&mut buf[filled..]) {
+					Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+					Ok(n) => {
+						filled += n;
+						waiting = Instant::now();
+						continue;
+					}
+					Err(e) => e,
+				};
+
+				let timed_out = matches!(
+					e.kind(),
+					ErrorKind::WouldBlock | ErrorKind::TimedOut
+				);
+				let waited =
+					self.patience.is_some_and(|p| waiting.elapsed() >= p);
+				match e.kind() {
+					ErrorKind::Interrupted => {}
+					_ if timed_out
+						&& self.offset > self.start
+						&& !waited && !self.stop.load(Ordering::Relaxed) => {}
+					_ => return Err(e),
+				}
+			}
+			Ok(())
+		}
+	}
+
+	pub const DEFAULT_READ_BUFFER: usize = 64 * 1024;
+	/// Bytes of an entry message before its values, the header and the uid.
+	const ENTRY_HEADER: usize = sdd_core::HEADER_SIZE + 4;
+	const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+	/// How often blocking reads check whether to end the capture, see
+	/// `Daemon::stop_requested`.
+	pub const STOP_POLL: Duration = Duration::from_millis(100);
+	/// Larger attachments are skipped as a protocol violation.
+	const MAX_ATTACHMENT_SIZE: usize = 64 * 1024 * 1024;
+
+	/// Reader counting the reads of the connection into `Stats`.
+	struct Metered<R> {
+		inner: R,
+		stats: SharedStats,
+		/// The last read returned less than asked for, the stream having
+		/// nothing more at hand.
+		short: bool,
+	}
+
+	impl<R: Read> Metered<R> {
+		/// Empty reads, at the end of the stream, are not counted.
+		fn count(&self, n: usize) {
+			if n == 0 {
+				return;
+			}
+
+			let mut stats = self.stats.lock().unwrap();
+			stats.reads += 1;
+			stats.bytes += n as u64;
+		}
+	}
+
+	impl<R: Read> Read for Metered<R> {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			let n = self.inner.read(buf)?;
+			self.count(n);
+			self.short = n < buf.len();
+			Ok(n)
+		}
+	}
+
+	/// Classic 16 bytes per line hex dump, offsets starting at `offset`.
+	fn hexdump(offset: u64, bytes: &[u8]) -> String {
+		let mut out = String::new();
+		for (i, line) in bytes.chunks(16).enumerate() {
+			let hex: Vec<String> =
+				line.iter().map(|b| format!("{:02x}", b)).collect();
+			let text: String = line
+				.iter()
+				.map(|b| {
+					if b.is_ascii_graphic() || *b == b' ' {
+						*b as char
+					} else {
+						'.'
+					}
+				})
+				.collect();
+
+			let _ = writeln!(
+				out,
+				"{:08x}  {:<47}  |{}|",
+				offset + i as u64 * 16,
+				hex.join(" "),
+				text
+			);
+		}
+
+		out
+	}
+
+	//---------------------------------------------------------------------------
+	const MAX_RECENT_ERRORS: usize = 64;
+
+	#[derive(Clone, Default)]
+	pub struct TableStats {
+		pub name: String,
+		pub columns: Vec<String>,
+		pub rows: u64,
+		/// Entries dropped by the rate limit.
+		pub dropped: u64,
+		/// Entries skipped for repeating the previous one.
+		pub suppressed: u64,
+		/// Entries breaking a validation rule, see `validate::Validations`.
+		pub invalid: u64,
+		last: [Option<FieldDescriptor>; 32],
+	}
+
+	impl TableStats {
+		/// Values of the most recently inserted entry, in column order.
+		pub fn last_values(&self) -> Vec<String> {
+			if self.rows == 0 {
+				return vec![];
+			}
+
+			self.last
+				.iter()
+				.flatten()
+				.map(|f| f.data_type.value_string())
+				.collect()
+		}
+	}
+
+	/// Ingest counters shared with the monitoring front ends.
+	#[derive(Clone, Default)]
+	pub struct Stats {
+		/// Indexed by descriptor uid.
+		pub tables: Vec<TableStats>,
+		/// Tables of the ended sessions, one per name, see `end_session`.
+		pub ended: Vec<TableStats>,
+		pub entries: u64,
+		/// Address of the producer while connected.
+		pub peer: Option<String>,
+		pub last_receive: Option<Instant>,
+		/// No data arrived within the idle timeout.
+		pub stale: bool,
+		/// Reads from the connection and the bytes they returned.
+		pub reads: u64,
+		pub bytes: u64,
+		/// Whether entries are persisted, see `Daemon::set_recording`.
+		pub recording: bool,
+		/// The producer paused persisting, see `Message::Pause`.
+		pub paused: bool,
+		pub errors: VecDeque<String>,
+		/// Number of reported problems, `errors` keeps only the recent ones.
+		pub error_count: u64,
+		/// Protocol violations skipped by a lenient capture.
+		pub violations: u64,
+	}
+
+	impl Stats {
+		pub fn error(&mut self, msg: String) {
+			self.error_count += 1;
+			if self.errors.len() == MAX_RECENT_ERRORS {
+				self.errors.pop_front();
+			}
+			self.errors.push_back(msg);
+		}
+
+		/// Adds the counts of the tables of the session to `ended`.
+		pub fn end_session(&mut self) {
+			for table in self.tables.drain(..).filter(|t| !t.name.is_empty()) {
+				match self.ended.iter_mut().find(|t| t.name == table.name) {
+					Some(ended) => {
+						ended.rows += table.rows;
+						ended.dropped += table.dropped;
+						ended.suppressed += table.suppressed;
+						ended.invalid += table.invalid;
+					}
+					None => self.ended.push(table),
+				}
+			}
+		}
+	}
+
+	pub type SharedStats = Arc<Mutex<Stats>>;
+
+	/// `dir/name.ext` becomes `dir/name-<suffix>.ext`, used for rotated
+	/// captures and shards.
+	pub fn suffixed_path(path: &str, suffix: u64) -> String {
+		let path = Path::new(path);
+		let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+		let name = match path.extension() {
+			Some(ext) => {
+				format!("{}-{}.{}", stem, suffix, ext.to_string_lossy())
+			}
+			None => format!("{}-{}", stem, suffix),
+		};
+
+		path.with_file_name(name).to_string_lossy().into_owned()
+	}
+
+	/// SQLite integer of a u64, saturated at `i64::MAX`.
+	fn sql_int(v: u64) -> i64 {
+		v.min(i64::MAX as u64) as i64
+	}
+
+	/// Turns a source name into a valid table name prefix.
+	fn table_prefix(source: &str) -> String {
+		let mut prefix: String = source
+			.chars()
+			.map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+			.collect();
+
+		if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+			prefix.insert(0, '_');
+		}
+
+		prefix.push('_');
+		prefix
+	}
+
+	//---------------------------------------------------------------------------
+	/// Encoding of the incoming stream, see proto.md.
+	#[derive(Clone, Copy, Debug, PartialEq)]
+	pub enum Format {
+		/// Protocol messages, or MessagePack frames after `msgpack::MAGIC`.
+		Binary,
+		/// Newline-delimited JSON entries, see `json::Translator`.
+		Json,
+		/// InfluxDB line protocol, see `influx::translator`.
+		Influx,
+	}
+
+	impl Format {
+		/// Whether the stream is made of lines, datagrams and broker
+		/// payloads holding whole lines may lack the last newline.
+		pub fn lines(self) -> bool {
+			matches!(self, Format::Json | Format::Influx)
+		}
+	}
+
+	impl std::str::FromStr for Format {
+		type Err = &'static str;
+
+		fn from_str(s: &str) -> Result<Self, Self::Err> {
+			match s {
+				"binary" => Ok(Format::Binary),
+				"json" => Ok(Format::Json),
+				"influx" => Ok(Format::Influx),
+				_ => Err("Expected one of binary, json, influx"),
+			}
+		}
+	}
+
+	/// A scope begun by the producer, written to `_sdd_spans` once it ends.
+	struct Scope {
+		parent: Option<u32>,
+		name: String,
+		/// Names from the root scope down, separated by `;`.
+		path: String,
+		start: u64,
+		depth: u32,
+	}
+
+	/// A pause of the producer, written to `_sdd_pauses` once it ends.
+	struct Pause {
+		since: u64,
+		reason: Option<String>,
+		/// Entries not persisted during the pause.
+		skipped: u64,
+	}
+
+	pub struct Daemon {
+		pub proto: Protocol,
+		pub stats: SharedStats,
+		/// Echo reported problems to stdout.
+		pub verbose: bool,
+		pub alerts: Alerts,
+		/// Webhooks of the start, end, disconnects and rotations.
+		pub notify: Notify,
+		pub rollups: Rollups,
+		pub search: Search,
+		pub limits: Limits,
+		/// Caps on what each connection creates.
+		pub quota: Quota,
+		pub validations: Validations,
+		pub dedupe: Dedupe,
+		/// Destinations of the entries besides the databases.
+		pub sinks: Vec<Box<dyn Sink>>,
+		/// Persist entries, toggled by start and stop markers.
+		pub recording: bool,
+		/// Finish the capture after this many entries.
+		pub max_entries: Option<u64>,
+		/// Finish the capture this long after connecting.
+		pub duration: Option<Duration>,
+		started: Instant,
+		/// Commands from the control socket, see `ctl::serve`.
+		pub control: Option<mpsc::Receiver<ctl::Request>>,
+		/// Time between checkpoints, see `checkpoint`.
+		pub checkpoint_interval: Option<Duration>,
+		/// Has a checkpoint done before the next message once set, e.g. by
+		/// a signal handler.
+		pub checkpoint_requested: Arc<AtomicBool>,
+		last_checkpoint: Instant,
+		/// Ends the capture as its bounds do once set, e.g. by a signal
+		/// handler.
+		pub stop_requested: Arc<AtomicBool>,
+		/// Store the text of string fields instead of their string ids.
+		pub resolve_strings: bool,
+		pub source: SourceConfig,
+		/// Prefix and casing of the producer tables.
+		pub naming: NamingConfig,
+		/// Columns left out of the tables.
+		pub drops: Vec<DropConfig>,
+		/// Name of the connected producer, see `SourceConfig`.
+		source_name: String,
+		table_prefix: String,
+		/// Validate the stream without writing anything, the protocol is
+		/// expected to come from `Protocol::without_output`.
+		pub dry_run: bool,
+		/// Dump the bytes of malformed messages to the log and `_sdd_errors`.
+		pub hexdump: bool,
+		/// Abort the capture on the first protocol violation instead of
+		/// skipping it, see `Daemon::violation`.
+		pub strict: bool,
+		/// Capacity of the read buffer, larger buffers need fewer reads.
+		pub read_buffer: usize,
+		/// Connections are read by a thread of their own into this many
+		/// buffers of `read_buffer` bytes, see `spsc::ReadAhead`.
+		pub read_ahead: Option<usize>,
+		/// Finish the capture once the producer disconnects.
+		pub exit_on_eof: bool,
+		/// Longer string messages are skipped, see `MemoryConfig`.
+		pub max_string_length: Option<usize>,
+		/// Cap on the received bodies waiting to be read, see
+		/// `ingest::Bodies::limit`.
+		pub max_queued: Option<usize>,
+		/// Whether the string memory cap was reported.
+		strings_capped: bool,
+		/// Open scopes of the session by id.
+		scopes: HashMap<u32, Scope>,
+		/// Thread of the entries of threaded tables, see `Message::Thread`.
+		thread: Option<String>,
+		/// Pause of the producer, entries are not persisted meanwhile and
+		/// the session does not go stale.
+		paused: Option<Pause>,
+		/// Encoding of the incoming stream.
+		pub format: Format,
+		/// Mark the session stale when no data arrives for this long.
+		pub idle_timeout: Option<Duration>,
+		/// Connect again once the session goes stale.
+		pub reconnect: bool,
+		/// Descriptors have to match their table in the schema when set.
+		pub schema: Option<Schema>,
+		/// Raw capture of the streams, see `raw::Recorder`.
+		pub raw: Option<raw::Recorder>,
+		/// Share of the entries the producers send per table name, one in
+		/// `every`, see `sdd_core::MSG_CONTROL`.
+		pub sampling: HashMap<String, u32>,
+		/// Connection the controls are sent back on.
+		back: Option<TcpStream>,
+		/// Add `recv_time` and `recv_mono` columns to the tables, the wall
+		/// clock time an entry arrived at in microseconds since the Unix
+		/// epoch and the monotonic time since the capture started, immune to
+		/// clock adjustments.
+		pub receive_time: bool,
+	}
+
+	impl Daemon {
+		pub fn new(proto: Protocol) -> Daemon {
+			Daemon {
+				proto,
+				stats: SharedStats::default(),
+				verbose: true,
+				alerts: Alerts::default(),
+				notify: Notify::default(),
+				rollups: Rollups::default(),
+				search: Search::default(),
+				limits: Limits::default(),
+				quota: Quota::default(),
+				validations: Validations::default(),
+				dedupe: Dedupe::default(),
+				sinks: vec![],
+				recording: true,
+				max_entries: None,
+				duration: None,
+				started: Instant::now(),
+				checkpoint_interval: None,
+				checkpoint_requested: Arc::new(AtomicBool::new(false)),
+				last_checkpoint: Instant::now(),
+				stop_requested: Arc::new(AtomicBool::new(false)),
+				control: None,
+				resolve_strings: false,
+				source: SourceConfig::default(),
+				naming: NamingConfig::default(),
+				drops: vec![],
+				source_name: String::new(),
+				table_prefix: String::new(),
+				dry_run: false,
+				hexdump: false,
+				strict: false,
+				read_buffer: DEFAULT_READ_BUFFER,
+				read_ahead: None,
+				exit_on_eof: false,
+				max_string_length: None,
+				max_queued: None,
+				strings_capped: false,
+				scopes: HashMap::new(),
+				thread: None,
+				paused: None,
+				format: Format::Binary,
+				idle_timeout: None,
+				reconnect: false,
+				schema: None,
+				raw: None,
+				sampling: HashMap::new(),
+				back: None,
+				receive_time: false,
+			}
+		}
+
+		/// Delivers the entries of the tables matching the glob `pattern`
+		/// along with storing them, for applications embedding the daemon
+		/// and reacting to the rows as they arrive. Rows are dropped while
+		/// the receiver is `subscribe::QUEUE` rows behind. Subscribe before
+		/// the capture starts, the receiver is drained from another thread.
+		pub fn subscribe(&mut self, pattern: &str) -> mpsc::Receiver<Row> {
+			let (subscription, rows) = Subscription::new(pattern);
+			self.sinks.push(Box::new(subscription));
+			rows
+		}
+
+		/// Delivers the entries of the tables matching `pattern` as `T`,
+		/// see `subscribe::Row::deserialize`, or why they do not fit it.
+		pub fn subscribe_as<T>(
+			&mut self,
+			pattern: &str,
+		) -> mpsc::Receiver<Result<T, String>>
+		where
+			T: DeserializeOwned + Send + 'static,
+		{
+			let (subscription, rows) =
+				Subscription::with(pattern, |row| row.deserialize());
+			self.sinks.push(Box::new(subscription));
+			rows
+		}
+
+		/// Sends the sampling of table `uid` back to the producer.
+		fn send_control(&mut self, uid: u32, every: u32) -> Result<(), String> {
+			let back = match &mut self.back {
+				Some(back) => back,
+				None => return Err(String::from("no producer connection")),
+			};
+
+			let mut buf = [0; sdd_core::CONTROL_SIZE];
+			let mut encoder = sdd_core::Encoder::new(&mut buf);
+			let _ = encoder.control(uid, every);
+			if let Err(e) = back.write_all(encoder.bytes()) {
+				self.back = None;
+				return Err(e.to_string());
+			}
+			Ok(())
+		}
+
+		/// Samples table `name` at one in `every` entries, sending it to the
+		/// producer right away when the table is declared.
+		fn sample(&mut self, name: &str, every: u32) -> String {
+			self.sampling.insert(name.to_string(), every);
+
+			let strings = &self.proto.strings;
+			let uid = self
+				.proto
+				.descriptors
+				.iter()
+				.position(|d| strings.get(d.name) == Some(name));
+			let sent = match uid {
+				Some(uid) => self.send_control(uid as u32, every),
+				None => return format!("{} is sampled once declared", name),
+			};
+			match (sent, every) {
+				(Ok(()), 0) => format!("{} disabled", name),
+				(Ok(()), 1) => format!("{} enabled", name),
+				(Ok(()), _) => format!("{} sampled at 1/{}", name, every),
+				(Err(e), _) => format!("Sampling {} failed: {}", name, e),
+			}
+		}
+
+		fn report(&self, msg: String) {
+			if self.verbose {
+				println!("{}", msg);
+			}
+
+			self.stats.lock().unwrap().error(msg);
+		}
+
+		/// Reports a protocol violation of the message `reader` is in. A
+		/// lenient capture counts it and goes on, the caller skipping the
+		/// message, while a strict one fails.
+		fn violation<R: Read>(
+			&self,
+			error: &str,
+			detail: String,
+			reader: &Tracked<R>,
+		) -> Result<(), Error> {
+			let result = self.flag_violation(error, detail, reader.start);
+			self.diagnose(error, reader);
+			result
+		}
+
+		/// Counts and reports a protocol violation at `offset` of the
+		/// stream, failing a strict capture.
+		fn flag_violation(
+			&self,
+			error: &str,
+			detail: String,
+			offset: u64,
+		) -> Result<(), Error> {
+			self.stats.lock().unwrap().violations += 1;
+			let msg = format!("{} at offset {}: {}", error, offset, detail);
+			self.report(msg.clone());
+
+			match self.strict {
+				true => Err(Error::Violation(msg)),
+				false => Ok(()),
+			}
+		}
+
+		/// Keeps an entry rejected by a lenient capture in `_sdd_quarantine`
+		/// along with the reason, `bytes` being its values as received.
+		fn quarantine(
+			&self,
+			uid: u32,
+			table: Option<&str>,
+			bytes: &[u8],
+			reason: &str,
+		) {
+			let con = match self.proto.dbs.first() {
+				Some(con) => con,
+				None => return,
+			};
+
+			if let Err(e) = con.execute(
+				"INSERT INTO _sdd_quarantine VALUES (?1, ?2, ?3, ?4, ?5)",
+				&[
+					Value::from(unix_now() as i64),
+					uid.into(),
+					table.into(),
+					reason.into(),
+					bytes.into(),
+				],
+			) {
+				self.report(format!("Quarantine failed: {}", e));
+			}
+		}
+
+		/// Dumps the bytes around the malformed message `reader` is in.
+		fn diagnose<R: Read>(&self, error: &str, reader: &Tracked<R>) {
+			if !self.hexdump && !self.strict {
+				return;
+			}
+
+			let bytes = reader.dump();
+			if self.verbose {
+				print!(
+					"{} at offset {}:\n{}",
+					error,
+					reader.start,
+					hexdump(reader.start, &bytes)
+				);
+			}
+
+			let con = match self.proto.dbs.first() {
+				Some(con) => con,
+				None => return,
+			};
+
+			if let Err(e) = con.execute(
+				"INSERT INTO _sdd_errors VALUES (?1, ?2, ?3, ?4)",
+				&[
+					Value::from(unix_now() as i64),
+					(reader.start as i64).into(),
+					error.into(),
+					bytes.into(),
+				],
+			) {
+				self.report(format!("Error logging failed: {}", e));
+			}
+		}
+
+		/// Stores the entry of table `uid` decoded into its descriptor,
+		/// `frame` being its bytes as received, and passes it on to the
+		/// alerts, rollups, search and sinks.
+		fn store_entry(&mut self, uid: u32, frame: &[u8]) -> Result<(), Error> {
+			let desc = &self.proto.descriptors[uid as usize];
+			let fields = &desc.fields;
+			let raw =
+				|i: usize| fields[i].map_or(0, |f| f.data_type.raw_bits());
+			let value = |i: usize| fields[i].and_then(|f| f.data_type.as_f64());
+			let broken = self.validations.check(uid, value);
+			let flagged = broken.is_some();
+
+			if !self.recording {
+				return Ok(());
+			}
+
+			if let Some(pause) = &mut self.paused {
+				pause.skipped += 1;
+				return Ok(());
+			}
+
+			if self.dedupe.is_repeat(uid, desc.num_fields as usize, raw) {
+				let mut stats = self.stats.lock().unwrap();
+				stats.tables[uid as usize].suppressed += 1;
+				return Ok(());
+			}
+
+			if !self.limits.allow(uid, Instant::now()) {
+				let mut stats = self.stats.lock().unwrap();
+				stats.tables[uid as usize].dropped += 1;
+				return Ok(());
+			}
+
+			if !self.quota.allow(Instant::now()) {
+				let mut stats = self.stats.lock().unwrap();
+				stats.tables[uid as usize].dropped += 1;
+				return Ok(());
+			}
+
+			if let Some((policy, reason)) =
+				broken.filter(|(p, _)| *p != Policy::Flag)
+			{
+				let mut stats = self.stats.lock().unwrap();
+				stats.tables[uid as usize].invalid += 1;
+				drop(stats);
+
+				if policy == Policy::Quarantine {
+					self.quarantine(uid, Some(&desc.table), frame, &reason);
+				}
+				return Ok(());
+			}
+
+			let stored = !self.dry_run && !self.proto.dbs.is_empty();
+			let strings = if self.resolve_strings {
+				Some(&self.proto.strings)
+			} else {
+				None
+			};
+			if stored {
+				let received = desc.received.then(|| {
+					let time = SystemTime::now()
+						.duration_since(UNIX_EPOCH)
+						.map_or(0, |d| d.as_micros() as i64);
+					(time, self.started.elapsed().as_micros() as i64)
+				});
+				let con = &self.proto.dbs[desc.db];
+				if let Err(e) = self.proto.batches[desc.db].add(con) {
+					self.report(format!(
+						"Could not begin a transaction: {}",
+						e
+					));
+					return Err(Error::Fatal("Could not begin a transaction"));
+				}
+				desc.insert(
+					con,
+					strings,
+					&self.source_name,
+					self.thread.as_deref(),
+					received,
+					!flagged,
+				)
+				.expect("SQL Query failed");
+			}
+
+			let mut stats = self.stats.lock().unwrap();
+			let table = &mut stats.tables[uid as usize];
+			table.invalid += flagged as u64;
+			table.rows += 1;
+			table.last = desc.fields;
+			stats.entries += 1;
+			drop(stats);
+			self.dedupe.remember(uid, desc.num_fields as usize, raw);
+
+			if self.dry_run {
+				return Ok(());
+			}
+
+			let mut log = self.alerts.check(uid, value, Instant::now());
+
+			let strings = &self.proto.strings;
+			let text = |i: usize| match fields[i].map(|f| f.data_type) {
+				Some(FieldType::Str(id)) => strings.resolve(id),
+				_ => None,
+			};
+
+			// Rollups and search live in the databases.
+			if stored {
+				let con = &self.proto.dbs[desc.db];
+				if let Err(e) = self.rollups.add(uid, value, con) {
+					log.push(format!("Rollup update failed: {}", e));
+				}
+
+				if let Err(e) =
+					self.search.add(uid, con.last_insert_rowid(), text, con)
+				{
+					log.push(format!("Search index update failed: {}", e));
+				}
+			}
+
+			if !self.sinks.is_empty() {
+				let entry = desc.entry();
+				for sink in &mut self.sinks {
+					if let Err(e) = sink.send(
+						uid,
+						&self.source_name,
+						&entry,
+						&self.proto.strings,
+					) {
+						log.push(e);
+					}
+				}
+			}
+
+			if stored {
+				self.proto.descriptors[uid as usize].rows += 1;
+			}
+			for msg in log {
+				self.report(msg);
+			}
+			Ok(())
+		}
+
+		/// Logs an ended scope into `_sdd_spans` while recording and not
+		/// paused.
+		fn end_scope(&self, id: u32, scope: Scope, end: u64) {
+			let con = match self.proto.dbs.first() {
+				Some(con) if self.recording && self.paused.is_none() => con,
+				_ => return,
+			};
+
+			if let Err(e) = con.execute(
+				"INSERT INTO _sdd_spans VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+				&[
+					Value::from(id),
+					scope.parent.into(),
+					scope.name.as_str().into(),
+					scope.path.as_str().into(),
+					sql_int(scope.start).into(),
+					sql_int(end.saturating_sub(scope.start)).into(),
+					scope.depth.into(),
+				],
+			) {
+				self.report(format!("Span logging failed: {}", e));
+			}
+		}
+
+		/// Starts or stops persisting entries, logging the change along with
+		/// an optional label into `_sdd_markers`.
+		pub fn set_recording(&mut self, recording: bool, label: Option<&str>) {
+			self.recording = recording;
+			self.stats.lock().unwrap().recording = recording;
+
+			let action = if recording { "start" } else { "stop" };
+			if self.verbose {
+				println!("Capture {}: {}", action, label.unwrap_or("-"));
+			}
+
+			let con = match self.proto.dbs.first() {
+				Some(con) => con,
+				None => return,
+			};
+
+			if let Err(e) = con.execute(
+				"INSERT INTO _sdd_markers VALUES (?1, ?2, ?3)",
+				&[Value::from(unix_now() as i64), action.into(), label.into()],
+			) {
+				self.report(format!("Marker logging failed: {}", e));
+			}
+		}
+
+		/// Stores an attachment into `_sdd_attachments` while recording and
+		/// not paused.
+		fn store_attachment(&self, name: &str, data: &[u8]) {
+			let con = match self.proto.dbs.first() {
+				Some(con) if self.recording && self.paused.is_none() => con,
+				_ => return,
+			};
+
+			if self.verbose {
+				println!("Attachment {} of {} bytes", name, data.len());
+			}
+
+			if let Err(e) = con.execute(
+				"INSERT INTO _sdd_attachments VALUES (?1, ?2, ?3, ?4, ?5)",
+				&[
+					Value::from(unix_now() as i64),
+					self.source_name.as_str().into(),
+					name.into(),
+					(data.len() as i64).into(),
+					data.into(),
+				],
+			) {
+				self.report(format!("Attachment logging failed: {}", e));
+			}
+		}
+
+		/// Pauses persisting until `resume`, see `Message::Pause`.
+		fn pause(&mut self, reason: Option<String>) {
+			if self.verbose {
+				println!(
+					"Capture paused: {}",
+					reason.as_deref().unwrap_or("-")
+				);
+			}
+
+			self.stats.lock().unwrap().paused = true;
+			self.paused = Some(Pause {
+				since: unix_now(),
+				reason,
+				skipped: 0,
+			});
+		}
+
+		/// Ends the pause, if any, logging it into `_sdd_pauses`.
+		fn resume(&mut self) {
+			let pause = match self.paused.take() {
+				Some(pause) => pause,
+				None => return,
+			};
+			self.stats.lock().unwrap().paused = false;
+
+			if self.verbose {
+				println!("Capture resumed, {} entries skipped", pause.skipped);
+			}
+
+			let con = match self.proto.dbs.first() {
+				Some(con) => con,
+				None => return,
+			};
+
+			if let Err(e) = con.execute(
+				"INSERT INTO _sdd_pauses VALUES (?1, ?2, ?3, ?4)",
+				&[
+					Value::from(pause.since as i64),
+					(unix_now() as i64).into(),
+					pause.reason.as_deref().into(),
+					(pause.skipped as i64).into(),
+				],
+			) {
+				self.report(format!("Pause logging failed: {}", e));
+			}
+		}
+
+		fn read_descriptor<R: Read>(
+			reader: &mut R,
+		) -> Result<(EntryDescriptor, u32), Error> {
+			let (wire, uid) = Descriptor::read(reader)?;
+			Ok((EntryDescriptor::from(wire), uid))
+		}
+
+		/// Checks a descriptor named `name` with fields `fields` against the
+		/// schema, if any, returning the columns it lacks along with their
+		/// defaults.
+		fn check_schema(
+			&self,
+			desc: &EntryDescriptor,
+			uid: u32,
+			name: &str,
+			fields: &[String],
+		) -> Result<Vec<(String, FieldType)>, String> {
+			let schema = match &self.schema {
+				Some(schema) => schema,
+				None => return Ok(vec![]),
+			};
+
+			// The schema is shared with the producers, so it has their names.
+			let columns: Vec<(String, Type)> = desc
+				.fields
+				.iter()
+				.flatten()
+				.zip(fields)
+				.map(|(f, name)| (name.clone(), f.data_type.into()))
+				.collect();
+			let missing = schema.check(uid, name, &columns)?;
+			Ok(missing
+				.iter()
+				.map(|c| (self.naming.column(name, &c.name), c.fill().unwrap()))
+				.collect())
+		}
+
+		/// Bits of the fields of a named descriptor whose columns are
+		/// dropped, none when that would leave the table without columns.
+		fn dropped_columns(&self, desc: &EntryDescriptor) -> u32 {
+			let patterns: Vec<&String> = self
+				.drops
+				.iter()
+				.filter(|d| glob_match(&d.tables, &desc.table))
+				.flat_map(|d| &d.columns)
+				.collect();
+
+			let mut dropped: u32 = 0;
+			for (i, name) in desc.columns.iter().enumerate() {
+				if patterns.iter().any(|p| glob_match(p, name)) {
+					dropped |= 1 << i;
+				}
+			}
+
+			if dropped.count_ones() as usize == desc.columns.len() {
+				self.report(format!(
+					"Not dropping every column of {}",
+					desc.table
+				));
+				return 0;
+			}
+			dropped
+		}
+
+		/// Records a descriptor lacking columns of the schema, as sent by
+		/// producers built before they were added.
+		fn schema_skew<R: Read>(
+			&self,
+			desc: &EntryDescriptor,
+			reader: &Tracked<R>,
+		) {
+			let missing: Vec<(String, Type)> = desc
+				.backfill
+				.iter()
+				.map(|(name, value)| (name.clone(), (*value).into()))
+				.collect();
+			let msg = format!(
+				"Schema skew: {} lacks ({}), filled with defaults",
+				desc.table,
+				schema::signature(&missing)
+			);
+			self.report(msg.clone());
+
+			if let Some(con) = self.proto.dbs.first() {
+				if let Err(e) = con.execute(
+					"INSERT INTO _sdd_errors VALUES (?1, ?2, ?3, NULL)",
+					&[
+						Value::from(unix_now() as i64),
+						(reader.start as i64).into(),
+						msg.as_str().into(),
+					],
+				) {
+					self.report(format!("Error logging failed: {}", e));
+				}
+			}
+		}
+
+		/// Whether the table of `desc` can have the `valid` column of the
+		/// flagged entries, adding it to a table being appended to.
+		fn valid_column(&self, desc: &EntryDescriptor) -> bool {
+			if desc.columns.iter().any(|c| c == "valid") {
+				self.report(format!(
+					"{} has a valid column of its own, not flagging its entries",
+					desc.table
+				));
+				return false;
+			}
+
+			let con = match self.proto.dbs.get(desc.db) {
+				Some(con) if self.proto.append => con,
+				_ => return true,
+			};
+			let columns = con
+				.prepare(&format!("PRAGMA table_info(\"{}\")", desc.table))
+				.and_then(|mut stmt| {
+					stmt.query_map(rusqlite::NO_PARAMS, |r| r.get(1))?
+						.collect::<rusqlite::Result<Vec<String>>>()
+				});
+			let added = match columns {
+				Ok(columns)
+					if columns.is_empty()
+						|| columns.iter().any(|c| c == "valid") =>
+				{
+					return true
+				}
+				Ok(_) => con.execute(
+					&format!(
+						"ALTER TABLE {} ADD COLUMN valid INTEGER",
+						desc.table
+					),
+					rusqlite::NO_PARAMS,
+				),
+				Err(e) => Err(e),
+			};
+			if let Err(e) = added {
+				self.report(format!(
+					"Adding the valid column to {} failed: {}",
+					desc.table, e
+				));
+				return false;
+			}
+			true
+		}
+
+		/// Checks a descriptor against the definition of its table stored in
+		/// the capture, storing it for tables new to the capture.
+		fn check_fingerprint(
+			&self,
+			desc: &EntryDescriptor,
+		) -> Result<(), String> {
+			let con = match self.proto.dbs.get(desc.db) {
+				Some(con) => con,
+				None => return Ok(()),
+			};
+
+			let mut columns = desc.column_types();
+			for (name, value) in &desc.backfill {
+				columns.push((name.clone(), (*value).into()));
+			}
+			let fingerprint = schema::fingerprint(&desc.table, &columns);
+			let stored: Option<(String, String)> = con
+				.query_row(
+					"SELECT columns, fingerprint FROM _sdd_descriptors WHERE name = ?1",
+					&[&desc.table],
+					|row| Ok((row.get(0)?, row.get(1)?)),
+				)
+				.optional()
+				.map_err(|e| e.to_string())?;
+
+			match stored {
+				Some((_, stored)) if stored == fingerprint => Ok(()),
+				Some((stored, _)) => Err(format!(
+					"{} is ({}) in the capture, not ({})",
+					desc.table,
+					stored,
+					schema::signature(&columns)
+				)),
+				None => con
+					.execute(
+						"INSERT INTO _sdd_descriptors VALUES (?1, ?2, ?3)",
+						&[
+							&desc.table,
+							&schema::signature(&columns),
+							&fingerprint,
+						],
+					)
+					.map(|_| ())
+					.map_err(|e| e.to_string()),
+			}
+		}
+
+		/// Reads the uid of an entry, returning it with its descriptor if
+		/// the table was declared.
+		fn find_descriptor<'b, R: Read>(
+			reader: &mut R,
+			register: &'b mut [EntryDescriptor],
+		) -> std::io::Result<(u32, Option<&'b mut EntryDescriptor>)> {
+			let mut uid_bytes = [0; 4];
+			reader.read_exact(&mut uid_bytes)?;
+
+			let uid = u32::from_le_bytes(uid_bytes);
+			Result::Ok((uid, register.get_mut(uid as usize)))
+		}
+
+		fn register_descriptor(
+			desc: EntryDescriptor,
+			uid: u32,
+			register: &mut Vec<EntryDescriptor>,
+		) -> Result<(), Error> {
+			if uid as usize != register.len() {
+				return Err(Error::Fatal("Unexpected UID"));
+			}
+
+			register.push(desc);
+			Result::Ok(())
+		}
+
+		pub fn start(&mut self, addr: &String) -> Result<(), Error> {
+			if self.verbose {
+				println!("Starting the daemon");
+			}
+
+			let stream = TcpStream::connect(addr)
+				.expect("Could not connect to the address.");
+
+			self.started = Instant::now();
+			let mut result = self.capture(stream, addr);
+
+			while let (Err(Error::Stale), true) = (&result, self.reconnect) {
+				self.report(format!("Reconnecting to {}", addr));
+				let stream = loop {
+					match TcpStream::connect(addr) {
+						Ok(s) => break s,
+						Err(_) if self.bound_reached() => return Ok(()),
+						Err(_) => thread::sleep(RECONNECT_INTERVAL),
+					}
+				};
+
+				self.begin_session();
+				result = self.capture(stream, addr);
+			}
+
+			result
+		}
+
+		/// Connects to every producer of `addrs` and captures them into one
+		/// session, each read by a thread of its own, see `merge::Merge`.
+		/// Unlike `start`, lost producers are not reconnected.
+		pub fn start_merged(&mut self, addrs: &[String]) -> Result<(), Error> {
+			if self.verbose {
+				println!("Starting the daemon on {} producers", addrs.len());
+			}
+
+			let (merge, bodies) = Merge::new();
+			let merge = Arc::new(merge);
+			for (index, addr) in addrs.iter().enumerate() {
+				let stream = match TcpStream::connect(addr) {
+					Ok(s) => s,
+					Err(e) => {
+						self.report(format!("{}: {}", addr, e));
+						continue;
+					}
+				};
+				let (merge, addr) = (merge.clone(), addr.clone());
+				thread::spawn(move || {
+					if let Err(e) = merge.forward(index, &addr, stream) {
+						println!("{}: {}", addr, e);
+					}
+				});
+			}
+			drop(merge);
+
+			self.started = Instant::now();
+			self.exit_on_eof = true;
+			self.capture_reader(bodies, "merged")
+		}
+
+		/// Time since the capture started, or since it connected to the
+		/// producer.
+		pub fn elapsed(&self) -> Duration {
+			self.started.elapsed()
+		}
+
+		/// Reports the first idle timeout without data.
+		fn mark_stale(&mut self, idle: Duration) {
+			let (stale, peer, entries) = {
+				let mut stats = self.stats.lock().unwrap();
+				let peer = stats.peer.clone().unwrap_or_default();
+				(mem::replace(&mut stats.stale, true), peer, stats.entries)
+			};
+			if !stale {
+				let msg = format!(
+					"No data for {:.1}s, the session is stale",
+					idle.as_secs_f64()
+				);
+				self.notify.disconnect(&peer, entries, Some(msg.clone()));
+				self.report(msg);
+			}
+		}
+
+		/// Forgets the strings and descriptors of the previous connection,
+		/// its tables are registered again by the next one.
+		pub fn begin_session(&mut self) {
+			self.proto.strings.begin_session();
+			self.proto.descriptors.clear();
+			self.stats.lock().unwrap().end_session();
+		}
+
+		/// Captures a producer connection, `peer` being its address.
+		pub fn capture(
+			&mut self,
+			stream: TcpStream,
+			peer: &str,
+		) -> Result<(), Error> {
+			let clone = stream
+				.try_clone()
+				.map_err(|_| Error::Fatal("Could not clone the connection"))?;
+
+			match self.read_ahead {
+				Some(buffers) => {
+					let ahead = spsc::ReadAhead::spawn(
+						clone,
+						buffers,
+						self.read_buffer,
+					);
+					self.capture_from(stream, ahead, peer)
+				}
+				None => self.capture_from(stream, clone, peer),
+			}
+		}
+
+		/// Captures producer connection `stream` read through `reader`, e.g.
+		/// one reading it ahead. The connection is shut down once the
+		/// capture ends, which stops the reads of such a reader.
+		pub fn capture_from<R: Read>(
+			&mut self,
+			stream: TcpStream,
+			reader: R,
+			peer: &str,
+		) -> Result<(), Error> {
+			// Unblock the reader once the capture runs out of time or is
+			// stopped, until the capture ends on its own.
+			let capturing = Arc::new(());
+			let end = self.duration.map(|d| self.started + d);
+			let stop = self.stop_requested.clone();
+			let watched = stream
+				.try_clone()
+				.map_err(|_| Error::Fatal("Could not clone the connection"))?;
+			let watch = Arc::downgrade(&capturing);
+			thread::spawn(move || {
+				while watch.strong_count() > 0 {
+					if stop.load(Ordering::Relaxed)
+						|| end.is_some_and(|end| Instant::now() >= end)
+					{
+						let _ = watched.shutdown(Shutdown::Both);
+						return;
+					}
+					thread::sleep(STOP_POLL);
+				}
+			});
+
+			if stream.set_read_timeout(self.idle_timeout).is_err() {
+				return Err(Error::Fatal("Could not set the idle timeout"));
+			}
+
+			self.back = stream.try_clone().ok();
+			let result = self.capture_reader(reader, peer);
+			self.back = None;
+			let _ = stream.shutdown(Shutdown::Both);
+			drop(capturing);
+			result
+		}
+
+		/// Captures a producer stream other than a connection, its reads
+		/// have to time out for the bounds and idle timeout to apply.
+		pub fn capture_reader<R: Read>(
+			&mut self,
+			reader: R,
+			peer: &str,
+		) -> Result<(), Error> {
+			self.proto.strings.begin_session();
+			self.quota.begin_session();
+			self.scopes.clear();
+			self.thread = None;
+			let source =
+				self.source.name.clone().unwrap_or_else(|| peer.to_string());
+			self.set_source(source);
+			let mut stats = self.stats.lock().unwrap();
+			stats.peer = Some(peer.to_string());
+			stats.recording = self.recording;
+			drop(stats);
+			self.notify.start(peer);
+
+			let result = self.run(reader);
+			let entries = {
+				let mut stats = self.stats.lock().unwrap();
+				stats.peer = None;
+				stats.entries
+			};
+			// A stale session was posted by `mark_stale` and the end of the
+			// capture itself is posted by its caller.
+			let stale = matches!(result, Err(Error::Stale));
+			if !stale && !self.bound_reached() {
+				let error = result.as_ref().err().map(|e| e.to_string());
+				self.notify.disconnect(peer, entries, error);
+			}
+			// A pause ends with its session.
+			self.resume();
+			self.flush();
+
+			if result.is_ok() && self.verbose {
+				let stats = self.stats.lock().unwrap();
+				println!(
+					"Capture finished after {} entries in {:.1}s, {} reads of {:.0} bytes on average",
+					stats.entries,
+					self.started.elapsed().as_secs_f64(),
+					stats.reads,
+					stats.bytes as f64 / stats.reads.max(1) as f64
+				);
+				if stats.violations > 0 {
+					println!(
+						"{} protocol violations were skipped",
+						stats.violations
+					);
+				}
+				let invalid: u64 = stats.tables.iter().map(|t| t.invalid).sum();
+				if invalid > 0 {
+					println!("{} entries broke a validation rule", invalid);
+				}
+			}
+
+			if self.dry_run {
+				self.print_dry_run(&result);
+			}
+
+			result
+		}
+
+		fn print_dry_run(&self, result: &Result<(), Error>) {
+			let stats = self.stats.lock().unwrap();
+			println!("Dry run of {} entries:", stats.entries);
+			for table in &stats.tables {
+				println!(
+					"  {:<32} {:>3} columns {:>10} rows",
+					table.name,
+					table.columns.len(),
+					table.rows
+				);
+			}
+
+			// Reported problems were already echoed when verbose.
+			if !self.verbose {
+				for e in &stats.errors {
+					println!("  {}", e);
+				}
+			}
+
+			match result {
+				Ok(()) if stats.error_count == 0 => {
+					println!("No problems found")
+				}
+				Ok(()) => println!("{} problems found", stats.error_count),
+				Err(_) => println!("Stopped by a fatal protocol error"),
+			}
+		}
+
+		/// Captures a recorded stream. Returns at its end in a dry run,
+		/// otherwise once a bound is reached.
+		pub fn ingest<R: Read>(&mut self, reader: R) -> Result<(), Error> {
+			self.proto.strings.begin_session();
+			self.run(reader)
+		}
+
+		/// Stores the raw capture in `bytes`, indexed by `index`, its entries
+		/// decoded ahead on `threads` threads and stored in order on this
+		/// one, see `import::decode`. The other messages go through the
+		/// stream decoder. Returns at the end of the capture or once a bound
+		/// is reached.
+		pub fn import(
+			&mut self,
+			bytes: &[u8],
+			index: &raw::Index,
+			threads: usize,
+		) -> Result<(), Error> {
+			self.proto.strings.begin_session();
+			let exit_on_eof = mem::replace(&mut self.exit_on_eof, true);
+			let mut result = Ok(());
+
+			import::decode(bytes, index, threads, |part| {
+				match self.apply(bytes, part) {
+					Ok(more) => more,
+					Err(e) => {
+						result = Err(e);
+						false
+					}
+				}
+			});
+
+			self.exit_on_eof = exit_on_eof;
+			self.flush();
+			result
+		}
+
+		/// Stores a segment of an import. Returns whether to go on.
+		fn apply(
+			&mut self,
+			bytes: &[u8],
+			part: Vec<Decoded>,
+		) -> Result<bool, Error> {
+			self.poll_control();
+			self.poll_checkpoint();
+
+			// Messages since the last entry, undecoded ones included.
+			let mut stream = 0..0;
+			for (message, range) in part {
+				let (uid, entry) = match message {
+					Ok(Message::Entry { uid, entry }) => (uid, entry),
+					_ if stream.is_empty() => {
+						stream = range;
+						continue;
+					}
+					_ => {
+						stream.end = range.end;
+						continue;
+					}
+				};
+
+				if !stream.is_empty() {
+					let offset = stream.start as u64;
+					self.decode(&bytes[mem::take(&mut stream)], offset)?;
+				}
+				if self.bound_reached() {
+					return Ok(false);
+				}
+
+				self.poll_commit(false);
+				let frame = &bytes[range.start + ENTRY_HEADER..range.end];
+				self.apply_entry(uid, &entry, frame, range.start as u64)?;
+			}
+
+			if !stream.is_empty() {
+				self.decode(&bytes[stream.clone()], stream.start as u64)?;
+			}
+			Ok(!self.bound_reached())
+		}
+
+		/// Stores an entry of table `uid` decoded ahead, `frame` being its
+		/// values as received at `offset` of the stream.
+		fn apply_entry(
+			&mut self,
+			uid: u32,
+			entry: &Entry,
+			frame: &[u8],
+			offset: u64,
+		) -> Result<(), Error> {
+			let desc = match self.proto.descriptors.get_mut(uid as usize) {
+				Some(desc) => desc,
+				None => {
+					let detail = format!("uid {}", uid);
+					self.flag_violation(
+						"Entry of an undeclared table",
+						detail,
+						offset,
+					)?;
+					self.quarantine(uid, None, frame, "Undeclared table");
+					return Ok(());
+				}
+			};
+			desc.set(entry);
+			desc.loaded = true;
+
+			let mut unknown = None;
+			for val in desc.fields.iter_mut().flatten() {
+				if let FieldType::Str(id) = &mut val.data_type {
+					match self.proto.strings.canonical(*id) {
+						Some(c) => *id = c,
+						None => {
+							unknown = Some(*id);
+							break;
+						}
+					}
+				}
+			}
+
+			if let Some(id) = unknown {
+				let table = desc.table.clone();
+				let e = format!("Unknown string id {}", id);
+				self.flag_violation("Malformed entry", e.clone(), offset)?;
+				self.quarantine(uid, Some(&table), frame, &e);
+				return Ok(());
+			}
+			self.store_entry(uid, frame)
+		}
+
+		/// Attributes the entries that follow to producer `name`, see
+		/// `SourceConfig`.
+		fn set_source(&mut self, name: String) {
+			self.table_prefix = if self.source.prefix {
+				table_prefix(&name)
+			} else {
+				String::new()
+			};
+			self.source_name = name;
+		}
+
+		/// Writes out what the raw capture, the rollups and the sinks
+		/// buffer.
+		fn flush(&mut self) {
+			if let Err(e) = self.rollups.flush(&self.proto.dbs) {
+				self.report(format!("Rollup flush failed: {}", e));
+			}
+			self.flush_sinks();
+			self.commit();
+		}
+
+		/// Commits the rows written behind, see `Protocol::commit_with`.
+		fn commit(&mut self) {
+			if let Err(e) = self.proto.commit() {
+				self.report(format!("Commit failed: {}", e));
+			}
+		}
+
+		fn poll_commit(&mut self, idle: bool) {
+			if let Err(e) = self.proto.poll_commit(idle) {
+				self.report(format!("Commit failed: {}", e));
+			}
+		}
+
+		/// Where the sinks moved the rotated captures, by their path, see
+		/// `Sink::moved`.
+		pub fn moved(&mut self) -> HashMap<String, String> {
+			self.sinks
+				.iter_mut()
+				.flat_map(|sink| sink.moved())
+				.collect()
+		}
+
+		/// Delivers the rows buffered by the raw capture and the sinks,
+		/// unlike the partial rollup buckets, which are still filling up.
+		fn flush_sinks(&mut self) {
+			if let Some(Err(e)) = self.raw.as_ref().map(|r| r.flush()) {
+				self.report(format!("Raw capture write failed: {}", e));
+			}
+
+			let failed: Vec<String> = self
+				.sinks
+				.iter_mut()
+				.filter_map(|sink| sink.flush().err())
+				.collect();
+			for e in failed {
+				self.report(e);
+			}
+		}
+
+		/// Flushes the buffered rows and checkpoints the WAL of the
+		/// databases, so that readers of the files see the capture so far.
+		pub fn checkpoint(&mut self) {
+			self.flush_sinks();
+			self.commit();
+			self.last_checkpoint = Instant::now();
+
+			match self.proto.checkpoint() {
+				Ok(()) if self.verbose => println!("Checkpointed"),
+				Ok(()) => {}
+				Err(e) => self.report(format!("Checkpoint failed: {}", e)),
+			}
+		}
+
+		/// Checkpoints when requested or once the interval elapsed.
+		fn poll_checkpoint(&mut self) {
+			let due = self
+				.checkpoint_interval
+				.is_some_and(|i| self.last_checkpoint.elapsed() >= i);
+			if self.checkpoint_requested.swap(false, Ordering::Relaxed) || due {
+				self.checkpoint();
+			}
+		}
+
+		/// Executes the pending control socket commands.
+		fn poll_control(&mut self) {
+			let requests: Vec<ctl::Request> = match &self.control {
+				Some(control) => control.try_iter().collect(),
+				None => return,
+			};
+
+			for request in requests {
+				let response = self.execute(request.command);
+				let _ = request.reply.send(response);
+			}
+		}
+
+		fn execute(&mut self, command: ctl::Command) -> String {
+			match command {
+				ctl::Command::Flush => {
+					match self.rollups.flush(&self.proto.dbs) {
+						Ok(()) => String::from("Flushed"),
+						Err(e) => format!("Rollup flush failed: {}", e),
+					}
+				}
+				ctl::Command::Rotate => {
+					if let Err(e) = self.rollups.flush(&self.proto.dbs) {
+						return format!("Rollup flush failed: {}", e);
+					}
+
+					let paths = match self.proto.rotate() {
+						Ok(paths) => paths,
+						Err(e) => return format!("Rotation failed: {}", e),
+					};
+
+					for sink in &mut self.sinks {
+						for path in &paths {
+							sink.rotated(path);
+						}
+					}
+					self.notify.rotate(&paths);
+					format!("Rotated to {}", paths.join(", "))
+				}
+				ctl::Command::Pause => {
+					self.set_recording(false, Some("ctl"));
+					String::from("Paused")
+				}
+				ctl::Command::Resume => {
+					self.set_recording(true, Some("ctl"));
+					String::from("Resumed")
+				}
+				ctl::Command::SetLogLevel(verbose) => {
+					self.verbose = verbose;
+					String::from("Log level set")
+				}
+				ctl::Command::Sample(table, every) => {
+					self.sample(&table, every)
+				}
+				ctl::Command::Tables => ctl::tables(&self.proto.tables()),
+			}
+		}
+
+		/// Whether the capture hit its `max_entries` or `duration` bound, or
+		/// was asked to stop.
+		pub fn bound_reached(&self) -> bool {
+			let entries = self.stats.lock().unwrap().entries;
+
+			self.stop_requested.load(Ordering::Relaxed)
+				|| self.max_entries.is_some_and(|max| entries >= max)
+				|| self.duration.is_some_and(|d| self.started.elapsed() >= d)
+		}
+
+		fn run<TBuf: Read>(&mut self, reader: TBuf) -> Result<(), Error> {
+			match self.format {
+				Format::Binary => {
+					self.decode(msgpack::Handshake::new(reader), 0)
+				}
+				Format::Json => self.decode(json::Translator::new(reader), 0),
+				Format::Influx => self.decode(influx::translator(reader), 0),
+			}
+		}
+
+		/// Decodes the binary protocol in `reader`, found at `offset` of the
+		/// stream.
+		fn decode<TBuf: Read>(
+			&mut self,
+			reader: TBuf,
+			offset: u64,
+		) -> Result<(), Error> {
+			enum State {
+				Header,
+				Desc,
+				Entry,
+				String,
+				Marker,
+				Entity,
+				ScopeBegin,
+				ScopeEnd,
+				Counter,
+				Pause,
+				Attachment,
+				Thread,
+				Source,
+			}
+
+			let mut state = State::Header;
+			let reader = BufReader::with_capacity(
+				self.read_buffer,
+				Metered {
+					inner: raw::tee(reader, self.raw.clone()),
+					stats: self.stats.clone(),
+					short: false,
+				},
+			);
+			let mut reader = Tracked::new(reader, self.hexdump || self.strict);
+			reader.offset = offset;
+			reader.stop = self.stop_requested.clone();
+			reader.patience = self.idle_timeout;
+			let mut frame = vec![];
+			let mut last_data = Instant::now();
+
+			// Read protocol messages until shutdown.
+			loop {
+				match state {
+					State::Header => {
+						self.poll_commit(reader.idle());
+						self.poll_control();
+						self.poll_checkpoint();
+						if self.bound_reached() {
+							return Ok(());
+						}
+
+						let mut proto_bytes: [u8; 4] = [0; 4];
+						let mut type_bytes: [u8; 1] = [0];
+
+						reader.begin();
+
+						let read = reader
+							.read_exact(&mut proto_bytes)
+							.and_then(|_| reader.read_exact(&mut type_bytes));
+
+						if let Err(e) = read {
+							let timed_out = matches!(
+								e.kind(),
+								ErrorKind::WouldBlock | ErrorKind::TimedOut
+							);
+
+							// Dry runs and shards capture a single stream.
+							if !timed_out && (self.dry_run || self.exit_on_eof)
+							{
+								return Ok(());
+							}
+
+							if self.paused.is_none()
+								&& self
+									.idle_timeout
+									.is_some_and(|t| last_data.elapsed() >= t)
+							{
+								self.mark_stale(last_data.elapsed());
+								if self.reconnect || self.exit_on_eof {
+									return Err(Error::Stale);
+								}
+							}
+
+							if !timed_out {
+								thread::sleep(time::Duration::from_millis(50));
+							}
+							continue;
+						};
+
+						last_data = Instant::now();
+						let mut stats = self.stats.lock().unwrap();
+						stats.last_receive = Some(last_data);
+						let resumed = mem::replace(&mut stats.stale, false);
+						drop(stats);
+
+						if resumed {
+							self.report(String::from("Data resumed"));
+						}
+
+						let mut msg_type = type_bytes[0];
+						if u32::from_le_bytes(proto_bytes) != PROTOCOL {
+							self.violation(
+								"Not a protocol header",
+								format!("{:02x?}", proto_bytes),
+								&reader,
+							)?;
+
+							let mut header = [0; 5];
+							header[..4].copy_from_slice(&proto_bytes);
+							header[4] = msg_type;
+							msg_type = match reader.resync(header) {
+								Ok(t) => t,
+								// The end of the stream is handled above.
+								Err(_) => continue,
+							};
+						}
+
+						state = match msg_type.into() {
+							MsgType::Desc => State::Desc,
+							MsgType::Entry => State::Entry,
+							MsgType::Str => State::String,
+							MsgType::Marker => State::Marker,
+							MsgType::Entity => State::Entity,
+							MsgType::ScopeBegin => State::ScopeBegin,
+							MsgType::ScopeEnd => State::ScopeEnd,
+							MsgType::Counter => State::Counter,
+							MsgType::Pause => State::Pause,
+							MsgType::Attachment => State::Attachment,
+							MsgType::Thread => State::Thread,
+							MsgType::Source => State::Source,
+							MsgType::Invalid => {
+								self.violation(
+									"Unknown message type",
+									msg_type.to_string(),
+									&reader,
+								)?;
+								State::Header
+							}
+						};
+					}
+					State::Desc => {
+						match Daemon::read_descriptor(&mut reader) {
+							Ok((_, uid))
+								if uid as usize
+									!= self.proto.descriptors.len() =>
+							{
+								self.violation(
+									"Unexpected table uid",
+									format!(
+										"{} instead of {}",
+										uid,
+										self.proto.descriptors.len()
+									),
+									&reader,
+								)?;
+							}
+							Ok((mut desc, uid)) => {
+								if let Err(e) =
+									self.quota.table(uid as usize + 1)
+								{
+									self.report(format!(
+										"Descriptor {} is {}",
+										uid, e
+									));
+									return Err(Error::Quota(e));
+								}
+								let (producer, fields) =
+									match self.proto.strings.names(&desc) {
+										Some((name, fields)) => (
+											name.to_string(),
+											fields
+												.iter()
+												.map(|f| f.to_string())
+												.collect::<Vec<_>>(),
+										),
+										None => {
+											self.violation(
+												"Unknown string id",
+												format!("descriptor {}", uid),
+												&reader,
+											)?;
+											state = State::Header;
+											continue;
+										}
+									};
+
+								if let Err(e) =
+									desc.validate(&self.proto.strings)
+								{
+									self.report(format!(
+										"Invalid descriptor {}: {}",
+										uid, e
+									));
+									self.diagnose(
+										"Invalid descriptor",
+										&reader,
+									);
+									return Err(Error::Fatal(
+										"Invalid descriptor",
+									));
+								}
+
+								desc.table = format!(
+									"{}{}{}",
+									self.naming.prefix,
+									self.table_prefix,
+									self.naming.table(&producer)
+								);
+								desc.columns = fields
+									.iter()
+									.map(|f| self.naming.column(&producer, f))
+									.collect();
+								desc.dropped = self.dropped_columns(&desc);
+
+								desc.backfill = match self.check_schema(
+									&desc, uid, &producer, &fields,
+								) {
+									Ok(backfill) => backfill,
+									Err(e) => {
+										self.report(format!(
+											"Descriptor {} does not match the schema: {}",
+											uid, e
+										));
+										self.diagnose(
+											"Descriptor not in the schema",
+											&reader,
+										);
+										return Err(Error::Fatal(
+											"Descriptor does not match the schema",
+										));
+									}
+								};
+
+								let columns = desc.columns.clone();
+
+								desc.source = self.source.column;
+								desc.received = self.receive_time;
+								// Rules name the tables as the producers do.
+								let flagged = self
+									.validations
+									.bind(uid, &producer, &columns);
+								desc.db = self.proto.route(&desc.table);
+								desc.valid =
+									flagged && self.valid_column(&desc);
+								desc.compile();
+								if !desc.backfill.is_empty() {
+									self.schema_skew(&desc, &reader);
+								}
+
+								if let Err(e) = self.check_fingerprint(&desc) {
+									self.report(format!(
+										"Descriptor {} differs from the capture: {}",
+										uid, e
+									));
+									self.diagnose(
+										"Descriptor differs from the capture",
+										&reader,
+									);
+									return Err(Error::Fatal(
+										"Descriptor differs from the capture",
+									));
+								}
+
+								let name = desc.table.clone();
+								let text: Vec<usize> = desc
+									.fields
+									.iter()
+									.flatten()
+									.enumerate()
+									.filter(|(_, f)| {
+										f.data_type == FieldType::Str(0)
+									})
+									.map(|(i, _)| i)
+									.collect();
+
+								let create_cmd = desc.make_create_cmd();
+								let view_cmd = if self.resolve_strings {
+									None
+								} else {
+									desc.make_view_cmd()
+								};
+								let entity_view_cmd =
+									desc.make_entity_view_cmd();
+
+								let db = desc.db;
+								let every =
+									self.sampling.get(&producer).copied();
+								Daemon::register_descriptor(
+									desc,
+									uid,
+									&mut self.proto.descriptors,
+								)?;
+
+								if let Some(every) = every {
+									if let Err(e) =
+										self.send_control(uid, every)
+									{
+										self.report(format!(
+											"Sampling {} failed: {}",
+											name, e
+										));
+									}
+								}
+
+								self.alerts.bind(uid, &name, &columns);
+								self.limits.bind(uid, &name);
+								for sink in &mut self.sinks {
+									sink.bind(uid, &name, &columns);
+								}
+								if let Err(e) =
+									self.dedupe.bind(uid, &name, &columns)
+								{
+									self.report(e);
+								}
+
+								if !self.proto.dbs.is_empty() {
+									let con = &self.proto.dbs[db];
+									con.execute(
+										&create_cmd,
+										rusqlite::NO_PARAMS,
+									)
+									.expect("SQL creation query failed");
+
+									for view_cmd in
+										view_cmd.iter().chain(&entity_view_cmd)
+									{
+										if let Err(e) = con.execute(
+											view_cmd,
+											rusqlite::NO_PARAMS,
+										) {
+											self.report(format!(
+												"View creation failed: {}",
+												e
+											));
+										}
+									}
+
+									if let Err(e) = self.rollups.bind(
+										uid,
+										&name,
+										&columns,
+										db,
+										&self.proto.dbs,
+									) {
+										self.report(e);
+									}
+									if let Err(e) = self.search.bind(
+										uid,
+										&name,
+										&columns,
+										&text,
+										&self.proto.dbs[db],
+									) {
+										self.report(format!(
+											"Search index creation failed: {}",
+											e
+										));
+									}
+								}
+
+								let mut stats = self.stats.lock().unwrap();
+								stats.tables.push(TableStats {
+									name,
+									columns,
+									..TableStats::default()
+								});
+							}
+							Err(Error::ReadFailure) => {
+								self.report(String::from(
+									"Read failure occured during descriptor parsing.",
+								));
+								self.diagnose("Malformed descriptor", &reader);
+							}
+							Err(e) => self.violation(
+								"Malformed descriptor",
+								e.to_string(),
+								&reader,
+							)?,
+						};
+
+						state = State::Header
+					}
+					State::Entry => {
+						match Daemon::find_descriptor(
+							&mut reader,
+							&mut self.proto.descriptors,
+						) {
+							Ok((uid, None)) => {
+								self.violation(
+									"Entry of an undeclared table",
+									format!("uid {}", uid),
+									&reader,
+								)?;
+								self.quarantine(
+									uid,
+									None,
+									reader.ahead(),
+									"Undeclared table",
+								);
+							}
+							Ok((uid, Some(desc))) => {
+								// Read the whole entry at once, its size is
+								// given by the descriptor.
+								frame.resize(desc.entry_size(), 0);
+								let mut failed =
+									reader.read_exact(&mut frame).err();
+								if failed.is_none() {
+									desc.load(&frame);
+									desc.loaded = true;
+								}
+
+								for val in desc.fields.iter_mut().flatten() {
+									if let FieldType::Str(id) =
+										&mut val.data_type
+									{
+										match self.proto.strings.canonical(*id)
+										{
+											Some(c) => *id = c,
+											None => {
+												failed = Some(std::io::Error::new(
+													std::io::ErrorKind::InvalidData,
+													format!("Unknown string id {}", id),
+												));
+												break;
+											}
+										}
+									}
+								}
+
+								match failed {
+									None => self.store_entry(uid, &frame)?,
+									Some(e)
+										if e.kind()
+											== ErrorKind::InvalidData =>
+									{
+										let table = desc.table.clone();
+										self.violation(
+											"Malformed entry",
+											e.to_string(),
+											&reader,
+										)?;
+										self.quarantine(
+											uid,
+											Some(&table),
+											&frame,
+											&e.to_string(),
+										);
+									}
+									Some(e) => {
+										self.report(format!(
+											"Error during the read_raw! {}",
+											e
+										));
+										self.diagnose(
+											"Malformed entry",
+											&reader,
+										);
+									}
+								}
+							}
+							Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+								self.report(String::from(
+									"Not enough data in the buffer",
+								));
+							}
+							Err(e) => {
+								self.report(format!(
+									"Error: entry uid read failed: {}",
+									e
+								));
+							}
+						};
+
+						state = State::Header;
+					}
+					State::String => {
+						let mut uid_bytes = [0; 4];
+						let mut size_bytes = [0; 4];
 
 						if reader.read_exact(&mut uid_bytes).is_err()
 							|| reader.read_exact(&mut size_bytes).is_err()
 						{
-							println!("Error: string metadata read failed.");
-							state = State::HeaderParsing;
+							self.report(String::from(
+								"Error: string metadata read failed.",
+							));
+							state = State::Header;
+							continue;
+						};
+
+						let uid = u32::from_le_bytes(uid_bytes);
+						if uid as usize != self.proto.strings.session_len() {
+							self.violation(
+								"Unexpected string id",
+								format!(
+									"{} instead of {}",
+									uid,
+									self.proto.strings.session_len()
+								),
+								&reader,
+							)?;
+							state = State::Header;
+							continue;
+						}
+
+						if let Err(e) = self.quota.string(uid as usize + 1) {
+							self.report(format!("String {} is {}", uid, e));
+							return Err(Error::Quota(e));
+						}
+
+						let size = u32::from_le_bytes(size_bytes) as usize;
+						let (id, new) = if self
+							.max_string_length
+							.is_some_and(|max| size > max)
+						{
+							// Skip the text without buffering it.
+							let mut text = (&mut reader).take(size as u64);
+							if std::io::copy(&mut text, &mut std::io::sink())
+								.is_err()
+							{
+								self.report(String::from(
+									"Error: failed reading string data.",
+								));
+								state = State::Header;
+								continue;
+							}
+
+							self.report(format!(
+								"String {} of {} bytes is over the length cap",
+								uid, size
+							));
+							self.proto.strings.intern_dropped()
+						} else {
+							let mut string_bytes = vec![0; size];
+							if reader
+								.read_exact(&mut string_bytes[0..size])
+								.is_err()
+							{
+								self.report(String::from(
+									"Error: failed reading string data.",
+								));
+								state = State::Header;
+								continue;
+							};
+
+							let string = match String::from_utf8(string_bytes) {
+								Ok(s) => s,
+								Err(e) => {
+									self.violation(
+										"Malformed string",
+										e.to_string(),
+										&reader,
+									)?;
+									state = State::Header;
+									continue;
+								}
+							};
+
+							let dropped = self.proto.strings.dropped;
+							let interned = self.proto.strings.intern(string);
+							if self.proto.strings.dropped > dropped
+								&& !self.strings_capped
+							{
+								self.strings_capped = true;
+								self.report(format!(
+									"String memory cap reached, new strings are stored as {}",
+									DROPPED_STRING
+								));
+							}
+							interned
+						};
+						if new {
+							let value = self.proto.strings.resolve(id);
+							let failed =
+								self.proto.dbs.iter().find_map(|con| {
+									con.execute(
+									"INSERT INTO _sdd_strings VALUES (?1, ?2)",
+									&[Value::from(id), value.into()],
+								)
+								.err()
+								});
+
+							if let Some(e) = failed {
+								self.report(format!(
+									"Persisting string {} failed: {}",
+									id, e
+								));
+							}
+						}
+
+						state = State::Header;
+					}
+					State::Marker => {
+						let mut action_bytes = [0; 1];
+						let mut label_bytes = [0; 4];
+
+						if reader.read_exact(&mut action_bytes).is_err()
+							|| reader.read_exact(&mut label_bytes).is_err()
+						{
+							self.report(String::from(
+								"Error: marker read failed.",
+							));
+							state = State::Header;
+							continue;
+						}
+
+						let label = self
+							.proto
+							.strings
+							.get(u32::from_le_bytes(label_bytes))
+							.map(|l| l.to_string());
+
+						match action_bytes[0] {
+							0 => self.set_recording(false, label.as_deref()),
+							1 => self.set_recording(true, label.as_deref()),
+							a => self.violation(
+								"Unknown marker action",
+								a.to_string(),
+								&reader,
+							)?,
+						}
+
+						state = State::Header;
+					}
+					State::Entity => {
+						let mut bytes = [0; 12];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: entity read failed.",
+							));
+							state = State::Header;
+							continue;
+						}
+						state = State::Header;
+
+						let id = sdd_core::word(&bytes);
+						let ids = [
+							sdd_core::word(&bytes[4..]),
+							sdd_core::word(&bytes[8..]),
+						];
+						let strings = &self.proto.strings;
+						let (name, kind) = match ids.map(|s| strings.get(s)) {
+							[Some(name), Some(kind)] => {
+								(name.to_string(), kind.to_string())
+							}
+							_ => {
+								self.violation(
+									"Entity with an unknown string",
+									format!("entity {}", id),
+									&reader,
+								)?;
+								continue;
+							}
+						};
+
+						if let Err(e) =
+							self.proto.register_entity(id, &name, &kind)
+						{
+							self.report(format!(
+								"Registering entity {} failed: {}",
+								id, e
+							));
+						}
+					}
+					State::ScopeBegin => {
+						let mut bytes = [0; 20];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: scope read failed.",
+							));
+							state = State::Header;
+							continue;
+						}
+						state = State::Header;
+
+						let id = sdd_core::word(&bytes);
+						let parent = Some(sdd_core::word(&bytes[4..]))
+							.filter(|p| *p != sdd_core::NO_SCOPE);
+						let name = sdd_core::word(&bytes[8..]);
+						let name = match self.proto.strings.get(name) {
+							Some(name) => name.to_string(),
+							None => {
+								self.violation(
+									"Scope with an unknown string",
+									format!("scope {}", id),
+									&reader,
+								)?;
+								continue;
+							}
+						};
+
+						if self.scopes.contains_key(&id) {
+							self.violation(
+								"Scope begun twice",
+								format!("scope {}", id),
+								&reader,
+							)?;
+						}
+						let (path, depth) =
+							match parent.and_then(|p| self.scopes.get(&p)) {
+								Some(p) => (
+									format!(
+										"{};{}",
+										p.path,
+										name.replace(';', ":")
+									),
+									p.depth + 1,
+								),
+								None => (name.replace(';', ":"), 0),
+							};
+						let scope = Scope {
+							parent,
+							name,
+							path,
+							start: sdd_core::dword(&bytes[12..]),
+							depth,
+						};
+						self.scopes.insert(id, scope);
+					}
+					State::Counter => {
+						let mut uid_bytes = [0; 4];
+						let mut delta_bytes = [0; 5];
+						let mut len = 0;
+						let mut read = reader.read_exact(&mut uid_bytes);
+						while read.is_ok() && len < delta_bytes.len() {
+							read = reader
+								.read_exact(&mut delta_bytes[len..len + 1]);
+							len += 1;
+							if delta_bytes[len - 1] & 0x80 == 0 {
+								break;
+							}
+						}
+						state = State::Header;
+						if read.is_err() {
+							self.report(String::from(
+								"Error: counter read failed.",
+							));
+							continue;
+						}
+
+						let uid = sdd_core::word(&uid_bytes);
+						let delta = match sdd_core::varint(&delta_bytes) {
+							Some((delta, _)) => delta,
+							None => {
+								self.violation(
+									"Malformed counter delta",
+									format!("{:02x?}", delta_bytes),
+									&reader,
+								)?;
+								continue;
+							}
+						};
+
+						let desc = self.proto.descriptors.get_mut(uid as usize);
+						let counted = match desc {
+							None => Err("Counter of an undeclared table"),
+							Some(desc) if !desc.loaded => {
+								Err("Counter before an entry of its table")
+							}
+							Some(desc) => {
+								let last = desc.num_fields as usize - 1;
+								match desc.fields[last].as_mut() {
+									Some(FieldDescriptor {
+										data_type: FieldType::Int(v),
+										..
+									}) => {
+										*v = v.wrapping_add(delta);
+										Ok(())
+									}
+									_ => Err("Counter of a table not ending in an int column"),
+								}
+							}
+						};
+
+						match counted {
+							Ok(()) => {
+								self.store_entry(uid, &delta_bytes[..len])?
+							}
+							Err(e) => self.violation(
+								e,
+								format!("uid {}", uid),
+								&reader,
+							)?,
+						}
+					}
+					State::ScopeEnd => {
+						let mut bytes = [0; 12];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: scope read failed.",
+							));
+							state = State::Header;
+							continue;
+						}
+						state = State::Header;
+
+						let id = sdd_core::word(&bytes);
+						match self.scopes.remove(&id) {
+							Some(scope) => self.end_scope(
+								id,
+								scope,
+								sdd_core::dword(&bytes[4..]),
+							),
+							None => self.violation(
+								"Scope end without a begin",
+								format!("scope {}", id),
+								&reader,
+							)?,
+						}
+					}
+					State::Pause => {
+						let mut bytes = [0; 5];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: pause read failed.",
+							));
+							state = State::Header;
 							continue;
-						};
+						}
+						state = State::Header;
 
-						let uid = u32::from_le_bytes(uid_bytes);
-						if uid as usize != self.proto.strings.len() {
-							// error string ids broken.
-							println!("{} String uid does not match!", uid);
-							state = State::HeaderParsing;
+						let reason = self
+							.proto
+							.strings
+							.get(sdd_core::word(&bytes[1..]))
+							.map(|r| r.to_string());
+
+						match (bytes[0], &self.paused) {
+							(1, None) => self.pause(reason),
+							(0, Some(_)) => self.resume(),
+							(1, Some(_)) => self.violation(
+								"Pause while paused",
+								reason.unwrap_or_default(),
+								&reader,
+							)?,
+							(0, None) => self.violation(
+								"Resume without a pause",
+								String::new(),
+								&reader,
+							)?,
+							(a, _) => self.violation(
+								"Unknown pause action",
+								a.to_string(),
+								&reader,
+							)?,
+						}
+					}
+					State::Thread => {
+						let mut bytes = [0; 4];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: thread read failed.",
+							));
+							state = State::Header;
 							continue;
 						}
+						state = State::Header;
 
-						let size = u32::from_le_bytes(size_bytes) as usize;
-						let mut string_bytes = vec![0; size];
-						if reader
-							.read_exact(&mut string_bytes[0..size])
-							.is_err()
-						{
-							println!("Error: failed reading string data.");
-							state = State::HeaderParsing;
+						let name = sdd_core::word(&bytes);
+						self.thread =
+							self.proto.strings.get(name).map(|n| n.to_string());
+						if self.thread.is_none() {
+							self.violation(
+								"Thread with an unknown string",
+								format!("string {}", name),
+								&reader,
+							)?;
+						}
+					}
+					State::Source => {
+						let mut bytes = [0; 4];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: source read failed.",
+							));
+							state = State::Header;
 							continue;
-						};
+						}
+						state = State::Header;
 
-						let string = match String::from_utf8(string_bytes) {
-							Ok(s) => s,
-							Err(e) => {
-								println!("{}", e);
-								state = State::HeaderParsing;
+						let name = sdd_core::word(&bytes);
+						match self.proto.strings.get(name) {
+							Some(name) => self.set_source(name.to_string()),
+							None => self.violation(
+								"Source with an unknown string",
+								format!("string {}", name),
+								&reader,
+							)?,
+						}
+					}
+					State::Attachment => {
+						let mut bytes = [0; 8];
+						if reader.read_exact(&mut bytes).is_err() {
+							self.report(String::from(
+								"Error: attachment read failed.",
+							));
+							state = State::Header;
+							continue;
+						}
+						state = State::Header;
+
+						let size = sdd_core::word(&bytes[4..]) as usize;
+						if size > MAX_ATTACHMENT_SIZE {
+							// Skip the data without buffering it.
+							let mut data = (&mut reader).take(size as u64);
+							if std::io::copy(&mut data, &mut std::io::sink())
+								.is_err()
+							{
+								self.report(String::from(
+									"Error: failed reading attachment data.",
+								));
 								continue;
 							}
-						};
+							self.violation(
+								"Attachment over the size cap",
+								format!("{} bytes", size),
+								&reader,
+							)?;
+							continue;
+						}
 
-						self.proto.strings.push(string);
+						let mut data = vec![0; size];
+						if reader.read_exact(&mut data).is_err() {
+							self.report(String::from(
+								"Error: failed reading attachment data.",
+							));
+							continue;
+						}
 
-						state = State::HeaderParsing;
+						let name = sdd_core::word(&bytes);
+						match self.proto.strings.get(name) {
+							Some(name) => {
+								let name = name.to_string();
+								self.store_attachment(&name, &data);
+							}
+							None => self.violation(
+								"Attachment with an unknown string",
+								format!("string {}", name),
+								&reader,
+							)?,
+						}
 					}
 				}
 			}
@@ -509,11 +3835,15 @@ pub mod dae {
 	#[cfg(test)]
 	mod tests {
 		use super::*;
+		use crate::producer::{Producer, Type, Value};
+		use proptest::prelude::*;
+		use rusqlite::types::ValueRef;
 
 		#[test]
 		fn read_proto() {
-			let data: [u8; 15] = [
+			let data: [u8; 19] = [
 				0x6, 0x0, 0x0, 0x0, // id
+				0x5, 0x0, 0x0, 0x0, // name
 				0x2, // num_fields
 				0x1, // field type
 				0x7, 0x0, 0x0, 0x0, // field name
@@ -521,9 +3851,11 @@ pub mod dae {
 				0x8, 0x0, 0x0, 0x0, // field name
 			];
 
-			match Daemon::read_descriptor(&data) {
-				Ok((desc, id, _read)) => {
+			let mut reader = BufReader::new(&data[..]);
+			match Daemon::read_descriptor(&mut reader) {
+				Ok((desc, id)) => {
 					assert_eq!(id, 6);
+					assert_eq!(desc.name, 5);
 					assert_eq!(desc.num_fields, 2);
 
 					fn match_field(
@@ -553,5 +3885,1021 @@ pub mod dae {
 				_ => panic!(),
 			};
 		}
+
+		#[test]
+		fn view_cmd() {
+			let mut desc = EntryDescriptor::make();
+			desc.table = String::from("log");
+			desc.columns = vec![String::from("ts"), String::from("msg")];
+			desc.num_fields = 2;
+			desc.fields[0] = Some(FieldDescriptor {
+				data_type: FieldType::Int(0),
+				name: 1,
+			});
+			desc.fields[1] = Some(FieldDescriptor {
+				data_type: FieldType::Str(0),
+				name: 2,
+			});
+
+			assert_eq!(
+				desc.make_view_cmd().unwrap(),
+				"CREATE VIEW IF NOT EXISTS log_view AS SELECT t.ts, \
+				 s1.value AS msg FROM log t \
+				 LEFT JOIN _sdd_strings s1 ON s1.id = t.msg"
+			);
+
+			desc.source = true;
+			assert!(desc
+				.make_view_cmd()
+				.unwrap()
+				.contains("s1.value AS msg, t.source FROM"));
+
+			desc.num_fields = 1;
+			desc.fields[1] = None;
+			desc.columns.truncate(1);
+			assert!(desc.make_view_cmd().is_none());
+
+			desc.compile();
+			assert_eq!(
+				desc.sql_cmd,
+				"INSERT INTO log (ts, source) VALUES (?1, ?2)"
+			);
+			assert_eq!(
+				desc.make_create_cmd(),
+				"CREATE TABLE IF NOT EXISTS log (ts INTEGER, source TEXT)"
+			);
+			assert_eq!(table_prefix("127.0.0.1:2001"), "_127_0_0_1_2001_");
+		}
+
+		#[test]
+		fn decode_slices() {
+			let data = vec![
+				0x04, 0x00, 0x00, 0x00, // uid
+				0x07, 0x00, 0x00, 0x00, // name
+				0x03, // field num
+				0x01, 0x01, 0x00, 0x00, 0x00, // Int
+				0x03, 0x02, 0x00, 0x00, 0x00, // Bool
+				0x02, 0x03, 0x00, 0x00, 0x00, // Float
+			];
+			let (desc, uid, len) = decode_descriptor(&data).unwrap();
+			assert_eq!((uid, len), (4, data.len()));
+			assert_eq!(desc.entry_size(), 9);
+
+			let mut bytes = vec![0x2a, 0x00, 0x00, 0x00, 0x01];
+			bytes.extend_from_slice(&1.5f32.to_le_bytes());
+			let entry = decode_entry(&desc, &bytes).unwrap();
+			let values: Vec<String> =
+				entry.values().map(|v| v.value_string()).collect();
+			assert_eq!(values, vec!["42", "true", "1.500"]);
+
+			assert!(decode_entry(&desc, &bytes[..8]).is_err());
+		}
+
+		#[test]
+		fn hexdump_lines() {
+			let bytes: Vec<u8> = (0x41..0x53).collect();
+			let dump = hexdump(0x10, &bytes);
+			let lines: Vec<&str> = dump.lines().collect();
+
+			assert_eq!(lines.len(), 2);
+			assert!(lines[0].starts_with("00000010  41 42 43"));
+			assert!(lines[0].ends_with("|ABCDEFGHIJKLMNOP|"));
+			assert!(lines[1].starts_with("00000020  51 52 "));
+			assert!(lines[1].ends_with("|QR|"));
+		}
+
+		#[test]
+		fn validate_descriptor() {
+			let mut strings = Strings::default();
+			strings.begin_session();
+			strings.intern(String::from("log"));
+
+			let mut desc = EntryDescriptor::make();
+			assert!(desc.validate(&strings).is_err());
+
+			desc.num_fields = 1;
+			desc.fields[0] = Some(FieldDescriptor {
+				data_type: FieldType::Int(0),
+				name: 1,
+			});
+			assert!(desc.validate(&strings).is_err());
+
+			strings.intern(String::from("ts"));
+			assert!(desc.validate(&strings).is_ok());
+		}
+
+		#[test]
+		fn string_memory_cap() {
+			let mut strings = Strings {
+				limit: Some(4),
+				..Strings::default()
+			};
+
+			assert_eq!(strings.intern(String::from("abc")), (0, true));
+			// Over the cap, replaced by the placeholder.
+			assert_eq!(strings.intern(String::from("defg")), (1, true));
+			assert_eq!(strings.intern(String::from("abc")), (0, false));
+			assert_eq!(strings.intern(String::from("hij")), (1, false));
+			assert_eq!(strings.resolve(1), Some(DROPPED_STRING));
+			assert_eq!(strings.dropped, 2);
+		}
+
+		#[test]
+		fn string_interning() {
+			let mut strings = Strings::default();
+			assert_eq!(strings.intern(String::from("a")), (0, true));
+			assert_eq!(strings.intern(String::from("b")), (1, true));
+
+			strings.begin_session();
+			assert_eq!(strings.intern(String::from("b")), (1, false));
+			assert_eq!(strings.intern(String::from("c")), (2, true));
+
+			assert_eq!(strings.canonical(0), Some(1));
+			assert_eq!(strings.get(1), Some("c"));
+			assert_eq!(strings.resolve(0), Some("a"));
+			assert_eq!(strings.get(2), None);
+		}
+
+		/// Captures `reader` into `proto`, `configure` setting up the daemon
+		/// first.
+		fn capture_into<R: Read>(
+			proto: Protocol,
+			reader: R,
+			configure: impl FnOnce(&mut Daemon),
+		) -> (Daemon, Result<(), Error>) {
+			let mut daemon = Daemon::new(proto);
+			daemon.verbose = false;
+			daemon.exit_on_eof = true;
+			configure(&mut daemon);
+			let result = daemon.capture_reader(reader, "test");
+			(daemon, result)
+		}
+
+		/// Captures `bytes` into an in-memory database, which has to succeed.
+		fn capture(
+			bytes: &[u8],
+			configure: impl FnOnce(&mut Daemon),
+		) -> Daemon {
+			let proto = Protocol::new(String::from(":memory:")).unwrap();
+			let (daemon, result) = capture_into(proto, bytes, configure);
+			assert!(result.is_ok());
+			daemon
+		}
+
+		/// Rows of `sql` as the sqlite shell lists them, `|` separating the
+		/// columns.
+		fn rows(con: &rusqlite::Connection, sql: &str) -> Vec<String> {
+			let mut stmt = con.prepare(sql).unwrap();
+			let columns = stmt.column_count();
+			let row = |r: &rusqlite::Row| {
+				let cells: Vec<String> = (0..columns)
+					.map(|i| match r.get_raw(i) {
+						ValueRef::Null => String::new(),
+						ValueRef::Integer(v) => v.to_string(),
+						ValueRef::Real(v) => v.to_string(),
+						ValueRef::Text(t) => {
+							String::from_utf8_lossy(t).into_owned()
+						}
+						ValueRef::Blob(b) => format!("{:?}", b),
+					})
+					.collect();
+				Ok(cells.join("|"))
+			};
+			stmt.query_map(rusqlite::NO_PARAMS, row)
+				.unwrap()
+				.collect::<rusqlite::Result<_>>()
+				.unwrap()
+		}
+
+		#[test]
+		fn append_fingerprint() {
+			use crate::producer::{Producer, Type, Value};
+
+			let path = std::env::temp_dir()
+				.join(format!("sdd-append-test-{}.db", std::process::id()));
+			let path = path.to_string_lossy().into_owned();
+			let capture = |proto: Protocol, frame_ms: Type| {
+				let (mut producer, bodies) = Producer::channel();
+				producer.string("unused").unwrap();
+				let columns = [("ts", Type::Int), ("frame_ms", frame_ms)];
+				let uid = producer.table("frame", &columns).unwrap();
+				producer
+					.entry(uid, &[Value::Int(1), Value::Int(2)])
+					.unwrap();
+				drop(producer);
+
+				let (daemon, result) = capture_into(proto, bodies, |_| {});
+				(result, daemon.proto)
+			};
+
+			let (result, _) =
+				capture(Protocol::new(path.clone()).unwrap(), Type::Int);
+			assert!(result.is_ok());
+
+			let (result, proto) =
+				capture(Protocol::append(path.clone()).unwrap(), Type::Int);
+			assert!(result.is_ok());
+			assert_eq!(
+				rows(&proto.dbs[0], "SELECT COUNT(*) FROM frame"),
+				["2"]
+			);
+			assert_eq!(proto.strings.resolve(1), Some("frame"));
+			drop(proto);
+
+			let (result, _) =
+				capture(Protocol::append(path.clone()).unwrap(), Type::Float);
+			assert!(matches!(result, Err(Error::Fatal(_))));
+			let _ = fs::remove_file(&path);
+		}
+
+		#[test]
+		fn sampled_counters() {
+			let mut producer = Producer::new(vec![]);
+			let columns = [("ts", Type::Int), ("n", Type::Int)];
+			let uid = producer.table("hits", &columns).unwrap();
+			producer.sample(uid, 2);
+			for ts in 0..4 {
+				producer
+					.entry(uid, &[Value::Int(ts), Value::Int(0)])
+					.unwrap();
+				producer.counter(uid, 1).unwrap();
+				producer.counter(uid, 2).unwrap();
+			}
+			let daemon = capture(&producer.into_inner(), |_| {});
+
+			let sql = "SELECT ts, n FROM hits ORDER BY rowid";
+			assert_eq!(
+				rows(&daemon.proto.dbs[0], sql),
+				["0|0", "0|1", "0|3", "2|0", "2|1", "2|3"]
+			);
+		}
+
+		/// Reads a byte at a time, timing out in between.
+		struct Trickle<'a> {
+			bytes: &'a [u8],
+			timed_out: bool,
+		}
+
+		impl Read for Trickle<'_> {
+			fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+				self.timed_out = !self.timed_out;
+				if !self.timed_out || self.bytes.is_empty() {
+					return self.bytes.read(&mut buf[..1]);
+				}
+				Err(ErrorKind::TimedOut.into())
+			}
+		}
+
+		#[test]
+		fn timeouts_within_messages() {
+			let mut producer = Producer::new(vec![]);
+			let columns = [("ts", Type::Int), ("n", Type::Int)];
+			let uid = producer.table("hits", &columns).unwrap();
+			producer
+				.entry(uid, &[Value::Int(1), Value::Int(10)])
+				.unwrap();
+			producer.counter(uid, 300).unwrap();
+			let bytes = producer.into_inner();
+
+			let proto = Protocol::new(String::from(":memory:")).unwrap();
+			let trickle = Trickle {
+				bytes: &bytes[..],
+				timed_out: false,
+			};
+			let (daemon, result) = capture_into(proto, trickle, |_| {});
+			assert!(result.is_ok());
+
+			let sql = "SELECT ts, n FROM hits ORDER BY rowid";
+			assert_eq!(rows(&daemon.proto.dbs[0], sql), ["1|10", "1|310"]);
+			assert_eq!(daemon.stats.lock().unwrap().error_count, 0);
+		}
+
+		#[test]
+		fn schema_backfill() {
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+			producer.entry(uid, &[Value::Int(7)]).unwrap();
+			let schema = Schema::parse(
+				"[[table]]\nname = \"frame\"\ncolumns = [\
+				 { name = \"ts\", type = \"int\" }, \
+				 { name = \"lod\", type = \"int\", default = 2 }]",
+			)
+			.unwrap();
+			let daemon = capture(&producer.into_inner(), |daemon| {
+				daemon.schema = Some(schema);
+			});
+
+			let con = &daemon.proto.dbs[0];
+			assert_eq!(rows(con, "SELECT ts, lod FROM frame"), ["7|2"]);
+			assert_eq!(
+				rows(con, "SELECT error FROM _sdd_errors"),
+				["Schema skew: frame lacks (lod: int), filled with defaults"]
+			);
+		}
+
+		#[test]
+		fn receive_times() {
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+			for ts in 0..2 {
+				producer.entry(uid, &[Value::Int(ts)]).unwrap();
+			}
+			let daemon = capture(&producer.into_inner(), |daemon| {
+				daemon.receive_time = true;
+			});
+
+			let con = &daemon.proto.dbs[0];
+			let times = |sql| -> Vec<i64> {
+				rows(con, sql).iter().map(|t| t.parse().unwrap()).collect()
+			};
+			assert!(
+				times("SELECT recv_time FROM frame")[0] > 1_600_000_000_000_000
+			);
+			let mono = times("SELECT recv_mono FROM frame ORDER BY ts");
+			assert!(mono[0] <= mono[1]);
+		}
+
+		#[test]
+		fn table_info() {
+			let mut producer = Producer::new(vec![]);
+			let columns = [("ts", Type::Int), ("gpu_ms", Type::Float)];
+			let uid = producer.table("frame", &columns).unwrap();
+			for ts in 0..3 {
+				producer
+					.entry(uid, &[Value::Int(ts), Value::Float(1.)])
+					.unwrap();
+			}
+			let daemon = capture(&producer.into_inner(), |_| {});
+
+			let tables = daemon.proto.tables();
+			assert_eq!(tables.len(), 1);
+			assert_eq!((tables[0].name.as_str(), tables[0].rows), ("frame", 3));
+			let column = &tables[0].columns[1];
+			assert_eq!(
+				(column.name.as_str(), column.data_type),
+				("gpu_ms", Type::Float)
+			);
+		}
+
+		#[test]
+		fn table_naming() {
+			let mut producer = Producer::new(vec![]);
+			let columns = [("Frame Id", Type::Int), ("gpuMs", Type::Float)];
+			producer.table("FrameTime", &columns).unwrap();
+			let daemon = capture(&producer.into_inner(), |daemon| {
+				daemon.naming = NamingConfig {
+					prefix: String::from("cap1_"),
+					snake_case: true,
+					..NamingConfig::default()
+				};
+				let rename =
+					(String::from("FrameTime.gpuMs"), String::from("gpu"));
+				daemon.naming.rename.extend([rename]);
+			});
+
+			let tables = daemon.proto.tables();
+			assert_eq!(tables[0].name, "cap1_frame_time");
+			let columns: Vec<&str> =
+				tables[0].columns.iter().map(|c| c.name.as_str()).collect();
+			assert_eq!(columns, ["frame_id", "gpu"]);
+			let con = &daemon.proto.dbs[0];
+			assert!(con.prepare("SELECT gpu FROM cap1_frame_time").is_ok());
+		}
+
+		#[test]
+		fn drop_columns() {
+			let mut producer = Producer::new(vec![]);
+			let columns =
+				[("ts", Type::Int), ("debug", Type::Str), ("ms", Type::Float)];
+			let uid = producer.table("frame", &columns).unwrap();
+			let text = producer.string("culled 3 meshes").unwrap();
+			for ts in 0..2 {
+				let values =
+					[Value::Int(ts), Value::Str(text), Value::Float(2.)];
+				producer.entry(uid, &values).unwrap();
+			}
+			let daemon = capture(&producer.into_inner(), |daemon| {
+				daemon.drops = vec![DropConfig {
+					tables: String::from("fr*"),
+					columns: vec![String::from("debug*")],
+				}];
+			});
+
+			let con = &daemon.proto.dbs[0];
+			let stmt = con.prepare("SELECT * FROM frame").unwrap();
+			assert_eq!(stmt.column_names(), ["ts", "ms"]);
+			assert_eq!(rows(con, "SELECT * FROM frame"), ["0|2", "1|2"]);
+		}
+
+		#[test]
+		fn finalize_on_end() {
+			use crate::finalize::IndexConfig;
+
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+			for ts in 0..3 {
+				producer.entry(uid, &[Value::Int(ts)]).unwrap();
+			}
+			let bytes = producer.into_inner();
+
+			let path = std::env::temp_dir()
+				.join(format!("sdd-finalize-test-{}.db", std::process::id()));
+			let path = path.to_string_lossy().into_owned();
+			let mut proto = Protocol::new(path.clone()).unwrap();
+			proto.finalize_with(FinalizeConfig {
+				index: vec![IndexConfig {
+					table: String::from("frame"),
+					columns: vec![String::from("ts")],
+				}],
+				analyze: true,
+				vacuum: true,
+				zstd: None,
+			});
+			let (daemon, result) = capture_into(proto, &bytes[..], |_| {});
+			assert!(result.is_ok());
+			drop(daemon);
+
+			let con = rusqlite::Connection::open(&path).unwrap();
+			let sql =
+				"SELECT name FROM sqlite_master WHERE tbl_name = 'frame' \
+			           AND type = 'index'";
+			assert_eq!(rows(&con, sql), ["frame_ts"]);
+			let _ = fs::remove_file(&path);
+		}
+
+		#[test]
+		fn batched_commits() {
+			use crate::commit::{CommitConfig, Policy};
+
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+			for ts in 0..5 {
+				producer.entry(uid, &[Value::Int(ts)]).unwrap();
+			}
+			let bytes = producer.into_inner();
+
+			let path = std::env::temp_dir()
+				.join(format!("sdd-commit-test-{}.db", std::process::id()));
+			let path = path.to_string_lossy().into_owned();
+			let mut proto = Protocol::new(path.clone()).unwrap();
+			let config = CommitConfig {
+				policy: Policy::Fixed,
+				rows: 2,
+				..CommitConfig::default()
+			};
+			proto.commit_with(config.pacing().unwrap());
+			let (daemon, result) = capture_into(proto, &bytes[..], |_| {});
+			assert!(result.is_ok());
+			assert!(daemon.proto.dbs[0].is_autocommit());
+
+			let con = rusqlite::Connection::open(&path).unwrap();
+			assert_eq!(rows(&con, "SELECT count(*) FROM frame"), ["5"]);
+			drop(daemon);
+			let _ = fs::remove_file(&path);
+		}
+
+		#[test]
+		fn parallel_import() {
+			let mut producer = Producer::new(vec![]);
+			let columns = [("ts", Type::Int), ("scene", Type::Str)];
+			let uid = producer.table("frame", &columns).unwrap();
+			let mut scene = producer.string("menu").unwrap();
+			for ts in 0..10_000 {
+				if ts == 6000 {
+					scene = producer.string("hud").unwrap();
+				}
+				let values = [Value::Int(ts), Value::Str(scene)];
+				producer.entry(uid, &values).unwrap();
+			}
+			let bytes = producer.into_inner();
+			let index = raw::Index::build(&bytes);
+
+			let path = std::env::temp_dir()
+				.join(format!("sdd-import-test-{}.db", std::process::id()));
+			let path = path.to_string_lossy().into_owned();
+			let mut proto = Protocol::new(path.clone()).unwrap();
+			proto.commit_with(
+				crate::commit::CommitConfig {
+					policy: crate::commit::Policy::Fixed,
+					..Default::default()
+				}
+				.pacing()
+				.unwrap(),
+			);
+			let mut daemon = Daemon::new(proto);
+			daemon.verbose = false;
+			assert!(daemon.import(&bytes, &index, 3).is_ok());
+			assert!(!daemon.exit_on_eof);
+			assert_eq!(daemon.stats.lock().unwrap().entries, 10_000);
+			drop(daemon);
+
+			let con = rusqlite::Connection::open(&path).unwrap();
+			let sql = "SELECT count(*), max(rowid) FROM frame \
+			           WHERE ts = rowid - 1";
+			assert_eq!(rows(&con, sql), ["10000|10000"]);
+			let sql = "SELECT count(DISTINCT scene) FROM frame";
+			assert_eq!(rows(&con, sql), ["2"]);
+			let _ = fs::remove_file(&path);
+		}
+
+		#[test]
+		fn checkpoint_on_request() {
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+			for ts in 0..3 {
+				producer.entry(uid, &[Value::Int(ts)]).unwrap();
+			}
+			let bytes = producer.into_inner();
+
+			let path = std::env::temp_dir()
+				.join(format!("sdd-checkpoint-test-{}.db", std::process::id()));
+			let path = path.to_string_lossy().into_owned();
+			let mut proto = Protocol::new(path.clone()).unwrap();
+			proto.wal().unwrap();
+			let (mut daemon, result) =
+				capture_into(proto, &bytes[..], |daemon| {
+					daemon.checkpoint_requested.store(true, Ordering::Relaxed);
+				});
+			assert!(result.is_ok());
+			assert!(!daemon.checkpoint_requested.load(Ordering::Relaxed));
+			daemon.checkpoint();
+
+			let con = rusqlite::Connection::open(&path).unwrap();
+			// Busy, frames in the log and frames checkpointed.
+			let checkpoint = rows(&con, "PRAGMA wal_checkpoint(PASSIVE)");
+			let frames: Vec<&str> = checkpoint[0].split('|').collect();
+			assert_eq!(frames[1], frames[2]);
+			assert_eq!(rows(&con, "SELECT count(*) FROM frame"), ["3"]);
+			drop(daemon);
+			for suffix in ["", "-wal", "-shm"] {
+				let _ = fs::remove_file(format!("{}{}", path, suffix));
+			}
+		}
+
+		#[test]
+		fn rate_limited_entries() {
+			use crate::limit::LimitConfig;
+
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("hits", &[("n", Type::Int)]).unwrap();
+			for n in 0..5 {
+				producer.entry(uid, &[Value::Int(n)]).unwrap();
+			}
+			let daemon = capture(&producer.into_inner(), |daemon| {
+				daemon.limits = Limits::new(&[LimitConfig {
+					tables: String::from("hits"),
+					rows_per_second: 2,
+				}]);
+			});
+			assert_eq!(daemon.stats.lock().unwrap().tables[0].dropped, 3);
+
+			let con = &daemon.proto.dbs[0];
+			assert_eq!(rows(con, "SELECT count(*) FROM hits"), ["2"]);
+			let quarantined = rows(con, "SELECT count(*) FROM _sdd_quarantine");
+			assert_eq!(quarantined, ["0"]);
+		}
+
+		#[test]
+		fn connection_quota() {
+			use crate::limit::QuotaConfig;
+
+			let mut producer = Producer::new(vec![]);
+			for name in ["frame", "hud", "net"] {
+				producer.table(name, &[("ts", Type::Int)]).unwrap();
+			}
+			let bytes = producer.into_inner();
+
+			let proto = Protocol::new(String::from(":memory:")).unwrap();
+			let (daemon, result) = capture_into(proto, &bytes[..], |daemon| {
+				daemon.quota = Quota::new(&QuotaConfig {
+					tables: Some(2),
+					..QuotaConfig::default()
+				});
+			});
+			assert!(matches!(result, Err(Error::Quota(_))));
+			assert_eq!(daemon.proto.descriptors.len(), 2);
+
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("log", &[("msg", Type::Str)]).unwrap();
+			for msg in ["a", "b", "c"] {
+				let id = producer.string(msg).unwrap();
+				producer.entry(uid, &[Value::Str(id)]).unwrap();
+			}
+			let bytes = producer.into_inner();
+
+			let proto = Protocol::new(String::from(":memory:")).unwrap();
+			let (_, result) = capture_into(proto, &bytes[..], |daemon| {
+				daemon.quota = Quota::new(&QuotaConfig {
+					strings: Some(2),
+					..QuotaConfig::default()
+				});
+			});
+			assert!(matches!(result, Err(Error::Quota(_))));
+		}
+
+		#[test]
+		fn quota_rows_per_second() {
+			use crate::limit::QuotaConfig;
+
+			let mut producer = Producer::new(vec![]);
+			let frame = producer.table("frame", &[("n", Type::Int)]).unwrap();
+			let hud = producer.table("hud", &[("n", Type::Int)]).unwrap();
+			for n in 0..3 {
+				producer.entry(frame, &[Value::Int(n)]).unwrap();
+				producer.entry(hud, &[Value::Int(n)]).unwrap();
+			}
+			let daemon = capture(&producer.into_inner(), |daemon| {
+				daemon.quota = Quota::new(&QuotaConfig {
+					rows_per_second: Some(4),
+					..QuotaConfig::default()
+				});
+			});
+
+			let stats = daemon.stats.lock().unwrap();
+			assert_eq!(stats.tables[0].dropped + stats.tables[1].dropped, 2);
+			drop(stats);
+			let con = &daemon.proto.dbs[0];
+			let sql = "SELECT (SELECT count(*) FROM frame) + \
+			           (SELECT count(*) FROM hud), \
+			           (SELECT count(*) FROM _sdd_quarantine)";
+			assert_eq!(rows(con, sql), ["4|0"]);
+		}
+
+		#[test]
+		fn stop_request() {
+			use std::net::TcpListener;
+
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let stream = TcpStream::connect(listener.local_addr().unwrap());
+			let (_producer, _) = listener.accept().unwrap();
+
+			let mut daemon = Daemon::new(Protocol::without_output());
+			daemon.verbose = false;
+			let stop = daemon.stop_requested.clone();
+			let capture =
+				thread::spawn(move || daemon.capture(stream.unwrap(), "test"));
+			thread::sleep(STOP_POLL);
+			stop.store(true, Ordering::Relaxed);
+			assert!(capture.join().unwrap().is_ok());
+		}
+
+		#[test]
+		fn send_sampling() {
+			use std::net::TcpListener;
+
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let addr = listener.local_addr().unwrap();
+			let producer = thread::spawn(move || {
+				let (mut stream, _) = listener.accept().unwrap();
+				let mut producer = Producer::new(vec![]);
+				producer.table("particle", &[("n", Type::Int)]).unwrap();
+				stream.write_all(&producer.into_inner()).unwrap();
+				stream.shutdown(Shutdown::Write).unwrap();
+
+				let mut controls = vec![];
+				stream.read_to_end(&mut controls).unwrap();
+				controls
+			});
+
+			let mut daemon = Daemon::new(Protocol::without_output());
+			daemon.verbose = false;
+			daemon.exit_on_eof = true;
+			daemon.sampling.insert(String::from("particle"), 0);
+			let stream = TcpStream::connect(addr).unwrap();
+			assert!(daemon.capture(stream, "test").is_ok());
+			drop(daemon);
+
+			let mut expected = [0; sdd_core::CONTROL_SIZE];
+			sdd_core::Encoder::new(&mut expected).control(0, 0).unwrap();
+			assert_eq!(producer.join().unwrap(), expected);
+		}
+
+		#[test]
+		fn strict_and_lenient() {
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("n", Type::Int)]).unwrap();
+			producer.entry(uid, &[Value::Int(1)]).unwrap();
+			producer.get_mut().extend_from_slice(b"junk");
+			producer.entry(uid, &[Value::Int(2)]).unwrap();
+			producer.entry(uid + 1, &[Value::Int(3)]).unwrap();
+			producer.entry(uid, &[Value::Int(4)]).unwrap();
+			let bytes = producer.into_inner();
+
+			let capture = |strict: bool| {
+				let proto = Protocol::without_output();
+				let (daemon, result) =
+					capture_into(proto, &bytes[..], |d| d.strict = strict);
+				let stats = daemon.stats.lock().unwrap();
+				(result, stats.entries, stats.violations)
+			};
+
+			let (result, entries, violations) = capture(false);
+			assert!(result.is_ok());
+			assert_eq!((entries, violations), (3, 3));
+
+			let (result, entries, violations) = capture(true);
+			assert!(matches!(result, Err(Error::Violation(_))));
+			assert_eq!((entries, violations), (1, 1));
+		}
+
+		#[test]
+		fn unknown_descriptor_names() {
+			let mut buf = [0; 64];
+			let mut encoder = sdd_core::Encoder::new(&mut buf);
+			encoder.table(0, 7, &[(8, Type::Int)]).unwrap();
+			let bytes = encoder.bytes().to_vec();
+
+			let capture = |strict: bool| {
+				let proto = Protocol::without_output();
+				let (daemon, result) =
+					capture_into(proto, &bytes[..], |d| d.strict = strict);
+				let violations = daemon.stats.lock().unwrap().violations;
+				(result, violations, daemon.proto.descriptors.len())
+			};
+
+			let (result, violations, tables) = capture(false);
+			assert!(result.is_ok());
+			assert_eq!((violations, tables), (1, 0));
+
+			let (result, _, _) = capture(true);
+			assert!(matches!(result, Err(Error::Violation(_))));
+		}
+
+		#[test]
+		fn failed_session_disconnects() {
+			use crate::http;
+			use crate::notify::{Event, Format, NotifyConfig};
+			use std::io::Write;
+			use std::net::TcpListener;
+
+			let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+			let url = format!("http://{}/hook", listener.local_addr().unwrap());
+			let received = thread::spawn(move || {
+				let (mut stream, _) = listener.accept().unwrap();
+				let mut reader = BufReader::new(stream.try_clone().unwrap());
+				let request = http::Request::read(&mut reader).unwrap();
+				stream
+					.write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+					.unwrap();
+				request.body
+			});
+
+			let mut producer = Producer::new(vec![]);
+			let uid = producer.table("frame", &[("n", Type::Int)]).unwrap();
+			producer.entry(uid, &[Value::Int(1)]).unwrap();
+			producer.get_mut().extend_from_slice(b"junk");
+			producer.entry(uid, &[Value::Int(2)]).unwrap();
+			let bytes = producer.into_inner();
+
+			let config = NotifyConfig {
+				url,
+				format: Format::Json,
+				events: vec![Event::Disconnect],
+			};
+			let proto = Protocol::without_output();
+			let (daemon, result) = capture_into(proto, &bytes[..], |daemon| {
+				daemon.strict = true;
+				daemon.notify = Notify::new(&[config], String::new()).unwrap();
+			});
+			assert!(result.is_err());
+			drop(daemon);
+
+			let posted: serde_json::Value =
+				serde_json::from_slice(&received.join().unwrap()).unwrap();
+			assert_eq!(posted["event"], "disconnect");
+			assert_eq!(posted["entries"], 1);
+			assert!(posted["error"].is_string());
+		}
+
+		#[test]
+		fn entity_view() {
+			let mut producer = Producer::new(vec![]);
+			let uid = producer
+				.table(
+					"hit",
+					&[
+						("source_entity_id", Type::Int),
+						("entity_id", Type::Int),
+					],
+				)
+				.unwrap();
+			producer.entity(7, "player", "hero").unwrap();
+			producer.entity(8, "orc", "enemy").unwrap();
+			producer
+				.entry(uid, &[Value::Int(7), Value::Int(8)])
+				.unwrap();
+			producer
+				.entry(uid, &[Value::Int(8), Value::Int(9)])
+				.unwrap();
+			let daemon = capture(&producer.into_inner(), |_| {});
+
+			let sql =
+				"SELECT source_entity_name, entity_type FROM hit_entities \
+			           ORDER BY rowid";
+			assert_eq!(
+				rows(&daemon.proto.dbs[0], sql),
+				["player|enemy", "orc|"]
+			);
+		}
+
+		#[test]
+		fn validation_policies() {
+			let mut producer = Producer::new(vec![]);
+			let uid = producer
+				.table("frame", &[("n", Type::Int), ("frame_ms", Type::Int)])
+				.unwrap();
+			for (n, frame_ms) in [(1, 16), (2, 50000), (3, 20), (4, 0)] {
+				producer
+					.entry(uid, &[Value::Int(n), Value::Int(frame_ms)])
+					.unwrap();
+			}
+			let bytes = producer.into_inner();
+
+			let rule = |rule: &str, policy| crate::validate::ValidateConfig {
+				rule: String::from(rule),
+				policy,
+			};
+			let configs = [
+				rule("frame.frame_ms in ..10000", Policy::Flag),
+				rule("frame_ms in 1..", Policy::Quarantine),
+			];
+			let daemon = capture(&bytes, |daemon| {
+				daemon.naming.prefix = String::from("run_");
+				daemon.validations = Validations::new(&configs).unwrap();
+			});
+			assert_eq!(daemon.stats.lock().unwrap().tables[0].invalid, 2);
+
+			let con = &daemon.proto.dbs[0];
+			let sql = "SELECT n, valid FROM run_frame ORDER BY rowid";
+			assert_eq!(rows(con, sql), ["1|1", "2|0", "3|1"]);
+			assert_eq!(
+				rows(con, "SELECT reason FROM _sdd_quarantine"),
+				["frame_ms = 0 breaks frame_ms in 1.."]
+			);
+		}
+
+		#[test]
+		fn validation_columns() {
+			let path = std::env::temp_dir()
+				.join(format!("sdd-valid-test-{}.db", std::process::id()));
+			let path = path.to_string_lossy().into_owned();
+			let capture = |proto: Protocol, rule: &str| {
+				let mut producer = Producer::new(vec![]);
+				let uid = producer.table("frame", &[("n", Type::Int)]).unwrap();
+				producer.entry(uid, &[Value::Int(7)]).unwrap();
+				let probe = producer.table("probe", &[("valid", Type::Int)]);
+				producer.entry(probe.unwrap(), &[Value::Int(2)]).unwrap();
+				let bytes = producer.into_inner();
+
+				let config = crate::validate::ValidateConfig {
+					rule: String::from(rule),
+					policy: Policy::Flag,
+				};
+				let (daemon, result) = capture_into(proto, &bytes[..], |d| {
+					d.validations = Validations::new(&[config]).unwrap();
+				});
+				assert!(result.is_ok());
+				daemon.proto
+			};
+
+			// The producer's own `valid` column is not flagged over.
+			let proto =
+				capture(Protocol::new(path.clone()).unwrap(), "valid in 0..1");
+			drop(proto);
+
+			// Appending adds the column to a table created without it.
+			let proto = capture(
+				Protocol::append(path.clone()).unwrap(),
+				"frame.n in ..5",
+			);
+			let con = &proto.dbs[0];
+			let sql = "SELECT n, valid FROM frame ORDER BY rowid";
+			assert_eq!(rows(con, sql), ["7|", "7|0"]);
+			let sql = "SELECT COUNT(*) FROM probe WHERE valid = 2";
+			assert_eq!(rows(con, sql), ["2"]);
+			drop(proto);
+			for suffix in ["", "-wal", "-shm"].iter() {
+				let _ = fs::remove_file(format!("{}{}", path, suffix));
+			}
+		}
+
+		/// A value of type `t`, strings being sent as text.
+		fn value(t: Type) -> BoxedStrategy<(Value, Option<String>)> {
+			match t {
+				Type::Int => {
+					any::<u32>().prop_map(|v| (Value::Int(v), None)).boxed()
+				}
+				Type::Float => {
+					any::<f32>().prop_map(|v| (Value::Float(v), None)).boxed()
+				}
+				Type::Bool => {
+					any::<bool>().prop_map(|v| (Value::Bool(v), None)).boxed()
+				}
+				Type::Str => {
+					".{0,12}".prop_map(|s| (Value::Str(0), Some(s))).boxed()
+				}
+			}
+		}
+
+		/// Columns of a table along with its rows.
+		fn table() -> impl Strategy<Value = Vec<Vec<(Value, Option<String>)>>> {
+			let types = prop_oneof![
+				Just(Type::Int),
+				Just(Type::Float),
+				Just(Type::Bool),
+				Just(Type::Str),
+			];
+			prop::collection::vec(types, 1..=sdd_core::MAX_FIELDS)
+				.prop_flat_map(|types| {
+					let row: Vec<_> = types.into_iter().map(value).collect();
+					prop::collection::vec(row, 1..8)
+				})
+		}
+
+		proptest! {
+			#[test]
+			fn round_trip(tables in prop::collection::vec(table(), 1..4)) {
+				let mut producer = Producer::new(vec![]);
+				for (i, rows) in tables.iter().enumerate() {
+					let columns: Vec<(String, Type)> = rows[0]
+						.iter()
+						.enumerate()
+						.map(|(c, (v, _))| (format!("c{}", c), v.data_type()))
+						.collect();
+					let columns: Vec<(&str, Type)> =
+						columns.iter().map(|(c, t)| (c.as_str(), *t)).collect();
+					producer.table(&format!("t{}", i), &columns).unwrap();
+				}
+				// Entries of the tables interleaved.
+				let longest = tables.iter().map(Vec::len).max().unwrap();
+				for r in 0..longest {
+					for (uid, rows) in tables.iter().enumerate() {
+						let row = match rows.get(r) {
+							Some(row) => row,
+							None => continue,
+						};
+						let mut values = vec![];
+						for (v, s) in row {
+							values.push(match s {
+								Some(s) => Value::Str(producer.string(s).unwrap()),
+								None => *v,
+							});
+						}
+						producer.entry(uid as u32, &values).unwrap();
+					}
+				}
+
+				let daemon = capture(&producer.into_inner(), |_| {});
+
+				let con = &daemon.proto.dbs[0];
+				for (i, rows) in tables.iter().enumerate() {
+					let mut stmt = con
+						.prepare(&format!("SELECT * FROM t{} ORDER BY rowid", i))
+						.unwrap();
+					let mut stored = stmt.query(rusqlite::NO_PARAMS).unwrap();
+					for row in rows {
+						let stored = stored.next().unwrap().unwrap();
+						for (c, (v, s)) in row.iter().enumerate() {
+							let cell = stored.get_raw(c);
+							match (v, s, cell) {
+								(Value::Int(v), _, ValueRef::Integer(x)) => {
+									prop_assert_eq!(*v as i64, x)
+								}
+								(Value::Float(v), _, ValueRef::Null) => {
+									prop_assert!(v.is_nan())
+								}
+								(Value::Float(v), _, ValueRef::Real(x)) => {
+									prop_assert_eq!(*v, x as f32)
+								}
+								(Value::Float(v), _, ValueRef::Integer(x)) => {
+									prop_assert_eq!(*v, x as f32)
+								}
+								(Value::Bool(v), _, ValueRef::Integer(x)) => {
+									prop_assert_eq!(*v as i64, x)
+								}
+								(Value::Str(_), Some(s), ValueRef::Text(id)) => {
+									let text: String = con
+										.query_row(
+											"SELECT value FROM _sdd_strings \
+											 WHERE id = ?1",
+											&[&String::from_utf8_lossy(id)],
+											|r| r.get(0),
+										)
+										.unwrap();
+									prop_assert_eq!(s, &text)
+								}
+								(v, _, cell) => prop_assert!(
+									false,
+									"{:?} stored as {:?}",
+									v,
+									cell
+								),
+							}
+						}
+					}
+					prop_assert!(stored.next().unwrap().is_none());
+				}
+			}
+		}
 	}
 }
@@ -0,0 +1,187 @@
+use crate::config::glob_match;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LimitConfig {
+	/// Table name glob, e.g. `render_*`.
+	pub tables: String,
+	pub rows_per_second: u32,
+}
+
+/// Caps on what a single connection creates, see `Quota`.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+	/// Tables a connection may declare.
+	pub tables: Option<u32>,
+	/// Strings a connection may send.
+	pub strings: Option<u32>,
+	/// Entries per second of a connection over all its tables.
+	pub rows_per_second: Option<u32>,
+}
+
+//---------------------------------------------------------------------------
+struct Window {
+	cap: u32,
+	start: Option<Instant>,
+	count: u32,
+}
+
+impl Window {
+	fn new(cap: u32) -> Window {
+		Window {
+			cap,
+			start: None,
+			count: 0,
+		}
+	}
+
+	/// Whether another row received at `now` fits the cap.
+	fn allow(&mut self, now: Instant) -> bool {
+		match self.start {
+			Some(start) if now - start < Duration::from_secs(1) => {}
+			_ => {
+				self.start = Some(now);
+				self.count = 0;
+			}
+		}
+
+		self.count += 1;
+		self.count <= self.cap
+	}
+}
+
+/// Per-table caps on the inserted rows per second, entries over the cap are
+/// dropped.
+#[derive(Default)]
+pub struct Limits {
+	configs: Vec<LimitConfig>,
+	/// Indexed by descriptor uid.
+	windows: Vec<Option<Window>>,
+}
+
+impl Limits {
+	pub fn new(configs: &[LimitConfig]) -> Limits {
+		Limits {
+			configs: configs.to_vec(),
+			windows: vec![],
+		}
+	}
+
+	/// Picks the first limit matching a newly registered table.
+	pub fn bind(&mut self, uid: u32, table: &str) {
+		let uid = uid as usize;
+		if self.windows.len() <= uid {
+			self.windows.resize_with(uid + 1, || None);
+		}
+
+		self.windows[uid] = self
+			.configs
+			.iter()
+			.find(|c| glob_match(&c.tables, table))
+			.map(|c| Window::new(c.rows_per_second));
+	}
+
+	/// Whether an entry of `uid` received at `now` may be inserted.
+	pub fn allow(&mut self, uid: u32, now: Instant) -> bool {
+		match self.windows.get_mut(uid as usize) {
+			Some(Some(w)) => w.allow(now),
+			_ => true,
+		}
+	}
+}
+
+/// Per-connection caps protecting the daemon from a misbehaving producer. A
+/// connection declaring more tables or sending more strings than allowed is
+/// closed, its entries over the rate are dropped.
+#[derive(Default)]
+pub struct Quota {
+	config: QuotaConfig,
+	window: Option<Window>,
+}
+
+impl Quota {
+	pub fn new(config: &QuotaConfig) -> Quota {
+		Quota {
+			config: config.clone(),
+			window: config.rows_per_second.map(Window::new),
+		}
+	}
+
+	/// Checks that the `count`th table of a connection is allowed.
+	pub fn table(&self, count: usize) -> Result<(), String> {
+		match self.config.tables {
+			Some(cap) if count > cap as usize => {
+				Err(format!("over {} tables", cap))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Checks that the `count`th string of a connection is allowed.
+	pub fn string(&self, count: usize) -> Result<(), String> {
+		match self.config.strings {
+			Some(cap) if count > cap as usize => {
+				Err(format!("over {} strings", cap))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Whether an entry of the connection received at `now` may be inserted.
+	pub fn allow(&mut self, now: Instant) -> bool {
+		self.window.as_mut().is_none_or(|w| w.allow(now))
+	}
+
+	/// Restarts the rate for a new connection.
+	pub fn begin_session(&mut self) {
+		self.window = self.config.rows_per_second.map(Window::new);
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn caps_rows_per_second() {
+		let config = LimitConfig {
+			tables: String::from("render_*"),
+			rows_per_second: 2,
+		};
+
+		let mut limits = Limits::new(&[config]);
+		limits.bind(0, "render_stats");
+		limits.bind(1, "gameplay");
+
+		let t0 = Instant::now();
+		let allowed: Vec<bool> = (0..4).map(|_| limits.allow(0, t0)).collect();
+		assert_eq!(allowed, vec![true, true, false, false]);
+		assert!(limits.allow(1, t0));
+		assert!(limits.allow(0, t0 + Duration::from_secs(1)));
+	}
+
+	#[test]
+	fn connection_quota() {
+		let config = QuotaConfig {
+			tables: Some(2),
+			rows_per_second: Some(3),
+			..QuotaConfig::default()
+		};
+		let mut quota = Quota::new(&config);
+
+		assert!(quota.table(2).is_ok());
+		assert!(quota.table(3).is_err());
+		assert!(quota.string(1_000_000).is_ok());
+
+		let t0 = Instant::now();
+		let allowed: Vec<bool> = (0..4).map(|_| quota.allow(t0)).collect();
+		assert_eq!(allowed, vec![true, true, true, false]);
+		quota.begin_session();
+		assert!(quota.allow(t0));
+	}
+}
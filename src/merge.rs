@@ -0,0 +1,320 @@
+use crate::compact::{self, Expander};
+use crate::ingest::{self, Bodies};
+use crate::wire;
+use sdd_core::{Encoder, Type, HEADER_SIZE, MSG_ATTACHMENT, MSG_COUNTER};
+use sdd_core::{MSG_DESC, MSG_ENTITY, MSG_ENTRY, MSG_MARKER, MSG_PAUSE};
+use sdd_core::{MSG_SCOPE_BEGIN, MSG_SOURCE, MSG_STR, MSG_THREAD, NO_LABEL};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+//---------------------------------------------------------------------------
+/// Bytes read from a producer at once.
+const READ_SIZE: usize = 64 * 1024;
+/// Id the merged stream gives the strings a producer never sent, so the
+/// daemon reports them.
+const UNKNOWN: u32 = 0xFFFF_FFFE;
+
+/// Strings and tables of the merged stream.
+struct Merged {
+	writer: ingest::Writer,
+	strings: HashMap<Vec<u8>, u32>,
+	tables: u32,
+	/// Producer whose messages were written last, `None` once a producer
+	/// sent a source message of its own.
+	source: Option<usize>,
+}
+
+impl Merged {
+	/// Id of `text` in the merged stream, writing it the first time.
+	fn intern(&mut self, text: &[u8], out: &mut Vec<u8>) -> u32 {
+		if let Some(id) = self.strings.get(text) {
+			return *id;
+		}
+
+		let id = self.strings.len() as u32;
+		out.extend_from_slice(&sdd_core::PROTOCOL.to_le_bytes());
+		out.push(MSG_STR);
+		out.extend_from_slice(&id.to_le_bytes());
+		out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+		out.extend_from_slice(text);
+		self.strings.insert(text.to_vec(), id);
+		id
+	}
+}
+
+/// Strings and tables of a producer, by its own ids.
+#[derive(Default)]
+struct Stream {
+	strings: HashMap<u32, u32>,
+	/// Uid in the merged stream and column types of each table.
+	tables: HashMap<u32, (u32, Vec<Type>)>,
+}
+
+impl Stream {
+	fn string(&self, id: u32) -> u32 {
+		self.strings.get(&id).copied().unwrap_or(UNKNOWN)
+	}
+
+	/// Rewrites the string id at `pos` of `msg` to its merged one.
+	fn map_string(&self, msg: &mut [u8], pos: usize) {
+		let id = self.string(sdd_core::word(&msg[pos..]));
+		msg[pos..pos + 4].copy_from_slice(&id.to_le_bytes());
+	}
+
+	fn map_label(&self, msg: &mut [u8], pos: usize) {
+		if sdd_core::word(&msg[pos..]) != NO_LABEL {
+			self.map_string(msg, pos);
+		}
+	}
+
+	fn map_uid(&self, msg: &mut [u8]) -> Option<&[Type]> {
+		let (uid, columns) = self.tables.get(&sdd_core::word(&msg[5..]))?;
+		msg[5..9].copy_from_slice(&uid.to_le_bytes());
+		Some(columns)
+	}
+
+	/// Counters of undeclared tables get an uid the daemon reports.
+	fn map_counter(&self, msg: &mut [u8]) {
+		if self.map_uid(msg).is_none() {
+			msg[5..9].copy_from_slice(&UNKNOWN.to_le_bytes());
+		}
+	}
+
+	fn entry_size(&self, uid: u32) -> Option<usize> {
+		let (_, columns) = self.tables.get(&uid)?;
+		let size = |t: &Type| if *t == Type::Bool { 1 } else { 4 };
+		Some(columns.iter().map(size).sum())
+	}
+
+	/// Appends the plain message `msg` with the ids of the merged stream to
+	/// `out`.
+	fn rewrite(
+		&mut self,
+		merged: &mut Merged,
+		msg: &[u8],
+		out: &mut Vec<u8>,
+	) -> Result<(), &'static str> {
+		let mut msg = msg.to_vec();
+		let body = HEADER_SIZE;
+
+		match msg[4] {
+			MSG_STR => {
+				let id = merged.intern(&msg[body + 8..], out);
+				self.strings.insert(sdd_core::word(&msg[body..]), id);
+				return Ok(());
+			}
+			MSG_DESC => {
+				let (desc, uid, _) = wire::decode_descriptor(&msg[body..])
+					.map_err(|_| "Invalid table")?;
+				let columns = desc.columns().map(|(_, t)| t).collect();
+				self.tables.insert(uid, (merged.tables, columns));
+				merged.tables += 1;
+
+				self.map_uid(&mut msg);
+				self.map_string(&mut msg, body + 4);
+				for i in 0..desc.columns().count() {
+					self.map_string(&mut msg, body + 10 + i * 5);
+				}
+			}
+			MSG_ENTRY => {
+				let columns = self.map_uid(&mut msg).unwrap().to_vec();
+				let mut pos = body + 4;
+				for t in columns {
+					match t {
+						Type::Bool => pos += 1,
+						Type::Str => {
+							self.map_string(&mut msg, pos);
+							pos += 4;
+						}
+						_ => pos += 4,
+					}
+				}
+			}
+			MSG_COUNTER => self.map_counter(&mut msg),
+			MSG_MARKER | MSG_PAUSE => self.map_label(&mut msg, body + 1),
+			MSG_ENTITY => {
+				self.map_string(&mut msg, body + 4);
+				self.map_string(&mut msg, body + 8);
+			}
+			MSG_SCOPE_BEGIN => self.map_string(&mut msg, body + 8),
+			MSG_ATTACHMENT | MSG_THREAD => self.map_string(&mut msg, body),
+			MSG_SOURCE => {
+				self.map_string(&mut msg, body);
+				merged.source = None;
+			}
+			_ => {}
+		}
+
+		out.extend_from_slice(&msg);
+		Ok(())
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Merges the streams of several producers into one session of a single
+/// daemon, each producer being read by a thread of its own. Strings and
+/// tables get ids of the merged stream, and a source message naming the
+/// producer precedes its messages. The session ends once the `Merge` and
+/// all its readers are dropped.
+pub struct Merge {
+	merged: Mutex<Merged>,
+}
+
+impl Merge {
+	/// Returns the merge along with the merged stream for
+	/// `Daemon::capture_reader`.
+	pub fn new() -> (Merge, Bodies) {
+		let (writer, bodies) = ingest::pipe();
+		let merged = Merged {
+			writer,
+			strings: HashMap::new(),
+			tables: 0,
+			source: None,
+		};
+		let merge = Merge {
+			merged: Mutex::new(merged),
+		};
+		(merge, bodies)
+	}
+
+	/// Forwards the stream of producer `index`, named `name`, until it ends.
+	/// Only binary streams, plain or compact, can be merged.
+	pub fn forward<R: Read>(
+		&self,
+		index: usize,
+		name: &str,
+		mut reader: R,
+	) -> io::Result<()> {
+		let invalid = |e: &str| io::Error::new(io::ErrorKind::InvalidData, e);
+
+		let mut stream = Stream::default();
+		let mut expander = None;
+		let mut input = vec![];
+		let mut plain = vec![];
+		let mut buf = vec![0; READ_SIZE];
+		let mut start = true;
+
+		loop {
+			let n = match reader.read(&mut buf) {
+				Ok(0) => return Ok(()),
+				Ok(n) => n,
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+				Err(e) => return Err(e),
+			};
+			input.extend_from_slice(&buf[..n]);
+
+			if start && input.len() < 4 {
+				continue;
+			}
+			if start && input[..4] == sdd_core::COMPACT_MAGIC {
+				expander = Some(Expander::default());
+				input.drain(..4);
+			}
+			start = false;
+
+			// Plain messages, expanded from compact ones if need be.
+			let read = match &mut expander {
+				Some(expander) => {
+					let mut pos = 0;
+					while let Some(size) = expander
+						.expand(&input[pos..], &mut plain)
+						.map_err(invalid)?
+					{
+						pos += size;
+					}
+					input.drain(..pos);
+					&mut plain
+				}
+				None => &mut input,
+			};
+
+			let mut merged = self.merged.lock().unwrap();
+			let mut out = vec![];
+			let mut pos = 0;
+			while let Some(size) = compact::message_size(&read[pos..], |uid| {
+				stream.entry_size(uid)
+			})
+			.map_err(invalid)?
+			{
+				if merged.source != Some(index) {
+					let name = merged.intern(name.as_bytes(), &mut out);
+					let mut header = [0; sdd_core::SOURCE_SIZE];
+					let _ = Encoder::new(&mut header).source(name);
+					out.extend_from_slice(&header);
+					merged.source = Some(index);
+				}
+
+				let msg = &read[pos..pos + size];
+				stream
+					.rewrite(&mut merged, msg, &mut out)
+					.map_err(invalid)?;
+				pos += size;
+			}
+			read.drain(..pos);
+
+			merged.writer.write_all(&out)?;
+			merged.writer.flush()?;
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::{Producer, Value};
+	use crate::wire::{Message, Messages};
+
+	#[test]
+	fn merge_streams() {
+		let mut streams = vec![];
+		for (name, ts) in [("hud", 1), ("frame", 2)] {
+			let mut producer = Producer::new(vec![]);
+			producer.string("unused").unwrap();
+			let uid = producer
+				.table(name, &[("ts", Type::Int), ("scene", Type::Str)])
+				.unwrap();
+			let scene = producer.string("menu").unwrap();
+			producer
+				.entry(uid, &[Value::Int(ts), Value::Str(scene)])
+				.unwrap();
+			streams.push(producer.into_inner());
+		}
+
+		let (merge, mut bodies) = Merge::new();
+		merge.forward(0, "a", &streams[0][..]).unwrap();
+		merge.forward(1, "b", &streams[1][..]).unwrap();
+		drop(merge);
+		let mut bytes = vec![];
+		bodies.read_to_end(&mut bytes).unwrap();
+
+		let mut strings = HashMap::new();
+		let mut lines = vec![];
+		for message in Messages::new(&bytes) {
+			match message.unwrap() {
+				Message::String { id, value } => {
+					strings.insert(id, String::from_utf8_lossy(value));
+				}
+				Message::Source { name } => {
+					lines.push(strings[&name].to_string())
+				}
+				Message::Table { uid, desc } => {
+					lines.push(format!("{} {}", uid, strings[&desc.name()]))
+				}
+				Message::Entry { uid, entry } => {
+					let scene = match entry.values().nth(1) {
+						Some(wire::FieldType::Str(id)) => {
+							strings[id].to_string()
+						}
+						_ => panic!(),
+					};
+					lines.push(format!("{} {}", uid, scene));
+				}
+				_ => {}
+			}
+		}
+		assert_eq!(lines, ["a", "0 hud", "0 menu", "b", "1 frame", "1 menu"]);
+		assert_eq!(strings.len(), 8);
+	}
+}
@@ -0,0 +1,180 @@
+use crate::dae::{Daemon, SharedStats};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::collections::HashMap;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+const TICK: Duration = Duration::from_millis(250);
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+//---------------------------------------------------------------------------
+#[derive(Default)]
+struct Rates {
+	sampled: Option<Instant>,
+	rows: HashMap<String, u64>,
+	rates: HashMap<String, f64>,
+}
+
+impl Rates {
+	fn update(&mut self, stats: &SharedStats) {
+		let now = Instant::now();
+		let elapsed = match self.sampled {
+			Some(t) if now - t < RATE_WINDOW => return,
+			Some(t) => (now - t).as_secs_f64(),
+			None => 0.0,
+		};
+
+		let stats = stats.lock().unwrap();
+		for table in &stats.tables {
+			let prev = self.rows.insert(table.name.clone(), table.rows);
+			if let Some(prev) = prev {
+				let rate = (table.rows - prev) as f64 / elapsed;
+				self.rates.insert(table.name.clone(), rate);
+			}
+		}
+
+		self.sampled = Some(now);
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Runs the daemon on a background thread and renders its statistics until
+/// `q` or `Esc` is pressed.
+pub fn run(mut daemon: Daemon, addr: String) -> io::Result<()> {
+	daemon.verbose = false;
+	let stats = daemon.stats.clone();
+
+	let mut worker = Some(thread::spawn(move || daemon.start(&addr)));
+	let mut status = String::from("running");
+	let mut rates = Rates::default();
+
+	let mut terminal = ratatui::init();
+	let result = loop {
+		if worker.as_ref().is_some_and(|w| w.is_finished()) {
+			status = match worker.take().unwrap().join() {
+				Ok(Ok(())) => String::from("stopped"),
+				Ok(Err(e)) => format!("stopped: {}", e),
+				Err(_) => String::from("crashed"),
+			};
+		}
+
+		rates.update(&stats);
+		if let Err(e) = terminal.draw(|f| draw(f, &stats, &rates, &status)) {
+			break Err(e);
+		}
+
+		match event::poll(TICK) {
+			Ok(true) => {}
+			Ok(false) => continue,
+			Err(e) => break Err(e),
+		}
+
+		match event::read() {
+			Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+				if let KeyCode::Char('q') | KeyCode::Esc = key.code {
+					break Ok(());
+				}
+			}
+			Ok(_) => {}
+			Err(e) => break Err(e),
+		}
+	};
+	ratatui::restore();
+
+	result
+}
+
+fn draw(frame: &mut Frame, stats: &SharedStats, rates: &Rates, status: &str) {
+	let stats = stats.lock().unwrap().clone();
+
+	let [header, tables, errors] = Layout::vertical([
+		Constraint::Length(3),
+		Constraint::Min(5),
+		Constraint::Length(10),
+	])
+	.areas(frame.area());
+
+	let connection = match &stats.peer {
+		Some(peer) if stats.stale => format!("stale connection to {}", peer),
+		Some(peer) => format!("connected to {}", peer),
+		None => String::from("disconnected"),
+	};
+	let recording = if stats.paused {
+		"paused by the producer"
+	} else if stats.recording {
+		"recording"
+	} else {
+		"paused"
+	};
+	let idle = match stats.last_receive {
+		Some(t) => format!("{:.1}s ago", t.elapsed().as_secs_f64()),
+		None => String::from("never"),
+	};
+
+	frame.render_widget(
+		Paragraph::new(format!(
+			"daemon {} | {} | {} | {} entries | last data {}",
+			status, connection, recording, stats.entries, idle
+		))
+		.block(Block::bordered().title(" sdd monitor (q to quit) ")),
+		header,
+	);
+
+	let rows = stats.tables.iter().map(|t| {
+		let rate = rates.rates.get(&t.name).copied().unwrap_or(0.0);
+		let last = t
+			.columns
+			.iter()
+			.zip(t.last_values())
+			.map(|(c, v)| format!("{}={}", c, v))
+			.collect::<Vec<String>>()
+			.join(" ");
+
+		Row::new(vec![
+			t.name.clone(),
+			t.rows.to_string(),
+			format!("{:.1}", rate),
+			t.dropped.to_string(),
+			last,
+		])
+	});
+
+	let widths = [
+		Constraint::Length(24),
+		Constraint::Length(12),
+		Constraint::Length(10),
+		Constraint::Length(10),
+		Constraint::Min(20),
+	];
+
+	frame.render_widget(
+		Table::new(rows, widths)
+			.header(
+				Row::new(vec![
+					"table",
+					"rows",
+					"rows/s",
+					"dropped",
+					"last values",
+				])
+				.style(Style::default().add_modifier(Modifier::BOLD)),
+			)
+			.block(Block::bordered().title(" tables ")),
+		tables,
+	);
+
+	let visible = errors.height.saturating_sub(2) as usize;
+	let recent: Vec<String> =
+		stats.errors.iter().rev().take(visible).cloned().collect();
+
+	frame.render_widget(
+		List::new(recent).block(Block::bordered().title(" recent errors ")),
+		errors,
+	);
+}
@@ -0,0 +1,99 @@
+use crate::config::MqttConfig;
+use crate::dae::STOP_POLL;
+use crate::ingest::Message;
+use rumqttc::RecvTimeoutError;
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, QoS};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Pending requests of the client, only subscriptions are sent.
+const REQUESTS: usize = 64;
+/// Wait between attempts to reach the broker.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+//---------------------------------------------------------------------------
+/// Iterates the messages of the configured topics, reconnecting to the
+/// broker as needed, until `stop` is set.
+pub struct Subscriber {
+	client: Client,
+	connection: Connection,
+	topics: Vec<String>,
+	broker: String,
+	stop: Arc<AtomicBool>,
+}
+
+pub fn subscribe(
+	config: &MqttConfig,
+	stop: Arc<AtomicBool>,
+) -> Result<Subscriber, String> {
+	let (host, port) = match config.broker.rsplit_once(':') {
+		Some((host, port)) => match port.parse() {
+			Ok(port) => (host, port),
+			Err(_) => return Err(format!("Bad broker port: {}", port)),
+		},
+		None => (config.broker.as_str(), 1883),
+	};
+
+	if config.topics.is_empty() {
+		return Err(String::from("The mqtt section needs topics"));
+	}
+
+	let max_payload = config.max_payload()?;
+	let mut options = MqttOptions::new(&config.client_id, host, port);
+	options.set_max_packet_size(max_payload, max_payload);
+	if let Some(user) = &config.username {
+		let password = config.password.clone().unwrap_or_default();
+		options.set_credentials(user, password);
+	}
+
+	let (client, connection) = Client::new(options, REQUESTS);
+	Ok(Subscriber {
+		client,
+		connection,
+		topics: config.topics.clone(),
+		broker: config.broker.clone(),
+		stop,
+	})
+}
+
+impl Iterator for Subscriber {
+	type Item = Message;
+
+	fn next(&mut self) -> Option<Message> {
+		while !self.stop.load(Ordering::Relaxed) {
+			let event = match self.connection.recv_timeout(STOP_POLL) {
+				Ok(event) => event,
+				Err(RecvTimeoutError::Timeout) => continue,
+				Err(RecvTimeoutError::Disconnected) => return None,
+			};
+			match event {
+				Ok(Event::Incoming(Packet::Publish(p))) => {
+					return Some(Message {
+						topic: p.topic,
+						payload: p.payload.to_vec(),
+					});
+				}
+				// Clean sessions lose their subscriptions on reconnect.
+				Ok(Event::Incoming(Packet::ConnAck(_))) => {
+					println!("Connected to {}", self.broker);
+					for topic in &self.topics {
+						let subscribed =
+							self.client.try_subscribe(topic, QoS::AtLeastOnce);
+						if let Err(e) = subscribed {
+							println!("Could not subscribe to {}: {}", topic, e);
+						}
+					}
+				}
+				Ok(_) => {}
+				Err(e) => {
+					println!("{}: {}", self.broker, e);
+					thread::sleep(RETRY_INTERVAL);
+				}
+			}
+		}
+		None
+	}
+}
@@ -0,0 +1,225 @@
+use crate::compact::Expander;
+use crate::json::Encoder;
+use sdd_core::COMPACT_MAGIC;
+use std::io::{self, Read};
+use std::mem;
+
+//---------------------------------------------------------------------------
+/// "SDDM", selects MessagePack frames when a stream starts with it.
+pub const MAGIC: [u8; 4] = *b"SDDM";
+/// Largest frame, longer ones are skipped.
+const MAX_FRAME: usize = 1024 * 1024;
+/// Bytes read from the producer at once.
+const CHUNK: usize = 4096;
+
+#[derive(PartialEq)]
+enum Mode {
+	/// Waiting for the first bytes of the stream.
+	Handshake,
+	Binary,
+	MsgPack,
+	/// Compact entries after `sdd_core::COMPACT_MAGIC`.
+	Compact,
+}
+
+//---------------------------------------------------------------------------
+/// Passes binary streams through and translates streams opened with
+/// `MAGIC` into protocol messages. Their frames are a u32 length followed by
+/// a MessagePack map like `{"table": "frame", "fields": {"ts": 16}}`, see
+/// `json::Encoder`. Streams opened with `sdd_core::COMPACT_MAGIC` have their
+/// entries expanded, see `compact::Expander`.
+pub struct Handshake<R: Read> {
+	reader: R,
+	mode: Mode,
+	/// Input not yet translated, up to a partial frame.
+	input: Vec<u8>,
+	/// Bytes of an oversized frame still to discard.
+	skip: usize,
+	encoder: Encoder,
+	expander: Expander,
+	output: Vec<u8>,
+	pos: usize,
+}
+
+impl<R: Read> Handshake<R> {
+	pub fn new(reader: R) -> Handshake<R> {
+		Handshake {
+			reader,
+			mode: Mode::Handshake,
+			input: vec![],
+			skip: 0,
+			encoder: Encoder::new(),
+			expander: Expander::default(),
+			output: vec![],
+			pos: 0,
+		}
+	}
+
+	/// Translates the complete frames of the input, true if there were any.
+	fn translate(&mut self) -> bool {
+		let skipped = self.skip.min(self.input.len());
+		self.input.drain(..skipped);
+		self.skip -= skipped;
+
+		let mut translated = false;
+		while self.input.len() >= 4 {
+			let mut len = [0; 4];
+			len.copy_from_slice(&self.input[..4]);
+			let len = u32::from_le_bytes(len) as usize;
+
+			if len > MAX_FRAME {
+				println!("Skipping a MessagePack frame of {} bytes", len);
+				self.input.drain(..4);
+				self.skip = len;
+				return self.translate() || translated;
+			}
+
+			if self.input.len() < 4 + len {
+				break;
+			}
+
+			let encoded = rmp_serde::from_slice(&self.input[4..4 + len])
+				.map_err(|e| e.to_string())
+				.and_then(|entry| self.encoder.encode(&entry));
+			if let Err(e) = encoded {
+				println!("MessagePack frame: {}", e);
+			}
+			self.input.drain(..4 + len);
+			translated = true;
+		}
+
+		self.encoder.take(&mut self.output);
+		translated
+	}
+
+	/// Expands the complete messages of a compact input, true if there
+	/// were any. The rest of a stream failing to expand passes through.
+	fn expand(&mut self) -> bool {
+		let mut pos = 0;
+		loop {
+			match self.expander.expand(&self.input[pos..], &mut self.output) {
+				Ok(Some(size)) => pos += size,
+				Ok(None) => break,
+				Err(e) => {
+					println!("Compact stream: {}", e);
+					self.mode = Mode::Binary;
+					break;
+				}
+			}
+		}
+		self.input.drain(..pos);
+		pos > 0 || self.mode == Mode::Binary
+	}
+}
+
+impl<R: Read> Read for Handshake<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			if self.pos < self.output.len() {
+				let n = buf.len().min(self.output.len() - self.pos);
+				buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+				self.pos += n;
+				return Ok(n);
+			}
+
+			self.pos = 0;
+			self.output.clear();
+
+			match self.mode {
+				Mode::Binary if self.input.is_empty() => {
+					return self.reader.read(buf);
+				}
+				Mode::Binary => {
+					self.output = mem::take(&mut self.input);
+					continue;
+				}
+				Mode::Handshake if self.input.len() >= MAGIC.len() => {
+					self.mode = if self.input.starts_with(&MAGIC) {
+						self.input.drain(..MAGIC.len());
+						Mode::MsgPack
+					} else if self.input.starts_with(&COMPACT_MAGIC) {
+						self.input.drain(..COMPACT_MAGIC.len());
+						Mode::Compact
+					} else {
+						Mode::Binary
+					};
+					continue;
+				}
+				Mode::Handshake => {}
+				Mode::MsgPack => {
+					if self.translate() {
+						continue;
+					}
+				}
+				Mode::Compact => {
+					if self.expand() {
+						continue;
+					}
+				}
+			}
+
+			let mut chunk = [0; CHUNK];
+			let n = self.reader.read(&mut chunk)?;
+			if n == 0 {
+				if self.mode == Mode::Handshake && !self.input.is_empty() {
+					self.mode = Mode::Binary;
+					continue;
+				}
+				if self.mode == Mode::MsgPack && !self.input.is_empty() {
+					println!("MessagePack stream ended in a frame");
+				}
+				if self.mode == Mode::Compact && !self.input.is_empty() {
+					println!("Compact stream ended in a message");
+				}
+				return Ok(0);
+			}
+
+			self.input.extend_from_slice(&chunk[..n]);
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::{Producer, Type, Value};
+
+	#[test]
+	fn select_format() {
+		let mut input = MAGIC.to_vec();
+		for ts in [7u32, 8] {
+			let entry = serde_json::json!({
+				"table": "log",
+				"fields": {"ts": ts, "ok": true},
+			});
+			let frame = rmp_serde::to_vec(&entry).unwrap();
+			input.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+			input.extend_from_slice(&frame);
+		}
+
+		let mut output = vec![];
+		Handshake::new(input.as_slice())
+			.read_to_end(&mut output)
+			.unwrap();
+
+		let mut expected = Producer::new(vec![]);
+		let uid = expected
+			.table("log", &[("ok", Type::Bool), ("ts", Type::Int)])
+			.unwrap();
+		for ts in [7, 8] {
+			expected
+				.entry(uid, &[Value::Bool(true), Value::Int(ts)])
+				.unwrap();
+		}
+		let expected = expected.into_inner();
+		assert_eq!(output, expected);
+
+		// Binary streams pass through.
+		let mut output = vec![];
+		Handshake::new(expected.as_slice())
+			.read_to_end(&mut output)
+			.unwrap();
+		assert_eq!(output, expected);
+	}
+}
@@ -0,0 +1,91 @@
+use crate::config::NatsConfig;
+use crate::dae::STOP_POLL;
+use crate::ingest::Message;
+use futures::stream::{self, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+//---------------------------------------------------------------------------
+/// Messages waiting for the shards, the bus client buffers the rest.
+const QUEUE: usize = 64;
+
+//---------------------------------------------------------------------------
+/// Iterates the messages of the configured subjects, the client reconnects
+/// to the server on its own, until `stop` is set.
+pub struct Subscriber {
+	receiver: mpsc::Receiver<Message>,
+	stop: Arc<AtomicBool>,
+}
+
+pub fn subscribe(
+	config: &NatsConfig,
+	stop: Arc<AtomicBool>,
+) -> Result<Subscriber, String> {
+	if config.subjects.is_empty() {
+		return Err(String::from("The nats section needs subjects"));
+	}
+
+	let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+
+	let mut options = async_nats::ConnectOptions::new();
+	if let Some(token) = &config.token {
+		options = options.token(token.clone());
+	}
+	if let Some(user) = &config.username {
+		let password = config.password.clone().unwrap_or_default();
+		options = options.user_and_password(user.clone(), password);
+	}
+
+	let server = config.server.clone();
+	let subjects = config.subjects.clone();
+	let subscriptions = runtime.block_on(async {
+		let client = options.connect(server.as_str()).await?;
+
+		let mut subscriptions = vec![];
+		for subject in subjects {
+			subscriptions.push(client.subscribe(subject).await?);
+		}
+		Ok::<_, async_nats::Error>(subscriptions)
+	});
+
+	let subscriptions =
+		subscriptions.map_err(|e| format!("{}: {}", config.server, e))?;
+	println!("Connected to {}", config.server);
+
+	let (sender, receiver) = mpsc::sync_channel(QUEUE);
+	thread::spawn(move || {
+		runtime.block_on(async {
+			let mut messages = stream::select_all(subscriptions);
+			while let Some(m) = messages.next().await {
+				let message = Message {
+					topic: m.subject.to_string(),
+					payload: m.payload.to_vec(),
+				};
+
+				// Blocking only holds back this loop, the client keeps
+				// running on the runtime's workers.
+				if sender.send(message).is_err() {
+					break;
+				}
+			}
+		})
+	});
+
+	Ok(Subscriber { receiver, stop })
+}
+
+impl Iterator for Subscriber {
+	type Item = Message;
+
+	fn next(&mut self) -> Option<Message> {
+		while !self.stop.load(Ordering::Relaxed) {
+			match self.receiver.recv_timeout(STOP_POLL) {
+				Ok(message) => return Some(message),
+				Err(mpsc::RecvTimeoutError::Timeout) => {}
+				Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+			}
+		}
+		None
+	}
+}
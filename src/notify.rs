@@ -0,0 +1,298 @@
+use crate::http;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io;
+use std::thread::{self, JoinHandle};
+
+//---------------------------------------------------------------------------
+/// Webhook posted the lifecycle events of the capture, e.g. to the channel
+/// of the team running it overnight.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+	/// Url receiving a POST per event, `https://` ones need the `https`
+	/// feature.
+	pub url: String,
+	#[serde(default)]
+	pub format: Format,
+	/// Events posted, all of them when empty.
+	#[serde(default)]
+	pub events: Vec<Event>,
+}
+
+/// Body of the requests.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+	/// `{"event": .., "capture": .., "text": .., ..}` with the details of
+	/// the event.
+	#[default]
+	Json,
+	/// `{"text": ..}` of a Slack incoming webhook.
+	Slack,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+	/// The first producer connected.
+	Start,
+	/// The capture ended cleanly.
+	Finish,
+	/// A producer disconnected or its session failed.
+	Disconnect,
+	/// An error ended the capture.
+	Error,
+	/// The outputs were moved aside, see `ctl`.
+	Rotate,
+}
+
+impl Event {
+	fn name(self) -> &'static str {
+		match self {
+			Event::Start => "start",
+			Event::Finish => "finish",
+			Event::Disconnect => "disconnect",
+			Event::Error => "error",
+			Event::Rotate => "rotate",
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Webhooks of the lifecycle events of a capture. Events are posted on
+/// threads of their own, dropping waits for the posts in flight so that the
+/// last events of a capture are delivered before it exits.
+#[derive(Default)]
+pub struct Notify {
+	hooks: Vec<NotifyConfig>,
+	/// Name of the capture in the events, e.g. its output.
+	pub name: String,
+	started: bool,
+	posting: Vec<JoinHandle<()>>,
+}
+
+impl Notify {
+	pub fn new(
+		configs: &[NotifyConfig],
+		name: String,
+	) -> Result<Notify, String> {
+		for config in configs {
+			match config.url.split_once("://") {
+				Some(("http", _)) => {}
+				Some(("https", _)) if cfg!(feature = "https") => {}
+				Some(("https", _)) => {
+					return Err(format!(
+						"{}: sdd was built without the https feature.",
+						config.url
+					))
+				}
+				_ => return Err(format!("Bad webhook url '{}'", config.url)),
+			}
+		}
+
+		Ok(Notify {
+			hooks: configs.to_vec(),
+			name,
+			started: false,
+			posting: vec![],
+		})
+	}
+
+	/// Posts the start of the capture, once, on the first connection.
+	pub fn start(&mut self, peer: &str) {
+		if !std::mem::replace(&mut self.started, true) {
+			let text = format!("Capture started on {}", peer);
+			self.post(Event::Start, text, json!({ "peer": peer }));
+		}
+	}
+
+	pub fn disconnect(
+		&mut self,
+		peer: &str,
+		entries: u64,
+		error: Option<String>,
+	) {
+		let text = match &error {
+			Some(e) => format!("{} disconnected: {}", peer, e),
+			None => format!("{} disconnected after {} entries", peer, entries),
+		};
+		let details =
+			json!({ "peer": peer, "entries": entries, "error": error });
+		self.post(Event::Disconnect, text, details);
+	}
+
+	/// Posts the end of the capture, an error or a clean finish.
+	pub fn end(
+		&mut self,
+		error: Option<String>,
+		entries: u64,
+		errors: u64,
+		outputs: &[String],
+	) {
+		let (event, text) = match &error {
+			Some(e) => (Event::Error, format!("Capture failed: {}", e)),
+			None => (
+				Event::Finish,
+				format!(
+					"Capture finished with {} entries and {} errors",
+					entries, errors
+				),
+			),
+		};
+		let details = json!({
+			"entries": entries,
+			"errors": errors,
+			"error": error,
+			"outputs": outputs,
+		});
+		self.post(event, text, details);
+	}
+
+	pub fn rotate(&mut self, paths: &[String]) {
+		let text = format!("Rotated to {}", paths.join(", "));
+		self.post(Event::Rotate, text, json!({ "outputs": paths }));
+	}
+
+	fn post(&mut self, event: Event, text: String, details: Value) {
+		self.posting.retain(|p| !p.is_finished());
+
+		let hooks = self
+			.hooks
+			.iter()
+			.filter(|h| h.events.is_empty() || h.events.contains(&event));
+		for hook in hooks {
+			let body = body(hook.format, event, &self.name, &text, &details);
+			let url = hook.url.clone();
+			self.posting.push(thread::spawn(move || {
+				match post(&url, body.as_bytes()) {
+					Ok(status) if (200..300).contains(&status) => {}
+					Ok(status) => {
+						println!("Webhook {} failed: status {}", url, status)
+					}
+					Err(e) => println!("Webhook {} failed: {}", url, e),
+				}
+			}));
+		}
+	}
+}
+
+impl Drop for Notify {
+	fn drop(&mut self) {
+		for posting in self.posting.drain(..) {
+			let _ = posting.join();
+		}
+	}
+}
+
+fn body(
+	format: Format,
+	event: Event,
+	name: &str,
+	text: &str,
+	details: &Value,
+) -> String {
+	match format {
+		Format::Json => {
+			let mut body = json!({
+				"event": event.name(),
+				"capture": name,
+				"text": text,
+			});
+			if let (Some(body), Some(details)) =
+				(body.as_object_mut(), details.as_object())
+			{
+				body.extend(details.clone());
+			}
+			body.to_string()
+		}
+		Format::Slack => {
+			json!({ "text": format!("sdd {}: {}", name, text) }).to_string()
+		}
+	}
+}
+
+#[cfg(feature = "https")]
+fn post(url: &str, body: &[u8]) -> io::Result<u16> {
+	if !url.starts_with("https://") {
+		return http::post(url, "application/json", body);
+	}
+
+	// Dropping `Notify` waits for the posts, a hanging endpoint must not
+	// keep the daemon from exiting.
+	let agent = ureq::AgentBuilder::new()
+		.timeout(http::CLIENT_TIMEOUT)
+		.build();
+	match agent
+		.post(url)
+		.set("Content-Type", "application/json")
+		.send_bytes(body)
+	{
+		Ok(response) => Ok(response.status()),
+		Err(ureq::Error::Status(status, _)) => Ok(status),
+		Err(e) => Err(io::Error::other(e.to_string())),
+	}
+}
+
+#[cfg(not(feature = "https"))]
+fn post(url: &str, body: &[u8]) -> io::Result<u16> {
+	http::post(url, "application/json", body)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{BufReader, Write};
+	use std::net::TcpListener;
+
+	#[test]
+	fn post_events() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/hook", listener.local_addr().unwrap());
+		let received = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			let mut reader = BufReader::new(stream.try_clone().unwrap());
+			let request = http::Request::read(&mut reader).unwrap();
+			let mut stream = stream;
+			stream
+				.write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+				.unwrap();
+			request.body
+		});
+
+		let config = NotifyConfig {
+			url,
+			format: Format::Json,
+			events: vec![Event::Finish],
+		};
+		let mut notify =
+			Notify::new(&[config], String::from("soak.db")).unwrap();
+		notify.start("127.0.0.1:2001");
+		notify.end(None, 12, 0, &[String::from("soak.db")]);
+		drop(notify);
+
+		let posted: Value =
+			serde_json::from_slice(&received.join().unwrap()).unwrap();
+		assert_eq!(posted["event"], "finish");
+		assert_eq!(posted["capture"], "soak.db");
+		assert_eq!(posted["entries"], 12);
+		assert_eq!(posted["outputs"][0], "soak.db");
+
+		let slack = body(
+			Format::Slack,
+			Event::Start,
+			"soak.db",
+			"Started",
+			&json!({}),
+		);
+		assert_eq!(slack, r#"{"text":"sdd soak.db: Started"}"#);
+
+		let ftp = NotifyConfig {
+			url: String::from("ftp://host"),
+			format: Format::Slack,
+			events: vec![],
+		};
+		assert!(Notify::new(&[ftp], String::new()).is_err());
+	}
+}
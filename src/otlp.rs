@@ -0,0 +1,731 @@
+use crate::config::OtlpConfig;
+use crate::dae::{Entry, FieldType, Strings};
+use crate::http::{self, Request, Response};
+use crate::ingest::{self, Bodies};
+use crate::json::{self, Encoder};
+use crate::sink::{Delivery, Sink};
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+	metrics_service_server::{MetricsService, MetricsServiceServer},
+	ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::{
+	trace_service_server::{TraceService, TraceServiceServer},
+	ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::{
+	any_value, AnyValue, InstrumentationScope, KeyValue,
+};
+use opentelemetry_proto::tonic::logs::v1::{
+	LogRecord, ResourceLogs, ScopeLogs,
+};
+use opentelemetry_proto::tonic::metrics::v1::{
+	metric, number_data_point, Gauge, Metric, NumberDataPoint, ResourceMetrics,
+	ScopeMetrics,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use opentelemetry_proto::tonic::trace::v1::span::SpanKind;
+use prost::Message;
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request as GrpcRequest, Response as GrpcResponse, Status};
+
+//---------------------------------------------------------------------------
+/// Encodes the received telemetry for the daemon, all of it one session.
+struct Translator {
+	encoder: Encoder,
+	sender: ingest::Sender,
+}
+
+type Shared = Arc<Mutex<Translator>>;
+
+impl Translator {
+	/// Queues the rows for the daemon, false once it has finished.
+	fn send(&mut self, rows: Vec<Value>) -> bool {
+		for row in rows {
+			if let Err(e) = self.encoder.encode(&row) {
+				println!("OTLP: {}", e);
+			}
+		}
+
+		let mut bytes = vec![];
+		self.encoder.take(&mut bytes);
+		// An empty body would end the session.
+		bytes.is_empty() || self.sender.send(bytes).is_ok()
+	}
+}
+
+//---------------------------------------------------------------------------
+fn any_value(value: &Option<AnyValue>) -> String {
+	match value.as_ref().and_then(|v| v.value.as_ref()) {
+		Some(any_value::Value::StringValue(s)) => s.clone(),
+		Some(any_value::Value::BoolValue(b)) => b.to_string(),
+		Some(any_value::Value::IntValue(i)) => i.to_string(),
+		Some(any_value::Value::DoubleValue(d)) => d.to_string(),
+		Some(_) => String::from("..."),
+		None => String::new(),
+	}
+}
+
+/// `key=value` pairs joined by commas.
+fn attributes(attributes: &[KeyValue]) -> String {
+	let pairs: Vec<String> = attributes
+		.iter()
+		.map(|kv| format!("{}={}", kv.key, any_value(&kv.value)))
+		.collect();
+	pairs.join(",")
+}
+
+fn service(resource: &Option<Resource>) -> String {
+	resource
+		.iter()
+		.flat_map(|r| &r.attributes)
+		.find(|kv| kv.key == "service.name")
+		.map(|kv| any_value(&kv.value))
+		.unwrap_or_default()
+}
+
+/// Inserts an id of `words` 32 bit words as the `<name>_0`, `<name>_1`, ..
+/// columns, most significant first. Ids are unique, interning them as
+/// strings would grow the string table with every span.
+fn insert_id(
+	fields: &mut Map<String, Value>,
+	name: &str,
+	id: &[u8],
+	words: usize,
+) {
+	let mut bytes = vec![0; words * 4];
+	let n = id.len().min(bytes.len());
+	let padding = bytes.len() - n;
+	bytes[padding..].copy_from_slice(&id[id.len() - n..]);
+
+	for (i, word) in bytes.chunks(4).enumerate() {
+		let word = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+		fields.insert(format!("{}_{}", name, i), Value::from(word));
+	}
+}
+
+/// Whole seconds since the epoch, rows have 32 bit ints.
+fn seconds(unix_nano: u64) -> Value {
+	Value::from(unix_nano / 1_000_000_000)
+}
+
+/// One `otel_metrics` row per data point. Histograms and summaries store
+/// their sum as the value.
+fn metric_rows(request: &ExportMetricsServiceRequest) -> Vec<Value> {
+	let mut rows = vec![];
+
+	for resource in &request.resource_metrics {
+		let service = service(&resource.resource);
+		let metrics = resource.scope_metrics.iter().flat_map(|s| &s.metrics);
+
+		for metric in metrics {
+			// (kind, attributes, time, value, count) of the data points.
+			let points: Vec<(&str, String, u64, f64, u64)> = match &metric.data
+			{
+				Some(metric::Data::Gauge(g)) => g
+					.data_points
+					.iter()
+					.map(|p| {
+						(
+							"gauge",
+							attributes(&p.attributes),
+							p.time_unix_nano,
+							number(&p.value),
+							1,
+						)
+					})
+					.collect(),
+				Some(metric::Data::Sum(s)) => s
+					.data_points
+					.iter()
+					.map(|p| {
+						(
+							"sum",
+							attributes(&p.attributes),
+							p.time_unix_nano,
+							number(&p.value),
+							1,
+						)
+					})
+					.collect(),
+				Some(metric::Data::Histogram(h)) => h
+					.data_points
+					.iter()
+					.map(|p| {
+						(
+							"histogram",
+							attributes(&p.attributes),
+							p.time_unix_nano,
+							p.sum.unwrap_or(0.0),
+							p.count,
+						)
+					})
+					.collect(),
+				Some(metric::Data::ExponentialHistogram(h)) => h
+					.data_points
+					.iter()
+					.map(|p| {
+						(
+							"exponential_histogram",
+							attributes(&p.attributes),
+							p.time_unix_nano,
+							p.sum.unwrap_or(0.0),
+							p.count,
+						)
+					})
+					.collect(),
+				Some(metric::Data::Summary(s)) => s
+					.data_points
+					.iter()
+					.map(|p| {
+						(
+							"summary",
+							attributes(&p.attributes),
+							p.time_unix_nano,
+							p.sum,
+							p.count,
+						)
+					})
+					.collect(),
+				None => vec![],
+			};
+
+			for (kind, attributes, time, value, count) in points {
+				let mut fields = Map::new();
+				fields.insert(
+					String::from("service"),
+					Value::from(service.as_str()),
+				);
+				fields.insert(
+					String::from("name"),
+					Value::from(metric.name.as_str()),
+				);
+				fields.insert(String::from("kind"), Value::from(kind));
+				fields.insert(
+					String::from("attributes"),
+					Value::from(attributes),
+				);
+				fields.insert(String::from("time"), seconds(time));
+				fields.insert(String::from("value"), json::float(value));
+				fields.insert(String::from("count"), json::float(count as f64));
+				rows.push(json::entry("otel_metrics", fields));
+			}
+		}
+	}
+
+	rows
+}
+
+fn number(value: &Option<number_data_point::Value>) -> f64 {
+	match value {
+		Some(number_data_point::Value::AsDouble(v)) => *v,
+		Some(number_data_point::Value::AsInt(v)) => *v as f64,
+		None => 0.0,
+	}
+}
+
+/// One `otel_spans` row per span.
+fn span_rows(request: &ExportTraceServiceRequest) -> Vec<Value> {
+	let mut rows = vec![];
+
+	for resource in &request.resource_spans {
+		let service = service(&resource.resource);
+		let spans = resource.scope_spans.iter().flat_map(|s| &s.spans);
+
+		for span in spans {
+			let kind = SpanKind::try_from(span.kind)
+				.map(|k| k.as_str_name())
+				.unwrap_or("SPAN_KIND_UNSPECIFIED");
+			let duration = span
+				.end_time_unix_nano
+				.saturating_sub(span.start_time_unix_nano);
+
+			let mut fields = Map::new();
+			fields
+				.insert(String::from("service"), Value::from(service.as_str()));
+			fields
+				.insert(String::from("name"), Value::from(span.name.as_str()));
+			fields.insert(String::from("kind"), Value::from(kind));
+			insert_id(&mut fields, "trace_id", &span.trace_id, 4);
+			insert_id(&mut fields, "span_id", &span.span_id, 2);
+			insert_id(&mut fields, "parent_span_id", &span.parent_span_id, 2);
+			fields.insert(
+				String::from("attributes"),
+				Value::from(attributes(&span.attributes)),
+			);
+			fields.insert(
+				String::from("start"),
+				seconds(span.start_time_unix_nano),
+			);
+			fields.insert(
+				String::from("start_ns"),
+				Value::from(span.start_time_unix_nano % 1_000_000_000),
+			);
+			fields.insert(
+				String::from("duration_ms"),
+				json::float(duration as f64 / 1e6),
+			);
+			fields.insert(
+				String::from("status"),
+				Value::from(
+					span.status.as_ref().map_or(0, |s| s.code.max(0) as u32),
+				),
+			);
+			rows.push(json::entry("otel_spans", fields));
+		}
+	}
+
+	rows
+}
+
+//---------------------------------------------------------------------------
+struct Service {
+	translator: Shared,
+}
+
+impl Service {
+	/// Queues the rows for the daemon, false once it has finished.
+	fn send(&self, rows: Vec<Value>) -> bool {
+		tokio::task::block_in_place(|| {
+			self.translator.lock().unwrap().send(rows)
+		})
+	}
+}
+
+#[tonic::async_trait]
+impl MetricsService for Service {
+	async fn export(
+		&self,
+		request: GrpcRequest<ExportMetricsServiceRequest>,
+	) -> Result<GrpcResponse<ExportMetricsServiceResponse>, Status> {
+		if !self.send(metric_rows(request.get_ref())) {
+			return Err(Status::unavailable("The capture has finished"));
+		}
+		Ok(GrpcResponse::new(ExportMetricsServiceResponse::default()))
+	}
+}
+
+#[tonic::async_trait]
+impl TraceService for Service {
+	async fn export(
+		&self,
+		request: GrpcRequest<ExportTraceServiceRequest>,
+	) -> Result<GrpcResponse<ExportTraceServiceResponse>, Status> {
+		if !self.send(span_rows(request.get_ref())) {
+			return Err(Status::unavailable("The capture has finished"));
+		}
+		Ok(GrpcResponse::new(ExportTraceServiceResponse::default()))
+	}
+}
+
+fn serve_grpc(addr: &str, translator: Shared) -> io::Result<()> {
+	let addr: SocketAddr = addr.parse().map_err(|_| {
+		io::Error::new(io::ErrorKind::InvalidInput, "Bad address")
+	})?;
+
+	let runtime = tokio::runtime::Runtime::new()?;
+	let incoming = {
+		let _guard = runtime.enter();
+		TcpIncoming::new(addr, true, None).map_err(io::Error::other)?
+	};
+
+	thread::spawn(move || {
+		let server = Server::builder()
+			.add_service(MetricsServiceServer::new(Service {
+				translator: translator.clone(),
+			}))
+			.add_service(TraceServiceServer::new(Service { translator }))
+			.serve_with_incoming(incoming);
+
+		if let Err(e) = runtime.block_on(server) {
+			println!("OTLP gRPC server failed: {}", e);
+		}
+	});
+
+	Ok(())
+}
+
+//---------------------------------------------------------------------------
+/// Handles protobuf `POST /v1/metrics` and `POST /v1/traces` exports.
+fn handle(req: &Request, translator: &Shared) -> Response {
+	if req.header("content-type") == Some("application/json") {
+		return Response::text(415, "Only protobuf exports are supported");
+	}
+
+	let rows = match (req.method.as_str(), req.path.as_str()) {
+		("POST", "/v1/metrics") => {
+			ExportMetricsServiceRequest::decode(req.body.as_slice())
+				.map(|r| metric_rows(&r))
+		}
+		("POST", "/v1/traces") => {
+			ExportTraceServiceRequest::decode(req.body.as_slice())
+				.map(|r| span_rows(&r))
+		}
+		_ => return Response::text(404, "Not found"),
+	};
+
+	let rows = match rows {
+		Ok(rows) => rows,
+		Err(e) => return Response::text(400, &e.to_string()),
+	};
+
+	match translator.lock().unwrap().send(rows) {
+		// Empty partial success messages.
+		true => Response::new(200, "application/x-protobuf", vec![]),
+		false => Response::text(503, "The capture has finished"),
+	}
+}
+
+/// Receives OTLP metrics and traces over gRPC on `grpc` and over HTTP on
+/// `http`, as rows of the `otel_metrics` and `otel_spans` tables.
+pub fn serve(grpc: Option<&str>, http: Option<&str>) -> io::Result<Bodies> {
+	let (sender, bodies) = ingest::channel();
+	let translator = Arc::new(Mutex::new(Translator {
+		encoder: Encoder::new(),
+		sender,
+	}));
+
+	if let Some(addr) = grpc {
+		serve_grpc(addr, translator.clone())?;
+	}
+
+	if let Some(addr) = http {
+		http::serve(addr, move |req| handle(req, &translator))?;
+	}
+
+	Ok(bodies)
+}
+
+//---------------------------------------------------------------------------
+/// Entries buffered before an export.
+const BATCH: usize = 512;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Signal {
+	Metrics,
+	Logs,
+}
+
+struct Table {
+	name: String,
+	columns: Vec<String>,
+}
+
+/// Exports the entries to an OpenTelemetry collector. As metrics, numeric
+/// columns become gauges named `<table>.<column>` with the text and bool
+/// columns as attributes. As logs, each entry is a record with the table
+/// name as its body and the columns as attributes.
+pub struct Exporter {
+	signal: Signal,
+	resource: Resource,
+	tables: Vec<Option<Table>>,
+	metrics: Vec<Metric>,
+	logs: Vec<LogRecord>,
+	/// Entries buffered since the last export.
+	entries: usize,
+	/// Base url of the collector.
+	endpoint: String,
+	delivery: Delivery,
+}
+
+impl Exporter {
+	pub fn new(config: &OtlpConfig) -> Result<Exporter, String> {
+		let signal = match config.signal.as_deref() {
+			None | Some("metrics") => Signal::Metrics,
+			Some("logs") => Signal::Logs,
+			Some(s) => return Err(format!("Unknown otlp signal: {}", s)),
+		};
+		if !config.endpoint.starts_with("http://") {
+			return Err(format!(
+				"OTLP: {} is not an http:// url",
+				config.endpoint
+			));
+		}
+
+		let delivery = Delivery::new(
+			"OTLP",
+			config.spool.as_deref(),
+			config.spool_size.as_deref(),
+			vec![],
+		)?;
+
+		let service = config.service.as_deref().unwrap_or("sdd");
+		let resource = Resource {
+			attributes: vec![attribute(
+				"service.name",
+				any_value::Value::StringValue(service.to_string()),
+			)],
+			..Default::default()
+		};
+
+		Ok(Exporter {
+			signal,
+			resource,
+			tables: vec![],
+			metrics: vec![],
+			logs: vec![],
+			entries: 0,
+			endpoint: config.endpoint.trim_end_matches('/').to_string(),
+			delivery,
+		})
+	}
+
+	/// Queues an export of the buffered entries.
+	fn export(&mut self) -> Result<(), String> {
+		if self.entries == 0 {
+			return Ok(());
+		}
+		self.entries = 0;
+
+		let scope = Some(InstrumentationScope {
+			name: String::from("sdd"),
+			version: String::from(env!("CARGO_PKG_VERSION")),
+			..Default::default()
+		});
+		let resource = Some(self.resource.clone());
+
+		let (path, body) = match self.signal {
+			Signal::Metrics => {
+				let request = ExportMetricsServiceRequest {
+					resource_metrics: vec![ResourceMetrics {
+						resource,
+						scope_metrics: vec![ScopeMetrics {
+							scope,
+							metrics: mem::take(&mut self.metrics),
+							..Default::default()
+						}],
+						..Default::default()
+					}],
+				};
+				("/v1/metrics", request.encode_to_vec())
+			}
+			Signal::Logs => {
+				let request = ExportLogsServiceRequest {
+					resource_logs: vec![ResourceLogs {
+						resource,
+						scope_logs: vec![ScopeLogs {
+							scope,
+							log_records: mem::take(&mut self.logs),
+							..Default::default()
+						}],
+						..Default::default()
+					}],
+				};
+				("/v1/logs", request.encode_to_vec())
+			}
+		};
+
+		let url = format!("{}{}", self.endpoint, path);
+		self.delivery.post(url, "application/x-protobuf", body)
+	}
+}
+
+fn attribute(key: &str, value: any_value::Value) -> KeyValue {
+	KeyValue {
+		key: key.to_string(),
+		value: Some(AnyValue { value: Some(value) }),
+	}
+}
+
+impl Sink for Exporter {
+	fn bind(&mut self, uid: u32, table: &str, columns: &[String]) {
+		let uid = uid as usize;
+		if self.tables.len() <= uid {
+			self.tables.resize_with(uid + 1, || None);
+		}
+
+		self.tables[uid] = Some(Table {
+			name: table.to_string(),
+			columns: columns.to_vec(),
+		});
+	}
+
+	fn send(
+		&mut self,
+		uid: u32,
+		source: &str,
+		entry: &Entry,
+		strings: &Strings,
+	) -> Result<(), String> {
+		let table = match self.tables.get(uid as usize) {
+			Some(Some(t)) => t,
+			_ => return Ok(()),
+		};
+
+		// Entries carry no time of their own.
+		let time = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_nanos() as u64)
+			.unwrap_or(0);
+
+		let source = any_value::Value::StringValue(source.to_string());
+		let mut attributes = vec![attribute("source", source)];
+		let mut numbers = vec![];
+		for (column, value) in table.columns.iter().zip(entry.values()) {
+			match *value {
+				FieldType::Int(v) => numbers.push((column, v as f64, true)),
+				FieldType::Float(v) => numbers.push((column, v, false)),
+				FieldType::Bool(v) => attributes
+					.push(attribute(column, any_value::Value::BoolValue(v))),
+				FieldType::Str(id) => {
+					let text = match strings.resolve(id) {
+						Some(s) => s.to_string(),
+						None => format!("#{}", id),
+					};
+					let text = any_value::Value::StringValue(text);
+					attributes.push(attribute(column, text));
+				}
+			}
+		}
+
+		match self.signal {
+			Signal::Metrics => {
+				for (column, v, int) in numbers {
+					let value = match int {
+						true => number_data_point::Value::AsInt(v as i64),
+						false => number_data_point::Value::AsDouble(v),
+					};
+					let point = NumberDataPoint {
+						attributes: attributes.clone(),
+						time_unix_nano: time,
+						value: Some(value),
+						..Default::default()
+					};
+					self.metrics.push(Metric {
+						name: format!("{}.{}", table.name, column),
+						data: Some(metric::Data::Gauge(Gauge {
+							data_points: vec![point],
+						})),
+						..Default::default()
+					});
+				}
+			}
+			Signal::Logs => {
+				for (column, v, int) in numbers {
+					let value = match int {
+						true => any_value::Value::IntValue(v as i64),
+						false => any_value::Value::DoubleValue(v),
+					};
+					attributes.push(attribute(column, value));
+				}
+
+				let body = any_value::Value::StringValue(table.name.clone());
+				self.logs.push(LogRecord {
+					time_unix_nano: time,
+					observed_time_unix_nano: time,
+					body: Some(AnyValue { value: Some(body) }),
+					attributes,
+					..Default::default()
+				});
+			}
+		}
+
+		self.entries += 1;
+		match self.entries < BATCH {
+			true => Ok(()),
+			false => self.export(),
+		}
+	}
+
+	fn flush(&mut self) -> Result<(), String> {
+		let exported = self.export();
+		self.delivery.flush();
+		exported
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use opentelemetry_proto::tonic::trace::v1::{
+		ResourceSpans, ScopeSpans, Span,
+	};
+
+	#[test]
+	fn span_row() {
+		let span = Span {
+			trace_id: vec![0xab; 16],
+			span_id: vec![1, 2],
+			name: String::from("GET /"),
+			kind: SpanKind::Server as i32,
+			start_time_unix_nano: 1_700_000_000_250_000_000,
+			end_time_unix_nano: 1_700_000_000_260_000_000,
+			..Default::default()
+		};
+		let resource = Resource {
+			attributes: vec![KeyValue {
+				key: String::from("service.name"),
+				value: Some(AnyValue {
+					value: Some(any_value::Value::StringValue(String::from(
+						"api",
+					))),
+				}),
+			}],
+			..Default::default()
+		};
+		let request = ExportTraceServiceRequest {
+			resource_spans: vec![ResourceSpans {
+				resource: Some(resource),
+				scope_spans: vec![ScopeSpans {
+					spans: vec![span],
+					..Default::default()
+				}],
+				..Default::default()
+			}],
+		};
+
+		let rows = span_rows(&request);
+		let fields = &rows[0]["fields"];
+		assert_eq!(fields["service"], "api");
+		assert_eq!(fields["kind"], "SPAN_KIND_SERVER");
+		assert_eq!(fields["span_id_0"], 0);
+		assert_eq!(fields["span_id_1"], 0x0102);
+		assert_eq!(fields["trace_id_3"], 0xabababab_u32);
+		assert_eq!(fields["parent_span_id_1"], 0);
+		assert_eq!(fields["start"], 1_700_000_000);
+		assert_eq!(fields["start_ns"], 250_000_000);
+		assert_eq!(fields["duration_ms"], 10.0);
+
+		let mut encoder = Encoder::new();
+		encoder.encode(&rows[0]).unwrap();
+	}
+
+	#[test]
+	fn export_gauges() {
+		let config = OtlpConfig {
+			endpoint: String::from("http://127.0.0.1:4318"),
+			signal: None,
+			service: None,
+			spool: None,
+			spool_size: None,
+		};
+		let mut exporter = Exporter::new(&config).unwrap();
+
+		let (strings, entry) = crate::sink::test_entry();
+
+		let columns = [String::from("n"), String::from("ok")];
+		exporter.bind(0, "log", &columns);
+		exporter.send(0, "dev", &entry, &strings).unwrap();
+
+		let metric = &exporter.metrics[0];
+		assert_eq!(metric.name, "log.n");
+		let point = match &metric.data {
+			Some(metric::Data::Gauge(g)) => &g.data_points[0],
+			_ => panic!("Expected a gauge"),
+		};
+		assert_eq!(point.value, Some(number_data_point::Value::AsInt(7)));
+		let keys: Vec<&str> =
+			point.attributes.iter().map(|kv| kv.key.as_str()).collect();
+		assert_eq!(keys, ["source", "ok"]);
+	}
+}
@@ -0,0 +1,513 @@
+use crate::ingest;
+#[cfg(feature = "shm")]
+use crate::shm;
+#[cfg(feature = "websocket")]
+use crate::websocket;
+use sdd_core::Encoder;
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+#[cfg(feature = "websocket")]
+use tungstenite::stream::MaybeTlsStream;
+
+/// Column types and values, shared with the no_std encoder of sdd-core.
+pub use sdd_core::{Type, Value};
+
+fn encoded(e: sdd_core::Error) -> io::Error {
+	let message = match e {
+		sdd_core::Error::Full => "Too many values",
+		sdd_core::Error::Columns => "Tables have 1 to 32 columns",
+	};
+	io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Reads the (uid, every) controls the daemon sends back on `reader` until
+/// the connection closes or falls out of step.
+fn read_controls<R: Read>(mut reader: R, controls: mpsc::Sender<(u32, u32)>) {
+	let mut buf = [0; sdd_core::CONTROL_SIZE];
+	while reader.read_exact(&mut buf).is_ok() {
+		if sdd_core::word(&buf) != sdd_core::PROTOCOL
+			|| buf[4] != sdd_core::MSG_CONTROL
+		{
+			return;
+		}
+
+		let body = &buf[sdd_core::HEADER_SIZE..];
+		let control = (sdd_core::word(body), sdd_core::word(&body[4..]));
+		if controls.send(control).is_err() {
+			return;
+		}
+	}
+}
+
+/// Controls received by a thread of their own, stopped along with the
+/// producer.
+struct Controls {
+	received: mpsc::Receiver<(u32, u32)>,
+	stream: TcpStream,
+}
+
+impl Drop for Controls {
+	fn drop(&mut self) {
+		let _ = self.stream.shutdown(Shutdown::Read);
+	}
+}
+
+/// Share of the entries of a table the daemon asked for.
+struct Sampling {
+	every: u32,
+	/// Entries since the last one sent.
+	skipped: u32,
+	/// Whether the last entry was sent, its counters go along with it.
+	sending: bool,
+}
+
+//---------------------------------------------------------------------------
+/// Encodes tables, strings and entries for the daemon.
+pub struct Producer<W: Write> {
+	writer: W,
+	strings: HashMap<String, u32>,
+	tables: u32,
+	/// Last entry of each table once the stream is compact.
+	compact: Option<HashMap<u32, Vec<Value>>>,
+	sampling: HashMap<u32, Sampling>,
+	controls: Option<Controls>,
+}
+
+impl Producer<BufWriter<TcpStream>> {
+	/// Waits for the daemon to connect on `addr`, applying the controls it
+	/// sends back.
+	pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		let listener = TcpListener::bind(addr)?;
+		let (stream, _) = listener.accept()?;
+		let controls = stream.try_clone()?;
+
+		let mut producer = Producer::new(BufWriter::new(stream));
+		producer.receive_controls(controls)?;
+		Ok(producer)
+	}
+}
+
+impl Producer<ingest::Writer> {
+	/// Producer for a daemon embedded in the same process, which captures
+	/// the returned reader with `Daemon::capture_reader`. Flushes hand the
+	/// encoded messages over, dropping the producer ends the session.
+	pub fn channel() -> (Self, ingest::Bodies) {
+		let (writer, bodies) = ingest::pipe();
+		(Producer::new(writer), bodies)
+	}
+}
+
+#[cfg(feature = "websocket")]
+impl Producer<websocket::Sender<MaybeTlsStream<TcpStream>>> {
+	/// Connects to a daemon accepting WebSockets on `url`, each flush is
+	/// sent as one binary message.
+	pub fn websocket(url: &str) -> io::Result<Self> {
+		Ok(Producer::new(websocket::connect(url)?))
+	}
+}
+
+#[cfg(feature = "shm")]
+impl Producer<BufWriter<shm::Writer>> {
+	/// Attaches to the shared-memory ring of a daemon started with
+	/// `--input shm:<handshake>`.
+	pub fn shm<P: AsRef<std::path::Path>>(handshake: P) -> io::Result<Self> {
+		let writer = shm::attach(handshake.as_ref())?;
+		Ok(Producer::new(BufWriter::new(writer)))
+	}
+}
+
+impl<W: Write> Producer<W> {
+	pub fn new(writer: W) -> Producer<W> {
+		Producer {
+			writer,
+			strings: HashMap::new(),
+			tables: 0,
+			compact: None,
+			sampling: HashMap::new(),
+			controls: None,
+		}
+	}
+
+	/// Applies the controls the daemon sends back on `stream`, a clone of
+	/// the connection the producer writes to, see `sample`.
+	pub fn receive_controls(&mut self, stream: TcpStream) -> io::Result<()> {
+		let reader = stream.try_clone()?;
+		let (sender, received) = mpsc::channel();
+		thread::spawn(move || read_controls(reader, sender));
+
+		self.controls = Some(Controls { received, stream });
+		Ok(())
+	}
+
+	/// Sends one in `every` entries of table `uid`, none when 0, all when 1,
+	/// as the daemon asks with `sdd_core::MSG_CONTROL`. The counters of a
+	/// skipped entry are skipped along with it, as the daemon adds them to
+	/// the last entry it received.
+	pub fn sample(&mut self, uid: u32, every: u32) {
+		let sampling = Sampling {
+			every,
+			skipped: 0,
+			sending: every != 0,
+		};
+		match every {
+			1 => self.sampling.remove(&uid),
+			_ => self.sampling.insert(uid, sampling),
+		};
+	}
+
+	/// Whether to send an entry or, unless `entry`, a counter of table
+	/// `uid`, applying the received controls first.
+	fn sampled(&mut self, uid: u32, entry: bool) -> bool {
+		let received: Vec<(u32, u32)> = match &self.controls {
+			Some(controls) => controls.received.try_iter().collect(),
+			None => vec![],
+		};
+		for (uid, every) in received {
+			self.sample(uid, every);
+		}
+
+		match self.sampling.get_mut(&uid) {
+			None => true,
+			Some(s) if s.every == 0 => false,
+			Some(s) if !entry => s.sending,
+			Some(s) => {
+				s.sending = s.skipped == 0;
+				s.skipped = (s.skipped + 1) % s.every;
+				s.sending
+			}
+		}
+	}
+
+	/// Opens the stream with `sdd_core::AUTH_MAGIC` and `token`, for a
+	/// daemon listening with an `[auth]` token. Has to precede any other
+	/// message, `compact` included.
+	pub fn authenticate(&mut self, token: &str) -> io::Result<()> {
+		if !self.strings.is_empty() || self.compact.is_some() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Authentication starts the stream",
+			));
+		}
+		if token.len() > sdd_core::MAX_TOKEN {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Token too long",
+			));
+		}
+
+		self.writer.write_all(&sdd_core::AUTH_MAGIC)?;
+		self.writer.write_all(&(token.len() as u32).to_le_bytes())?;
+		self.writer.write_all(token.as_bytes())
+	}
+
+	/// Opens the stream with `sdd_core::COMPACT_MAGIC`, encoding the
+	/// entries after it with `Encoder::compact_entry`. Has to precede any
+	/// other message.
+	pub fn compact(&mut self) -> io::Result<()> {
+		if !self.strings.is_empty() || self.compact.is_some() {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Compact encoding starts the stream",
+			));
+		}
+
+		self.writer.write_all(&sdd_core::COMPACT_MAGIC)?;
+		self.compact = Some(HashMap::new());
+		Ok(())
+	}
+
+	/// Id of `s`, sending it the first time it is used.
+	pub fn string(&mut self, s: &str) -> io::Result<u32> {
+		if let Some(id) = self.strings.get(s) {
+			return Ok(*id);
+		}
+
+		let id = self.strings.len() as u32;
+		let mut buf = vec![0; sdd_core::string_size(s.len())];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.string(id, s).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())?;
+
+		self.strings.insert(s.to_string(), id);
+		Ok(id)
+	}
+
+	/// Declares a table of (name, type) columns, returning its uid.
+	pub fn table(
+		&mut self,
+		name: &str,
+		columns: &[(&str, Type)],
+	) -> io::Result<u32> {
+		self.declare(name, columns, false)
+	}
+
+	/// Declares a table like `table` whose entries the daemon tags with the
+	/// thread of the last `thread` call in a `thread` column.
+	pub fn threaded_table(
+		&mut self,
+		name: &str,
+		columns: &[(&str, Type)],
+	) -> io::Result<u32> {
+		self.declare(name, columns, true)
+	}
+
+	fn declare(
+		&mut self,
+		name: &str,
+		columns: &[(&str, Type)],
+		threaded: bool,
+	) -> io::Result<u32> {
+		if columns.is_empty() || columns.len() > sdd_core::MAX_FIELDS {
+			return Err(encoded(sdd_core::Error::Columns));
+		}
+
+		let name = self.string(name)?;
+		let mut fields = vec![];
+		for (column, data_type) in columns {
+			fields.push((self.string(column)?, *data_type));
+		}
+
+		let uid = self.tables;
+		let mut buf = [0; sdd_core::MAX_TABLE_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		let encoded_table = if threaded {
+			encoder.threaded_table(uid, name, &fields)
+		} else {
+			encoder.table(uid, name, &fields)
+		};
+		encoded_table.map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())?;
+
+		self.tables += 1;
+		Ok(uid)
+	}
+
+	/// Sends an entry of table `uid`, `values` in column order.
+	pub fn entry(&mut self, uid: u32, values: &[Value]) -> io::Result<()> {
+		if !self.sampled(uid, true) {
+			return Ok(());
+		}
+
+		let mut buf = [0; sdd_core::MAX_COMPACT_ENTRY_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		match &mut self.compact {
+			Some(previous) => {
+				let last = previous.entry(uid).or_default();
+				encoder.compact_entry(uid, values, last).map_err(encoded)?;
+				*last = values.to_vec();
+			}
+			None => encoder.entry(uid, values).map_err(encoded)?,
+		}
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Starts or stops persisting entries on the daemon.
+	pub fn marker(
+		&mut self,
+		start: bool,
+		label: Option<&str>,
+	) -> io::Result<()> {
+		let label = label.map(|l| self.string(l)).transpose()?;
+
+		let mut buf = [0; sdd_core::HEADER_SIZE + 5];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.marker(start, label).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Tags the entries of threaded tables that follow with thread `name`.
+	pub fn thread(&mut self, name: &str) -> io::Result<()> {
+		let name = self.string(name)?;
+
+		let mut buf = [0; sdd_core::THREAD_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.thread(name).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Attributes the messages that follow to producer `name`, for relays
+	/// forwarding several producers over one connection.
+	pub fn source(&mut self, name: &str) -> io::Result<()> {
+		let name = self.string(name)?;
+
+		let mut buf = [0; sdd_core::SOURCE_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.source(name).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Stops persisting entries without ending the session, e.g. during a
+	/// loading screen, until `resume`.
+	pub fn pause(&mut self, reason: Option<&str>) -> io::Result<()> {
+		let reason = reason.map(|r| self.string(r)).transpose()?;
+
+		let mut buf = [0; sdd_core::PAUSE_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.pause(true, reason).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	pub fn resume(&mut self) -> io::Result<()> {
+		let mut buf = [0; sdd_core::PAUSE_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.pause(false, None).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Registers entity `id`, e.g. a spawned object, whose entries refer to
+	/// it in `entity_id` columns.
+	pub fn entity(
+		&mut self,
+		id: u32,
+		name: &str,
+		kind: &str,
+	) -> io::Result<()> {
+		let name = self.string(name)?;
+		let kind = self.string(kind)?;
+
+		let mut buf = [0; sdd_core::ENTITY_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.entity(id, name, kind).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Sends a named binary payload, e.g. a minidump or a config dump,
+	/// stored in `_sdd_attachments`.
+	pub fn attachment(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+		let name = self.string(name)?;
+
+		let mut buf = vec![0; sdd_core::attachment_size(data.len())];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.attachment(name, data).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Adds `delta` to the last column of table `uid`, see
+	/// `Encoder::counter`.
+	pub fn counter(&mut self, uid: u32, delta: u32) -> io::Result<()> {
+		if !self.sampled(uid, false) {
+			return Ok(());
+		}
+
+		let mut buf = [0; sdd_core::MAX_COUNTER_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.counter(uid, delta).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	/// Opens scope `id` within `parent`, see `Encoder::scope_begin`.
+	pub fn scope_begin(
+		&mut self,
+		id: u32,
+		parent: Option<u32>,
+		name: &str,
+		ts: u64,
+	) -> io::Result<()> {
+		let name = self.string(name)?;
+
+		let mut buf = [0; sdd_core::SCOPE_BEGIN_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.scope_begin(id, parent, name, ts).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	pub fn scope_end(&mut self, id: u32, ts: u64) -> io::Result<()> {
+		let mut buf = [0; sdd_core::SCOPE_END_SIZE];
+		let mut encoder = Encoder::new(&mut buf);
+		encoder.scope_end(id, ts).map_err(encoded)?;
+		self.writer.write_all(encoder.bytes())
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.writer.flush()
+	}
+
+	pub fn get_mut(&mut self) -> &mut W {
+		&mut self.writer
+	}
+
+	pub fn into_inner(self) -> W {
+		self.writer
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encode_messages() {
+		let mut producer = Producer::new(vec![]);
+		let uid = producer.table("log", &[("ts", Type::Int)]).unwrap();
+		producer.entry(uid, &[Value::Int(7)]).unwrap();
+		assert_eq!(producer.string("ts").unwrap(), 1);
+
+		let mut expected = vec![];
+		for (id, s) in ["log", "ts"].iter().enumerate() {
+			expected.extend_from_slice(&[0xEF, 0xBE, 0xED, 0xFE, 1]);
+			expected.extend_from_slice(&(id as u32).to_le_bytes());
+			expected.extend_from_slice(&(s.len() as u32).to_le_bytes());
+			expected.extend_from_slice(s.as_bytes());
+		}
+		expected.extend_from_slice(&[0xEF, 0xBE, 0xED, 0xFE, 3]);
+		expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0]);
+		expected.extend_from_slice(&[0xEF, 0xBE, 0xED, 0xFE, 2]);
+		expected.extend_from_slice(&[0, 0, 0, 0, 7, 0, 0, 0]);
+
+		assert_eq!(producer.writer, expected);
+	}
+
+	#[test]
+	fn sample_tables() {
+		let mut producer = Producer::new(vec![]);
+		let uid = producer.table("log", &[("ts", Type::Int)]).unwrap();
+		let len = producer.writer.len();
+
+		producer.sample(uid, 0);
+		producer.entry(uid, &[Value::Int(0)]).unwrap();
+		producer.counter(uid, 1).unwrap();
+		assert_eq!(producer.writer.len(), len);
+
+		producer.sample(uid, 3);
+		for ts in 0..7 {
+			producer.entry(uid, &[Value::Int(ts)]).unwrap();
+		}
+		let sent: Vec<u32> = producer.writer[len..]
+			.chunks(sdd_core::HEADER_SIZE + 8)
+			.map(|m| sdd_core::word(&m[sdd_core::HEADER_SIZE + 4..]))
+			.collect();
+		assert_eq!(sent, [0, 3, 6]);
+
+		let mut controls = [0; sdd_core::CONTROL_SIZE];
+		let mut encoder = Encoder::new(&mut controls);
+		encoder.control(uid, 0).unwrap();
+		let (sender, received) = mpsc::channel();
+		read_controls(&controls[..], sender);
+		assert_eq!(received.try_iter().collect::<Vec<_>>(), [(uid, 0)]);
+	}
+
+	#[cfg(feature = "sqlite")]
+	#[test]
+	fn in_process_capture() {
+		use crate::dae::{Daemon, Protocol};
+
+		let (mut producer, bodies) = Producer::channel();
+		let uid = producer.table("log", &[("ts", Type::Int)]).unwrap();
+		for ts in 0..3 {
+			producer.entry(uid, &[Value::Int(ts)]).unwrap();
+		}
+
+		let mut daemon = Daemon::new(Protocol::without_output());
+		daemon.verbose = false;
+		daemon.exit_on_eof = true;
+		drop(producer);
+
+		daemon.capture_reader(bodies, "in-process").unwrap();
+		assert_eq!(daemon.stats.lock().unwrap().entries, 3);
+	}
+}
@@ -0,0 +1,199 @@
+use crate::config::QuicConfig;
+use crate::ingest::{self, Bodies};
+use quinn::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use quinn::rustls::RootCertStore;
+use quinn::{ClientConfig, Connection, Endpoint, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Largest chunk handed to the daemon at once.
+const MAX_CHUNK: usize = 64 * 1024;
+/// Wait between attempts to reach a producer.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+//---------------------------------------------------------------------------
+fn open(path: &str) -> Result<BufReader<File>, String> {
+	File::open(path)
+		.map(BufReader::new)
+		.map_err(|e| format!("{}: {}", path, e))
+}
+
+fn certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+	rustls_pemfile::certs(&mut open(path)?)
+		.collect::<Result<_, _>>()
+		.map_err(|e| format!("{}: {}", path, e))
+}
+
+fn server_config(config: &QuicConfig) -> Result<ServerConfig, String> {
+	let (cert, key) = match (&config.cert, &config.key) {
+		(Some(cert), Some(key)) => (cert, key),
+		_ => return Err(String::from("Accepting QUIC needs a cert and key")),
+	};
+
+	let key: PrivateKeyDer = rustls_pemfile::private_key(&mut open(key)?)
+		.map_err(|e| format!("{}: {}", key, e))?
+		.ok_or_else(|| format!("{}: no private key", key))?;
+
+	ServerConfig::with_single_cert(certs(cert)?, key)
+		.map_err(|e| format!("QUIC: {}", e))
+}
+
+fn client_config(config: &QuicConfig) -> Result<ClientConfig, String> {
+	let ca = match &config.ca {
+		Some(ca) => ca,
+		None => return Err(String::from("Connecting QUIC needs a ca")),
+	};
+
+	let mut roots = RootCertStore::empty();
+	for cert in certs(ca)? {
+		roots.add(cert).map_err(|e| format!("{}: {}", ca, e))?;
+	}
+
+	ClientConfig::with_root_certificates(Arc::new(roots))
+		.map_err(|e| format!("QUIC: {}", e))
+}
+
+fn parse_addr(addr: &str) -> Result<SocketAddr, String> {
+	addr.parse().map_err(|_| format!("Bad address: {}", addr))
+}
+
+//---------------------------------------------------------------------------
+/// Hands the first unidirectional stream of `connection` to the daemon as
+/// one session.
+async fn forward(connection: Connection, sender: &ingest::Sender) {
+	let peer = connection.remote_address();
+	println!("{} connected", peer);
+
+	let mut stream = match connection.accept_uni().await {
+		Ok(s) => s,
+		Err(e) => {
+			println!("{}: {}", peer, e);
+			return;
+		}
+	};
+
+	loop {
+		match stream.read_chunk(MAX_CHUNK, true).await {
+			Ok(Some(chunk)) => {
+				// Blocking only holds back this stream, the endpoint keeps
+				// running on the runtime's workers.
+				if sender.send(chunk.bytes.to_vec()).is_err() {
+					return;
+				}
+			}
+			Ok(None) => break,
+			Err(e) => {
+				println!("{}: {}", peer, e);
+				break;
+			}
+		}
+	}
+
+	let _ = sender.send(vec![]);
+}
+
+/// Accepts producers on `addr` one at a time, each connection read as one
+/// session.
+pub fn listen(addr: &str, config: &QuicConfig) -> Result<Bodies, String> {
+	let server = server_config(config)?;
+	let addr = parse_addr(addr)?;
+
+	let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+	let endpoint = {
+		let _guard = runtime.enter();
+		Endpoint::server(server, addr).map_err(|e| e.to_string())?
+	};
+
+	let (sender, bodies) = ingest::channel();
+	thread::spawn(move || {
+		runtime.block_on(async {
+			while let Some(incoming) = endpoint.accept().await {
+				match incoming.await {
+					Ok(connection) => forward(connection, &sender).await,
+					Err(e) => println!("QUIC handshake failed: {}", e),
+				}
+			}
+		})
+	});
+
+	Ok(bodies)
+}
+
+/// Connects to a producer accepting QUIC on `addr`, again after each
+/// session or failed attempt if `reconnect` is set.
+pub fn connect(
+	addr: &str,
+	config: &QuicConfig,
+	reconnect: bool,
+) -> Result<Bodies, String> {
+	let client = client_config(config)?;
+	let addr = parse_addr(addr)?;
+	let server_name = config
+		.server_name
+		.clone()
+		.unwrap_or_else(|| addr.ip().to_string());
+
+	let runtime = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+	let mut endpoint = {
+		let _guard = runtime.enter();
+		let local = if addr.is_ipv6() {
+			"[::]:0"
+		} else {
+			"0.0.0.0:0"
+		};
+		Endpoint::client(local.parse().unwrap()).map_err(|e| e.to_string())?
+	};
+	endpoint.set_default_client_config(client);
+
+	let (sender, bodies) = ingest::channel();
+	thread::spawn(move || {
+		runtime.block_on(async {
+			loop {
+				match endpoint.connect(addr, &server_name) {
+					Ok(connecting) => match connecting.await {
+						Ok(connection) => forward(connection, &sender).await,
+						Err(e) => println!("{}: {}", addr, e),
+					},
+					Err(e) => println!("{}: {}", addr, e),
+				}
+
+				if !reconnect {
+					return;
+				}
+				tokio::time::sleep(RETRY_INTERVAL).await;
+			}
+		})
+	});
+
+	Ok(bodies)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn missing_certificates() {
+		let config = QuicConfig {
+			cert: None,
+			key: None,
+			ca: None,
+			server_name: None,
+		};
+		assert!(listen("127.0.0.1:0", &config).is_err());
+		assert!(connect("127.0.0.1:4433", &config, false).is_err());
+
+		let config = QuicConfig {
+			ca: Some(String::from("/nonexistent/ca.pem")),
+			..config
+		};
+		let e = connect("127.0.0.1:4433", &config, false).err().unwrap();
+		assert!(e.starts_with("/nonexistent/ca.pem"));
+	}
+}
@@ -0,0 +1,341 @@
+use crate::wire::{Descriptor, Message, Messages, TS_COLUMNS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::mem;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+//---------------------------------------------------------------------------
+/// Extension of raw captures, the protocol stream as the daemon read it.
+pub const EXTENSION: &str = "sddcap";
+
+/// Entries between the checkpoints of an index.
+const CHECKPOINT: u64 = 4096;
+const INDEX_VERSION: u32 = 1;
+
+//---------------------------------------------------------------------------
+/// Appends the streams read by the daemon to a raw capture, shared by the
+/// readers of its sessions.
+#[derive(Clone)]
+pub struct Recorder {
+	file: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Recorder {
+	pub fn create(path: &Path) -> io::Result<Self> {
+		let file = BufWriter::new(File::create(path)?);
+		Ok(Recorder {
+			file: Arc::new(Mutex::new(file)),
+		})
+	}
+
+	pub fn flush(&self) -> io::Result<()> {
+		self.file.lock().unwrap().flush()
+	}
+}
+
+/// Reader passing what it reads from `inner` to the capture, if any.
+pub fn tee<R: Read>(inner: R, recorder: Option<Recorder>) -> Tee<R> {
+	Tee { inner, recorder }
+}
+
+pub struct Tee<R: Read> {
+	inner: R,
+	recorder: Option<Recorder>,
+}
+
+impl<R: Read> Read for Tee<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		if let Some(recorder) = &self.recorder {
+			recorder.file.lock().unwrap().write_all(&buf[..n])?;
+		}
+		Ok(n)
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Bytes of a raw capture, mapped into memory with the `mmap` feature so
+/// that the messages are decoded in place, without reading the whole file
+/// into a buffer first, and read otherwise.
+pub struct Capture {
+	#[cfg(feature = "mmap")]
+	bytes: memmap2::Mmap,
+	#[cfg(not(feature = "mmap"))]
+	bytes: Vec<u8>,
+}
+
+impl Capture {
+	#[cfg(feature = "mmap")]
+	pub fn open(path: &Path) -> io::Result<Capture> {
+		let file = File::open(path)?;
+		// A capture still being recorded only grows past the mapping, one
+		// truncated meanwhile would fault on the pages it lost.
+		let bytes = unsafe { memmap2::Mmap::map(&file)? };
+		#[cfg(unix)]
+		let _ = bytes.advise(memmap2::Advice::Sequential);
+		Ok(Capture { bytes })
+	}
+
+	#[cfg(not(feature = "mmap"))]
+	pub fn open(path: &Path) -> io::Result<Capture> {
+		Ok(Capture {
+			bytes: fs::read(path)?,
+		})
+	}
+}
+
+impl Deref for Capture {
+	type Target = [u8];
+
+	fn deref(&self) -> &[u8] {
+		&self.bytes
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Entry of a table, the first after the previous checkpoint, from which a
+/// capture can be read once its declarations are known.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+	pub offset: usize,
+	/// Entries before this one.
+	pub entry: u64,
+	/// Timestamp of the first entry having one from here on, in the unit
+	/// of the timestamp columns.
+	pub ts: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TableIndex {
+	pub uid: u32,
+	pub name: String,
+	pub entries: u64,
+	pub first_ts: Option<f64>,
+	pub last_ts: Option<f64>,
+}
+
+/// Sidecar index of a raw capture, `<capture>.idx`, to seek to a time
+/// without decoding what comes before.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Index {
+	pub version: u32,
+	/// Size of the indexed capture, a capture of another size is indexed
+	/// again.
+	pub size: usize,
+	/// Offsets and sizes of the string and table messages, which have to be
+	/// read before starting at a checkpoint.
+	pub declarations: Vec<(usize, usize)>,
+	pub checkpoints: Vec<Checkpoint>,
+	pub tables: Vec<TableIndex>,
+	/// Offsets of the messages that could not be decoded.
+	pub errors: Vec<usize>,
+}
+
+/// Position of the timestamp column of a table.
+pub fn ts_column(
+	desc: &Descriptor,
+	strings: &HashMap<u32, String>,
+) -> Option<usize> {
+	desc.columns().position(|(c, _)| {
+		strings.get(&c).is_some_and(|c| {
+			TS_COLUMNS.iter().any(|t| c.eq_ignore_ascii_case(t))
+		})
+	})
+}
+
+pub fn index_path(capture: &Path) -> PathBuf {
+	let mut path = capture.as_os_str().to_owned();
+	path.push(".idx");
+	PathBuf::from(path)
+}
+
+impl Index {
+	/// Decodes a raw capture, indexing its messages.
+	pub fn build(bytes: &[u8]) -> Index {
+		let mut index = Index {
+			version: INDEX_VERSION,
+			size: bytes.len(),
+			declarations: vec![],
+			checkpoints: vec![],
+			tables: vec![],
+			errors: vec![],
+		};
+		let mut strings: HashMap<u32, String> = HashMap::new();
+		// Table and timestamp column by uid.
+		let mut uids: HashMap<u32, (usize, Option<usize>)> = HashMap::new();
+		let mut entries = 0;
+		// Whether the last checkpoint waits for its timestamp.
+		let mut pending = false;
+
+		let mut messages = Messages::new(bytes);
+		loop {
+			let offset = messages.offset();
+			let message = match messages.next() {
+				Some(Ok(m)) => m,
+				Some(Err(_)) => {
+					index.errors.push(offset);
+					continue;
+				}
+				None => break,
+			};
+			let size = messages.offset() - offset;
+
+			match message {
+				Message::String { id, value } => {
+					let value = String::from_utf8_lossy(value).into_owned();
+					strings.insert(id, value);
+					index.declarations.push((offset, size));
+				}
+				Message::Table { uid, desc } => {
+					let name = strings.get(&desc.name()).cloned();
+					let ts = ts_column(&desc, &strings);
+					index.tables.push(TableIndex {
+						uid,
+						name: name.unwrap_or_else(|| format!("#{}", uid)),
+						entries: 0,
+						first_ts: None,
+						last_ts: None,
+					});
+					uids.insert(uid, (index.tables.len() - 1, ts));
+					index.declarations.push((offset, size));
+				}
+				Message::Entry { uid, entry } => {
+					if entries % CHECKPOINT == 0 {
+						index.checkpoints.push(Checkpoint {
+							offset,
+							entry: entries,
+							ts: None,
+						});
+						pending = true;
+					}
+					entries += 1;
+
+					let (table, ts) = uids[&uid];
+					let table = &mut index.tables[table];
+					table.entries += 1;
+
+					let ts = ts
+						.and_then(|ts| entry.values().nth(ts))
+						.and_then(|v| v.as_f64());
+					if let Some(ts) = ts {
+						table.first_ts.get_or_insert(ts);
+						table.last_ts = Some(ts);
+						if mem::take(&mut pending) {
+							index.checkpoints.last_mut().unwrap().ts = Some(ts);
+						}
+					}
+				}
+				Message::Marker { .. }
+				| Message::Entity { .. }
+				| Message::ScopeBegin { .. }
+				| Message::ScopeEnd { .. }
+				| Message::Counter { .. }
+				| Message::Pause { .. }
+				| Message::Attachment { .. }
+				| Message::Thread { .. }
+				| Message::Source { .. } => {}
+			}
+		}
+
+		index
+	}
+
+	/// Index of the raw capture at `capture`, read from its sidecar when it
+	/// is up to date and built and stored otherwise.
+	pub fn open(capture: &Path, bytes: &[u8]) -> io::Result<Index> {
+		let path = index_path(capture);
+		let stored = fs::read(&path)
+			.ok()
+			.and_then(|json| serde_json::from_slice::<Index>(&json).ok())
+			.filter(|i| i.version == INDEX_VERSION && i.size == bytes.len());
+		if let Some(index) = stored {
+			return Ok(index);
+		}
+
+		let index = Index::build(bytes);
+		index.store(capture)?;
+		Ok(index)
+	}
+
+	pub fn store(&self, capture: &Path) -> io::Result<()> {
+		let json = serde_json::to_vec(self).map_err(io::Error::from)?;
+		fs::write(index_path(capture), json)
+	}
+
+	/// Offset to read from to reach the entries at `from` onwards, the last
+	/// checkpoint at or before it for captures in timestamp order.
+	pub fn seek(&self, from: f64) -> usize {
+		self.checkpoints
+			.iter()
+			.take_while(|c| c.ts.is_none_or(|ts| ts <= from))
+			.last()
+			.or_else(|| self.checkpoints.first())
+			.map_or(self.size, |c| c.offset)
+	}
+
+	/// Offset past the entries up to `to`, the first checkpoint after it for
+	/// captures in timestamp order.
+	pub fn end(&self, to: f64) -> usize {
+		self.checkpoints
+			.iter()
+			.find(|c| c.ts.is_some_and(|ts| ts > to))
+			.map_or(self.size, |c| c.offset)
+	}
+
+	/// The declarations before `offset`.
+	pub fn declarations(
+		&self,
+		offset: usize,
+	) -> impl Iterator<Item = (usize, usize)> + '_ {
+		self.declarations
+			.iter()
+			.cloned()
+			.take_while(move |(o, _)| *o < offset)
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::producer::{Producer, Type, Value};
+
+	#[test]
+	fn index_checkpoints() {
+		let mut producer = Producer::new(vec![]);
+		let frame = producer.table("frame", &[("ts", Type::Int)]).unwrap();
+		let log = producer.table("log", &[("level", Type::Int)]).unwrap();
+		for ts in 0..CHECKPOINT as u32 * 2 + 1 {
+			producer.entry(log, &[Value::Int(1)]).unwrap();
+			producer.entry(frame, &[Value::Int(ts * 10)]).unwrap();
+		}
+		let bytes = producer.into_inner();
+
+		let index = Index::build(&bytes);
+		assert_eq!(index.declarations.len(), 6);
+		assert_eq!(index.checkpoints.len(), 5);
+		assert_eq!(index.checkpoints[1].ts, Some(20480.0));
+		assert_eq!(index.tables[0].last_ts, Some(81920.0));
+		assert_eq!(index.tables[1].entries, CHECKPOINT * 2 + 1);
+		assert!(index.errors.is_empty());
+
+		assert_eq!(index.seek(-1.0), index.checkpoints[0].offset);
+		assert_eq!(index.seek(20490.0), index.checkpoints[1].offset);
+		assert_eq!(index.declarations(index.seek(1e9)).count(), 6);
+		assert_eq!(index.end(20480.0), index.checkpoints[2].offset);
+		assert_eq!(index.end(1e9), bytes.len());
+
+		let path = std::env::temp_dir()
+			.join(format!("sdd-raw-test-{}.sddcap", std::process::id()));
+		fs::write(&path, &bytes).unwrap();
+		let capture = Capture::open(&path).unwrap();
+		assert_eq!(&capture[..], &bytes[..]);
+		assert_eq!(Index::build(&capture), index);
+		let _ = fs::remove_file(&path);
+	}
+}
@@ -0,0 +1,421 @@
+use crate::cipher;
+use crate::export::{self, Error, Range, Table, TimeUnit, TS_COLUMNS};
+use crate::producer::{Producer, Type, Value};
+use crate::raw;
+use crate::wire::{Message, Messages};
+use rusqlite::types::ValueRef;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+/// Pace of a replay relative to the recorded timestamps.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Speed {
+	/// As fast as the daemon reads.
+	Max,
+	/// Recorded time divided by the factor, 1 being realtime.
+	Scale(f64),
+}
+
+impl FromStr for Speed {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let err = "Expected realtime, max or a factor such as 2x";
+		match s {
+			"max" => Ok(Speed::Max),
+			"realtime" => Ok(Speed::Scale(1.0)),
+			_ => match s.strip_suffix('x').map(f64::from_str) {
+				Some(Ok(f)) if f > 0.0 && f.is_finite() => Ok(Speed::Scale(f)),
+				_ => Err(err),
+			},
+		}
+	}
+}
+
+/// Time to wait before sending rows, the first one being sent right away.
+struct Pacer {
+	speed: Speed,
+	unit: TimeUnit,
+	start: Instant,
+	first: Option<f64>,
+}
+
+impl Pacer {
+	fn new(opts: &Options) -> Self {
+		Pacer {
+			speed: opts.speed,
+			unit: opts.unit,
+			start: Instant::now(),
+			first: None,
+		}
+	}
+
+	/// How long to wait before sending a row recorded at `ts`.
+	fn wait(&mut self, ts: f64) -> Option<Duration> {
+		let factor = match self.speed {
+			Speed::Scale(factor) if ts.is_finite() => factor,
+			_ => return None,
+		};
+
+		let first = *self.first.get_or_insert(ts);
+		let ns = self.unit.to_ns(ts - first) as f64 / factor;
+		let due = Duration::from_nanos(ns as u64);
+		due.checked_sub(self.start.elapsed())
+			.filter(|d| !d.is_zero())
+	}
+}
+
+pub struct Options {
+	/// Tables to replay, all of them when empty.
+	pub tables: Vec<String>,
+	pub speed: Speed,
+	/// Unit of the timestamp columns.
+	pub unit: TimeUnit,
+	/// Rows to replay by their timestamp, tables without a timestamp column
+	/// being replayed whole.
+	pub range: Range,
+}
+
+/// A table being replayed along with its next row.
+struct Cursor<'a> {
+	rows: rusqlite::Rows<'a>,
+	uid: u32,
+	types: Vec<Type>,
+	ts: Option<usize>,
+	next: Option<(f64, Vec<Value>)>,
+}
+
+/// Sends the rows of the capture at `db` to `producer` as entries of their
+/// tables, merged by their timestamp columns and paced by `opts.speed`.
+/// Tables without a timestamp column are sent first. Raw captures are sent
+/// as they were recorded. Returns the number of rows sent.
+pub fn replay<W: Write>(
+	db: &Path,
+	producer: &mut Producer<W>,
+	opts: &Options,
+) -> Result<usize, Error> {
+	if db.extension().is_some_and(|e| e == raw::EXTENSION) {
+		return replay_raw(db, producer.get_mut(), opts);
+	}
+
+	let con = cipher::open(db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+	let signatures = signatures(&con);
+	let strings = strings(&con);
+
+	let tables: Vec<Table> = export::tables(&con)?
+		.into_iter()
+		.filter(|t| opts.tables.is_empty() || opts.tables.contains(&t.name))
+		.collect();
+
+	let mut stmts = vec![];
+	for table in &tables {
+		let (filter, order) = match table.find_column(&TS_COLUMNS) {
+			Some(ts) => {
+				let ts = &table.columns[ts].name;
+				(opts.range.where_sql(ts), format!("\"{}\", rowid", ts))
+			}
+			None => (String::new(), String::from("rowid")),
+		};
+		stmts.push(con.prepare(&format!(
+			"SELECT * FROM \"{}\"{} ORDER BY {}",
+			table.name, filter, order
+		))?);
+	}
+
+	let mut cursors = vec![];
+	for (table, stmt) in tables.iter().zip(stmts.iter_mut()) {
+		let types = types(table, signatures.get(&table.name));
+		let columns: Vec<(&str, Type)> = table
+			.columns
+			.iter()
+			.zip(&types)
+			.map(|(c, t)| (c.name.as_str(), *t))
+			.collect();
+		let uid = producer.table(&table.name, &columns)?;
+
+		let mut cursor = Cursor {
+			rows: stmt.query(rusqlite::NO_PARAMS)?,
+			uid,
+			types,
+			ts: table.find_column(&TS_COLUMNS),
+			next: None,
+		};
+		cursor.advance(producer, &strings)?;
+		cursors.push(cursor);
+	}
+
+	let mut pacer = Pacer::new(opts);
+	let mut sent = 0;
+	loop {
+		let next = cursors
+			.iter()
+			.enumerate()
+			.filter_map(|(i, c)| c.next.as_ref().map(|(ts, _)| (i, *ts)))
+			.min_by(|a, b| a.1.total_cmp(&b.1));
+		let (i, ts) = match next {
+			Some(next) => next,
+			None => break,
+		};
+
+		if let Some(wait) = pacer.wait(ts) {
+			producer.flush()?;
+			thread::sleep(wait);
+		}
+
+		let cursor = &mut cursors[i];
+		let (_, values) = cursor.next.take().unwrap();
+		producer.entry(cursor.uid, &values)?;
+		cursor.advance(producer, &strings)?;
+		sent += 1;
+	}
+
+	producer.flush()?;
+	Ok(sent)
+}
+
+/// Strings and tables declared by a raw capture.
+#[derive(Default)]
+struct Declared {
+	strings: HashMap<u32, String>,
+	/// Timestamp column and whether the table is replayed, by uid.
+	tables: HashMap<u32, (Option<usize>, bool)>,
+}
+
+impl Declared {
+	fn read(&mut self, message: &Message, opts: &Options) {
+		match message {
+			Message::String { id, value } => {
+				let value = String::from_utf8_lossy(value).into_owned();
+				self.strings.insert(*id, value);
+			}
+			Message::Table { uid, desc } => {
+				let name = self.strings.get(&desc.name());
+				let replayed = opts.tables.is_empty()
+					|| name.is_some_and(|n| opts.tables.contains(n));
+				let ts = raw::ts_column(desc, &self.strings);
+				self.tables.insert(*uid, (ts, replayed));
+			}
+			_ => {}
+		}
+	}
+}
+
+/// Sends the messages of a raw capture, the entries of other tables than
+/// `opts.tables` or outside of `opts.range` left out, using its index to
+/// seek to the start of the range and find the declarations before it.
+fn replay_raw<W: Write>(
+	path: &Path,
+	writer: &mut W,
+	opts: &Options,
+) -> Result<usize, Error> {
+	let bytes = raw::Capture::open(path)?;
+	let index = raw::Index::open(path, &bytes)?;
+	let start = opts.range.from.map_or(0, |from| index.seek(from));
+	let end = opts.range.to.map_or(bytes.len(), |to| index.end(to));
+
+	let mut messages = Messages::new(&bytes);
+	let mut declared = Declared::default();
+
+	// A seek skips to a checkpoint, declaring what comes before it.
+	for (offset, size) in index.declarations(start) {
+		messages.seek(offset);
+		if let Some(Ok(message)) = messages.next() {
+			declared.read(&message, opts);
+		}
+		writer.write_all(&bytes[offset..offset + size])?;
+	}
+
+	let mut pacer = Pacer::new(opts);
+	let mut sent = 0;
+	messages.seek(start);
+	while messages.offset() < end {
+		let offset = messages.offset();
+		let message = match messages.next() {
+			Some(Ok(message)) => message,
+			Some(Err(_)) => continue,
+			None => break,
+		};
+		let message_bytes = &bytes[offset..messages.offset()];
+
+		if let Message::Entry { uid, entry } = &message {
+			let (ts, replayed) = declared.tables[uid];
+			if !replayed {
+				continue;
+			}
+
+			let ts = ts
+				.and_then(|ts| entry.values().nth(ts))
+				.and_then(|v| v.as_f64());
+			if ts.is_some_and(|ts| !opts.range.contains(ts)) {
+				continue;
+			}
+			if let Some(wait) = ts.and_then(|ts| pacer.wait(ts)) {
+				writer.flush()?;
+				thread::sleep(wait);
+			}
+			sent += 1;
+		}
+		declared.read(&message, opts);
+		writer.write_all(message_bytes)?;
+	}
+
+	writer.flush()?;
+	Ok(sent)
+}
+
+impl<'a> Cursor<'a> {
+	/// Reads the next row, strings being sent as they come.
+	fn advance<W: Write>(
+		&mut self,
+		producer: &mut Producer<W>,
+		strings: &HashMap<i64, String>,
+	) -> Result<(), Error> {
+		let row = match self.rows.next()? {
+			Some(row) => row,
+			None => return Ok(()),
+		};
+
+		let mut values = Vec::with_capacity(self.types.len());
+		for (i, t) in self.types.iter().enumerate() {
+			let raw = row.get_raw(i);
+			let value = match (t, raw) {
+				(Type::Str, ValueRef::Text(s)) => {
+					Value::Str(producer.string(&String::from_utf8_lossy(s))?)
+				}
+				(Type::Str, ValueRef::Integer(id)) => {
+					let s = strings.get(&id).cloned();
+					Value::Str(producer.string(&s.unwrap_or(id.to_string()))?)
+				}
+				(Type::Str, _) => Value::Str(producer.string("")?),
+				(Type::Float, v) => {
+					Value::Float(export::as_f64(v).unwrap_or(f64::NAN) as f32)
+				}
+				(Type::Bool, v) => Value::Bool(export::as_f64(v) != Some(0.0)),
+				(Type::Int, v) => {
+					Value::Int(export::as_f64(v).unwrap_or(0.0) as i64 as u32)
+				}
+			};
+			values.push(value);
+		}
+
+		// Rows without a timestamp come first.
+		let ts = self
+			.ts
+			.and_then(|ts| export::as_f64(row.get_raw(ts)))
+			.unwrap_or(f64::NEG_INFINITY);
+		self.next = Some((ts, values));
+		Ok(())
+	}
+}
+
+/// Column types of the tables, recorded by the daemon in `_sdd_descriptors`.
+fn signatures(con: &rusqlite::Connection) -> HashMap<String, String> {
+	let mut stmt =
+		match con.prepare("SELECT name, columns FROM _sdd_descriptors") {
+			Ok(stmt) => stmt,
+			Err(_) => return HashMap::new(),
+		};
+	stmt.query_map(rusqlite::NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+		.and_then(|rows| rows.collect())
+		.unwrap_or_default()
+}
+
+/// Producer strings of a capture, to resolve string ids stored as integers.
+fn strings(con: &rusqlite::Connection) -> HashMap<i64, String> {
+	let mut stmt = match con.prepare("SELECT id, value FROM _sdd_strings") {
+		Ok(stmt) => stmt,
+		Err(_) => return HashMap::new(),
+	};
+	stmt.query_map(rusqlite::NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))
+		.and_then(|rows| rows.collect())
+		.unwrap_or_default()
+}
+
+/// Types of the columns of `table`, from its `signature` when recorded and
+/// from the declared SQL types otherwise.
+fn types(table: &Table, signature: Option<&String>) -> Vec<Type> {
+	let recorded: HashMap<&str, &str> = signature
+		.map(|s| s.split(", ").filter_map(|c| c.split_once(": ")).collect())
+		.unwrap_or_default();
+
+	table
+		.columns
+		.iter()
+		.map(|c| match recorded.get(c.name.as_str()) {
+			Some(&"int") => Type::Int,
+			Some(&"float") => Type::Float,
+			Some(&"bool") => Type::Bool,
+			Some(&"str") => Type::Str,
+			_ => match c.decl_type.as_str() {
+				"INTEGER" => Type::Int,
+				"REAL" => Type::Float,
+				_ => Type::Str,
+			},
+		})
+		.collect()
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn speed_and_order() {
+		assert_eq!("2x".parse(), Ok(Speed::Scale(2.0)));
+		assert_eq!("realtime".parse(), Ok(Speed::Scale(1.0)));
+		assert_eq!("max".parse(), Ok(Speed::Max));
+		assert!("0x".parse::<Speed>().is_err());
+
+		let db = std::env::temp_dir()
+			.join(format!("sdd-replay-test-{}.db", std::process::id()));
+		let _ = std::fs::remove_file(&db);
+
+		let con = rusqlite::Connection::open(&db).unwrap();
+		con.execute_batch(
+			"CREATE TABLE a (ts INTEGER, on_ INTEGER); \
+			 CREATE TABLE b (ts INTEGER, tag TEXT); \
+			 CREATE TABLE _sdd_descriptors (name TEXT PRIMARY KEY, \
+			 columns TEXT, fingerprint TEXT); \
+			 INSERT INTO _sdd_descriptors VALUES \
+			 ('a', 'ts: int, on_: bool', ''); \
+			 INSERT INTO a VALUES (0, 1), (20, 0); \
+			 INSERT INTO b VALUES (10, 'x');",
+		)
+		.unwrap();
+		drop(con);
+
+		let opts = Options {
+			tables: vec![],
+			speed: Speed::Scale(2.0),
+			unit: TimeUnit::Ms,
+			range: Range::default(),
+		};
+		let mut producer = Producer::new(vec![]);
+		let start = Instant::now();
+		assert_eq!(replay(&db, &mut producer, &opts).unwrap(), 3);
+		assert!(start.elapsed() >= Duration::from_millis(10));
+
+		let mut expected = Producer::new(vec![]);
+		expected
+			.table("a", &[("ts", Type::Int), ("on_", Type::Bool)])
+			.unwrap();
+		expected
+			.table("b", &[("ts", Type::Int), ("tag", Type::Str)])
+			.unwrap();
+		let x = expected.string("x").unwrap();
+		expected
+			.entry(0, &[Value::Int(0), Value::Bool(true)])
+			.unwrap();
+		expected.entry(1, &[Value::Int(10), Value::Str(x)]).unwrap();
+		expected
+			.entry(0, &[Value::Int(20), Value::Bool(false)])
+			.unwrap();
+		assert_eq!(producer.into_inner(), expected.into_inner());
+	}
+}
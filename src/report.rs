@@ -0,0 +1,172 @@
+use crate::dae::{Error, Stats};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Rows of a table and the entries it lost, over all the sessions.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TableReport {
+	pub name: String,
+	pub rows: u64,
+	/// Entries dropped by the rate limit.
+	pub dropped: u64,
+	/// Entries skipped for repeating the previous one.
+	pub suppressed: u64,
+	/// Entries breaking a validation rule.
+	pub invalid: u64,
+}
+
+/// Machine-readable summary of a finished capture, written with `--report`
+/// so that the jobs running captures can check how they went.
+#[derive(Serialize, Debug)]
+pub struct Report {
+	/// `ok`, or `failed` when an error ended the capture.
+	pub status: &'static str,
+	/// Error ending the capture.
+	pub error: Option<String>,
+	pub duration_secs: f64,
+	pub entries: u64,
+	/// Bytes read from the producers.
+	pub bytes: u64,
+	/// Problems reported during the capture, see `recent_errors`.
+	pub errors: u64,
+	/// Protocol violations skipped by a lenient capture.
+	pub violations: u64,
+	/// Last of the reported problems.
+	pub recent_errors: Vec<String>,
+	pub tables: Vec<TableReport>,
+	/// Databases written, rotated ones included, those archived and deleted
+	/// by their object url.
+	pub outputs: Vec<String>,
+}
+
+impl Report {
+	/// Report of a capture that ran for `duration` into `outputs` and ended
+	/// with `result`.
+	pub fn new(
+		stats: &Stats,
+		duration: Duration,
+		outputs: Vec<String>,
+		result: &Result<(), Error>,
+	) -> Report {
+		// The tables of the last session count along with the ended ones.
+		let mut totals = Stats {
+			tables: stats.tables.clone(),
+			ended: stats.ended.clone(),
+			..Default::default()
+		};
+		totals.end_session();
+		let tables = totals
+			.ended
+			.into_iter()
+			.map(|t| TableReport {
+				name: t.name,
+				rows: t.rows,
+				dropped: t.dropped,
+				suppressed: t.suppressed,
+				invalid: t.invalid,
+			})
+			.collect();
+
+		Report {
+			status: match result {
+				Ok(()) => "ok",
+				Err(_) => "failed",
+			},
+			error: result.as_ref().err().map(|e| e.to_string()),
+			duration_secs: duration.as_secs_f64(),
+			entries: stats.entries,
+			bytes: stats.bytes,
+			errors: stats.error_count,
+			violations: stats.violations,
+			recent_errors: stats.errors.iter().cloned().collect(),
+			tables,
+			outputs,
+		}
+	}
+
+	/// Writes the report to the file at `path`, or as a single line to
+	/// stdout for `-`.
+	pub fn write(&self, path: &Path) -> io::Result<()> {
+		if path == Path::new("-") {
+			println!("{}", serde_json::to_string(self)?);
+			return Ok(());
+		}
+
+		let json = serde_json::to_string_pretty(self)?;
+		fs::write(path, json + "\n")
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dae::{Daemon, Protocol, TableStats};
+	use crate::producer::{Producer, Type, Value};
+
+	#[test]
+	fn capture_report() {
+		let mut stats = Stats {
+			entries: 3,
+			bytes: 120,
+			..Default::default()
+		};
+		stats.tables.push(TableStats::default());
+		let mut frame = TableStats::default();
+		frame.name = String::from("frame");
+		frame.rows = 2;
+		stats.tables.push(frame);
+		stats.error("Unknown table 7".into());
+
+		let outputs = vec![String::from("capture.db")];
+		let failed = Err(Error::ReadFailure);
+		let report =
+			Report::new(&stats, Duration::from_secs(2), outputs, &failed);
+		assert_eq!(report.status, "failed");
+		assert_eq!(report.tables.len(), 1);
+		assert_eq!(report.tables[0].rows, 2);
+
+		let path = std::env::temp_dir()
+			.join(format!("sdd-report-test-{}.json", std::process::id()));
+		report.write(&path).unwrap();
+		let json: serde_json::Value =
+			serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+		fs::remove_file(&path).unwrap();
+		assert_eq!(json["entries"], 3);
+		assert_eq!(json["duration_secs"], 2.0);
+		assert_eq!(json["recent_errors"][0], "Unknown table 7");
+		assert_eq!(json["outputs"][0], "capture.db");
+	}
+
+	#[test]
+	fn session_totals() {
+		let mut producer = Producer::new(vec![]);
+		let uid = producer.table("frame", &[("n", Type::Int)]).unwrap();
+		producer.entry(uid, &[Value::Int(1)]).unwrap();
+		producer.entry(uid, &[Value::Int(2)]).unwrap();
+		let bytes = producer.into_inner();
+
+		let mut daemon =
+			Daemon::new(Protocol::new(String::from(":memory:")).unwrap());
+		daemon.verbose = false;
+		daemon.exit_on_eof = true;
+		for _ in 0..2 {
+			daemon.begin_session();
+			daemon.capture_reader(&bytes[..], "test").unwrap();
+		}
+
+		let stats = daemon.stats.lock().unwrap();
+		let report = Report::new(&stats, Duration::default(), vec![], &Ok(()));
+		assert_eq!(report.entries, 4);
+		let totals: Vec<(&str, u64)> = report
+			.tables
+			.iter()
+			.map(|t| (&t.name[..], t.rows))
+			.collect();
+		assert_eq!(totals, [("frame", 4)]);
+	}
+}
@@ -0,0 +1,326 @@
+use crate::config::parse_duration;
+use crate::export::{TimeUnit, TS_COLUMNS};
+use rusqlite;
+use serde::Deserialize;
+
+//---------------------------------------------------------------------------
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RollupConfig {
+	pub table: String,
+	/// Numeric columns to aggregate.
+	pub columns: Vec<String>,
+	/// Bucket sizes, one rollup table each (`1s`, `1m`, ...).
+	#[serde(default = "default_intervals")]
+	pub intervals: Vec<String>,
+}
+
+fn default_intervals() -> Vec<String> {
+	vec![String::from("1s"), String::from("1m")]
+}
+
+//---------------------------------------------------------------------------
+#[derive(Clone, Copy)]
+struct Acc {
+	min: f64,
+	max: f64,
+	sum: f64,
+	/// Values accumulated, entries without a numeric value left out.
+	count: u64,
+}
+
+impl Default for Acc {
+	fn default() -> Self {
+		Acc {
+			min: f64::MAX,
+			max: f64::MIN,
+			sum: 0.0,
+			count: 0,
+		}
+	}
+}
+
+struct Rollup {
+	table: String,
+	columns: Vec<String>,
+	interval: u64,
+	/// Index of the output database of the table.
+	db: usize,
+	/// Field index of the timestamp column and of `columns`, resolved once
+	/// the table is registered.
+	ts: usize,
+	fields: Vec<usize>,
+	insert_cmd: String,
+	bucket: Option<u64>,
+	count: u64,
+	acc: Vec<Acc>,
+}
+
+impl Rollup {
+	fn name(&self) -> String {
+		format!("{}_rollup_{}s", self.table, self.interval)
+	}
+
+	fn create_cmd(&self) -> String {
+		let mut cmd = format!(
+			"CREATE TABLE IF NOT EXISTS {} (bucket INTEGER, count INTEGER",
+			self.name()
+		);
+		for c in &self.columns {
+			cmd.push_str(&format!(
+				", {c}_min REAL, {c}_max REAL, {c}_avg REAL",
+				c = c
+			));
+		}
+		cmd.push(')');
+		cmd
+	}
+
+	fn make_insert_cmd(&self) -> String {
+		let params = (1..=2 + 3 * self.columns.len())
+			.map(|i| format!("?{}", i))
+			.collect::<Vec<String>>()
+			.join(", ");
+
+		format!("INSERT INTO {} VALUES ({})", self.name(), params)
+	}
+
+	fn flush(&mut self, con: &rusqlite::Connection) -> rusqlite::Result<()> {
+		let bucket = match self.bucket.take() {
+			Some(b) if self.count > 0 => b,
+			_ => return Ok(()),
+		};
+
+		let mut params = vec![Some(bucket as f64), Some(self.count as f64)];
+		for acc in &self.acc {
+			let n = acc.count as f64;
+			let counted = acc.count > 0;
+			params.push(Some(acc.min).filter(|_| counted));
+			params.push(Some(acc.max).filter(|_| counted));
+			params.push(Some(acc.sum / n).filter(|_| counted));
+		}
+
+		con.execute(&self.insert_cmd, params)?;
+
+		self.count = 0;
+		for acc in &mut self.acc {
+			*acc = Acc::default();
+		}
+
+		Ok(())
+	}
+}
+
+/// Per-interval min/max/avg/count tables maintained while entries arrive,
+/// bucketed by the timestamp column of the entries. A bucket is named by its
+/// start, in the unit of the timestamp column.
+#[derive(Default)]
+pub struct Rollups {
+	/// Unit of the timestamp columns.
+	pub time_unit: TimeUnit,
+	rollups: Vec<Rollup>,
+	/// Per descriptor uid, the rollups fed by that table.
+	bindings: Vec<Vec<usize>>,
+}
+
+impl Rollups {
+	pub fn new(configs: &[RollupConfig]) -> Result<Rollups, String> {
+		let mut rollups = Rollups::default();
+		for config in configs {
+			for interval in &config.intervals {
+				let interval = parse_duration(interval)?.as_secs();
+				if interval == 0 {
+					return Err(format!(
+						"Rollup interval of {} must be at least 1s",
+						config.table
+					));
+				}
+
+				rollups.rollups.push(Rollup {
+					table: config.table.clone(),
+					columns: config.columns.clone(),
+					interval,
+					db: 0,
+					ts: 0,
+					fields: vec![],
+					insert_cmd: String::new(),
+					bucket: None,
+					count: 0,
+					acc: vec![Acc::default(); config.columns.len()],
+				});
+			}
+		}
+
+		Ok(rollups)
+	}
+
+	/// Creates the rollup tables of a newly registered table, next to it in
+	/// `dbs[db]`.
+	pub fn bind(
+		&mut self,
+		uid: u32,
+		table: &str,
+		columns: &[String],
+		db: usize,
+		dbs: &[rusqlite::Connection],
+	) -> Result<(), String> {
+		let uid = uid as usize;
+		if self.bindings.len() <= uid {
+			self.bindings.resize(uid + 1, vec![]);
+		}
+		// A reconnected producer may reuse the uid for another table.
+		self.bindings[uid].clear();
+
+		for (i, rollup) in self.rollups.iter_mut().enumerate() {
+			if rollup.table != table {
+				continue;
+			}
+
+			let ts = columns
+				.iter()
+				.position(|c| {
+					TS_COLUMNS.iter().any(|t| c.eq_ignore_ascii_case(t))
+				})
+				.ok_or_else(|| {
+					format!("Rollup table {} has no timestamp column", table)
+				})?;
+
+			let mut fields = vec![];
+			for c in &rollup.columns {
+				match columns.iter().position(|name| name == c) {
+					Some(f) => fields.push(f),
+					None => {
+						return Err(format!(
+							"Rollup column {}.{} does not exist",
+							table, c
+						))
+					}
+				}
+			}
+
+			dbs[db]
+				.execute(&rollup.create_cmd(), rusqlite::NO_PARAMS)
+				.map_err(|e| e.to_string())?;
+
+			rollup.db = db;
+			rollup.ts = ts;
+			rollup.fields = fields;
+			rollup.insert_cmd = rollup.make_insert_cmd();
+			self.bindings[uid].push(i);
+		}
+
+		Ok(())
+	}
+
+	/// Accumulates an entry of `uid` into the bucket of its timestamp, `con`
+	/// being the database of its table. Entries without a timestamp are left
+	/// out, as are the values that are missing or not numeric.
+	pub fn add<F>(
+		&mut self,
+		uid: u32,
+		value: F,
+		con: &rusqlite::Connection,
+	) -> rusqlite::Result<()>
+	where
+		F: Fn(usize) -> Option<f64>,
+	{
+		let bindings = match self.bindings.get(uid as usize) {
+			Some(b) => b,
+			None => return Ok(()),
+		};
+
+		for i in bindings {
+			let rollup = &mut self.rollups[*i];
+			let ts = match value(rollup.ts) {
+				Some(ts) => self.time_unit.to_ns(ts) / 1_000_000_000,
+				None => continue,
+			};
+			let secs = ts / rollup.interval * rollup.interval;
+			let bucket = self.time_unit.from_ns(secs as f64 * 1e9) as u64;
+			if rollup.bucket != Some(bucket) {
+				rollup.flush(con)?;
+				rollup.bucket = Some(bucket);
+			}
+
+			rollup.count += 1;
+			for (acc, field) in rollup.acc.iter_mut().zip(&rollup.fields) {
+				let v = match value(*field) {
+					Some(v) if !v.is_nan() => v,
+					_ => continue,
+				};
+				acc.min = acc.min.min(v);
+				acc.max = acc.max.max(v);
+				acc.sum += v;
+				acc.count += 1;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Writes out the partially filled buckets.
+	pub fn flush(
+		&mut self,
+		dbs: &[rusqlite::Connection],
+	) -> rusqlite::Result<()> {
+		for rollup in &mut self.rollups {
+			if let Some(con) = dbs.get(rollup.db) {
+				rollup.flush(con)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn buckets() {
+		let dbs = [rusqlite::Connection::open_in_memory().unwrap()];
+		let con = &dbs[0];
+		let config = RollupConfig {
+			table: String::from("frame"),
+			columns: vec![String::from("frame_ms")],
+			intervals: vec![String::from("10s")],
+		};
+
+		let mut rollups = Rollups::new(&[config]).unwrap();
+		let columns = [String::from("ts"), String::from("frame_ms")];
+		rollups.bind(0, "frame", &columns, 0, &dbs).unwrap();
+
+		let entries = [
+			(Some(100_000.0), Some(10.0)),
+			(Some(105_000.0), Some(30.0)),
+			(Some(106_000.0), None),
+			(None, Some(99.0)),
+			(Some(112_000.0), Some(5.0)),
+		];
+		for entry in &entries {
+			let value = |i: usize| if i == 0 { entry.0 } else { entry.1 };
+			rollups.add(0, value, con).unwrap();
+		}
+		rollups.flush(&dbs).unwrap();
+
+		let rows: Vec<(i64, i64, f64, f64, f64)> = con
+			.prepare("SELECT * FROM frame_rollup_10s ORDER BY bucket")
+			.unwrap()
+			.query_map(rusqlite::NO_PARAMS, |r| {
+				Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?))
+			})
+			.unwrap()
+			.map(|r| r.unwrap())
+			.collect();
+
+		assert_eq!(
+			rows,
+			vec![(100_000, 3, 10.0, 30.0, 20.0), (110_000, 1, 5.0, 5.0, 5.0)]
+		);
+
+		let columns = [String::from("frame_ms")];
+		assert!(rollups.bind(0, "frame", &columns, 0, &dbs).is_err());
+	}
+}
@@ -0,0 +1,549 @@
+use crate::producer::Type;
+use crate::wire::FieldType;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+//---------------------------------------------------------------------------
+/// Rust keywords, the reserved and weak ones included.
+const RUST_KEYWORDS: &str =
+	"abstract as async await become box break const continue crate do dyn \
+	 else enum extern false final fn for gen if impl in let loop macro \
+	 macro_rules match mod move mut override priv pub ref return self Self \
+	 static struct super trait true try type typeof union unsafe unsized use \
+	 virtual where while yield";
+
+/// C++ keywords and alternative operator names.
+const CPP_KEYWORDS: &str =
+	"alignas alignof and and_eq asm auto bitand bitor bool break case catch \
+	 char char8_t char16_t char32_t class co_await co_return co_yield compl \
+	 concept const consteval constexpr constinit const_cast continue decltype \
+	 default delete do double dynamic_cast else enum explicit export extern \
+	 false float for friend goto if inline int long mutable namespace new \
+	 noexcept not not_eq nullptr operator or or_eq private protected public \
+	 register reinterpret_cast requires return short signed sizeof static \
+	 static_assert static_cast struct switch template this thread_local throw \
+	 true try typedef typeid typename union unsigned using virtual void \
+	 volatile wchar_t while xor xor_eq";
+
+/// Names of the generated code, the producer parameter `p` among them.
+const GENERATED: &str =
+	"declare p W io Producer Type Value Write sdd std string Column size_t \
+	 uint8_t uint32_t table_ header_ u8_ u32_ f32_ sink_ strings_";
+
+/// Entry types shared by the producers and the daemon, read from a
+/// `.sdd.toml` file. Tables get their uids in file order, so new tables go
+/// at the end to keep the uids of the others stable.
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+	#[serde(rename = "table", default)]
+	pub tables: Vec<TableSchema>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TableSchema {
+	pub name: String,
+	pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+	pub name: String,
+	#[serde(rename = "type")]
+	pub data_type: Type,
+	/// Value of the column for producers sending fewer columns, e.g. older
+	/// builds, see `Schema::check`.
+	#[serde(default)]
+	pub default: Option<ColumnDefault>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ColumnDefault {
+	Bool(bool),
+	Int(u32),
+	Float(f64),
+}
+
+impl ColumnSchema {
+	/// Default value of the column, `None` when it has none or when it does
+	/// not fit its type. Strings have no defaults, their ids belonging to
+	/// the producers.
+	pub fn fill(&self) -> Option<FieldType> {
+		match (self.data_type, self.default?) {
+			(Type::Int, ColumnDefault::Int(v)) => Some(FieldType::Int(v)),
+			(Type::Float, ColumnDefault::Int(v)) => {
+				Some(FieldType::Float(v as f64))
+			}
+			(Type::Float, ColumnDefault::Float(v)) => Some(FieldType::Float(v)),
+			(Type::Bool, ColumnDefault::Bool(v)) => Some(FieldType::Bool(v)),
+			_ => None,
+		}
+	}
+}
+
+impl Schema {
+	pub fn load(path: &Path) -> Result<Schema, String> {
+		let text = fs::read_to_string(path)
+			.map_err(|e| format!("{}: {}", path.display(), e))?;
+
+		Schema::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+	}
+
+	pub fn parse(text: &str) -> Result<Schema, String> {
+		let schema: Schema = toml::from_str(text).map_err(|e| e.to_string())?;
+		schema.validate()?;
+		Ok(schema)
+	}
+
+	/// Names are identifiers unique regardless of case, as SQLite compares
+	/// them, and tables have the 1 to 32 columns of the protocol.
+	fn validate(&self) -> Result<(), String> {
+		let mut tables = HashSet::new();
+		for table in &self.tables {
+			identifier(&table.name)?;
+			if !tables.insert(table.name.to_lowercase()) {
+				return Err(format!("table {} is defined twice", table.name));
+			}
+
+			if table.columns.is_empty() || table.columns.len() > 32 {
+				return Err(format!(
+					"table {} has {} columns, tables have 1 to 32",
+					table.name,
+					table.columns.len()
+				));
+			}
+
+			let mut columns = HashSet::new();
+			for column in &table.columns {
+				identifier(&column.name)?;
+				if !columns.insert(column.name.to_lowercase()) {
+					return Err(format!(
+						"column {}.{} is defined twice",
+						table.name, column.name
+					));
+				}
+				if column.default.is_some() && column.fill().is_none() {
+					return Err(format!(
+						"column {}.{} has a default unfit for a {:?} column",
+						table.name, column.name, column.data_type
+					));
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Checks a descriptor sent by a producer against the table of its uid,
+	/// returning the trailing columns it lacks. A descriptor may lack those
+	/// having defaults, which fill them.
+	pub fn check(
+		&self,
+		uid: u32,
+		name: &str,
+		columns: &[(String, Type)],
+	) -> Result<&[ColumnSchema], String> {
+		let table = self.tables.get(uid as usize).ok_or_else(|| {
+			format!("uid {} ({}) is not in the schema", uid, name)
+		})?;
+
+		if table.name != name {
+			return Err(format!(
+				"uid {} is {} in the schema, not {}",
+				uid, table.name, name
+			));
+		}
+
+		let expected: Vec<(String, Type)> = table
+			.columns
+			.iter()
+			.map(|c| (c.name.clone(), c.data_type))
+			.collect();
+		let sent = columns.len().min(expected.len());
+		let missing = &table.columns[sent..];
+		if signature(&expected[..sent]) != signature(columns)
+			|| missing.iter().any(|c| c.fill().is_none())
+		{
+			return Err(format!(
+				"{} is ({}) in the schema, not ({})",
+				name,
+				signature(&expected),
+				signature(columns)
+			));
+		}
+
+		Ok(missing)
+	}
+
+	/// `name` of a table function or column parameter with a `_` suffix
+	/// when taken by the generated code, the uid constants of the tables
+	/// included, or reserved in Rust or C++.
+	fn escape(&self, name: &str) -> String {
+		let mut reserved = [RUST_KEYWORDS, CPP_KEYWORDS, GENERATED]
+			.iter()
+			.flat_map(|r| r.split_whitespace());
+		let constant =
+			self.tables.iter().any(|t| t.name.to_uppercase() == name);
+		match constant || reserved.any(|r| r == name) {
+			true => format!("{}_", name),
+			false => name.to_string(),
+		}
+	}
+
+	/// Rust module declaring the tables through `sdd::producer::Producer`
+	/// with a function per table sending its entries.
+	pub fn rust(&self, source: &str) -> String {
+		let mut out = String::new();
+		let _ = writeln!(
+			out,
+			"// Generated by `sdd codegen` from {}, do not edit.\n\
+			 #![allow(dead_code, non_snake_case)]\n\n\
+			 use sdd::producer::{{Producer, Type, Value}};\n\
+			 use std::io::{{self, Write}};\n",
+			source
+		);
+
+		for (uid, table) in self.tables.iter().enumerate() {
+			let _ = writeln!(
+				out,
+				"pub const {}: u32 = {};",
+				table.name.to_uppercase(),
+				uid
+			);
+		}
+
+		let _ = writeln!(
+			out,
+			"\n/// Declares the tables of the schema, before any other table.\n\
+			 pub fn declare<W: Write>(p: &mut Producer<W>) -> io::Result<()> {{"
+		);
+		for table in &self.tables {
+			let columns: Vec<String> = table
+				.columns
+				.iter()
+				.map(|c| format!("(\"{}\", Type::{:?})", c.name, c.data_type))
+				.collect();
+			let _ = writeln!(
+				out,
+				"\tif p.table(\"{}\", &[{}])? != {} {{\n\
+				 \t\treturn Err(io::Error::other(\n\
+				 \t\t\t\"Schema tables have to be declared first\",\n\
+				 \t\t));\n\
+				 \t}}",
+				table.name,
+				columns.join(", "),
+				table.name.to_uppercase()
+			);
+		}
+		let _ = writeln!(out, "\tOk(())\n}}");
+
+		for table in &self.tables {
+			let params: Vec<String> = table
+				.columns
+				.iter()
+				.map(|c| {
+					let t = match c.data_type {
+						Type::Int => "u32",
+						Type::Float => "f32",
+						Type::Bool => "bool",
+						Type::Str => "&str",
+					};
+					format!(", {}: {}", self.escape(&c.name), t)
+				})
+				.collect();
+
+			let _ = writeln!(
+				out,
+				"\n/// Sends an entry of {}.\n\
+				 pub fn {}<W: Write>(\n\
+				 \tp: &mut Producer<W>{}\n\
+				 ) -> io::Result<()> {{",
+				table.name,
+				self.escape(&table.name),
+				params.join("")
+			);
+
+			let mut values = vec![];
+			for c in &table.columns {
+				let name = self.escape(&c.name);
+				if c.data_type == Type::Str {
+					let _ =
+						writeln!(out, "\tlet {n} = p.string({n})?;", n = name);
+				}
+				values.push(format!("Value::{:?}({})", c.data_type, name));
+			}
+			let _ = writeln!(
+				out,
+				"\tp.entry({}, &[{}])\n}}",
+				table.name.to_uppercase(),
+				values.join(", ")
+			);
+		}
+
+		out
+	}
+
+	/// Header-only C++ producer writing the messages to a `Sink` with a
+	/// `write(const void*, size_t)` method.
+	pub fn cpp(&self, source: &str) -> String {
+		let mut out = String::new();
+		let _ = writeln!(
+			out,
+			"// Generated by `sdd codegen` from {}, do not edit.\n\
+			 #pragma once\n\n\
+			 #include <cstddef>\n\
+			 #include <cstdint>\n\
+			 #include <cstring>\n\
+			 #include <string>\n\
+			 #include <unordered_map>\n\n\
+			 namespace sdd {{\n",
+			source
+		);
+
+		for (uid, table) in self.tables.iter().enumerate() {
+			let _ = writeln!(
+				out,
+				"constexpr uint32_t {} = {};",
+				table.name.to_uppercase(),
+				uid
+			);
+		}
+
+		let _ = writeln!(
+			out,
+			"\ntemplate <typename Sink>\n\
+			 class Producer {{\n\
+			 public:\n\
+			 \texplicit Producer(Sink& sink) : sink_(sink) {{}}\n\n\
+			 \t// Declares the tables of the schema, before any other table.\n\
+			 \tvoid declare() {{"
+		);
+		for table in &self.tables {
+			let columns: Vec<String> = table
+				.columns
+				.iter()
+				.map(|c| format!("{{{}, \"{}\"}}", c.data_type as u8, c.name))
+				.collect();
+			let _ = writeln!(
+				out,
+				"\t\tstatic const Column {}_columns[] = {{{}}};\n\
+				 \t\ttable_({}, \"{}\", {}_columns, {});",
+				table.name,
+				columns.join(", "),
+				table.name.to_uppercase(),
+				table.name,
+				table.name,
+				table.columns.len()
+			);
+		}
+		let _ = writeln!(out, "\t}}");
+
+		for table in &self.tables {
+			let params: Vec<String> = table
+				.columns
+				.iter()
+				.map(|c| {
+					let t = match c.data_type {
+						Type::Int => "uint32_t",
+						Type::Float => "float",
+						Type::Bool => "bool",
+						Type::Str => "const std::string&",
+					};
+					format!("{} {}", t, self.escape(&c.name))
+				})
+				.collect();
+
+			let _ = writeln!(
+				out,
+				"\n\t// Sends an entry of {}.\n\
+				 \tvoid {}({}) {{",
+				table.name,
+				self.escape(&table.name),
+				params.join(", ")
+			);
+
+			for c in table.columns.iter().filter(|c| c.data_type == Type::Str) {
+				let _ = writeln!(
+					out,
+					"\t\tuint32_t {n}_id = string({n});",
+					n = self.escape(&c.name)
+				);
+			}
+			let _ = writeln!(
+				out,
+				"\t\theader_(2);\n\t\tu32_({});",
+				table.name.to_uppercase()
+			);
+			for c in &table.columns {
+				let name = self.escape(&c.name);
+				let _ = match c.data_type {
+					Type::Int => writeln!(out, "\t\tu32_({});", name),
+					Type::Float => writeln!(out, "\t\tf32_({});", name),
+					Type::Bool => writeln!(out, "\t\tu8_({} ? 1 : 0);", name),
+					Type::Str => writeln!(out, "\t\tu32_({}_id);", name),
+				};
+			}
+			let _ = writeln!(out, "\t}}");
+		}
+
+		out.push_str(CPP_ENCODER);
+		out
+	}
+}
+
+/// Tail of the generated C++ producer, the message encoding of proto.md.
+const CPP_ENCODER: &str = "
+	// Id of `s`, sending it the first time it is used.
+	uint32_t string(const std::string& s) {
+		auto it = strings_.find(s);
+		if (it != strings_.end()) {
+			return it->second;
+		}
+
+		uint32_t id = static_cast<uint32_t>(strings_.size());
+		header_(1);
+		u32_(id);
+		u32_(static_cast<uint32_t>(s.size()));
+		sink_.write(s.data(), s.size());
+		strings_.emplace(s, id);
+		return id;
+	}
+
+private:
+	struct Column {
+		uint8_t type;
+		const char* name;
+	};
+
+	void table_(uint32_t uid, const char* name, const Column* columns,
+	            uint8_t count) {
+		uint32_t ids[32];
+		uint32_t name_id = string(name);
+		for (uint8_t i = 0; i < count; ++i) {
+			ids[i] = string(columns[i].name);
+		}
+
+		header_(3);
+		u32_(uid);
+		u32_(name_id);
+		u8_(count);
+		for (uint8_t i = 0; i < count; ++i) {
+			u8_(columns[i].type);
+			u32_(ids[i]);
+		}
+	}
+
+	void header_(uint8_t msg) {
+		u32_(0xFEEDBEEF);
+		u8_(msg);
+	}
+
+	void u8_(uint8_t v) { sink_.write(&v, 1); }
+
+	void u32_(uint32_t v) {
+		uint8_t bytes[4] = {
+			static_cast<uint8_t>(v), static_cast<uint8_t>(v >> 8),
+			static_cast<uint8_t>(v >> 16), static_cast<uint8_t>(v >> 24)};
+		sink_.write(bytes, 4);
+	}
+
+	void f32_(float v) {
+		uint32_t bits;
+		std::memcpy(&bits, &v, sizeof(bits));
+		u32_(bits);
+	}
+
+	Sink& sink_;
+	std::unordered_map<std::string, uint32_t> strings_;
+};
+
+}  // namespace sdd
+";
+
+/// `name: type` pairs of the columns, as shown in mismatch reports.
+pub fn signature(columns: &[(String, Type)]) -> String {
+	let columns: Vec<String> = columns
+		.iter()
+		.map(|(name, t)| {
+			format!("{}: {}", name, format!("{:?}", t).to_lowercase())
+		})
+		.collect();
+	columns.join(", ")
+}
+
+/// Stable hash of a table definition, FNV-1a of its name and signature.
+pub fn fingerprint(table: &str, columns: &[(String, Type)]) -> String {
+	let definition = format!("{}({})", table, signature(columns));
+	let hash = definition.bytes().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+		(h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+	});
+	format!("{:016x}", hash)
+}
+
+fn identifier(name: &str) -> Result<(), String> {
+	let mut chars = name.chars();
+	let valid = matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+		&& chars.all(|c| c == '_' || c.is_ascii_alphanumeric());
+	match valid {
+		true => Ok(()),
+		false => Err(format!("{:?} is not an identifier", name)),
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SCHEMA: &str = r#"
+		[[table]]
+		name = "frame"
+		columns = [
+			{ name = "ts", type = "int" },
+			{ name = "type", type = "str" },
+			{ name = "lod", type = "int", default = 0 },
+			{ name = "gpu_ms", type = "float", default = 1 },
+		]
+	"#;
+
+	#[test]
+	fn check_and_generate() {
+		let schema = Schema::parse(SCHEMA).unwrap();
+		let columns = |t| {
+			vec![(String::from("ts"), Type::Int), (String::from("type"), t)]
+		};
+
+		let missing = schema.check(0, "frame", &columns(Type::Str)).unwrap();
+		let fills: Vec<_> = missing.iter().map(|c| c.fill()).collect();
+		assert!(matches!(
+			fills[..],
+			[Some(FieldType::Int(0)), Some(FieldType::Float(v))] if v == 1.0
+		));
+		let mut all = columns(Type::Str);
+		all.push((String::from("lod"), Type::Int));
+		all.push((String::from("gpu_ms"), Type::Float));
+		assert!(schema.check(0, "frame", &all).unwrap().is_empty());
+		assert_eq!(
+			schema.check(0, "frame", &columns(Type::Int)).err().unwrap(),
+			"frame is (ts: int, type: str, lod: int, gpu_ms: float) in the \
+			 schema, not (ts: int, type: int)"
+		);
+		assert!(schema.check(0, "frame", &all[..1]).is_err());
+		assert!(schema.check(1, "frame", &columns(Type::Str)).is_err());
+
+		assert!(
+			Schema::parse("[[table]]\nname = \"a b\"\ncolumns = []").is_err()
+		);
+		assert!(Schema::parse(
+			"[[table]]\nname = \"a\"\n\
+			 columns = [{ name = \"b\", type = \"str\", default = 1 }]"
+		)
+		.is_err());
+	}
+}
@@ -0,0 +1,212 @@
+use crate::export;
+use crate::value::Value;
+use rusqlite;
+use serde::Deserialize;
+
+//---------------------------------------------------------------------------
+#[derive(Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+	/// Tables to index, every table with string columns when empty.
+	pub tables: Vec<String>,
+}
+
+//---------------------------------------------------------------------------
+struct Index {
+	/// Field indices of the indexed string columns.
+	fields: Vec<usize>,
+	insert_cmd: String,
+}
+
+/// FTS5 shadow tables (`<table>_fts`) indexing the string columns of the
+/// captured tables, filled while entries arrive.
+#[derive(Default)]
+pub struct Search {
+	config: Option<SearchConfig>,
+	/// Indexed by descriptor uid.
+	indices: Vec<Option<Index>>,
+}
+
+impl Search {
+	/// Indexing is disabled without a config.
+	pub fn new(config: Option<SearchConfig>) -> Search {
+		Search {
+			config,
+			indices: vec![],
+		}
+	}
+
+	/// Creates the index of a newly registered table, `text` being the
+	/// field indices of its string columns.
+	pub fn bind(
+		&mut self,
+		uid: u32,
+		table: &str,
+		columns: &[String],
+		text: &[usize],
+		con: &rusqlite::Connection,
+	) -> rusqlite::Result<()> {
+		let uid = uid as usize;
+		if self.indices.len() <= uid {
+			self.indices.resize_with(uid + 1, || None);
+		}
+
+		let wanted = match &self.config {
+			Some(c) => {
+				c.tables.is_empty() || c.tables.iter().any(|t| t == table)
+			}
+			None => false,
+		};
+
+		if !wanted || text.is_empty() {
+			return Ok(());
+		}
+
+		let names: Vec<&str> =
+			text.iter().map(|i| columns[*i].as_str()).collect();
+		let params: Vec<String> =
+			(1..=text.len()).map(|i| format!("?{}", i + 1)).collect();
+
+		con.execute(
+			&format!(
+				"CREATE VIRTUAL TABLE IF NOT EXISTS {}_fts USING fts5({})",
+				table,
+				names.join(", ")
+			),
+			rusqlite::NO_PARAMS,
+		)?;
+
+		self.indices[uid] = Some(Index {
+			fields: text.to_vec(),
+			insert_cmd: format!(
+				"INSERT INTO {}_fts (rowid, {}) VALUES (?1, {})",
+				table,
+				names.join(", "),
+				params.join(", ")
+			),
+		});
+
+		Ok(())
+	}
+
+	/// Indexes the entry of `uid` stored at `rowid`.
+	pub fn add<'a, F>(
+		&self,
+		uid: u32,
+		rowid: i64,
+		text: F,
+		con: &rusqlite::Connection,
+	) -> rusqlite::Result<()>
+	where
+		F: Fn(usize) -> Option<&'a str>,
+	{
+		let index = match self.indices.get(uid as usize) {
+			Some(Some(i)) => i,
+			_ => return Ok(()),
+		};
+
+		let mut params = Vec::with_capacity(index.fields.len() + 1);
+		params.push(Value::from(rowid));
+		params.extend(index.fields.iter().map(|f| Value::from(text(*f))));
+
+		con.execute(&index.insert_cmd, params)?;
+		Ok(())
+	}
+}
+
+//---------------------------------------------------------------------------
+pub struct Hit {
+	pub table: String,
+	pub rowid: i64,
+	/// Indexed (column, text) pairs of the matching row.
+	pub columns: Vec<(String, String)>,
+}
+
+/// Runs an FTS5 `query` against every search index of the capture.
+pub fn search(
+	con: &rusqlite::Connection,
+	query: &str,
+	limit: u32,
+) -> Result<Vec<Hit>, export::Error> {
+	let mut stmt = con.prepare(
+		"SELECT name FROM sqlite_master WHERE type = 'table' \
+		 AND sql LIKE 'CREATE VIRTUAL TABLE%USING fts5%' ORDER BY name",
+	)?;
+
+	let indices = stmt
+		.query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))?
+		.collect::<Result<Vec<String>, _>>()?;
+
+	let mut hits = vec![];
+	for index in indices {
+		let table = index.strip_suffix("_fts").unwrap_or(&index).to_string();
+
+		let mut stmt = con.prepare(&format!(
+			"SELECT rowid, * FROM \"{i}\" WHERE \"{i}\" MATCH ?1 \
+			 ORDER BY rank LIMIT ?2",
+			i = index
+		))?;
+
+		let names: Vec<String> =
+			stmt.column_names().iter().map(|n| n.to_string()).collect();
+
+		let mut rows = stmt.query(&[Value::from(query), limit.into()])?;
+		while let Some(row) = rows.next()? {
+			let mut columns = vec![];
+			for (i, name) in names.iter().enumerate().skip(1) {
+				if let Some(text) = row.get::<_, Option<String>>(i)? {
+					columns.push((name.clone(), text));
+				}
+			}
+
+			hits.push(Hit {
+				table: table.clone(),
+				rowid: row.get(0)?,
+				columns,
+			});
+		}
+	}
+
+	Ok(hits)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn index_and_search() {
+		let con = rusqlite::Connection::open_in_memory().unwrap();
+		con.execute_batch("CREATE TABLE log (ts INTEGER, message TEXT)")
+			.unwrap();
+
+		let mut index = Search::new(Some(SearchConfig::default()));
+		let columns = [String::from("ts"), String::from("message")];
+		index.bind(0, "log", &columns, &[1], &con).unwrap();
+
+		let messages = ["shader compile started", "upload done"];
+		for (ts, m) in messages.iter().enumerate() {
+			con.execute(
+				"INSERT INTO log VALUES (?1, ?2)",
+				&[Value::from(ts as i64), (*m).into()],
+			)
+			.unwrap();
+			index
+				.add(0, con.last_insert_rowid(), |_| Some(*m), &con)
+				.unwrap();
+		}
+
+		let hits = search(&con, "shader compile", 10).unwrap();
+		assert_eq!(hits.len(), 1);
+		assert_eq!(hits[0].table, "log");
+		assert_eq!(hits[0].rowid, 1);
+		assert_eq!(
+			hits[0].columns,
+			vec![(String::from("message"), String::from(messages[0]))]
+		);
+
+		let tables = export::tables(&con).unwrap();
+		assert_eq!(tables.len(), 1);
+	}
+}
@@ -0,0 +1,45 @@
+use serialport::SerialPort;
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Longest wait for a byte, lets the daemon check its bounds while the
+/// device is quiet.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+//---------------------------------------------------------------------------
+/// Splits `<path>:<baud>`, e.g. `/dev/ttyUSB0:115200`.
+fn parse(spec: &str) -> Result<(&str, u32), String> {
+	match spec.rsplit_once(':') {
+		Some((path, baud)) if !path.is_empty() => match baud.parse() {
+			Ok(baud) => Ok((path, baud)),
+			Err(_) => Err(format!("Bad baud rate: {}", baud)),
+		},
+		_ => Err(format!("Expected <path>:<baud>, got {}", spec)),
+	}
+}
+
+/// Opens the serial device described by `<path>:<baud>` as 8N1 without
+/// flow control. Reads time out while no bytes arrive.
+pub fn open(spec: &str) -> Result<Box<dyn SerialPort>, String> {
+	let (path, baud) = parse(spec)?;
+
+	serialport::new(path, baud)
+		.timeout(POLL_INTERVAL)
+		.open()
+		.map_err(|e| format!("{}: {}", path, e))
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_spec() {
+		assert_eq!(parse("/dev/ttyUSB0:115200"), Ok(("/dev/ttyUSB0", 115200)));
+		assert_eq!(parse("COM3:9600"), Ok(("COM3", 9600)));
+		assert!(parse("/dev/ttyUSB0").is_err());
+		assert!(parse("/dev/ttyUSB0:fast").is_err());
+		assert!(parse(":115200").is_err());
+	}
+}
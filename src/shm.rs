@@ -0,0 +1,304 @@
+use memmap2::MmapMut;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::hint;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//---------------------------------------------------------------------------
+/// Data bytes of a ring created by the daemon.
+pub const CAPACITY: u64 = 16 * 1024 * 1024;
+
+/// "SDDR", first word of a ring file.
+const MAGIC: u32 = 0x5244_4453;
+const VERSION: u32 = 1;
+
+// Header layout, the shared words sit on their own cache lines.
+const CAPACITY_OFFSET: usize = 8;
+const STATE_OFFSET: usize = 64;
+const HEAD_OFFSET: usize = 128;
+const TAIL_OFFSET: usize = 192;
+const DATA_OFFSET: usize = 256;
+
+/// No producer, one may attach.
+const FREE: u32 = 0;
+const ATTACHED: u32 = 1;
+/// The producer left, the daemon drains the ring and frees it.
+const DETACHED: u32 = 2;
+
+/// Longest wait for data, lets the daemon check its bounds.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Busy polls of an empty or full ring before sleeping.
+const SPINS: u32 = 1000;
+const BACKOFF: Duration = Duration::from_micros(50);
+
+//---------------------------------------------------------------------------
+/// Contents of the handshake file, a small TOML document.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Handshake {
+	ring: PathBuf,
+	capacity: u64,
+}
+
+/// Mapping of a ring file, `head` and `tail` count the bytes written and
+/// read since the producer attached.
+struct Ring {
+	map: MmapMut,
+	capacity: u64,
+}
+
+impl Ring {
+	fn map(path: &Path) -> io::Result<MmapMut> {
+		let file = OpenOptions::new().read(true).write(true).open(path)?;
+		// SAFETY: the file is only shared with the producer, which accesses
+		// the header words atomically and the data through head and tail.
+		unsafe { MmapMut::map_mut(&file) }
+	}
+
+	fn u64_at(&self, offset: usize) -> &AtomicU64 {
+		// SAFETY: the offsets lie within the header and are aligned, the
+		// mapping is page aligned and lives as long as `self`.
+		unsafe { &*(self.map.as_ptr().add(offset) as *const AtomicU64) }
+	}
+
+	fn state(&self) -> &AtomicU32 {
+		// SAFETY: as in `u64_at`.
+		unsafe { &*(self.map.as_ptr().add(STATE_OFFSET) as *const AtomicU32) }
+	}
+
+	fn head(&self) -> &AtomicU64 {
+		self.u64_at(HEAD_OFFSET)
+	}
+
+	fn tail(&self) -> &AtomicU64 {
+		self.u64_at(TAIL_OFFSET)
+	}
+
+	/// Position of `count` in the data and the bytes until it wraps.
+	fn span(&self, count: u64) -> (usize, u64) {
+		let pos = count % self.capacity;
+		(DATA_OFFSET + pos as usize, self.capacity - pos)
+	}
+}
+
+/// Spins, then sleeps while waiting for the other side.
+fn backoff(spins: &mut u32) {
+	if *spins < SPINS {
+		*spins += 1;
+		hint::spin_loop();
+	} else {
+		thread::sleep(BACKOFF);
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Daemon side of a ring, reads time out while it is empty and reach the
+/// end when the producer detaches.
+pub struct Reader {
+	ring: Ring,
+	handshake: PathBuf,
+}
+
+/// Creates a ring of `capacity` bytes next to `handshake`, then writes the
+/// handshake file producers attach through.
+pub fn create(handshake: &Path, capacity: u64) -> io::Result<Reader> {
+	if capacity == 0 {
+		return Err(io::Error::new(ErrorKind::InvalidInput, "empty ring"));
+	}
+
+	let path = handshake.with_extension("ring");
+	let file = OpenOptions::new()
+		.read(true)
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.open(&path)?;
+	file.set_len(DATA_OFFSET as u64 + capacity)?;
+
+	let mut map = Ring::map(&path)?;
+	map[..4].copy_from_slice(&MAGIC.to_le_bytes());
+	map[4..8].copy_from_slice(&VERSION.to_le_bytes());
+	map[CAPACITY_OFFSET..CAPACITY_OFFSET + 8]
+		.copy_from_slice(&capacity.to_le_bytes());
+	map.flush()?;
+
+	let text = toml::to_string(&Handshake {
+		ring: fs::canonicalize(&path)?,
+		capacity,
+	})
+	.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+	fs::write(handshake, text)?;
+
+	Ok(Reader {
+		ring: Ring { map, capacity },
+		handshake: handshake.to_path_buf(),
+	})
+}
+
+impl Reader {
+	/// Discards the rest of the current session.
+	pub fn skip_session(&mut self) {
+		let mut buf = [0; 4096];
+		loop {
+			match self.read(&mut buf) {
+				Ok(0) => break,
+				Ok(_) => {}
+				Err(e) if e.kind() == ErrorKind::TimedOut => {}
+				Err(_) => break,
+			}
+		}
+	}
+}
+
+impl Read for Reader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let ring = &mut self.ring;
+		let started = Instant::now();
+		let mut spins = 0;
+
+		loop {
+			let tail = ring.tail().load(Ordering::Relaxed);
+			let head = ring.head().load(Ordering::Acquire);
+
+			if head != tail {
+				let (pos, until_wrap) = ring.span(tail);
+				let n = (head - tail).min(until_wrap).min(buf.len() as u64);
+				let n = n as usize;
+				buf[..n].copy_from_slice(&ring.map[pos..pos + n]);
+				ring.tail().store(tail + n as u64, Ordering::Release);
+				return Ok(n);
+			}
+
+			if ring.state().load(Ordering::Acquire) == DETACHED {
+				// The last bytes may have landed before the detach was seen.
+				if ring.head().load(Ordering::Acquire) != tail {
+					continue;
+				}
+
+				ring.head().store(0, Ordering::Relaxed);
+				ring.tail().store(0, Ordering::Relaxed);
+				ring.state().store(FREE, Ordering::Release);
+				return Ok(0);
+			}
+
+			if started.elapsed() >= POLL_INTERVAL {
+				return Err(ErrorKind::TimedOut.into());
+			}
+			backoff(&mut spins);
+		}
+	}
+}
+
+impl Drop for Reader {
+	fn drop(&mut self) {
+		let _ = fs::remove_file(&self.handshake);
+		let _ = fs::remove_file(self.handshake.with_extension("ring"));
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Producer side of a ring, writes wait while it is full.
+pub struct Writer {
+	ring: Ring,
+}
+
+/// Attaches to the ring described by the `handshake` file, only one
+/// producer may be attached at a time.
+pub fn attach(handshake: &Path) -> io::Result<Writer> {
+	let text = fs::read_to_string(handshake)?;
+	let handshake: Handshake = toml::from_str(&text)
+		.map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+	let map = Ring::map(&handshake.ring)?;
+	let valid = map.len() as u64 == DATA_OFFSET as u64 + handshake.capacity
+		&& map[..4] == MAGIC.to_le_bytes()
+		&& map[4..8] == VERSION.to_le_bytes();
+	if !valid {
+		return Err(io::Error::new(ErrorKind::InvalidData, "not an sdd ring"));
+	}
+
+	let ring = Ring {
+		map,
+		capacity: handshake.capacity,
+	};
+	ring.state()
+		.compare_exchange(FREE, ATTACHED, Ordering::AcqRel, Ordering::Acquire)
+		.map_err(|_| io::Error::new(ErrorKind::AddrInUse, "ring in use"))?;
+
+	Ok(Writer { ring })
+}
+
+impl Write for Writer {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+
+		let ring = &mut self.ring;
+		let mut spins = 0;
+		loop {
+			let head = ring.head().load(Ordering::Relaxed);
+			let tail = ring.tail().load(Ordering::Acquire);
+			let free = ring.capacity - (head - tail);
+
+			if free > 0 {
+				let (pos, until_wrap) = ring.span(head);
+				let n = free.min(until_wrap).min(buf.len() as u64) as usize;
+				ring.map[pos..pos + n].copy_from_slice(&buf[..n]);
+				ring.head().store(head + n as u64, Ordering::Release);
+				return Ok(n);
+			}
+			backoff(&mut spins);
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Drop for Writer {
+	fn drop(&mut self) {
+		self.ring.state().store(DETACHED, Ordering::Release);
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ring_roundtrip() {
+		let handshake = std::env::temp_dir()
+			.join(format!("sdd-ring-{}.toml", std::process::id()));
+		let mut reader = create(&handshake, 8).unwrap();
+
+		let data: Vec<u8> = (0..20).collect();
+		let sent = data.clone();
+		let producer = thread::spawn(move || {
+			let mut writer = attach(&handshake).unwrap();
+			assert!(attach(&handshake).is_err());
+			writer.write_all(&sent).unwrap();
+		});
+
+		let mut received = vec![];
+		let mut buf = [0; 5];
+		loop {
+			match reader.read(&mut buf) {
+				Ok(0) => break,
+				Ok(n) => received.extend_from_slice(&buf[..n]),
+				Err(e) => assert_eq!(e.kind(), ErrorKind::TimedOut),
+			}
+		}
+		producer.join().unwrap();
+		assert_eq!(received, data);
+
+		// The drained ring takes the next producer.
+		assert!(attach(&reader.handshake).is_ok());
+	}
+}
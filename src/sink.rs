@@ -0,0 +1,510 @@
+use crate::config;
+use crate::dae::{Entry, FieldType, Strings};
+use crate::http;
+use crate::value;
+use serde_json::{Map, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//---------------------------------------------------------------------------
+/// Destination of the decoded entries besides the capture databases.
+pub trait Sink: Send {
+	/// Declares table `uid`, a reconnected producer may reuse the uid for
+	/// another table.
+	fn bind(&mut self, uid: u32, table: &str, columns: &[String]);
+
+	/// Publishes an entry of table `uid` received from `source`.
+	fn send(
+		&mut self,
+		uid: u32,
+		source: &str,
+		entry: &Entry,
+		strings: &Strings,
+	) -> Result<(), String>;
+
+	/// Delivers the buffered entries, called when a capture finishes.
+	fn flush(&mut self) -> Result<(), String> {
+		Ok(())
+	}
+
+	/// Takes a capture moved aside by a rotation.
+	fn rotated(&mut self, _path: &str) {}
+
+	/// (path, location) of the rotated captures the sink moved elsewhere,
+	/// e.g. uploaded and deleted.
+	fn moved(&mut self) -> Vec<(String, String)> {
+		vec![]
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Value of a field, string ids resolved to their text.
+pub fn value(field: &FieldType, strings: &Strings) -> value::Value {
+	match *field {
+		FieldType::Int(v) => v.into(),
+		FieldType::Float(v) => v.into(),
+		FieldType::Bool(v) => v.into(),
+		FieldType::Str(id) => match strings.resolve(id) {
+			Some(s) => s.into(),
+			None => format!("#{}", id).into(),
+		},
+	}
+}
+
+/// JSON value of a field, string ids resolved to their text.
+pub fn json_value(field: &FieldType, strings: &Strings) -> Value {
+	value(field, strings).to_json()
+}
+
+/// `{"table": .., "source": .., "values": {column: value}}`
+pub fn json_entry(
+	table: &str,
+	source: &str,
+	columns: &[String],
+	entry: &Entry,
+	strings: &Strings,
+) -> String {
+	let values: Map<String, Value> = columns
+		.iter()
+		.zip(entry.values())
+		.map(|(c, v)| (c.clone(), json_value(v, strings)))
+		.collect();
+
+	let mut object = Map::new();
+	object.insert(String::from("table"), Value::from(table));
+	object.insert(String::from("source"), Value::from(source));
+	object.insert(String::from("values"), Value::Object(values));
+	Value::Object(object).to_string()
+}
+
+//---------------------------------------------------------------------------
+/// Requests waiting for delivery, further ones are spooled or dropped.
+const QUEUE: usize = 16;
+/// Attempts of a request before it is spooled.
+const ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled with each further one.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// Bytes of requests a spool keeps when its size is not configured.
+pub const SPOOL_SIZE: u64 = 256 << 20;
+
+/// Tells apart the spool files written within the same nanosecond.
+static SPOOLED: AtomicU64 = AtomicU64::new(0);
+
+struct Post {
+	url: String,
+	content_type: String,
+	body: Vec<u8>,
+}
+
+enum Job {
+	Post(Post),
+	/// Acknowledged once the earlier requests are done.
+	Flush(mpsc::Sender<()>),
+}
+
+enum Outcome {
+	Delivered,
+	/// Refused by the service, sending it again would not help.
+	Rejected(u16),
+	Failed(String),
+}
+
+/// Posts the requests of a network sink in order from a background thread,
+/// so a slow service does not stall the capture. Failed requests are retried
+/// and then written to the spool directory if there is one, to be posted
+/// again once the service is back, also by a later run. Without a spool,
+/// or once it is full, they are dropped.
+pub struct Delivery {
+	name: &'static str,
+	spool: Option<Spool>,
+	jobs: mpsc::SyncSender<Job>,
+	/// Whether the last request overflowed, reported only once.
+	overflowing: bool,
+}
+
+impl Delivery {
+	/// `name` of the service in reports, `spool_size` like `1G`. The
+	/// `headers` are sent with every request, they are not spooled.
+	pub fn new(
+		name: &'static str,
+		spool: Option<&str>,
+		spool_size: Option<&str>,
+		headers: Vec<(String, String)>,
+	) -> Result<Delivery, String> {
+		let limit = match spool_size {
+			Some(size) => config::parse_count(size)?,
+			None => SPOOL_SIZE,
+		};
+		let spool = spool.map(|dir| Spool {
+			dir: PathBuf::from(dir),
+			limit,
+		});
+		if let Some(spool) = &spool {
+			fs::create_dir_all(&spool.dir)
+				.map_err(|e| format!("{}: {}", spool.dir.display(), e))?;
+		}
+
+		let (jobs, queue) = mpsc::sync_channel(QUEUE);
+		let courier = Courier {
+			name,
+			headers,
+			spool: spool.clone(),
+			failing: false,
+			spool_full: false,
+		};
+		thread::spawn(move || courier.run(queue));
+
+		Ok(Delivery {
+			name,
+			spool,
+			jobs,
+			overflowing: false,
+		})
+	}
+
+	/// Queues a POST request, spooling or dropping it when the queue is full.
+	pub fn post(
+		&mut self,
+		url: String,
+		content_type: &str,
+		body: Vec<u8>,
+	) -> Result<(), String> {
+		let post = Post {
+			url,
+			content_type: content_type.to_string(),
+			body,
+		};
+
+		let post = match self.jobs.try_send(Job::Post(post)) {
+			Ok(()) => {
+				self.overflowing = false;
+				return Ok(());
+			}
+			Err(mpsc::TrySendError::Full(Job::Post(p))) => p,
+			Err(_) => {
+				return Err(format!("{} delivery has stopped", self.name))
+			}
+		};
+
+		let report = !self.overflowing;
+		self.overflowing = true;
+		match self.spool.as_ref().map(|s| s.write(&post)) {
+			Some(Ok(true)) => Ok(()),
+			Some(Ok(false)) if report => {
+				Err(format!("{} spool is full, dropping requests", self.name))
+			}
+			Some(Ok(false)) => Ok(()),
+			Some(Err(e)) => Err(format!("{} spool: {}", self.name, e)),
+			None if report => Err(format!(
+				"{} is not keeping up, dropping requests",
+				self.name
+			)),
+			None => Ok(()),
+		}
+	}
+
+	/// Waits for the queued requests.
+	pub fn flush(&mut self) {
+		let (done, wait) = mpsc::channel();
+		if self.jobs.send(Job::Flush(done)).is_ok() {
+			let _ = wait.recv();
+		}
+	}
+}
+
+/// Delivers the queued requests.
+struct Courier {
+	name: &'static str,
+	headers: Vec<(String, String)>,
+	spool: Option<Spool>,
+	/// Whether the service is failing, requests are then not retried.
+	failing: bool,
+	/// Whether the spool was full, reported only once.
+	spool_full: bool,
+}
+
+impl Courier {
+	fn run(mut self, queue: mpsc::Receiver<Job>) {
+		self.unspool();
+
+		for job in queue {
+			let post = match job {
+				Job::Post(post) => post,
+				Job::Flush(done) => {
+					let _ = done.send(());
+					continue;
+				}
+			};
+
+			match self.deliver(&post) {
+				Outcome::Delivered => self.unspool(),
+				Outcome::Rejected(status) => {
+					println!(
+						"{} rejected a request: status {}",
+						self.name, status
+					)
+				}
+				Outcome::Failed(_) => self.spool(&post),
+			}
+		}
+	}
+
+	fn spool(&mut self, post: &Post) {
+		let spool = match &self.spool {
+			Some(spool) => spool,
+			None => return,
+		};
+
+		match spool.write(post) {
+			Ok(true) => self.spool_full = false,
+			Ok(false) if !self.spool_full => {
+				println!("{} spool is full, dropping requests", self.name);
+				self.spool_full = true;
+			}
+			Ok(false) => {}
+			Err(e) => println!("{} spool: {}", self.name, e),
+		}
+	}
+
+	fn attempt(&self, post: &Post) -> Outcome {
+		let headers = &self.headers;
+		match http::post_with(
+			&post.url,
+			&post.content_type,
+			headers,
+			&post.body,
+		) {
+			Ok(status) if (200..300).contains(&status) => Outcome::Delivered,
+			Ok(status) if (400..500).contains(&status) => {
+				Outcome::Rejected(status)
+			}
+			Ok(status) => Outcome::Failed(format!("status {}", status)),
+			Err(e) => Outcome::Failed(e.to_string()),
+		}
+	}
+
+	/// Posts the request, retried unless the service is already failing.
+	fn deliver(&mut self, post: &Post) -> Outcome {
+		let attempts = if self.failing { 1 } else { ATTEMPTS };
+		let mut wait = RETRY_INTERVAL;
+
+		let mut outcome = self.attempt(post);
+		for _ in 1..attempts {
+			if !matches!(outcome, Outcome::Failed(_)) {
+				break;
+			}
+			thread::sleep(wait);
+			wait *= 2;
+			outcome = self.attempt(post);
+		}
+
+		match &outcome {
+			Outcome::Failed(e) if !self.failing => {
+				println!("{} request failed: {}", self.name, e);
+				self.failing = true;
+			}
+			Outcome::Failed(_) => {}
+			_ if self.failing => {
+				println!("{} is reachable again", self.name);
+				self.failing = false;
+			}
+			_ => {}
+		}
+		outcome
+	}
+
+	/// Posts the spooled requests oldest first, until one fails.
+	fn unspool(&mut self) {
+		let spool = match &self.spool {
+			Some(spool) => spool,
+			None => return,
+		};
+
+		let mut paths = match spool.paths() {
+			Ok(paths) => paths,
+			Err(e) => {
+				println!("{}: {}", spool.dir.display(), e);
+				return;
+			}
+		};
+		paths.sort();
+
+		for path in paths {
+			let post = match unspool(&path) {
+				Ok(post) => post,
+				Err(e) => {
+					println!("{}: {}", path.display(), e);
+					continue;
+				}
+			};
+
+			match self.attempt(&post) {
+				Outcome::Failed(_) => return,
+				Outcome::Rejected(status) => {
+					println!(
+						"{} rejected a spooled request: status {}",
+						self.name, status
+					)
+				}
+				Outcome::Delivered => {}
+			}
+
+			if let Err(e) = fs::remove_file(&path) {
+				println!("{}: {}", path.display(), e);
+				return;
+			}
+		}
+	}
+}
+
+/// Directory of the requests that could not be delivered, holding at most
+/// `limit` bytes of them.
+#[derive(Clone)]
+struct Spool {
+	dir: PathBuf,
+	limit: u64,
+}
+
+impl Spool {
+	/// The spooled requests.
+	fn paths(&self) -> io::Result<Vec<PathBuf>> {
+		Ok(fs::read_dir(&self.dir)?
+			.flatten()
+			.map(|e| e.path())
+			.filter(|p| p.extension().is_some_and(|e| e == "post"))
+			.collect())
+	}
+
+	/// Writes the request to `<dir>/<unix ns>-<n>.post` as its url and
+	/// content type lines followed by the body. Returns false without
+	/// writing it when it would take the spool over its limit.
+	fn write(&self, post: &Post) -> io::Result<bool> {
+		let time = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_nanos())
+			.unwrap_or(0);
+		let n = SPOOLED.fetch_add(1, Ordering::Relaxed);
+
+		let mut bytes =
+			format!("{}\n{}\n", post.url, post.content_type).into_bytes();
+		bytes.extend_from_slice(&post.body);
+
+		let mut size = bytes.len() as u64;
+		for path in self.paths()? {
+			size += fs::metadata(path).map_or(0, |m| m.len());
+		}
+		if size > self.limit {
+			return Ok(false);
+		}
+
+		// Renamed once complete, a partial file is never posted.
+		let path = self.dir.join(format!("{:020}-{:06}.post", time, n));
+		let partial = path.with_extension("partial");
+		fs::write(&partial, bytes)?;
+		fs::rename(&partial, &path)?;
+		Ok(true)
+	}
+}
+
+fn unspool(path: &Path) -> io::Result<Post> {
+	let bytes = fs::read(path)?;
+	let invalid =
+		|| io::Error::new(io::ErrorKind::InvalidData, "Bad spool file");
+
+	let mut parts = bytes.splitn(3, |&b| b == b'\n');
+	let mut line = || {
+		let part = parts.next().ok_or_else(invalid)?;
+		String::from_utf8(part.to_vec()).map_err(|_| invalid())
+	};
+	let url = line()?;
+	let content_type = line()?;
+	let body = parts.next().ok_or_else(invalid)?.to_vec();
+
+	Ok(Post {
+		url,
+		content_type,
+		body,
+	})
+}
+
+//---------------------------------------------------------------------------
+/// Strings and entry `{n: 7, ok: true}` of a table `log (n, ok)`, as the
+/// sinks get them.
+#[cfg(test)]
+pub(crate) fn test_entry() -> (Strings, Entry) {
+	use crate::producer::{Producer, Type, Value};
+	use crate::wire::{Message, Messages};
+
+	let mut producer = Producer::new(vec![]);
+	let columns = [("n", Type::Int), ("ok", Type::Bool)];
+	let uid = producer.table("log", &columns).unwrap();
+	producer
+		.entry(uid, &[Value::Int(7), Value::Bool(true)])
+		.unwrap();
+	let bytes = producer.into_inner();
+
+	let mut strings = Strings::default();
+	let mut entry = None;
+	for message in Messages::new(&bytes) {
+		match message.unwrap() {
+			Message::String { id, value } => {
+				let value = String::from_utf8(value.to_vec()).unwrap();
+				assert_eq!(strings.intern(value).0, id);
+			}
+			Message::Entry { entry: e, .. } => entry = Some(*e),
+			_ => {}
+		}
+	}
+	(strings, entry.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn entry_json() {
+		let (strings, entry) = test_entry();
+		let columns = [String::from("n"), String::from("ok")];
+		assert_eq!(
+			json_entry("log", "dev", &columns, &entry, &strings),
+			r#"{"source":"dev","table":"log","values":{"n":7,"ok":true}}"#
+		);
+	}
+
+	#[test]
+	fn spool_roundtrip() {
+		let dir = std::env::temp_dir()
+			.join(format!("sdd-spool-test-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+
+		let post = Post {
+			url: String::from("http://127.0.0.1:9/insert"),
+			content_type: String::from("text/plain"),
+			body: b"a\nb".to_vec(),
+		};
+		let spool = Spool {
+			dir: dir.clone(),
+			limit: 64,
+		};
+		assert!(spool.write(&post).unwrap());
+		// A second request would take it over the limit.
+		assert!(!spool.write(&post).unwrap());
+
+		let paths = spool.paths().unwrap();
+		assert_eq!(paths.len(), 1);
+		let path = &paths[0];
+		let read = unspool(path).unwrap();
+		assert_eq!(read.url, post.url);
+		assert_eq!(read.content_type, post.content_type);
+		assert_eq!(read.body, post.body);
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
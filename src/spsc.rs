@@ -0,0 +1,356 @@
+use std::cell::UnsafeCell;
+use std::io::{self, ErrorKind, Read};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+//---------------------------------------------------------------------------
+/// Longest an end sleeps before looking at the ring again, in case it was
+/// moved to another thread than the one the other end wakes up.
+const WAIT: Duration = Duration::from_millis(10);
+
+/// Bounded queue between a single sending and a single receiving thread,
+/// pushing and popping without locks, see `channel`.
+struct Ring<T> {
+	slots: Box<[UnsafeCell<Option<T>>]>,
+	/// Next slot popped, only advanced by the receiver.
+	head: AtomicUsize,
+	/// Next slot pushed, only advanced by the sender.
+	tail: AtomicUsize,
+	/// Either end was dropped.
+	closed: AtomicBool,
+	/// Threads of the ends, once they waited, woken up by the other end.
+	sending: OnceLock<Thread>,
+	receiving: OnceLock<Thread>,
+}
+
+// The slots from head to tail belong to the receiver and the others to the
+// sender, the release stores of the indices handing them over.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+	fn slot(&self, index: usize) -> *mut Option<T> {
+		self.slots[index & (self.slots.len() - 1)].get()
+	}
+
+	fn close(&self) {
+		self.closed.store(true, Ordering::Release);
+		self.sending.get().map(Thread::unpark);
+		self.receiving.get().map(Thread::unpark);
+	}
+}
+
+/// Sending end of a ring, see `channel`.
+pub struct Sender<T> {
+	ring: Arc<Ring<T>>,
+}
+
+/// Receiving end of a ring, see `channel`.
+pub struct Receiver<T> {
+	ring: Arc<Ring<T>>,
+}
+
+/// Ring holding at least `capacity` values, preallocated.
+pub fn channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+	let slots = (0..capacity.max(1).next_power_of_two())
+		.map(|_| UnsafeCell::new(None))
+		.collect();
+	let ring = Arc::new(Ring {
+		slots,
+		head: AtomicUsize::new(0),
+		tail: AtomicUsize::new(0),
+		closed: AtomicBool::new(false),
+		sending: OnceLock::new(),
+		receiving: OnceLock::new(),
+	});
+
+	(Sender { ring: ring.clone() }, Receiver { ring })
+}
+
+impl<T> Sender<T> {
+	/// Pushes `value` unless the ring is full.
+	pub fn try_send(&mut self, value: T) -> Result<(), T> {
+		let ring = &self.ring;
+		let tail = ring.tail.load(Ordering::Relaxed);
+		if tail.wrapping_sub(ring.head.load(Ordering::Acquire))
+			== ring.slots.len()
+		{
+			return Err(value);
+		}
+
+		// The receiver is done with the slot once head moved past it.
+		unsafe { *ring.slot(tail) = Some(value) };
+		ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+		ring.receiving.get().map(Thread::unpark);
+		Ok(())
+	}
+
+	/// Whether the receiver is gone.
+	pub fn is_closed(&self) -> bool {
+		self.ring.closed.load(Ordering::Acquire)
+	}
+
+	/// Pushes `value`, waiting for room. Fails once the receiver is gone.
+	pub fn send(&mut self, mut value: T) -> Result<(), T> {
+		loop {
+			if self.ring.closed.load(Ordering::Acquire) {
+				return Err(value);
+			}
+			value = match self.try_send(value) {
+				Ok(()) => return Ok(()),
+				Err(value) => value,
+			};
+
+			self.ring.sending.get_or_init(thread::current);
+			thread::park_timeout(WAIT);
+		}
+	}
+}
+
+impl<T> Receiver<T> {
+	/// Pops a value unless the ring is empty.
+	pub fn try_recv(&mut self) -> Option<T> {
+		let ring = &self.ring;
+		let head = ring.head.load(Ordering::Relaxed);
+		if head == ring.tail.load(Ordering::Acquire) {
+			return None;
+		}
+
+		// The sender filled the slot before moving tail past it.
+		let value = unsafe { (*ring.slot(head)).take() };
+		ring.head.store(head.wrapping_add(1), Ordering::Release);
+		ring.sending.get().map(Thread::unpark);
+		value
+	}
+
+	/// Whether the sender is gone.
+	pub fn is_closed(&self) -> bool {
+		self.ring.closed.load(Ordering::Acquire)
+	}
+
+	/// Pops a value, waiting for one. `None` once the sender is gone and
+	/// the ring is empty.
+	pub fn recv(&mut self) -> Option<T> {
+		loop {
+			let closed = self.ring.closed.load(Ordering::Acquire);
+			if let Some(value) = self.try_recv() {
+				return Some(value);
+			}
+			if closed {
+				return None;
+			}
+
+			self.ring.receiving.get_or_init(thread::current);
+			thread::park_timeout(WAIT);
+		}
+	}
+}
+
+impl<T> Drop for Sender<T> {
+	fn drop(&mut self) {
+		self.ring.close();
+	}
+}
+
+impl<T> Drop for Receiver<T> {
+	fn drop(&mut self) {
+		self.ring.close();
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Part of the stream read ahead.
+pub enum Chunk {
+	/// Bytes read into a buffer, none ending the stream.
+	Data(Vec<u8>, usize),
+	/// Read error, a timeout is passed on and any other ends the stream.
+	Failed(io::Error),
+}
+
+/// Filling end of a `ReadAhead`, see `ReadAhead::new`.
+pub struct Filler {
+	/// Buffers to read into.
+	pub empty: Receiver<Vec<u8>>,
+	pub filled: Sender<Chunk>,
+}
+
+/// Reader of a stream read by a thread of its own into a fixed set of
+/// buffers, so that the stream is drained while the caller is busy storing
+/// what it read. Buffers go back and forth over two rings, nothing is
+/// allocated once it runs. Read errors like timeouts are passed on, the
+/// first other error or the end of the stream ends it.
+pub struct ReadAhead {
+	chunks: Receiver<Chunk>,
+	free: Sender<Vec<u8>>,
+	buf: Vec<u8>,
+	len: usize,
+	pos: usize,
+	/// Error received after some bytes, returned by the next read.
+	failed: Option<io::Error>,
+	ended: bool,
+}
+
+impl ReadAhead {
+	/// Reader of the chunks read into `buffers` buffers of `size` bytes
+	/// through the returned `Filler`, which has room for one more chunk, an
+	/// error needing no buffer.
+	pub fn new(buffers: usize, size: usize) -> (Filler, ReadAhead) {
+		let buffers = buffers.max(1);
+		let (mut free, empty) = channel(buffers);
+		for _ in 0..buffers {
+			let _ = free.send(vec![0; size.max(1)]);
+		}
+		let (filled, chunks) = channel(buffers + 1);
+
+		let ahead = ReadAhead {
+			chunks,
+			free,
+			buf: vec![],
+			len: 0,
+			pos: 0,
+			failed: None,
+			ended: false,
+		};
+		(Filler { empty, filled }, ahead)
+	}
+
+	/// Reads `reader` from a new thread into `buffers` buffers of `size`
+	/// bytes. The thread stops once the stream ends or at its first read
+	/// after the `ReadAhead` is dropped.
+	pub fn spawn<R: Read + Send + 'static>(
+		reader: R,
+		buffers: usize,
+		size: usize,
+	) -> ReadAhead {
+		let (filler, ahead) = ReadAhead::new(buffers, size);
+		thread::spawn(move || fill(reader, filler));
+		ahead
+	}
+}
+
+fn fill<R: Read>(mut reader: R, mut filler: Filler) {
+	// Buffer kept over a failed read.
+	let mut kept = None;
+
+	loop {
+		let mut buf = match kept.take().or_else(|| filler.empty.recv()) {
+			Some(buf) => buf,
+			None => return,
+		};
+
+		let (chunk, end) = match reader.read(&mut buf) {
+			Ok(n) => (Chunk::Data(buf, n), n == 0),
+			Err(e) if e.kind() == ErrorKind::Interrupted => {
+				kept = Some(buf);
+				continue;
+			}
+			Err(e) => {
+				kept = Some(buf);
+				let timed_out = matches!(
+					e.kind(),
+					ErrorKind::WouldBlock | ErrorKind::TimedOut
+				);
+				(Chunk::Failed(e), !timed_out)
+			}
+		};
+
+		if filler.filled.send(chunk).is_err() || end {
+			return;
+		}
+	}
+}
+
+impl Read for ReadAhead {
+	/// Reads what was read ahead so far, waiting only when nothing was.
+	fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+		if let Some(e) = self.failed.take() {
+			return Err(e);
+		}
+
+		let mut n = 0;
+		while n < out.len() && !self.ended {
+			if self.pos == self.len {
+				let chunk = match n {
+					0 => self.chunks.recv(),
+					_ => self.chunks.try_recv(),
+				};
+				match chunk {
+					Some(Chunk::Data(buf, len)) => {
+						let used = mem::replace(&mut self.buf, buf);
+						if !used.is_empty() {
+							let _ = self.free.send(used);
+						}
+						(self.len, self.pos) = (len, 0);
+						self.ended = len == 0;
+					}
+					Some(Chunk::Failed(e)) if n == 0 => return Err(e),
+					Some(Chunk::Failed(e)) => {
+						self.failed = Some(e);
+						break;
+					}
+					None if n == 0 => self.ended = true,
+					None => break,
+				}
+				continue;
+			}
+
+			let count = (self.len - self.pos).min(out.len() - n);
+			out[n..n + count]
+				.copy_from_slice(&self.buf[self.pos..self.pos + count]);
+			self.pos += count;
+			n += count;
+		}
+
+		Ok(n)
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Stream timing out once before its bytes.
+	struct Slow {
+		bytes: io::Cursor<Vec<u8>>,
+		timed_out: bool,
+	}
+
+	impl Read for Slow {
+		fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+			if !mem::replace(&mut self.timed_out, true) {
+				return Err(ErrorKind::TimedOut.into());
+			}
+			self.bytes.read(buf)
+		}
+	}
+
+	#[test]
+	fn read_ahead() {
+		let (mut sender, mut receiver) = channel(4);
+		let sent =
+			thread::spawn(move || (0..1000).all(|i| sender.send(i).is_ok()));
+		let received: Vec<u32> =
+			(0..1000).map_while(|_| receiver.recv()).collect();
+		assert!(sent.join().unwrap());
+		assert_eq!(received, (0..1000).collect::<Vec<_>>());
+		assert_eq!(receiver.recv(), None);
+
+		let bytes: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+		let slow = Slow {
+			bytes: io::Cursor::new(bytes.clone()),
+			timed_out: false,
+		};
+		let mut reader = ReadAhead::spawn(slow, 3, 7);
+		let mut buf = [0; 4];
+		let e = reader.read(&mut buf).unwrap_err();
+		assert_eq!(e.kind(), ErrorKind::TimedOut);
+
+		let mut read = vec![];
+		reader.read_to_end(&mut read).unwrap();
+		assert_eq!(read, bytes);
+		assert_eq!(reader.read(&mut buf).unwrap(), 0);
+	}
+}
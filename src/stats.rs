@@ -0,0 +1,219 @@
+use crate::export;
+use crate::value::Value;
+use rusqlite;
+use std::fmt;
+use std::fmt::Display;
+
+//---------------------------------------------------------------------------
+const PERCENTILES: [f64; 5] = [50.0, 90.0, 95.0, 99.0, 99.9];
+const BAR_WIDTH: u64 = 40;
+
+//---------------------------------------------------------------------------
+pub struct Summary {
+	pub count: usize,
+	pub min: f64,
+	pub max: f64,
+	pub mean: f64,
+	pub stddev: f64,
+	/// (percentile, value) pairs.
+	pub percentiles: Vec<(f64, f64)>,
+}
+
+impl Summary {
+	/// Summarizes `values`, sorting them in place.
+	pub fn new(values: &mut [f64]) -> Option<Summary> {
+		if values.is_empty() {
+			return None;
+		}
+
+		values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+		let count = values.len();
+		let mean = values.iter().sum::<f64>() / count as f64;
+		let var = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>()
+			/ count as f64;
+
+		Some(Summary {
+			count,
+			min: values[0],
+			max: values[count - 1],
+			mean,
+			stddev: var.sqrt(),
+			percentiles: PERCENTILES
+				.iter()
+				.map(|p| (*p, percentile(values, *p)))
+				.collect(),
+		})
+	}
+}
+
+impl Display for Summary {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(
+			f,
+			"count {}  min {}  max {}  mean {:.4}  stddev {:.4}",
+			self.count, self.min, self.max, self.mean, self.stddev
+		)?;
+
+		let percentiles: Vec<String> = self
+			.percentiles
+			.iter()
+			.map(|(p, v)| format!("p{} {:.4}", p, v))
+			.collect();
+		writeln!(f, "{}", percentiles.join("  "))
+	}
+}
+
+/// Linearly interpolated percentile of sorted `values`.
+fn percentile(values: &[f64], p: f64) -> f64 {
+	let rank = p / 100.0 * (values.len() - 1) as f64;
+	let lo = rank.floor() as usize;
+	let hi = rank.ceil() as usize;
+
+	values[lo] + (values[hi] - values[lo]) * (rank - lo as f64)
+}
+
+//---------------------------------------------------------------------------
+pub struct Bucket {
+	pub lo: f64,
+	pub hi: f64,
+	pub count: u64,
+}
+
+pub struct Histogram {
+	pub buckets: Vec<Bucket>,
+}
+
+impl Histogram {
+	/// Equal width histogram of `values` spanning their min and max.
+	pub fn new(values: &[f64], buckets: usize) -> Histogram {
+		let buckets = buckets.max(1);
+		let min = values.iter().cloned().fold(f64::MAX, f64::min);
+		let max = values.iter().cloned().fold(f64::MIN, f64::max);
+		let width = if max > min {
+			(max - min) / buckets as f64
+		} else {
+			1.0
+		};
+
+		let mut histogram = Histogram {
+			buckets: (0..buckets)
+				.map(|i| Bucket {
+					lo: min + width * i as f64,
+					hi: min + width * (i + 1) as f64,
+					count: 0,
+				})
+				.collect(),
+		};
+
+		for v in values {
+			let i = ((v - min) / width) as usize;
+			histogram.buckets[i.min(buckets - 1)].count += 1;
+		}
+
+		histogram
+	}
+
+	/// Replaces `name` in the capture with the buckets of this histogram.
+	pub fn store(
+		&self,
+		con: &rusqlite::Connection,
+		name: &str,
+	) -> rusqlite::Result<()> {
+		con.execute_batch(&format!(
+			"DROP TABLE IF EXISTS \"{name}\"; \
+			 CREATE TABLE \"{name}\" (lo REAL, hi REAL, count INTEGER);",
+			name = name
+		))?;
+
+		let mut stmt = con.prepare(&format!(
+			"INSERT INTO \"{}\" VALUES (?1, ?2, ?3)",
+			name
+		))?;
+		for b in &self.buckets {
+			stmt.execute(&[
+				Value::from(b.lo),
+				b.hi.into(),
+				(b.count as i64).into(),
+			])?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Display for Histogram {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let peak = self.buckets.iter().map(|b| b.count).max().unwrap_or(0);
+
+		for b in &self.buckets {
+			let bar = (b.count * BAR_WIDTH).checked_div(peak).unwrap_or(0);
+			writeln!(
+				f,
+				"[{:>12.4}, {:>12.4}) {:>10} {}",
+				b.lo,
+				b.hi,
+				b.count,
+				"#".repeat(bar as usize)
+			)?;
+		}
+
+		Ok(())
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Non-null numeric values of `table.column`.
+pub fn column_values(
+	con: &rusqlite::Connection,
+	table: &str,
+	column: &str,
+) -> Result<Vec<f64>, export::Error> {
+	let tables = export::tables(con)?;
+	let known = tables
+		.iter()
+		.find(|t| t.name == table)
+		.is_some_and(|t| t.columns.iter().any(|c| c.name == column));
+
+	if !known {
+		return Err(export::Error::Format("Unknown table or column"));
+	}
+
+	let mut stmt = con.prepare(&format!(
+		"SELECT \"{}\" FROM \"{}\" WHERE \"{}\" IS NOT NULL",
+		column, table, column
+	))?;
+
+	let mut values = vec![];
+	let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+	while let Some(row) = rows.next()? {
+		if let Some(v) = export::as_f64(row.get_raw(0)) {
+			values.push(v);
+		}
+	}
+
+	Ok(values)
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn summary_and_histogram() {
+		let mut values: Vec<f64> = (1..=100).rev().map(|v| v as f64).collect();
+
+		let summary = Summary::new(&mut values).unwrap();
+		assert_eq!(summary.count, 100);
+		assert_eq!(summary.min, 1.0);
+		assert_eq!(summary.max, 100.0);
+		assert_eq!(summary.mean, 50.5);
+		assert_eq!(summary.percentiles[0], (50.0, 50.5));
+
+		let histogram = Histogram::new(&values, 4);
+		let counts: Vec<u64> =
+			histogram.buckets.iter().map(|b| b.count).collect();
+		assert_eq!(counts, vec![25, 25, 25, 25]);
+	}
+}
@@ -0,0 +1,277 @@
+use crate::ingest::{self, Datagrams};
+use crate::json::{self, Encoder};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, ErrorKind, Read};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+//---------------------------------------------------------------------------
+/// Bytes read from the socket at once.
+const CHUNK: usize = 4096;
+
+#[derive(Default)]
+struct Timer {
+	values: Vec<f64>,
+}
+
+impl Timer {
+	/// Value below which `q` of the sorted values lie.
+	fn quantile(sorted: &[f64], q: f64) -> f64 {
+		let i = ((sorted.len() - 1) as f64 * q).round() as usize;
+		sorted[i]
+	}
+}
+
+/// Metrics received since the last flush, gauges keep their value.
+#[derive(Default)]
+struct Metrics {
+	counters: BTreeMap<String, f64>,
+	gauges: BTreeMap<String, f64>,
+	timers: BTreeMap<String, Timer>,
+	sets: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Metrics {
+	/// Adds a `name:value|type[|@rate]` line, DogStatsD tags are ignored.
+	fn add(&mut self, line: &str) -> Result<(), String> {
+		let bad = || format!("Bad StatsD line {}", line);
+
+		let (name, rest) = line.split_once(':').ok_or_else(bad)?;
+		let mut parts = rest.split('|');
+		let value = parts.next().ok_or_else(bad)?;
+		let kind = parts.next().ok_or_else(bad)?;
+		let rate = parts
+			.find_map(|p| p.strip_prefix('@'))
+			.map(|r| r.parse::<f64>().map_err(|_| bad()))
+			.transpose()?
+			.filter(|&r| r > 0.0)
+			.unwrap_or(1.0);
+
+		let number = || value.parse::<f64>().map_err(|_| bad());
+		match kind {
+			"c" => {
+				*self.counters.entry(name.to_string()).or_default() +=
+					number()? / rate
+			}
+			"g" => {
+				let v = number()?;
+				let gauge = self.gauges.entry(name.to_string()).or_default();
+				if value.starts_with(['+', '-']) {
+					*gauge += v;
+				} else {
+					*gauge = v;
+				}
+			}
+			"ms" | "h" | "d" => self
+				.timers
+				.entry(name.to_string())
+				.or_default()
+				.values
+				.push(number()?),
+			"s" => {
+				self.sets
+					.entry(name.to_string())
+					.or_default()
+					.insert(value.to_string());
+			}
+			_ => return Err(bad()),
+		}
+
+		Ok(())
+	}
+
+	/// Rows of the interval as `Encoder` entries of the
+	/// `statsd_<kind>` tables.
+	fn flush(&mut self, time: u64, interval: f64) -> Vec<Value> {
+		let mut rows = vec![];
+
+		for (name, count) in std::mem::take(&mut self.counters) {
+			rows.push(row(
+				"statsd_counter",
+				&name,
+				time,
+				&[("count", count), ("rate", count / interval)],
+			));
+		}
+
+		for (name, value) in &self.gauges {
+			rows.push(row("statsd_gauge", name, time, &[("value", *value)]));
+		}
+
+		for (name, timer) in std::mem::take(&mut self.timers) {
+			let mut sorted = timer.values;
+			sorted.sort_by(f64::total_cmp);
+			let sum: f64 = sorted.iter().sum();
+			rows.push(row(
+				"statsd_timer",
+				&name,
+				time,
+				&[
+					("count", sorted.len() as f64),
+					("min", sorted[0]),
+					("max", sorted[sorted.len() - 1]),
+					("mean", sum / sorted.len() as f64),
+					("p50", Timer::quantile(&sorted, 0.5)),
+					("p90", Timer::quantile(&sorted, 0.9)),
+					("p99", Timer::quantile(&sorted, 0.99)),
+				],
+			));
+		}
+
+		for (name, set) in std::mem::take(&mut self.sets) {
+			let count = set.len() as f64;
+			rows.push(row("statsd_set", &name, time, &[("count", count)]));
+		}
+
+		rows
+	}
+}
+
+/// Entry of a metric row, the values are float columns.
+fn row(table: &str, name: &str, time: u64, values: &[(&str, f64)]) -> Value {
+	let mut fields = Map::new();
+	fields.insert(String::from("name"), Value::from(name));
+	fields.insert(String::from("time"), Value::from(time));
+	for (column, v) in values {
+		fields.insert(column.to_string(), json::float(*v));
+	}
+	json::entry(table, fields)
+}
+
+//---------------------------------------------------------------------------
+/// Aggregates the StatsD metrics received on a socket, writing one row per
+/// metric and interval. Rows hold the metric name and the interval end in
+/// unix seconds as `time`.
+pub struct Aggregator {
+	datagrams: Datagrams,
+	interval: Duration,
+	flushed: Instant,
+	metrics: Metrics,
+	/// Input not yet aggregated, up to a partial line.
+	input: Vec<u8>,
+	encoder: Encoder,
+	output: Vec<u8>,
+	pos: usize,
+}
+
+/// Receives StatsD datagrams on `addr`, flushing the metrics every
+/// `interval`.
+pub fn bind(addr: &str, interval: Duration) -> io::Result<Aggregator> {
+	Ok(Aggregator {
+		datagrams: ingest::bind_udp(addr, true)?,
+		interval,
+		flushed: Instant::now(),
+		metrics: Metrics::default(),
+		input: vec![],
+		encoder: Encoder::new(),
+		output: vec![],
+		pos: 0,
+	})
+}
+
+impl Aggregator {
+	fn aggregate(&mut self) {
+		while let Some(end) = self.input.iter().position(|&b| b == b'\n') {
+			let line: Vec<u8> = self.input.drain(..=end).collect();
+			let line = String::from_utf8_lossy(&line);
+			let line = line.trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			if let Err(e) = self.metrics.add(line) {
+				println!("{}", e);
+			}
+		}
+	}
+
+	fn flush(&mut self) {
+		let interval = self.flushed.elapsed().as_secs_f64();
+		self.flushed = Instant::now();
+
+		let time = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		for row in self.metrics.flush(time, interval) {
+			if let Err(e) = self.encoder.encode(&row) {
+				println!("StatsD: {}", e);
+			}
+		}
+		self.encoder.take(&mut self.output);
+	}
+}
+
+impl Read for Aggregator {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		loop {
+			if self.pos < self.output.len() {
+				let n = buf.len().min(self.output.len() - self.pos);
+				buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+				self.pos += n;
+				return Ok(n);
+			}
+
+			self.pos = 0;
+			self.output.clear();
+
+			if self.flushed.elapsed() >= self.interval {
+				self.flush();
+				continue;
+			}
+
+			let mut chunk = [0; CHUNK];
+			match self.datagrams.read(&mut chunk) {
+				Ok(n) => {
+					self.input.extend_from_slice(&chunk[..n]);
+					self.aggregate();
+				}
+				// Quiet sockets still flush on time.
+				Err(e) if e.kind() == ErrorKind::TimedOut => {
+					if self.flushed.elapsed() < self.interval {
+						return Err(e);
+					}
+				}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn aggregate_metrics() {
+		let mut metrics = Metrics::default();
+		let lines = [
+			"hits:2|c",
+			"hits:1|c|@0.5",
+			"temp:20|g",
+			"temp:-5|g",
+			"load:10|ms",
+			"load:30|ms|#route:home",
+			"users:a|s",
+			"users:a|s",
+		];
+		for line in lines {
+			metrics.add(line).unwrap();
+		}
+		assert!(metrics.add("hits:x|c").is_err());
+
+		let rows = metrics.flush(7, 2.0);
+		let fields: Vec<&Value> = rows.iter().map(|r| &r["fields"]).collect();
+		assert_eq!(fields[0]["count"], 4.0);
+		assert_eq!(fields[0]["rate"], 2.0);
+		assert_eq!(fields[1]["value"], 15.0);
+		assert_eq!(fields[2]["mean"], 20.0);
+		assert_eq!(fields[2]["max"], 30.0);
+		assert_eq!(fields[3]["count"], 1.0);
+		assert_eq!(fields[3]["time"], 7);
+
+		// Only gauges are reported again.
+		assert_eq!(metrics.flush(8, 2.0).len(), 1);
+	}
+}
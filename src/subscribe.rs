@@ -0,0 +1,167 @@
+use crate::config::glob_match;
+use crate::dae::{Entry, Strings};
+use crate::sink::{self, Sink};
+use crate::value::Row;
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+//---------------------------------------------------------------------------
+/// Rows a subscriber may fall behind by, further ones are dropped.
+pub const QUEUE: usize = 4096;
+
+struct Table {
+	name: String,
+	columns: Vec<String>,
+}
+
+//---------------------------------------------------------------------------
+/// Sink handing the entries of the tables matching a glob over to a
+/// channel as `T`, see `Daemon::subscribe` and `Daemon::subscribe_as`.
+pub struct Subscription<T> {
+	pattern: String,
+	/// Matching tables by uid.
+	tables: HashMap<u32, Table>,
+	rows: mpsc::SyncSender<T>,
+	convert: fn(Row) -> T,
+	/// Whether the subscriber was behind on the last row, reported only
+	/// once.
+	behind: bool,
+	/// The receiver was dropped.
+	closed: bool,
+}
+
+impl Subscription<Row> {
+	pub fn new(pattern: &str) -> (Self, mpsc::Receiver<Row>) {
+		Subscription::with(pattern, |row| row)
+	}
+}
+
+impl<T> Subscription<T> {
+	/// Subscription sending the rows converted by `convert`.
+	pub fn with(
+		pattern: &str,
+		convert: fn(Row) -> T,
+	) -> (Self, mpsc::Receiver<T>) {
+		let (rows, receiver) = mpsc::sync_channel(QUEUE);
+		let subscription = Subscription {
+			pattern: pattern.to_string(),
+			tables: HashMap::new(),
+			rows,
+			convert,
+			behind: false,
+			closed: false,
+		};
+		(subscription, receiver)
+	}
+}
+
+impl<T: Send> Sink for Subscription<T> {
+	fn bind(&mut self, uid: u32, table: &str, columns: &[String]) {
+		if glob_match(&self.pattern, table) {
+			let name = table.to_string();
+			let columns = columns.to_vec();
+			self.tables.insert(uid, Table { name, columns });
+		} else {
+			self.tables.remove(&uid);
+		}
+	}
+
+	fn send(
+		&mut self,
+		uid: u32,
+		source: &str,
+		entry: &Entry,
+		strings: &Strings,
+	) -> Result<(), String> {
+		let table = match self.tables.get(&uid) {
+			Some(t) if !self.closed => t,
+			_ => return Ok(()),
+		};
+
+		let values = table
+			.columns
+			.iter()
+			.zip(entry.values())
+			.map(|(c, v)| (c.clone(), sink::value(v, strings)))
+			.collect();
+		let row = Row {
+			table: table.name.clone(),
+			source: source.to_string(),
+			values,
+		};
+
+		let behind = match self.rows.try_send((self.convert)(row)) {
+			Ok(()) => false,
+			Err(mpsc::TrySendError::Full(_)) => true,
+			Err(mpsc::TrySendError::Disconnected(_)) => {
+				self.closed = true;
+				false
+			}
+		};
+		let report = behind && !self.behind;
+		self.behind = behind;
+
+		match report {
+			true => Err(format!(
+				"Subscriber of {} is behind, rows are dropped",
+				self.pattern
+			)),
+			false => Ok(()),
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dae::{Daemon, Protocol};
+	use crate::producer::{Producer, Type, Value as Field};
+	use crate::value::Value;
+	use serde::Deserialize;
+
+	#[derive(Deserialize)]
+	struct Frame {
+		ts: u32,
+		scene: String,
+	}
+
+	#[derive(Deserialize)]
+	struct Gpu {
+		gpu_ms: f32,
+	}
+
+	#[test]
+	fn subscribe_tables() {
+		let mut producer = Producer::new(vec![]);
+		let columns = [("ts", Type::Int), ("scene", Type::Str)];
+		let frame = producer.table("frame", &columns).unwrap();
+		let hud = producer.table("hud", &columns).unwrap();
+		let scene = producer.string("menu").unwrap();
+		for uid in [frame, hud, frame] {
+			let values = [Field::Int(uid), Field::Str(scene)];
+			producer.entry(uid, &values).unwrap();
+		}
+		let bytes = producer.into_inner();
+
+		let mut daemon = Daemon::new(Protocol::without_output());
+		daemon.verbose = false;
+		daemon.exit_on_eof = true;
+		let rows = daemon.subscribe("fr*");
+		let frames = daemon.subscribe_as::<Frame>("frame");
+		assert!(daemon.capture_reader(&bytes[..], "test").is_ok());
+
+		let rows: Vec<Row> = rows.try_iter().collect();
+		assert_eq!(rows.len(), 2);
+		assert_eq!(rows[0].table, "frame");
+		assert_eq!(rows[0].source, "test");
+		assert_eq!(rows[1].get("scene"), Some(&Value::from("menu")));
+
+		let frames: Vec<Frame> = frames.try_iter().flatten().collect();
+		assert_eq!(frames.len(), 2);
+		assert_eq!((frames[1].ts, frames[1].scene.as_str()), (0, "menu"));
+
+		let e = rows[0].deserialize::<Gpu>().map(|g| g.gpu_ms).unwrap_err();
+		assert!(e.contains("missing field `gpu_ms`"), "{}", e);
+	}
+}
@@ -0,0 +1,313 @@
+use crate::spsc::ReadAhead;
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+use {
+	crate::spsc::{Chunk, Filler},
+	io_uring::{opcode, squeue, types, IoUring},
+	std::os::unix::io::AsRawFd,
+	std::sync::mpsc,
+	std::thread,
+};
+
+//---------------------------------------------------------------------------
+/// Submission entries of the ring, two per connection waiting for data.
+pub const ENTRIES: u32 = 256;
+
+/// Completions of the reactor timer and of the timeouts linked to reads.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+const TICK: u64 = u64::MAX;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+const LINKED: u64 = u64::MAX - 1;
+/// How often the reactor looks for new connections and returned buffers.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+fn unsupported() -> io::Error {
+	io::Error::other(
+		"sdd was built without the uring feature or for another OS than Linux.",
+	)
+}
+
+//---------------------------------------------------------------------------
+/// Single thread reading every registered connection through io_uring, in
+/// place of a blocking read per connection, so that many producers cost a
+/// system call per batch of reads instead of one per read. Each connection
+/// is read into buffers of its own handed over to its capture, see
+/// `spsc::ReadAhead`.
+#[derive(Clone)]
+pub struct Reactor {
+	#[cfg(all(feature = "uring", target_os = "linux"))]
+	conns: mpsc::Sender<Conn>,
+}
+
+#[cfg(not(all(feature = "uring", target_os = "linux")))]
+impl Reactor {
+	pub fn start(_entries: u32) -> io::Result<Reactor> {
+		Err(unsupported())
+	}
+
+	pub fn register(
+		&self,
+		_stream: TcpStream,
+		_buffers: usize,
+		_size: usize,
+		_timeout: Option<Duration>,
+	) -> io::Result<ReadAhead> {
+		Err(unsupported())
+	}
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+impl Reactor {
+	/// Starts the reactor thread on a ring of `entries` submission entries.
+	/// Fails where io_uring is not available, e.g. in some containers.
+	pub fn start(entries: u32) -> io::Result<Reactor> {
+		let ring = IoUring::new(entries)?;
+		let (conns, registered) = mpsc::channel();
+		thread::spawn(move || {
+			if let Err(e) = run(ring, registered) {
+				println!("The io_uring reader stopped: {}", e);
+			}
+		});
+
+		Ok(Reactor { conns })
+	}
+
+	/// Reads `stream` into `buffers` buffers of `size` bytes, a read waiting
+	/// longer than `timeout` failing with `TimedOut`. The reads stop once
+	/// the stream is shut down.
+	pub fn register(
+		&self,
+		stream: TcpStream,
+		buffers: usize,
+		size: usize,
+		timeout: Option<Duration>,
+	) -> io::Result<ReadAhead> {
+		let (filler, ahead) = ReadAhead::new(buffers, size);
+		let conn = Conn {
+			stream,
+			filler,
+			timeout: timeout.map(|t| Box::new(types::Timespec::from(t))),
+			reading: None,
+			spare: None,
+			ended: false,
+		};
+
+		self.conns
+			.send(conn)
+			.map_err(|_| io::Error::other("The io_uring reader stopped"))?;
+		Ok(ahead)
+	}
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+struct Conn {
+	stream: TcpStream,
+	filler: Filler,
+	/// Read timeout, boxed as the kernel refers to it.
+	timeout: Option<Box<types::Timespec>>,
+	/// Buffer of the read in flight, which the kernel writes into.
+	reading: Option<Vec<u8>>,
+	/// Buffer kept over a read that returned nothing.
+	spare: Option<Vec<u8>>,
+	ended: bool,
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+impl Conn {
+	/// Queues a read into the next empty buffer, none while the capture
+	/// holds them all.
+	fn read(&mut self, key: usize, ring: &mut IoUring) -> io::Result<()> {
+		self.ended |= self.filler.empty.is_closed();
+		let mut buf = match self.spare.take() {
+			Some(buf) => buf,
+			None => match self.filler.empty.try_recv() {
+				Some(buf) => buf,
+				None => return Ok(()),
+			},
+		};
+
+		let fd = types::Fd(self.stream.as_raw_fd());
+		let recv = opcode::Recv::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+			.build()
+			.user_data(key as u64);
+		self.reading = Some(buf);
+
+		match &self.timeout {
+			Some(timeout) => {
+				let recv = recv.flags(squeue::Flags::IO_LINK);
+				let timeout = opcode::LinkTimeout::new(&**timeout);
+				push(ring, &[recv, timeout.build().user_data(LINKED)])
+			}
+			None => push(ring, &[recv]),
+		}
+	}
+
+	/// Hands the completed read over to the capture.
+	fn complete(&mut self, result: i32) {
+		let buf = match self.reading.take() {
+			Some(buf) => buf,
+			None => return,
+		};
+
+		let chunk = match -result {
+			_ if result >= 0 => Chunk::Data(buf, result as usize),
+			libc::EINTR | libc::EAGAIN => {
+				self.spare = Some(buf);
+				return;
+			}
+			// The linked timeout cancelled the read.
+			libc::ECANCELED => {
+				self.spare = Some(buf);
+				Chunk::Failed(io::ErrorKind::TimedOut.into())
+			}
+			errno => {
+				self.spare = Some(buf);
+				Chunk::Failed(io::Error::from_raw_os_error(errno))
+			}
+		};
+
+		self.ended |= match chunk {
+			Chunk::Data(_, n) => n == 0,
+			Chunk::Failed(ref e) => e.kind() != io::ErrorKind::TimedOut,
+		};
+		// There is always room for the data, a timeout reported while the
+		// capture is behind is dropped.
+		if self.filler.filled.try_send(chunk).is_err() {
+			self.ended |= self.filler.filled.is_closed();
+		}
+	}
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+fn run(mut ring: IoUring, registered: mpsc::Receiver<Conn>) -> io::Result<()> {
+	let tick = types::Timespec::from(TICK_INTERVAL);
+	let mut ticking = false;
+	// Connections by the key of their reads.
+	let mut conns: Vec<Option<Conn>> = vec![];
+	let mut open = true;
+
+	loop {
+		while let Some(conn) = match registered.try_recv() {
+			Ok(conn) => Some(conn),
+			Err(mpsc::TryRecvError::Empty) => None,
+			Err(mpsc::TryRecvError::Disconnected) => {
+				open = false;
+				None
+			}
+		} {
+			match conns.iter().position(Option::is_none) {
+				Some(key) => conns[key] = Some(conn),
+				None => conns.push(Some(conn)),
+			}
+		}
+
+		for (key, slot) in conns.iter_mut().enumerate() {
+			let conn = match slot {
+				Some(conn) if conn.reading.is_none() => conn,
+				_ => continue,
+			};
+			conn.read(key, &mut ring)?;
+			if conn.ended && conn.reading.is_none() {
+				*slot = None;
+			}
+		}
+
+		if !open && conns.iter().all(Option::is_none) {
+			return Ok(());
+		}
+		if !ticking {
+			let timer = opcode::Timeout::new(&tick).build().user_data(TICK);
+			push(&mut ring, &[timer])?;
+			ticking = true;
+		}
+
+		match ring.submit_and_wait(1) {
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			result => result?,
+		};
+		let completed: Vec<(u64, i32)> = ring
+			.completion()
+			.map(|cqe| (cqe.user_data(), cqe.result()))
+			.collect();
+		for (data, result) in completed {
+			match data {
+				TICK => ticking = false,
+				LINKED => {}
+				key => {
+					let slot = &mut conns[key as usize];
+					if let Some(conn) = slot {
+						conn.complete(result);
+						if conn.ended && conn.reading.is_none() {
+							*slot = None;
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Queues `entries` together, linked ones have to be submitted at once.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+fn push(ring: &mut IoUring, entries: &[squeue::Entry]) -> io::Result<()> {
+	let room = {
+		let sq = ring.submission();
+		sq.capacity() - sq.len()
+	};
+	if room < entries.len() {
+		ring.submit()?;
+	}
+
+	let mut sq = ring.submission();
+	for entry in entries {
+		// The buffers and timeouts outlive the entries, see `Conn`.
+		unsafe { sq.push(entry) }
+			.map_err(|_| io::Error::other("The submission queue is full"))?;
+	}
+	Ok(())
+}
+
+//---------------------------------------------------------------------------
+#[cfg(all(test, feature = "uring", target_os = "linux"))]
+mod tests {
+	use super::*;
+	use std::io::{Read, Write};
+	use std::net::TcpListener;
+
+	#[test]
+	fn read_connections() {
+		let reactor = match Reactor::start(ENTRIES) {
+			Ok(reactor) => reactor,
+			// Not every kernel or sandbox allows io_uring.
+			Err(e) => return println!("Skipped without io_uring: {}", e),
+		};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let bytes: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+		let sent = bytes.clone();
+		let producer = thread::spawn(move || {
+			let mut stream = TcpStream::connect(addr).unwrap();
+			stream.write_all(&sent).unwrap();
+		});
+
+		let (stream, _) = listener.accept().unwrap();
+		let mut reader = reactor.register(stream, 4, 4096, None).unwrap();
+		let mut read = vec![];
+		reader.read_to_end(&mut read).unwrap();
+		producer.join().unwrap();
+		assert_eq!(read, bytes);
+
+		let _idle = TcpStream::connect(addr).unwrap();
+		let (stream, _) = listener.accept().unwrap();
+		let timeout = Some(Duration::from_millis(20));
+		let mut reader = reactor.register(stream, 4, 4096, timeout).unwrap();
+		let e = reader.read(&mut [0; 16]).unwrap_err();
+		assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+	}
+}
@@ -0,0 +1,193 @@
+use serde::Deserialize;
+use std::str::FromStr;
+
+//---------------------------------------------------------------------------
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ValidateConfig {
+	/// `[table.]column in <min>..<max>`, bounds included and either one
+	/// optional, e.g. `frame.frame_ms in 0..10000`.
+	pub rule: String,
+	#[serde(default)]
+	pub policy: Policy,
+}
+
+/// What becomes of an entry with a value out of range.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, PartialOrd, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+	/// Stored with 0 in an extra `valid` column of its table, 1 otherwise.
+	#[default]
+	Flag,
+	/// Kept in `_sdd_quarantine` instead of its table.
+	Quarantine,
+	Drop,
+}
+
+//---------------------------------------------------------------------------
+#[derive(Debug, PartialEq)]
+pub struct Bounds {
+	pub table: Option<String>,
+	pub column: String,
+	pub min: Option<f64>,
+	pub max: Option<f64>,
+}
+
+impl Bounds {
+	fn contains(&self, v: f64) -> bool {
+		self.min.is_none_or(|min| v >= min)
+			&& self.max.is_none_or(|max| v <= max)
+	}
+}
+
+impl FromStr for Bounds {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let malformed = || format!("Malformed validation rule '{}'", s);
+		let parts: Vec<&str> = s.split_whitespace().collect();
+		let (target, range) = match parts.as_slice() {
+			[t, "in", r] => (t, r),
+			_ => return Err(malformed()),
+		};
+
+		let (table, column) = match target.rfind('.') {
+			Some(i) => (Some(target[..i].to_string()), &target[i + 1..]),
+			None => (None, &target[..]),
+		};
+
+		let bound = |b: &str| match b {
+			"" => Ok(None),
+			b => b.parse().map(Some).map_err(|_| malformed()),
+		};
+		let (min, max) = range.split_once("..").ok_or_else(malformed)?;
+		let (min, max) = (bound(min)?, bound(max)?);
+		if min.is_none() && max.is_none() {
+			return Err(malformed());
+		}
+
+		Ok(Bounds {
+			table,
+			column: column.to_string(),
+			min,
+			max,
+		})
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Validity ranges checked against every entry before it is stored.
+#[derive(Default)]
+pub struct Validations {
+	rules: Vec<(ValidateConfig, Bounds)>,
+	/// Per descriptor uid, the (rule, field index) pairs to check.
+	bindings: Vec<Vec<(usize, usize)>>,
+}
+
+impl Validations {
+	pub fn new(configs: &[ValidateConfig]) -> Result<Validations, String> {
+		let mut rules = vec![];
+		for config in configs {
+			rules.push((config.clone(), config.rule.parse()?));
+		}
+
+		Ok(Validations {
+			rules,
+			bindings: vec![],
+		})
+	}
+
+	/// Resolves the rules checking columns of a newly registered table.
+	/// Returns whether the table needs a `valid` column.
+	pub fn bind(&mut self, uid: u32, table: &str, columns: &[String]) -> bool {
+		let uid = uid as usize;
+		if self.bindings.len() <= uid {
+			self.bindings.resize(uid + 1, vec![]);
+		}
+		// A reconnected producer may reuse the uid for another table.
+		self.bindings[uid].clear();
+
+		for (i, (_, bounds)) in self.rules.iter().enumerate() {
+			if bounds.table.as_ref().is_some_and(|t| t != table) {
+				continue;
+			}
+
+			if let Some(field) =
+				columns.iter().position(|c| *c == bounds.column)
+			{
+				self.bindings[uid].push((i, field));
+			}
+		}
+
+		self.bindings[uid]
+			.iter()
+			.any(|(i, _)| self.rules[*i].0.policy == Policy::Flag)
+	}
+
+	/// Checks an entry of `uid`, returning the strictest policy of the
+	/// rules it breaks along with the reason.
+	pub fn check<F>(&self, uid: u32, value: F) -> Option<(Policy, String)>
+	where
+		F: Fn(usize) -> Option<f64>,
+	{
+		let bindings = self.bindings.get(uid as usize)?;
+
+		let mut broken: Option<(Policy, String)> = None;
+		for (i, field) in bindings {
+			let (config, bounds) = &self.rules[*i];
+			let v = match value(*field) {
+				Some(v) if !bounds.contains(v) => v,
+				_ => continue,
+			};
+
+			if broken.as_ref().is_none_or(|(p, _)| config.policy > *p) {
+				let reason =
+					format!("{} = {} breaks {}", bounds.column, v, config.rule);
+				broken = Some((config.policy, reason));
+			}
+		}
+
+		broken
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn check_ranges() {
+		let rule: Bounds = "frame.frame_ms in 0..10000".parse().unwrap();
+		assert_eq!(rule.table.as_deref(), Some("frame"));
+		assert_eq!((rule.min, rule.max), (Some(0.0), Some(10000.0)));
+		assert_eq!("fps in ..240".parse::<Bounds>().unwrap().min, None);
+		assert!("fps in ..".parse::<Bounds>().is_err());
+		assert!("fps < 240".parse::<Bounds>().is_err());
+
+		let configs = [
+			ValidateConfig {
+				rule: String::from("frame.frame_ms in 0..10000"),
+				policy: Policy::Flag,
+			},
+			ValidateConfig {
+				rule: String::from("fps in 1.."),
+				policy: Policy::Drop,
+			},
+		];
+		let mut validations = Validations::new(&configs).unwrap();
+		let columns = [String::from("frame_ms"), String::from("fps")];
+		assert!(validations.bind(0, "frame", &columns));
+		assert!(!validations.bind(1, "render", &columns));
+
+		let values = [12000.0, 60.0];
+		let broken = validations.check(0, |i| Some(values[i]));
+		assert_eq!(broken.unwrap().0, Policy::Flag);
+
+		let values = [f64::NAN, 0.0];
+		let broken = validations.check(0, |i| Some(values[i]));
+		assert_eq!(broken.unwrap().0, Policy::Drop);
+
+		assert_eq!(validations.check(1, |_| Some(12000.0)), None);
+	}
+}
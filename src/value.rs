@@ -0,0 +1,182 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value as Json};
+
+//---------------------------------------------------------------------------
+/// A value of a row, independent of where the row is stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	Int(i64),
+	Float(f64),
+	Bool(bool),
+	Str(String),
+	Blob(Vec<u8>),
+	Null,
+}
+
+impl Value {
+	/// JSON of the value, blobs have no JSON text and become null.
+	pub fn to_json(&self) -> Json {
+		match self {
+			Value::Int(v) => Json::from(*v),
+			Value::Float(v) => Json::from(*v),
+			Value::Bool(v) => Json::from(*v),
+			Value::Str(s) => Json::from(s.as_str()),
+			Value::Blob(_) | Value::Null => Json::Null,
+		}
+	}
+}
+
+impl From<u32> for Value {
+	fn from(v: u32) -> Value {
+		Value::Int(v as i64)
+	}
+}
+
+impl From<i64> for Value {
+	fn from(v: i64) -> Value {
+		Value::Int(v)
+	}
+}
+
+impl From<f64> for Value {
+	fn from(v: f64) -> Value {
+		Value::Float(v)
+	}
+}
+
+impl From<bool> for Value {
+	fn from(v: bool) -> Value {
+		Value::Bool(v)
+	}
+}
+
+impl From<&str> for Value {
+	fn from(s: &str) -> Value {
+		Value::Str(s.to_string())
+	}
+}
+
+impl From<String> for Value {
+	fn from(s: String) -> Value {
+		Value::Str(s)
+	}
+}
+
+impl From<&[u8]> for Value {
+	fn from(b: &[u8]) -> Value {
+		Value::Blob(b.to_vec())
+	}
+}
+
+impl From<Vec<u8>> for Value {
+	fn from(b: Vec<u8>) -> Value {
+		Value::Blob(b)
+	}
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+	fn from(v: Option<T>) -> Value {
+		v.map_or(Value::Null, Into::into)
+	}
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for Value {
+	fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+		use rusqlite::types::{ToSqlOutput, ValueRef};
+
+		Ok(ToSqlOutput::Borrowed(match self {
+			Value::Int(v) => ValueRef::Integer(*v),
+			Value::Float(v) => ValueRef::Real(*v),
+			Value::Bool(v) => ValueRef::Integer(*v as i64),
+			Value::Str(s) => ValueRef::Text(s.as_bytes()),
+			Value::Blob(b) => ValueRef::Blob(b),
+			Value::Null => ValueRef::Null,
+		}))
+	}
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::types::ValueRef<'_>> for Value {
+	fn from(value: rusqlite::types::ValueRef) -> Value {
+		use rusqlite::types::ValueRef;
+
+		match value {
+			ValueRef::Integer(v) => Value::Int(v),
+			ValueRef::Real(v) => Value::Float(v),
+			ValueRef::Text(t) => {
+				Value::Str(String::from_utf8_lossy(t).into_owned())
+			}
+			ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+			ValueRef::Null => Value::Null,
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+/// An entry of a table as it goes through the pipeline, its values named by
+/// their columns in column order.
+#[derive(Clone, Debug)]
+pub struct Row {
+	pub table: String,
+	/// Producer the entry came from, see `Daemon::source_name`.
+	pub source: String,
+	pub values: Vec<(String, Value)>,
+}
+
+impl Row {
+	/// Value of `column`.
+	pub fn get(&self, column: &str) -> Option<&Value> {
+		self.values
+			.iter()
+			.find(|(c, _)| c == column)
+			.map(|(_, v)| v)
+	}
+
+	/// Deserializes the values into a `T` whose fields are named after the
+	/// columns, e.g. a struct deriving `serde::Deserialize`. Columns without
+	/// a field are ignored unless `T` denies unknown fields.
+	pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, String> {
+		let values: Map<String, Json> = self
+			.values
+			.iter()
+			.map(|(c, v)| (c.clone(), v.to_json()))
+			.collect();
+
+		serde_json::from_value(Json::Object(values))
+			.map_err(|e| format!("Row of {} does not fit: {}", self.table, e))
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde::Deserialize;
+
+	#[derive(Deserialize)]
+	struct Frame {
+		ts: u32,
+		scene: Option<String>,
+	}
+
+	#[test]
+	fn row_values() {
+		let row = Row {
+			table: String::from("frame"),
+			source: String::from("test"),
+			values: vec![
+				(String::from("ts"), Value::from(7u32)),
+				(String::from("scene"), Value::from(None::<&str>)),
+				(String::from("dump"), Value::from(vec![1, 2])),
+			],
+		};
+
+		assert_eq!(row.get("ts"), Some(&Value::Int(7)));
+		assert_eq!(row.get("gpu_ms"), None);
+		assert_eq!(row.get("dump").unwrap().to_json(), Json::Null);
+
+		let frame: Frame = row.deserialize().unwrap();
+		assert_eq!((frame.ts, frame.scene), (7, None));
+	}
+}
@@ -0,0 +1,131 @@
+use crate::cipher;
+use crate::dae::SharedStats;
+use crate::export;
+use crate::http::{Request, Response};
+use rusqlite;
+use serde_json::{json, Value};
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+
+//---------------------------------------------------------------------------
+const INDEX: &str = include_str!("web/index.html");
+const MAX_POINTS: u32 = 2000;
+
+//---------------------------------------------------------------------------
+pub struct Options {
+	pub db: PathBuf,
+	pub stats: SharedStats,
+}
+
+/// Serves the embedded dashboard and its json api on `addr`.
+pub fn serve(addr: &str, opts: Options) -> io::Result<thread::JoinHandle<()>> {
+	println!("Web dashboard listening on http://{}", addr);
+	crate::http::serve(addr, move |req| handle(req, &opts))
+}
+
+fn handle(req: &Request, opts: &Options) -> Response {
+	let result = match (req.method.as_str(), req.path.as_str()) {
+		("GET", "/") => {
+			return Response::new(200, "text/html", INDEX.as_bytes().to_vec())
+		}
+		("GET", "/api/tables") => tables(opts),
+		("GET", "/api/series") => series(req, opts),
+		_ => return Response::text(404, "Not found"),
+	};
+
+	match result {
+		Ok(body) => Response::json(body.to_string()),
+		Err(e) => Response::text(500, &e.to_string()),
+	}
+}
+
+fn open(opts: &Options) -> Result<rusqlite::Connection, export::Error> {
+	let con =
+		cipher::open(&opts.db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+	Ok(con)
+}
+
+fn tables(opts: &Options) -> Result<Value, export::Error> {
+	let stats = opts.stats.lock().unwrap().clone();
+	let schema = export::tables(&open(opts)?)?;
+
+	let tables: Vec<Value> = stats
+		.tables
+		.iter()
+		.map(|t| {
+			let columns: Vec<&str> = schema
+				.iter()
+				.find(|s| s.name == t.name)
+				.map(|s| {
+					s.columns
+						.iter()
+						.filter(|c| c.is_numeric())
+						.map(|c| c.name.as_str())
+						.collect()
+				})
+				.unwrap_or_default();
+
+			json!({
+				"name": t.name,
+				"rows": t.rows,
+				"dropped": t.dropped,
+				"suppressed": t.suppressed,
+				"invalid": t.invalid,
+				"columns": columns,
+			})
+		})
+		.collect();
+
+	Ok(json!({ "entries": stats.entries, "tables": tables }))
+}
+
+/// Latest rows of a numeric column, keyed by the timestamp column when the
+/// table has one and by rowid otherwise.
+fn series(req: &Request, opts: &Options) -> Result<Value, export::Error> {
+	let table_name = req.param("table").unwrap_or_default();
+	let column_name = req.param("column").unwrap_or_default();
+	let limit = req
+		.param("limit")
+		.and_then(|l| l.parse::<u32>().ok())
+		.unwrap_or(500)
+		.min(MAX_POINTS);
+
+	let con = open(opts)?;
+	let schema = export::tables(&con)?;
+
+	let table = schema
+		.iter()
+		.find(|t| t.name == table_name)
+		.ok_or(export::Error::Format("Unknown table"))?;
+
+	if !table.columns.iter().any(|c| c.name == column_name) {
+		return Err(export::Error::Format("Unknown column"));
+	}
+
+	let x = match table.find_column(&export::TS_COLUMNS) {
+		Some(i) => format!("\"{}\"", table.columns[i].name),
+		None => String::from("rowid"),
+	};
+
+	let mut stmt = con.prepare(&format!(
+		"SELECT {x}, \"{y}\" FROM \"{table}\" ORDER BY rowid DESC LIMIT ?1",
+		x = x,
+		y = column_name,
+		table = table.name,
+	))?;
+
+	let mut points = vec![];
+	let mut rows = stmt.query([limit])?;
+	while let Some(row) = rows.next()? {
+		let x = export::as_f64(row.get_raw(0));
+		let y = export::as_f64(row.get_raw(1));
+		if let (Some(x), Some(y)) = (x, y) {
+			points.push(json!([x, y]));
+		}
+	}
+
+	points.reverse();
+	Ok(json!({ "points": points }))
+}
@@ -0,0 +1,128 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+//---------------------------------------------------------------------------
+fn io_error(e: tungstenite::Error) -> io::Error {
+	match e {
+		tungstenite::Error::Io(e) => e,
+		e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Reads the binary messages of a WebSocket back to back, each holding whole
+/// protocol messages.
+pub struct Messages<S: Read + Write> {
+	socket: WebSocket<S>,
+	data: Vec<u8>,
+	pos: usize,
+}
+
+impl<S: Read + Write> Read for Messages<S> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.pos == self.data.len() {
+			match self.socket.read() {
+				Ok(Message::Binary(data)) => {
+					self.data = data;
+					self.pos = 0;
+				}
+				// Pings are answered by the socket, text is not protocol data.
+				Ok(_) => {}
+				Err(tungstenite::Error::ConnectionClosed)
+				| Err(tungstenite::Error::AlreadyClosed) => return Ok(0),
+				Err(e) => return Err(io_error(e)),
+			}
+		}
+
+		let n = buf.len().min(self.data.len() - self.pos);
+		buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+/// Completes the handshake of a connection accepted by the daemon.
+pub fn accept(stream: TcpStream) -> io::Result<Messages<TcpStream>> {
+	let socket = tungstenite::accept(stream).map_err(|e| {
+		io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+	})?;
+
+	Ok(Messages {
+		socket,
+		data: vec![],
+		pos: 0,
+	})
+}
+
+//---------------------------------------------------------------------------
+/// Buffers producer writes, sending them as one binary message per flush.
+pub struct Sender<S: Read + Write> {
+	socket: WebSocket<S>,
+	buffer: Vec<u8>,
+}
+
+impl<S: Read + Write> Write for Sender<S> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.buffer.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		if !self.buffer.is_empty() {
+			let data = std::mem::take(&mut self.buffer);
+			self.socket.send(Message::Binary(data)).map_err(io_error)?;
+		}
+		Ok(())
+	}
+}
+
+impl<S: Read + Write> Drop for Sender<S> {
+	fn drop(&mut self) {
+		let _ = self.flush();
+		let _ = self.socket.close(None);
+		let _ = self.socket.flush();
+	}
+}
+
+/// Connects to a daemon accepting WebSockets, e.g. `ws://127.0.0.1:2002`.
+pub fn connect(url: &str) -> io::Result<Sender<MaybeTlsStream<TcpStream>>> {
+	let (socket, _) = tungstenite::connect(url).map_err(io_error)?;
+
+	Ok(Sender {
+		socket,
+		buffer: vec![],
+	})
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::TcpListener;
+	use std::thread;
+
+	#[test]
+	fn stream_messages() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("ws://{}", listener.local_addr().unwrap());
+
+		let client = thread::spawn(move || {
+			let mut sender = connect(&url).unwrap();
+			sender.write_all(&[1, 2]).unwrap();
+			sender.flush().unwrap();
+			sender.write_all(&[3]).unwrap();
+		});
+
+		let (stream, _) = listener.accept().unwrap();
+		let mut messages = accept(stream).unwrap();
+
+		let mut data = vec![];
+		messages.read_to_end(&mut data).unwrap();
+		assert_eq!(data, [1, 2, 3]);
+
+		client.join().unwrap();
+	}
+}
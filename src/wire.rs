@@ -0,0 +1,600 @@
+// Parts of the decoding are only used by the daemon.
+#![cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+
+use sdd_core::{Type, MSG_DESC, MSG_ENTITY, MSG_ENTRY, MSG_MARKER, MSG_STR};
+use sdd_core::{MSG_ATTACHMENT, MSG_COUNTER, MSG_PAUSE, MSG_THREAD};
+use sdd_core::{MSG_SCOPE_BEGIN, MSG_SCOPE_END, MSG_SOURCE};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+use std::io::Read;
+
+//---------------------------------------------------------------------------
+/// Column names recognized as timestamps.
+pub const TS_COLUMNS: [&str; 3] = ["ts", "timestamp", "time"];
+
+/// Whether a column refers to the entities of the run, see `Message::Entity`.
+/// Returns the column name without the `_id` suffix.
+pub fn entity_column(name: &str) -> Option<&str> {
+	match name {
+		"entity_id" => Some("entity"),
+		n if n.ends_with("_entity_id") => n.strip_suffix("_id"),
+		_ => None,
+	}
+}
+
+//---------------------------------------------------------------------------
+pub(crate) enum MsgType {
+	Invalid = 0,
+	Str = 1,
+	Entry = 2,
+	Desc = 3,
+	Marker = 4,
+	Entity = 5,
+	ScopeBegin = 6,
+	ScopeEnd = 7,
+	Counter = 8,
+	Pause = 9,
+	Attachment = 10,
+	Thread = 11,
+	Source = 13,
+}
+
+impl From<u8> for MsgType {
+	fn from(t: u8) -> Self {
+		match t {
+			MSG_STR => MsgType::Str,
+			MSG_ENTRY => MsgType::Entry,
+			MSG_DESC => MsgType::Desc,
+			MSG_MARKER => MsgType::Marker,
+			MSG_ENTITY => MsgType::Entity,
+			MSG_SCOPE_BEGIN => MsgType::ScopeBegin,
+			MSG_SCOPE_END => MsgType::ScopeEnd,
+			MSG_COUNTER => MsgType::Counter,
+			MSG_PAUSE => MsgType::Pause,
+			MSG_ATTACHMENT => MsgType::Attachment,
+			MSG_THREAD => MsgType::Thread,
+			MSG_SOURCE => MsgType::Source,
+			_ => MsgType::Invalid,
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Column type of a field along with its last decoded value.
+#[derive(Debug, Copy, Clone)]
+pub enum FieldType {
+	Int(u32),
+	Float(f64),
+	Bool(bool),
+	Str(u32),
+}
+
+impl PartialEq for FieldType {
+	fn eq(&self, other: &Self) -> bool {
+		matches!(
+			(self, other),
+			(FieldType::Int(..), FieldType::Int(..))
+				| (FieldType::Float(..), FieldType::Float(..))
+				| (FieldType::Bool(..), FieldType::Bool(..))
+				| (FieldType::Str(..), FieldType::Str(..))
+		)
+	}
+}
+
+impl From<u8> for FieldType {
+	fn from(t: u8) -> Self {
+		match t {
+			1 => FieldType::Int(0),
+			2 => FieldType::Float(0.0),
+			3 => FieldType::Bool(false),
+			4 => FieldType::Str(0),
+			v => {
+				println!("{}", v);
+				panic!();
+			}
+		}
+	}
+}
+
+impl From<FieldType> for Type {
+	fn from(t: FieldType) -> Self {
+		match t {
+			FieldType::Int(..) => Type::Int,
+			FieldType::Float(..) => Type::Float,
+			FieldType::Bool(..) => Type::Bool,
+			FieldType::Str(..) => Type::Str,
+		}
+	}
+}
+
+impl FieldType {
+	pub(crate) fn as_f64(&self) -> Option<f64> {
+		match self {
+			FieldType::Int(v) => Some(*v as f64),
+			FieldType::Float(v) => Some(*v),
+			FieldType::Bool(v) => Some(*v as u8 as f64),
+			FieldType::Str(..) => None,
+		}
+	}
+
+	/// Bit pattern of the value, for equality checks.
+	pub(crate) fn raw_bits(&self) -> u64 {
+		match self {
+			FieldType::Int(v) => *v as u64,
+			FieldType::Float(v) => v.to_bits(),
+			FieldType::Bool(v) => *v as u64,
+			FieldType::Str(v) => *v as u64,
+		}
+	}
+
+	pub(crate) fn value_string(&self) -> String {
+		match self {
+			FieldType::Int(v) => v.to_string(),
+			FieldType::Float(v) => format!("{:.3}", v),
+			FieldType::Bool(v) => v.to_string(),
+			FieldType::Str(v) => format!("#{}", v),
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[derive(Copy, Clone)]
+pub(crate) struct FieldDescriptor {
+	pub(crate) data_type: FieldType,
+	pub(crate) name: u32,
+}
+
+impl FieldDescriptor {
+	/// Size of the field on the wire.
+	fn size(&self) -> usize {
+		Type::from(self.data_type).size()
+	}
+
+	/// Value of the field stored at the start of `bytes`, which holds at
+	/// least `size` bytes.
+	fn decode(&self, bytes: &[u8]) -> FieldType {
+		let word = || sdd_core::word(bytes);
+		match self.data_type {
+			FieldType::Int(..) => FieldType::Int(word()),
+			FieldType::Float(..) => {
+				FieldType::Float(f32::from_bits(word()).into())
+			}
+			FieldType::Bool(..) => FieldType::Bool(bytes[0] > 0),
+			FieldType::Str(..) => FieldType::Str(word()),
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Layout of the entries of a table, names being producer string ids.
+#[derive(Clone, Default)]
+pub struct Descriptor {
+	pub(crate) name: u32,
+	pub(crate) num_fields: u8,
+	pub(crate) fields: [Option<FieldDescriptor>; 32],
+	/// Entries are tagged with the producer thread, see `Message::Thread`.
+	pub(crate) threaded: bool,
+}
+
+impl Descriptor {
+	/// Reads a descriptor message body, returning it with its uid.
+	pub(crate) fn read<R: Read>(
+		reader: &mut R,
+	) -> Result<(Descriptor, u32), Error> {
+		let mut msg_id_bytes = [0; 4];
+		let mut msg_name_bytes = [0; 4];
+		let mut msg_num_fields_bytes = [0; 1];
+
+		if reader.read_exact(&mut msg_id_bytes).is_err()
+			|| reader.read_exact(&mut msg_name_bytes).is_err()
+			|| reader.read_exact(&mut msg_num_fields_bytes).is_err()
+		{
+			return Err(Error::ReadFailure);
+		}
+
+		let msg_id = u32::from_le_bytes(msg_id_bytes);
+		let msg_name = u32::from_le_bytes(msg_name_bytes);
+		let threaded = msg_num_fields_bytes[0] & sdd_core::THREADED != 0;
+		let msg_num_fields =
+			(msg_num_fields_bytes[0] & !sdd_core::THREADED) as usize;
+
+		let mut desc = Descriptor::default();
+		if msg_num_fields > desc.fields.len() {
+			return Err(Error::Fatal("Too many fields in a descriptor"));
+		}
+
+		desc.num_fields = msg_num_fields as u8;
+		desc.name = msg_name;
+		desc.threaded = threaded;
+
+		for i in 0..msg_num_fields {
+			let mut data_type_bytes = [0; 1];
+			let mut name_bytes = [0; 4];
+
+			if reader.read_exact(&mut data_type_bytes).is_err()
+				|| reader.read_exact(&mut name_bytes).is_err()
+			{
+				return Err(Error::ReadFailure);
+			}
+
+			if Type::from_u8(data_type_bytes[0]).is_none() {
+				return Err(Error::Fatal("Unknown field type"));
+			}
+
+			let data_type = FieldType::from(data_type_bytes[0]);
+			let name = u32::from_le_bytes(name_bytes);
+			let field = FieldDescriptor { data_type, name };
+
+			desc.fields[i] = Option::Some(field);
+		}
+
+		Result::Ok((desc, msg_id))
+	}
+
+	/// String id of the table name.
+	pub fn name(&self) -> u32 {
+		self.name
+	}
+
+	/// Whether the entries are tagged with the producer thread.
+	pub fn threaded(&self) -> bool {
+		self.threaded
+	}
+
+	/// String ids of the column names along with their types.
+	pub fn columns(&self) -> impl Iterator<Item = (u32, Type)> + '_ {
+		self.fields
+			.iter()
+			.flatten()
+			.map(|f| (f.name, f.data_type.into()))
+	}
+
+	/// Size of an entry of this descriptor on the wire.
+	pub fn entry_size(&self) -> usize {
+		self.fields.iter().flatten().map(|f| f.size()).sum()
+	}
+
+	/// Decodes an entry of `entry_size` bytes into the field values.
+	pub(crate) fn load(&mut self, bytes: &[u8]) {
+		let mut offset = 0;
+		for f in self.fields.iter_mut().flatten() {
+			f.data_type = f.decode(&bytes[offset..]);
+			offset += f.size();
+		}
+	}
+
+	/// Takes the values of an entry decoded elsewhere, as `load` does.
+	pub(crate) fn set(&mut self, entry: &Entry) {
+		let values = entry.values.iter().flatten();
+		for (f, value) in self.fields.iter_mut().flatten().zip(values) {
+			f.data_type = *value;
+		}
+	}
+
+	/// Values decoded by the last `load`.
+	pub(crate) fn entry(&self) -> Entry {
+		let mut entry = Entry {
+			num_fields: self.num_fields as usize,
+			values: [None; 32],
+		};
+
+		for (f, value) in self.fields.iter().zip(&mut entry.values) {
+			*value = f.map(|f| f.data_type);
+		}
+		entry
+	}
+}
+
+//---------------------------------------------------------------------------
+#[derive(Debug)]
+pub enum Error {
+	/// The producer went quiet, see `Daemon::idle_timeout`.
+	Stale,
+	Space,
+	ReadFailure,
+	Fatal(&'static str),
+	/// A protocol violation ending a strict capture, see `Daemon::strict`.
+	Violation(String),
+	/// A connection over its quota, see `Daemon::quota`.
+	Quota(String),
+}
+
+impl Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Space => write!(f, "SpaceError"),
+			Error::ReadFailure => write!(f, "ReadFailure"),
+			Error::Fatal(m) => write!(f, "Fatal: {}", m),
+			Error::Violation(m) => write!(f, "Protocol violation: {}", m),
+			Error::Quota(m) => write!(f, "Quota exceeded: {}", m),
+			Error::Stale => {
+				write!(f, "Stale: no data within the idle timeout")
+			}
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+/// Field values of a decoded entry, see `decode_entry`.
+#[derive(Clone, Copy)]
+pub struct Entry {
+	num_fields: usize,
+	values: [Option<FieldType>; 32],
+}
+
+impl Entry {
+	/// Values in column order.
+	pub fn values(&self) -> impl Iterator<Item = &FieldType> {
+		self.values[..self.num_fields].iter().flatten()
+	}
+}
+
+/// Decodes a descriptor message body from the start of `bytes`,
+/// returning it with its uid and the number of bytes consumed.
+pub fn decode_descriptor(
+	bytes: &[u8],
+) -> Result<(Descriptor, u32, usize), Error> {
+	let mut reader = bytes;
+	let (desc, uid) = Descriptor::read(&mut reader)?;
+	Ok((desc, uid, bytes.len() - reader.len()))
+}
+
+/// Decodes the body of an entry of `desc` from the start of `bytes`.
+/// Entries have no length prefix, their size is given by the descriptor.
+pub fn decode_entry(desc: &Descriptor, bytes: &[u8]) -> Result<Entry, Error> {
+	if bytes.len() < desc.entry_size() {
+		return Err(Error::Space);
+	}
+
+	let mut entry = Entry {
+		num_fields: desc.num_fields as usize,
+		values: [None; 32],
+	};
+
+	let mut offset = 0;
+	for (f, value) in desc.fields.iter().flatten().zip(&mut entry.values) {
+		*value = Some(f.decode(&bytes[offset..]));
+		offset += f.size();
+	}
+
+	Ok(entry)
+}
+
+//---------------------------------------------------------------------------
+/// Name of a column type in the listings of decoded streams.
+pub fn type_name(t: Type) -> &'static str {
+	match t {
+		Type::Int => "int",
+		Type::Float => "float",
+		Type::Bool => "bool",
+		Type::Str => "str",
+	}
+}
+
+/// Text of string `id` of a decoded stream, `#<id>` when it was not sent.
+pub fn string(strings: &HashMap<u32, String>, id: u32) -> String {
+	strings
+		.get(&id)
+		.cloned()
+		.unwrap_or_else(|| format!("#{}", id))
+}
+
+/// A decoded message of a stream, see `Messages`.
+pub enum Message<'a> {
+	String {
+		id: u32,
+		value: &'a [u8],
+	},
+	Table {
+		uid: u32,
+		desc: Box<Descriptor>,
+	},
+	Entry {
+		uid: u32,
+		entry: Box<Entry>,
+	},
+	Marker {
+		start: bool,
+		label: Option<u32>,
+	},
+	Entity {
+		id: u32,
+		name: u32,
+		kind: u32,
+	},
+	ScopeBegin {
+		id: u32,
+		parent: Option<u32>,
+		name: u32,
+		ts: u64,
+	},
+	ScopeEnd {
+		id: u32,
+		ts: u64,
+	},
+	Counter {
+		uid: u32,
+		delta: u32,
+	},
+	Pause {
+		paused: bool,
+		reason: Option<u32>,
+	},
+	Attachment {
+		name: u32,
+		data: &'a [u8],
+	},
+	Thread {
+		name: u32,
+	},
+	Source {
+		name: u32,
+	},
+}
+
+/// Decodes a recorded stream message by message, keeping the descriptors to
+/// size the entries. Bytes that are not a protocol header are skipped up to
+/// the next one, reported once as an error. A truncated message or an entry
+/// of an unknown table ends the stream.
+pub struct Messages<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+	descriptors: HashMap<u32, Descriptor>,
+	done: bool,
+}
+
+impl<'a> Messages<'a> {
+	pub fn new(bytes: &'a [u8]) -> Self {
+		Messages {
+			bytes,
+			pos: 0,
+			descriptors: HashMap::new(),
+			done: false,
+		}
+	}
+
+	/// Offset of the next message.
+	pub fn offset(&self) -> usize {
+		self.pos
+	}
+
+	/// Continues at `offset`, which has to start a message. Entries are
+	/// only decoded once their table was read.
+	pub fn seek(&mut self, offset: usize) {
+		self.pos = offset.min(self.bytes.len());
+		self.done = false;
+	}
+
+	fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+		if self.bytes.len() - self.pos < n {
+			self.done = true;
+			return Err(Error::ReadFailure);
+		}
+		let bytes = &self.bytes[self.pos..self.pos + n];
+		self.pos += n;
+		Ok(bytes)
+	}
+
+	fn message(&mut self) -> Result<Message<'a>, Error> {
+		let header = self.take(sdd_core::HEADER_SIZE)?;
+		if sdd_core::word(header) != sdd_core::PROTOCOL {
+			let rest = &self.bytes[self.pos - sdd_core::HEADER_SIZE + 1..];
+			let skip = rest
+				.windows(4)
+				.position(|w| sdd_core::word(w) == sdd_core::PROTOCOL)
+				.unwrap_or(rest.len());
+			self.pos = self.pos - sdd_core::HEADER_SIZE + 1 + skip;
+			return Err(Error::Fatal("Not a protocol header"));
+		}
+
+		match MsgType::from(header[4]) {
+			MsgType::Str => {
+				let id = sdd_core::word(self.take(4)?);
+				let len = sdd_core::word(self.take(4)?) as usize;
+				let value = self.take(len)?;
+				Ok(Message::String { id, value })
+			}
+			MsgType::Desc => {
+				let (desc, uid, n) = decode_descriptor(&self.bytes[self.pos..])
+					.inspect_err(|_| self.done = true)?;
+				self.pos += n;
+				self.descriptors.insert(uid, desc.clone());
+				let desc = Box::new(desc);
+				Ok(Message::Table { uid, desc })
+			}
+			MsgType::Entry => {
+				let uid = sdd_core::word(self.take(4)?);
+				let size = match self.descriptors.get(&uid) {
+					Some(desc) => desc.entry_size(),
+					None => {
+						self.done = true;
+						return Err(Error::Fatal("Entry of an unknown table"));
+					}
+				};
+				let bytes = self.take(size)?;
+				let entry = decode_entry(&self.descriptors[&uid], bytes)?;
+				let entry = Box::new(entry);
+				Ok(Message::Entry { uid, entry })
+			}
+			MsgType::Marker => {
+				let start = self.take(1)?[0] == 1;
+				let label = sdd_core::word(self.take(4)?);
+				let label = Some(label).filter(|l| *l != sdd_core::NO_LABEL);
+				Ok(Message::Marker { start, label })
+			}
+			MsgType::Entity => {
+				let id = sdd_core::word(self.take(4)?);
+				let name = sdd_core::word(self.take(4)?);
+				let kind = sdd_core::word(self.take(4)?);
+				Ok(Message::Entity { id, name, kind })
+			}
+			MsgType::ScopeBegin => {
+				let id = sdd_core::word(self.take(4)?);
+				let parent = sdd_core::word(self.take(4)?);
+				let parent = Some(parent).filter(|p| *p != sdd_core::NO_SCOPE);
+				let name = sdd_core::word(self.take(4)?);
+				let ts = sdd_core::dword(self.take(8)?);
+				Ok(Message::ScopeBegin {
+					id,
+					parent,
+					name,
+					ts,
+				})
+			}
+			MsgType::ScopeEnd => {
+				let id = sdd_core::word(self.take(4)?);
+				let ts = sdd_core::dword(self.take(8)?);
+				Ok(Message::ScopeEnd { id, ts })
+			}
+			MsgType::Counter => {
+				let uid = sdd_core::word(self.take(4)?);
+				let rest = &self.bytes[self.pos..];
+				match sdd_core::varint(rest) {
+					Some((delta, n)) => {
+						self.pos += n;
+						Ok(Message::Counter { uid, delta })
+					}
+					None if rest.len() < 5 => {
+						self.done = true;
+						Err(Error::ReadFailure)
+					}
+					None => Err(Error::Fatal("Malformed counter delta")),
+				}
+			}
+			MsgType::Pause => {
+				let paused = self.take(1)?[0] == 1;
+				let reason = sdd_core::word(self.take(4)?);
+				let reason = Some(reason).filter(|r| *r != sdd_core::NO_LABEL);
+				Ok(Message::Pause { paused, reason })
+			}
+			MsgType::Attachment => {
+				let name = sdd_core::word(self.take(4)?);
+				let len = sdd_core::word(self.take(4)?) as usize;
+				let data = self.take(len)?;
+				Ok(Message::Attachment { name, data })
+			}
+			MsgType::Thread => {
+				let name = sdd_core::word(self.take(4)?);
+				Ok(Message::Thread { name })
+			}
+			MsgType::Source => {
+				let name = sdd_core::word(self.take(4)?);
+				Ok(Message::Source { name })
+			}
+			MsgType::Invalid => Err(Error::Fatal("Unknown message type")),
+		}
+	}
+}
+
+impl<'a> Iterator for Messages<'a> {
+	type Item = Result<Message<'a>, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done || self.pos == self.bytes.len() {
+			return None;
+		}
+
+		Some(self.message())
+	}
+}
@@ -0,0 +1,75 @@
+use std::io;
+use std::io::Read;
+
+//---------------------------------------------------------------------------
+/// How long a read waits for the next message, the daemon polls its control
+/// socket and bounds in between.
+const POLL_MS: i32 = 100;
+
+//---------------------------------------------------------------------------
+/// Reads the messages of a PULL socket back to back, each holding whole
+/// protocol messages.
+pub struct Pull {
+	socket: zmq::Socket,
+	data: Vec<u8>,
+	pos: usize,
+}
+
+/// Binds a PULL socket on `endpoint`, e.g. `tcp://*:5557`.
+pub fn bind(endpoint: &str) -> io::Result<Pull> {
+	let socket = zmq::Context::new().socket(zmq::PULL)?;
+	socket.set_rcvtimeo(POLL_MS)?;
+	socket.bind(endpoint)?;
+
+	Ok(Pull {
+		socket,
+		data: vec![],
+		pos: 0,
+	})
+}
+
+impl Read for Pull {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		while self.pos == self.data.len() {
+			match self.socket.recv_bytes(0) {
+				Ok(data) => {
+					self.data = data;
+					self.pos = 0;
+				}
+				Err(zmq::Error::EAGAIN) => {
+					return Err(io::ErrorKind::TimedOut.into())
+				}
+				Err(e) => return Err(e.into()),
+			}
+		}
+
+		let n = buf.len().min(self.data.len() - self.pos);
+		buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pull_messages() {
+		let mut pull = bind("tcp://127.0.0.1:*").unwrap();
+		let endpoint = pull.socket.get_last_endpoint().unwrap().unwrap();
+
+		let push = zmq::Context::new().socket(zmq::PUSH).unwrap();
+		push.connect(&endpoint).unwrap();
+		push.send(&[1, 2][..], 0).unwrap();
+		push.send(&[3][..], 0).unwrap();
+
+		let mut buf = [0; 3];
+		pull.read_exact(&mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+
+		let timeout = pull.read(&mut buf).unwrap_err();
+		assert_eq!(timeout.kind(), io::ErrorKind::TimedOut);
+	}
+}
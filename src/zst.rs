@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+//---------------------------------------------------------------------------
+/// Extension of compressed captures, following the one of the database.
+pub const EXTENSION: &str = "zst";
+
+/// Whether `path` names a compressed capture.
+pub fn is_compressed(path: &Path) -> bool {
+	path.extension().is_some_and(|e| e == EXTENSION)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unsupported() -> io::Error {
+	io::Error::other("sdd was built without the zstd feature.")
+}
+
+/// Replaces the capture at `path` by `<path>.zst` compressed at `level`,
+/// returning the path of the compressed capture.
+#[cfg(feature = "zstd")]
+pub fn compress(path: &str, level: i32) -> io::Result<String> {
+	let target = format!("{}.{}", path, EXTENSION);
+	let input = fs::File::open(path)?;
+	let output = fs::File::create(&target)?;
+	if let Err(e) = zstd::stream::copy_encode(input, output, level) {
+		let _ = fs::remove_file(&target);
+		return Err(e);
+	}
+
+	fs::remove_file(path)?;
+	Ok(target)
+}
+
+#[cfg(not(feature = "zstd"))]
+pub fn compress(_path: &str, _level: i32) -> io::Result<String> {
+	Err(unsupported())
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(path: &Path, target: &Path) -> io::Result<()> {
+	let input = fs::File::open(path)?;
+	let output = fs::File::create(target)?;
+	let result = zstd::stream::copy_decode(input, output);
+	if result.is_err() {
+		let _ = fs::remove_file(target);
+	}
+	result
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_path: &Path, _target: &Path) -> io::Result<()> {
+	Err(unsupported())
+}
+
+//---------------------------------------------------------------------------
+/// A capture to read, a compressed one being decompressed into a temporary
+/// file removed once dropped.
+pub struct Unpacked {
+	path: PathBuf,
+	temporary: bool,
+}
+
+/// Opens the capture at `path` for reading, see `Unpacked`.
+pub fn open(path: &Path) -> io::Result<Unpacked> {
+	if !is_compressed(path) {
+		let path = path.to_path_buf();
+		return Ok(Unpacked {
+			path,
+			temporary: false,
+		});
+	}
+
+	let name = path.file_stem().unwrap_or_default().to_string_lossy();
+	let temp = format!("sdd-{}-{}", std::process::id(), name);
+	let temp = std::env::temp_dir().join(temp);
+	decompress(path, &temp)?;
+
+	Ok(Unpacked {
+		path: temp,
+		temporary: true,
+	})
+}
+
+impl Deref for Unpacked {
+	type Target = Path;
+
+	fn deref(&self) -> &Path {
+		&self.path
+	}
+}
+
+impl AsRef<Path> for Unpacked {
+	fn as_ref(&self) -> &Path {
+		&self.path
+	}
+}
+
+impl Drop for Unpacked {
+	fn drop(&mut self) {
+		if self.temporary {
+			let _ = fs::remove_file(&self.path);
+		}
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn compress_and_open() {
+		let path = std::env::temp_dir()
+			.join(format!("sdd-zst-test-{}.db", std::process::id()));
+		let path = path.to_string_lossy().into_owned();
+		let data: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+		fs::write(&path, &data).unwrap();
+
+		let packed = compress(&path, 3).unwrap();
+		assert_eq!(packed, format!("{}.zst", path));
+		assert!(!Path::new(&path).exists());
+		assert!(fs::metadata(&packed).unwrap().len() < data.len() as u64);
+
+		let unpacked = open(Path::new(&packed)).unwrap();
+		assert_eq!(fs::read(&*unpacked).unwrap(), data);
+		let temp = unpacked.to_path_buf();
+		drop(unpacked);
+		assert!(!temp.exists());
+		let _ = fs::remove_file(&packed);
+	}
+}
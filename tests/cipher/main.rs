@@ -0,0 +1,30 @@
+//! Keys a capture with SQLCipher. The key is process wide, so the test runs
+//! in a binary of its own instead of beside the unit tests it would key.
+use rusqlite::{Connection, OpenFlags};
+use sdd::cipher;
+use std::env;
+use std::fs;
+
+#[test]
+fn encrypted_capture() {
+	let path = env::temp_dir()
+		.join(format!("sdd-cipher-test-{}.db", std::process::id()));
+	let _ = fs::remove_file(&path);
+
+	cipher::set_key("secret");
+	let con = cipher::open(&path, OpenFlags::default()).unwrap();
+	con.execute_batch("CREATE TABLE _sdd_strings (id INTEGER, value TEXT)")
+		.unwrap();
+	drop(con);
+
+	let plain = Connection::open(&path).unwrap();
+	let read = plain.query_row(
+		"SELECT count(*) FROM sqlite_master",
+		rusqlite::NO_PARAMS,
+		|_| Ok(()),
+	);
+	assert!(read.is_err());
+	assert!(cipher::open(&path, OpenFlags::default()).is_ok());
+
+	fs::remove_file(&path).unwrap();
+}
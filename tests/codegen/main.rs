@@ -0,0 +1,28 @@
+//! Builds the producer code generated from `reserved.sdd.toml`, whose names
+//! are keywords of Rust or C++ or taken by the generated code. Run `sdd
+//! codegen tests/codegen/reserved.sdd.toml -o tests/codegen/reserved.rs`
+//! after changing the generator and review the diff.
+use sdd::producer::Producer;
+use sdd::schema::Schema;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[rustfmt::skip]
+mod reserved;
+
+fn fixtures() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/codegen")
+}
+
+#[test]
+fn generated_rust() {
+	let schema = Schema::load(&fixtures().join("reserved.sdd.toml")).unwrap();
+	let generated = fs::read_to_string(fixtures().join("reserved.rs")).unwrap();
+	assert_eq!(schema.rust("reserved.sdd.toml"), generated);
+
+	let mut producer = Producer::new(vec![]);
+	reserved::declare(&mut producer).unwrap();
+	reserved::where_(&mut producer, 1, 2.0, "a", true, "b", 3).unwrap();
+	reserved::trait_(&mut producer, 4, "c").unwrap();
+	assert!(!producer.into_inner().is_empty());
+}
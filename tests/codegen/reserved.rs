@@ -0,0 +1,40 @@
+// Generated by `sdd codegen` from reserved.sdd.toml, do not edit.
+#![allow(dead_code, non_snake_case)]
+
+use sdd::producer::{Producer, Type, Value};
+use std::io::{self, Write};
+
+pub const WHERE: u32 = 0;
+pub const TRAIT: u32 = 1;
+
+/// Declares the tables of the schema, before any other table.
+pub fn declare<W: Write>(p: &mut Producer<W>) -> io::Result<()> {
+	if p.table("where", &[("p", Type::Int), ("W", Type::Float), ("type", Type::Str), ("self", Type::Bool), ("union", Type::Str), ("TRAIT", Type::Int)])? != WHERE {
+		return Err(io::Error::other(
+			"Schema tables have to be declared first",
+		));
+	}
+	if p.table("trait", &[("dyn", Type::Int), ("this", Type::Str)])? != TRAIT {
+		return Err(io::Error::other(
+			"Schema tables have to be declared first",
+		));
+	}
+	Ok(())
+}
+
+/// Sends an entry of where.
+pub fn where_<W: Write>(
+	p: &mut Producer<W>, p_: u32, W_: f32, type_: &str, self_: bool, union_: &str, TRAIT_: u32
+) -> io::Result<()> {
+	let type_ = p.string(type_)?;
+	let union_ = p.string(union_)?;
+	p.entry(WHERE, &[Value::Int(p_), Value::Float(W_), Value::Str(type_), Value::Bool(self_), Value::Str(union_), Value::Int(TRAIT_)])
+}
+
+/// Sends an entry of trait.
+pub fn trait_<W: Write>(
+	p: &mut Producer<W>, dyn_: u32, this_: &str
+) -> io::Result<()> {
+	let this_ = p.string(this_)?;
+	p.entry(TRAIT, &[Value::Int(dyn_), Value::Str(this_)])
+}
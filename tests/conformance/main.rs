@@ -0,0 +1,60 @@
+//! Checks the daemon against the golden files of `sdd::conformance`, the
+//! streams having to match their cases byte for byte and their captures the
+//! expected dumps. Run `cargo run --bin sdd-fixtures` after changing a case
+//! and review the diff of the expected files.
+use sdd::conformance;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn fixtures() -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance")
+}
+
+#[test]
+fn golden_files() {
+	let mut failures = vec![];
+	for case in conformance::cases() {
+		let stream = fixtures().join(format!("{}.sddcap", case.name));
+		let expected = fixtures().join(format!("{}.expected", case.name));
+		let (stream, expected) =
+			match (fs::read(&stream), fs::read_to_string(&expected)) {
+				(Ok(s), Ok(e)) => (s, e),
+				_ => {
+					failures.push(format!("{}: missing fixtures", case.name));
+					continue;
+				}
+			};
+
+		if stream != case.stream {
+			failures.push(format!("{}: stream differs", case.name));
+		}
+
+		let db = std::env::temp_dir()
+			.join(format!("sdd-conformance-{}.db", case.name));
+		let dump = conformance::capture(&stream, &db);
+		let _ = fs::remove_file(&db);
+		match dump {
+			Ok(dump) if format!("# {}\n{}", case.about, dump) == expected => {}
+			Ok(dump) => failures.push(format!(
+				"{}: capture differs\n--- expected\n{}--- captured\n{}",
+				case.name, expected, dump
+			)),
+			Err(e) => failures.push(format!("{}: {}", case.name, e)),
+		}
+	}
+
+	assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn no_stale_fixtures() {
+	let names: Vec<&str> =
+		conformance::cases().iter().map(|c| c.name).collect();
+	for entry in fs::read_dir(fixtures()).unwrap() {
+		let path = entry.unwrap().path();
+		if path.extension().is_some_and(|e| e == "sddcap") {
+			let stem = path.file_stem().unwrap().to_string_lossy();
+			assert!(names.contains(&stem.as_ref()), "stale {}", stem);
+		}
+	}
+}
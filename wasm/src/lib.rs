@@ -0,0 +1,283 @@
+//! Decoder of recorded sdd streams for browsers, built for
+//! wasm32-unknown-unknown with wasm-pack. It runs the decoding of the
+//! daemon, see `sdd::wire`, without SQLite or sockets.
+use sdd::producer::Type;
+use sdd::wire::{string, type_name, FieldType, Message, Messages};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+//---------------------------------------------------------------------------
+struct Table {
+	name: String,
+	columns: Vec<(String, Type)>,
+	rows: Vec<Vec<Value>>,
+}
+
+/// A stream decoded in memory, tables being addressed by name. Rows,
+/// markers, entities, attachments and errors are returned as JSON text for `JSON.parse`.
+#[wasm_bindgen]
+pub struct Capture {
+	tables: Vec<Table>,
+	markers: Vec<Value>,
+	entities: Vec<Value>,
+	attachments: Vec<Value>,
+	errors: Vec<Value>,
+}
+
+#[wasm_bindgen]
+impl Capture {
+	/// Decodes the bytes of a stream, e.g. a dropped file. Errors do not
+	/// fail the decoding, they are listed by `errors`.
+	#[wasm_bindgen(constructor)]
+	pub fn new(bytes: &[u8]) -> Capture {
+		let mut capture = Capture {
+			tables: vec![],
+			markers: vec![],
+			entities: vec![],
+			attachments: vec![],
+			errors: vec![],
+		};
+		let mut strings = HashMap::new();
+		// Table index by uid.
+		let mut uids = HashMap::new();
+		// Threaded tables by uid, along with the thread of their entries.
+		let mut threaded = HashSet::new();
+		let mut thread = None;
+
+		let mut messages = Messages::new(bytes);
+		loop {
+			let offset = messages.offset();
+			let message = match messages.next() {
+				Some(Ok(m)) => m,
+				Some(Err(e)) => {
+					let error =
+						json!({"offset": offset, "error": e.to_string()});
+					capture.errors.push(error);
+					continue;
+				}
+				None => break,
+			};
+
+			match message {
+				Message::String { id, value } => {
+					let value = String::from_utf8_lossy(value).into_owned();
+					strings.insert(id, value);
+				}
+				Message::Table { uid, desc } => {
+					let name = string(&strings, desc.name());
+					let mut columns: Vec<_> = desc
+						.columns()
+						.map(|(c, t)| (string(&strings, c), t))
+						.collect();
+					if desc.threaded() {
+						columns.push((String::from("thread"), Type::Str));
+						threaded.insert(uid);
+					} else {
+						threaded.remove(&uid);
+					}
+					let index = match capture.table(&name) {
+						Some(i) if capture.tables[i].columns == columns => i,
+						_ => {
+							capture.tables.push(Table {
+								name,
+								columns,
+								rows: vec![],
+							});
+							capture.tables.len() - 1
+						}
+					};
+					uids.insert(uid, index);
+				}
+				Message::Entry { uid, entry } => {
+					let mut row: Vec<Value> = entry
+						.values()
+						.map(|v| match *v {
+							FieldType::Int(v) => json!(v),
+							FieldType::Float(v) => json!(v),
+							FieldType::Bool(v) => json!(v),
+							FieldType::Str(id) => json!(string(&strings, id)),
+						})
+						.collect();
+					if threaded.contains(&uid) {
+						row.push(json!(thread));
+					}
+					capture.tables[uids[&uid]].rows.push(row);
+				}
+				Message::Marker { start, label } => {
+					let label = label.map(|l| string(&strings, l));
+					let action = if start { "start" } else { "stop" };
+					capture.markers.push(json!({
+						"offset": offset,
+						"action": action,
+						"label": label,
+					}));
+				}
+				Message::Pause { paused, reason } => {
+					let label = reason.map(|r| string(&strings, r));
+					let action = if paused { "pause" } else { "resume" };
+					capture.markers.push(json!({
+						"offset": offset,
+						"action": action,
+						"label": label,
+					}));
+				}
+				Message::Counter { uid, delta } => {
+					let table = match uids.get(&uid) {
+						Some(i) => &mut capture.tables[*i],
+						None => continue,
+					};
+					// The thread column follows the last one of the stream.
+					let tagged = threaded.contains(&uid);
+					let last = table.columns.len() - 1 - tagged as usize;
+					let mut row = match table.rows.last() {
+						Some(row) if table.columns[last].1 == Type::Int => {
+							row.clone()
+						}
+						_ => continue,
+					};
+
+					// The previous row with delta added to its last column.
+					let value = row[last].as_u64().unwrap_or(0) as u32;
+					row[last] = json!(value.wrapping_add(delta));
+					if tagged {
+						row[last + 1] = json!(thread);
+					}
+					table.rows.push(row);
+				}
+				Message::Attachment { name, data } => {
+					capture.attachments.push(json!({
+						"offset": offset,
+						"name": string(&strings, name),
+						"size": data.len(),
+					}));
+				}
+				Message::Thread { name } => {
+					thread = Some(string(&strings, name));
+				}
+				Message::ScopeBegin { .. }
+				| Message::ScopeEnd { .. }
+				| Message::Source { .. } => {}
+				Message::Entity { id, name, kind } => {
+					capture.entities.push(json!({
+						"id": id,
+						"name": string(&strings, name),
+						"type": string(&strings, kind),
+					}));
+				}
+			}
+		}
+
+		capture
+	}
+
+	/// Index of the last table named `name`, a table redeclared with other
+	/// columns being a new one.
+	fn table(&self, name: &str) -> Option<usize> {
+		self.tables.iter().rposition(|t| t.name == name)
+	}
+
+	/// Table names in declaration order.
+	pub fn tables(&self) -> Vec<String> {
+		self.tables.iter().map(|t| t.name.clone()).collect()
+	}
+
+	pub fn columns(&self, table: &str) -> Vec<String> {
+		match self.table(table) {
+			Some(i) => self.tables[i]
+				.columns
+				.iter()
+				.map(|(c, _)| c.clone())
+				.collect(),
+			None => vec![],
+		}
+	}
+
+	/// Column types: int, float, bool or str.
+	pub fn types(&self, table: &str) -> Vec<String> {
+		match self.table(table) {
+			Some(i) => self.tables[i]
+				.columns
+				.iter()
+				.map(|(_, t)| type_name(*t).to_string())
+				.collect(),
+			None => vec![],
+		}
+	}
+
+	#[wasm_bindgen(js_name = rowCount)]
+	pub fn row_count(&self, table: &str) -> usize {
+		self.table(table).map_or(0, |i| self.tables[i].rows.len())
+	}
+
+	/// Up to `count` rows from `start` as a JSON array of arrays, to page
+	/// through large tables.
+	pub fn rows(&self, table: &str, start: usize, count: usize) -> String {
+		let rows = match self.table(table) {
+			Some(i) => &self.tables[i].rows[..],
+			None => &[],
+		};
+		let start = start.min(rows.len());
+		let end = start.saturating_add(count).min(rows.len());
+		Value::from(rows[start..end].to_vec()).to_string()
+	}
+
+	/// `[{"offset", "action", "label"}]` with the byte offsets of the
+	/// markers.
+	pub fn markers(&self) -> String {
+		Value::from(self.markers.clone()).to_string()
+	}
+
+	/// `[{"id", "name", "type"}]` of the registered entities, in stream
+	/// order.
+	pub fn entities(&self) -> String {
+		Value::from(self.entities.clone()).to_string()
+	}
+
+	/// `[{"offset", "name", "size"}]` of the attachments, their data being
+	/// the last `size` bytes of the message at `offset`.
+	pub fn attachments(&self) -> String {
+		Value::from(self.attachments.clone()).to_string()
+	}
+
+	/// `[{"offset", "error"}]`, empty for a well formed stream.
+	pub fn errors(&self) -> String {
+		Value::from(self.errors.clone()).to_string()
+	}
+}
+
+//---------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sdd::producer::{Producer, Value as Field};
+
+	#[test]
+	fn decode_stream() {
+		let mut producer = Producer::new(vec![]);
+		let uid = producer
+			.table("frame", &[("ts", Type::Int), ("scene", Type::Str)])
+			.unwrap();
+		let scene = producer.string("menu").unwrap();
+		producer.marker(true, Some("warmup")).unwrap();
+		for ts in 0..3 {
+			let values = [Field::Int(ts), Field::Str(scene)];
+			producer.entry(uid, &values).unwrap();
+		}
+
+		let mut bytes = producer.into_inner();
+		let len = bytes.len();
+		bytes.splice(len - 17..len - 17, b"junk".iter().cloned());
+		bytes.truncate(bytes.len() - 1);
+
+		let capture = Capture::new(&bytes);
+		assert_eq!(capture.tables(), vec!["frame"]);
+		assert_eq!(capture.types("frame"), vec!["int", "str"]);
+		assert_eq!(capture.row_count("frame"), 2);
+		assert_eq!(capture.rows("frame", 1, 5), r#"[[1,"menu"]]"#);
+		assert!(capture.markers().contains(r#""label":"warmup""#));
+
+		let errors: Value = serde_json::from_str(&capture.errors()).unwrap();
+		assert_eq!(errors.as_array().unwrap().len(), 2);
+	}
+}